@@ -0,0 +1,125 @@
+use winit::{
+    dpi::PhysicalSize,
+    event::*,
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    window::{Window, WindowId},
+};
+
+// Something that renders into a window: the full renderer, the WebGL2
+// fallback, or whatever a host embeds. run drives it with winit, a test
+// harness or an embedding host can call the callbacks itself.
+pub trait App {
+    fn window(&self) -> &Window;
+
+    fn size(&self) -> PhysicalSize<u32>;
+
+    // Called once when the event loop starts
+    fn init(&mut self, _target: &EventLoopWindowTarget<()>) {}
+
+    // True when the event was consumed
+    fn input(&mut self, event: &WindowEvent) -> bool;
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>);
+
+    fn update(&mut self, dt: instant::Duration);
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError>;
+
+    fn device_event(&mut self, _event: &DeviceEvent) {}
+
+    // Checked after every frame, e.g. when a replay ends
+    fn should_exit(&self) -> bool {
+        false
+    }
+
+    // Events of other windows the app opened, true when handled
+    fn other_window_event(&mut self, _window_id: WindowId, _event: &WindowEvent) -> bool {
+        false
+    }
+
+    // Events of the main window that input didn't consume and run doesn't
+    // handle itself
+    fn unhandled_event(&mut self, _target: &EventLoopWindowTarget<()>, _event: &WindowEvent) {}
+}
+
+// Updates and renders a frame, reconfiguring the surface if it was lost.
+// Returns false when the app should exit.
+pub fn frame<A: App>(app: &mut A, dt: instant::Duration) -> bool {
+    app.update(dt);
+    match app.render() {
+        Ok(_) => {}
+        // Reconfigure the surface if it's lost or outdated
+        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => app.resize(app.size()),
+        // The system is out of memory, we should probably quit
+        Err(wgpu::SurfaceError::OutOfMemory) => return false,
+        // We're ignoring timeouts
+        Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+    }
+    ::profiling::finish_frame!();
+    true
+}
+
+// Runs the app until its window is closed or Escape is pressed
+pub fn run<A: App + 'static>(
+    event_loop: EventLoop<()>,
+    mut app: A,
+    #[cfg(target_arch = "wasm32")] resizer: Option<crate::web::CanvasResizer>,
+) -> ! {
+    let mut last_render_time = instant::Instant::now();
+
+    event_loop.run(move |event, target, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match event {
+            Event::NewEvents(StartCause::Init) => app.init(target),
+
+            Event::MainEventsCleared => {
+                #[cfg(target_arch = "wasm32")]
+                if let Some(size) = resizer.as_ref().and_then(|resizer| resizer.take_size()) {
+                    app.window().set_inner_size(size);
+                    app.resize(size);
+                }
+                app.window().request_redraw()
+            }
+
+            Event::DeviceEvent { ref event, .. } => app.device_event(event),
+
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id != app.window().id() => {
+                app.other_window_event(window_id, event);
+            }
+
+            Event::WindowEvent { ref event, .. } if !app.input(event) => match event {
+                #[cfg(not(target_arch = "wasm32"))]
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => {
+                    app.resize(*physical_size);
+                }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    app.resize(**new_inner_size);
+                }
+                _ => app.unhandled_event(target, event),
+            },
+
+            Event::RedrawRequested(window_id) if window_id == app.window().id() => {
+                let now = instant::Instant::now();
+                let dt = now - last_render_time;
+                last_render_time = now;
+                if !frame(&mut app, dt) || app.should_exit() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    })
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::mpsc,
+};
+
+use crate::{
+    memory::{self, MemoryCategory, MemoryTracker},
+    upload::{TextureUpload, Uploader},
+    watcher::FileWatcher,
+};
+
+pub mod cube;
+pub mod hdr;
+pub mod heightmap;
+pub mod vox;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AssetKind {
+    // MagicaVoxel .vox, the first model as a 3D Rgba8Unorm texture, alpha 0
+    // where there is no voxel
+    VoxelModel,
+    // Grayscale PNG as an R8Unorm texture
+    Heightmap,
+    // Radiance .hdr as an Rgba32Float texture
+    Hdri,
+    // .cube colour grading LUT as a 3D Rgba32Float texture
+    Lut,
+}
+
+impl AssetKind {
+    fn hot_reload(self) -> bool {
+        matches!(self, AssetKind::VoxelModel | AssetKind::Heightmap)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AssetHandle(u64);
+
+// Decoded on a background thread, uploaded on the main thread
+pub struct CpuTexture {
+    pub size: wgpu::Extent3d,
+    pub dimension: wgpu::TextureDimension,
+    pub format: wgpu::TextureFormat,
+    pub data: Vec<u8>,
+}
+
+pub struct GpuAsset {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+pub enum AssetState {
+    Loading,
+    Ready(GpuAsset),
+    Failed(String),
+}
+
+struct Asset {
+    path: String,
+    kind: AssetKind,
+    state: AssetState,
+    // Voxel assets are loaded again when they change on disk, e.g. when
+    // saved from MagicaVoxel
+    watcher: Option<FileWatcher>,
+}
+
+type Loaded = (AssetHandle, Result<CpuTexture, String>);
+
+// Loads assets without blocking the frame: files are read and decoded on
+// background threads (fetched on the web) and pump streams the finished
+// ones to the GPU over as many frames as the upload budget needs
+pub struct AssetManager {
+    assets: HashMap<AssetHandle, Asset>,
+    next_handle: u64,
+    sender: mpsc::Sender<Loaded>,
+    receiver: mpsc::Receiver<Loaded>,
+    // Decoded, waiting for upload budget
+    decoded: VecDeque<(AssetHandle, CpuTexture)>,
+    // Partly uploaded, in the order they were started
+    uploading: VecDeque<(AssetHandle, TextureUpload)>,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            assets: HashMap::new(),
+            next_handle: 0,
+            sender,
+            receiver,
+            decoded: VecDeque::new(),
+            uploading: VecDeque::new(),
+        }
+    }
+
+    pub fn load(&mut self, path: &str, kind: AssetKind) -> AssetHandle {
+        let handle = AssetHandle(self.next_handle);
+        self.next_handle += 1;
+        self.assets.insert(
+            handle,
+            Asset {
+                path: path.to_string(),
+                kind,
+                state: AssetState::Loading,
+                watcher: kind.hot_reload().then(|| FileWatcher::new(path)),
+            },
+        );
+        self.start(handle);
+        handle
+    }
+
+    // Loads the file again, the current texture stays usable until the new
+    // one is uploaded
+    pub fn reload(&mut self, handle: AssetHandle) {
+        if self.assets.contains_key(&handle) {
+            self.start(handle);
+        }
+    }
+
+    // Uploads every asset again, e.g. to a recreated device
+    pub fn reload_all(&mut self) {
+        self.uploading.clear();
+        let handles: Vec<_> = self.assets.keys().copied().collect();
+        for handle in handles {
+            self.start(handle);
+        }
+    }
+
+    pub fn state(&self, handle: AssetHandle) -> Option<&AssetState> {
+        self.assets.get(&handle).map(|asset| &asset.state)
+    }
+
+    pub fn get(&self, handle: AssetHandle) -> Option<&GpuAsset> {
+        match self.state(handle)? {
+            AssetState::Ready(asset) => Some(asset),
+            _ => None,
+        }
+    }
+
+    pub fn path(&self, handle: AssetHandle) -> Option<&str> {
+        self.assets.get(&handle).map(|asset| asset.path.as_str())
+    }
+
+    // Stages finished loads within the uploader's budget and reloads changed
+    // voxel assets. Returns the assets that became ready or failed this
+    // frame, including reloaded ones whose texture was replaced. A texture
+    // becomes ready once all of it is staged, the uploader submits it before
+    // the frame. Textures count as world memory from when they are created,
+    // and fail to load past the VRAM budget.
+    pub fn pump(
+        &mut self,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+        memory: &mut MemoryTracker,
+        dt: f32,
+    ) -> Vec<AssetHandle> {
+        let changed: Vec<_> = self
+            .assets
+            .iter_mut()
+            .filter_map(|(handle, asset)| {
+                let watcher = asset.watcher.as_mut()?;
+                watcher.poll(dt).then_some(*handle)
+            })
+            .collect();
+        for handle in changed {
+            log::info!("Reloading {}", self.assets[&handle].path);
+            self.reload(handle);
+        }
+
+        let mut finished = Vec::new();
+        for (handle, result) in self.receiver.try_iter() {
+            match result {
+                Ok(texture) => self.decoded.push_back((handle, texture)),
+                Err(error) => {
+                    log::warn!("Couldn't load {}", error);
+                    // A reload that fails, e.g. on a half written file,
+                    // keeps the last good texture
+                    if let Some(asset) = self.assets.get_mut(&handle) {
+                        if !matches!(asset.state, AssetState::Ready(_)) {
+                            asset.state = AssetState::Failed(error);
+                            finished.push(handle);
+                        }
+                    }
+                }
+            }
+        }
+
+        while uploader.has_budget() {
+            if self.uploading.is_empty() {
+                let Some((handle, texture)) = self.decoded.pop_front() else {
+                    break;
+                };
+                let Some(asset) = self.assets.get_mut(&handle) else {
+                    continue;
+                };
+                // A reloaded texture replaces the current one once uploaded
+                let replaced = match &asset.state {
+                    AssetState::Ready(current) => memory::texture_bytes(&current.texture),
+                    _ => 0,
+                };
+                let bytes = texture.data.len() as u64;
+                if !memory.fits(bytes.saturating_sub(replaced)) {
+                    log::warn!("{} doesn't fit in the GPU memory budget", asset.path);
+                    if replaced == 0 {
+                        asset.state = AssetState::Failed("Over the GPU memory budget".to_string());
+                        finished.push(handle);
+                    }
+                    continue;
+                }
+                let gpu_texture = create_texture(device, &asset.path, &texture);
+                memory.allocate(MemoryCategory::World, memory::texture_bytes(&gpu_texture));
+                self.uploading
+                    .push_back((handle, TextureUpload::new(gpu_texture, texture.data)));
+            }
+
+            let (_, upload) = self.uploading.front_mut().unwrap();
+            if !uploader.write_texture(device, upload) {
+                break;
+            }
+            let (handle, upload) = self.uploading.pop_front().unwrap();
+            let Some(asset) = self.assets.get_mut(&handle) else {
+                continue;
+            };
+            if let AssetState::Ready(current) = &asset.state {
+                memory.free(
+                    MemoryCategory::World,
+                    memory::texture_bytes(&current.texture),
+                );
+            }
+            let view = upload
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            asset.state = AssetState::Ready(GpuAsset {
+                texture: upload.texture,
+                view,
+            });
+            log::info!("Loaded {}", asset.path);
+            finished.push(handle);
+        }
+        finished
+    }
+
+    fn start(&self, handle: AssetHandle) {
+        let asset = &self.assets[&handle];
+        let (path, kind, sender) = (asset.path.clone(), asset.kind, self.sender.clone());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || {
+            let result = std::fs::read(&path)
+                .map_err(|error| error.to_string())
+                .and_then(|bytes| decode(kind, &bytes))
+                .map_err(|error| format!("{}: {}", path, error));
+            // The manager is gone when the receiver is
+            let _ = sender.send((handle, result));
+        });
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = fetch(&path)
+                .await
+                .and_then(|bytes| decode(kind, &bytes))
+                .map_err(|error| format!("{}: {}", path, error));
+            let _ = sender.send((handle, result));
+        });
+    }
+}
+
+impl Default for AssetManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn decode(kind: AssetKind, bytes: &[u8]) -> Result<CpuTexture, String> {
+    match kind {
+        AssetKind::VoxelModel => vox::decode(bytes),
+        AssetKind::Heightmap => heightmap::decode(bytes),
+        AssetKind::Hdri => hdr::decode(bytes),
+        AssetKind::Lut => cube::decode(bytes),
+    }
+}
+
+// Filled by the uploader
+fn create_texture(device: &wgpu::Device, path: &str, texture: &CpuTexture) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(path),
+        size: texture.size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: texture.dimension,
+        format: texture.format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch(path: &str) -> Result<Vec<u8>, String> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let js_error = |error: wasm_bindgen::JsValue| format!("{:?}", error);
+    let window = web_sys::window().ok_or("No browser window")?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(path))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !response.ok() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
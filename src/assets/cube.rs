@@ -0,0 +1,54 @@
+use super::CpuTexture;
+
+// Adobe .cube 3D LUT, red changes fastest like the texel order of a 3D
+// texture. 1D LUTs and domains other than 0..1 aren't supported.
+pub fn decode(bytes: &[u8]) -> Result<CpuTexture, String> {
+    let source = std::str::from_utf8(bytes).map_err(|error| error.to_string())?;
+
+    let mut size = None;
+    let mut data = Vec::new();
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") {
+            continue;
+        }
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words[..] {
+            ["LUT_3D_SIZE", value] => {
+                size = Some(value.parse::<u32>().map_err(|error| error.to_string())?)
+            }
+            ["LUT_1D_SIZE", _] => return Err("1D LUTs aren't supported".to_string()),
+            ["DOMAIN_MIN", ..] | ["DOMAIN_MAX", ..] => {}
+            [r, g, b] => {
+                for value in [r, g, b] {
+                    let value = value
+                        .parse::<f32>()
+                        .map_err(|_| format!("Bad line {}", line))?;
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+                data.extend_from_slice(&1f32.to_le_bytes());
+            }
+            _ => return Err(format!("Bad line {}", line)),
+        }
+    }
+
+    let size = size.ok_or("Missing LUT_3D_SIZE")?;
+    let expected = (size * size * size) as usize * 16;
+    if data.len() != expected {
+        return Err(format!(
+            "Expected {} entries, got {}",
+            size * size * size,
+            data.len() / 16
+        ));
+    }
+
+    Ok(CpuTexture {
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba32Float,
+        data,
+    })
+}
@@ -0,0 +1,127 @@
+use super::CpuTexture;
+
+// Radiance RGBE image, flat or with run length encoded scanlines
+pub fn decode(bytes: &[u8]) -> Result<CpuTexture, String> {
+    let mut offset = 0;
+    let mut line = || {
+        let start = offset;
+        let end = bytes[start..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|end| start + end)
+            .ok_or("Truncated header")?;
+        offset = end + 1;
+        Ok::<_, String>(String::from_utf8_lossy(&bytes[start..end]).into_owned())
+    };
+
+    if !line()?.starts_with("#?") {
+        return Err("Not a Radiance .hdr file".to_string());
+    }
+    loop {
+        let header = line()?;
+        if header.is_empty() {
+            break;
+        }
+        if header.starts_with("FORMAT=") && header != "FORMAT=32-bit_rle_rgbe" {
+            return Err(format!("Unsupported {}", header));
+        }
+    }
+    let resolution = line()?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (
+            height.parse::<usize>().map_err(|error| error.to_string())?,
+            width.parse::<usize>().map_err(|error| error.to_string())?,
+        ),
+        _ => return Err(format!("Unsupported orientation {}", resolution)),
+    };
+
+    let mut rgbe = vec![0u8; width * height * 4];
+    for scanline in rgbe.chunks_exact_mut(width * 4) {
+        offset = read_scanline(bytes, offset, scanline, width)?;
+    }
+
+    let data = rgbe
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let scale = if pixel[3] == 0 {
+                0.
+            } else {
+                2f32.powi(pixel[3] as i32 - 136)
+            };
+            [
+                pixel[0] as f32 * scale,
+                pixel[1] as f32 * scale,
+                pixel[2] as f32 * scale,
+                1.,
+            ]
+        })
+        .flat_map(f32::to_le_bytes)
+        .collect();
+
+    Ok(CpuTexture {
+        size: wgpu::Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        data,
+    })
+}
+
+// Returns the offset after the scanline
+fn read_scanline(
+    bytes: &[u8],
+    mut offset: usize,
+    scanline: &mut [u8],
+    width: usize,
+) -> Result<usize, String> {
+    let truncated = || "Truncated pixel data".to_string();
+    let start = bytes.get(offset..offset + 4).ok_or_else(truncated)?;
+    let run_length = (8..0x8000).contains(&width)
+        && start[0] == 2
+        && start[1] == 2
+        && ((start[2] as usize) << 8 | start[3] as usize) == width;
+    if !run_length {
+        let flat = bytes
+            .get(offset..offset + width * 4)
+            .ok_or_else(truncated)?;
+        scanline.copy_from_slice(flat);
+        return Ok(offset + width * 4);
+    }
+
+    // Each channel is encoded separately
+    offset += 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *bytes.get(offset).ok_or_else(truncated)? as usize;
+            if count > 128 {
+                let count = count - 128;
+                let value = *bytes.get(offset + 1).ok_or_else(truncated)?;
+                if x + count > width {
+                    return Err("Bad scanline run".to_string());
+                }
+                for i in x..x + count {
+                    scanline[i * 4 + channel] = value;
+                }
+                x += count;
+                offset += 2;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("Bad scanline run".to_string());
+                }
+                let values = bytes
+                    .get(offset + 1..offset + 1 + count)
+                    .ok_or_else(truncated)?;
+                for (i, value) in values.iter().enumerate() {
+                    scanline[(x + i) * 4 + channel] = *value;
+                }
+                x += count;
+                offset += 1 + count;
+            }
+        }
+    }
+    Ok(offset)
+}
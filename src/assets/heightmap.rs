@@ -0,0 +1,59 @@
+use nalgebra::Vector3;
+
+use super::CpuTexture;
+use crate::{palette, world::VoxelGrid};
+
+// Voxels of the tallest column of a heightmap world, for a white pixel
+const MAX_HEIGHT: u32 = 64;
+
+// First channel of a PNG, 16 bit images are reduced to 8 bits
+pub fn decode(bytes: &[u8]) -> Result<CpuTexture, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|error| error.to_string())?;
+
+    let channels = info.color_type.samples();
+    let data = buffer[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|pixel| pixel[0])
+        .collect();
+
+    Ok(CpuTexture {
+        size: wgpu::Extent3d {
+            width: info.width,
+            height: info.height,
+            depth_or_array_layers: 1,
+        },
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        data,
+    })
+}
+
+// Terrain standing on y = 0 and centered on x and z, a column per pixel with
+// its brightness as the height. Image rows run along z.
+pub fn decode_grid(bytes: &[u8]) -> Result<VoxelGrid, String> {
+    let texture = decode(bytes)?;
+    let (width, depth) = (texture.size.width, texture.size.height);
+    let heights: Vec<u32> = texture
+        .data
+        .iter()
+        .map(|&value| (value as u32 * MAX_HEIGHT + 127) / 255)
+        .collect();
+    let height = heights.iter().copied().max().unwrap_or(0).max(1);
+
+    let (w, h) = (width as usize, height as usize);
+    let mut voxels = vec![palette::EMPTY; w * h * depth as usize];
+    for (column, &top) in heights.iter().enumerate() {
+        let (x, z) = (column % w, column / w);
+        for y in 0..top as usize {
+            voxels[(z * h + y) * w + x] = palette::TERRAIN;
+        }
+    }
+    let origin = Vector3::new(-(width as i32) / 2, 0, -(depth as i32) / 2);
+    VoxelGrid::new(origin, Vector3::new(width, height, depth), voxels)
+}
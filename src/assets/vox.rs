@@ -0,0 +1,163 @@
+use nalgebra::Vector3;
+
+use super::CpuTexture;
+use crate::{palette, world::VoxelGrid};
+
+// The first model of a .vox file with y up like the world
+struct Model<'a> {
+    // Voxels along x, y and z
+    size: [usize; 3],
+    // Position and colour index of each voxel inside of size, none of them 0
+    voxels: Vec<([usize; 3], usize)>,
+    palette: Option<&'a [u8]>,
+}
+
+impl Model<'_> {
+    // sRGB colour of index, palette entry i is colour index i + 1. Without a
+    // palette every voxel is white.
+    fn colour(&self, index: usize) -> [u8; 3] {
+        self.palette
+            .map(|palette| {
+                [
+                    palette[(index - 1) * 4],
+                    palette[(index - 1) * 4 + 1],
+                    palette[(index - 1) * 4 + 2],
+                ]
+            })
+            .unwrap_or([255; 3])
+    }
+}
+
+// Reads the first model of a MagicaVoxel .vox file. MagicaVoxel is z up, the
+// texture is y up like the world: texel (x, y, z) is voxel (x, z, y).
+pub fn decode(bytes: &[u8]) -> Result<CpuTexture, String> {
+    let model = parse(bytes)?;
+    let [width, height, depth] = model.size;
+    let mut data = vec![0; width * height * depth * 4];
+    for &([x, y, z], index) in &model.voxels {
+        let texel = ((z * height + y) * width + x) * 4;
+        data[texel..texel + 3].copy_from_slice(&model.colour(index));
+        data[texel + 3] = 255;
+    }
+
+    Ok(CpuTexture {
+        size: wgpu::Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: depth as u32,
+        },
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        data,
+    })
+}
+
+// The first model as a world standing on y = 0, centered on x and z, and the
+// palette its colour indices are drawn with. Colour index i is material i,
+// so it replaces the terrain's entry as well.
+pub fn decode_grid(bytes: &[u8]) -> Result<(VoxelGrid, [[f32; 4]; palette::SIZE]), String> {
+    let model = parse(bytes)?;
+    let [width, height, depth] = model.size;
+    let mut voxels = vec![palette::EMPTY; width * height * depth];
+    for &([x, y, z], index) in &model.voxels {
+        voxels[(z * height + y) * width + x] = index as u8;
+    }
+    let origin = Vector3::new(-(width as i32) / 2, 0, -(depth as i32) / 2);
+    let size = Vector3::new(width as u32, height as u32, depth as u32);
+    let grid = VoxelGrid::new(origin, size, voxels)?;
+
+    let mut colours = [[0.; 4]; palette::SIZE];
+    for (index, colour) in colours.iter_mut().enumerate().skip(1) {
+        let [r, g, b] = model.colour(index).map(|c| (c as f32 / 255.).powf(2.2));
+        *colour = [r, g, b, 1.];
+    }
+    Ok((grid, colours))
+}
+
+fn parse(bytes: &[u8]) -> Result<Model<'_>, String> {
+    if bytes.get(0..4) != Some(b"VOX ") {
+        return Err("Not a .vox file".to_string());
+    }
+    let main = chunk(bytes, 8)?;
+    if main.id != b"MAIN" {
+        return Err("Missing MAIN chunk".to_string());
+    }
+
+    let mut size = None;
+    let mut voxels = None;
+    let mut palette = None;
+    let mut offset = 0;
+    while offset < main.children.len() {
+        let Chunk { id, content, .. } = chunk(main.children, offset)?;
+        offset += 12 + content.len() + u32_at(main.children, offset + 8)? as usize;
+        match id {
+            b"SIZE" if size.is_none() => {
+                size = Some([
+                    u32_at(content, 0)?,
+                    u32_at(content, 4)?,
+                    u32_at(content, 8)?,
+                ])
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = u32_at(content, 0)? as usize;
+                voxels = Some(
+                    content
+                        .get(4..4 + count * 4)
+                        .ok_or("Truncated XYZI chunk")?,
+                );
+            }
+            b"RGBA" => palette = Some(content.get(0..256 * 4).ok_or("Truncated RGBA chunk")?),
+            _ => {}
+        }
+    }
+    let [size_x, size_y, size_z] = size.ok_or("Missing SIZE chunk")?;
+    let voxels = voxels.ok_or("Missing XYZI chunk")?;
+
+    let size = [size_x as usize, size_z as usize, size_y as usize];
+    let voxels = voxels
+        .chunks_exact(4)
+        .map(|voxel| {
+            (
+                [voxel[0] as usize, voxel[2] as usize, voxel[1] as usize],
+                voxel[3] as usize,
+            )
+        })
+        .filter(|&(position, index)| index != 0 && (0..3).all(|i| position[i] < size[i]))
+        .collect();
+    Ok(Model {
+        size,
+        voxels,
+        palette,
+    })
+}
+
+struct Chunk<'a> {
+    id: &'a [u8],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+// The chunk at offset
+fn chunk(bytes: &[u8], offset: usize) -> Result<Chunk<'_>, String> {
+    let id = bytes.get(offset..offset + 4).ok_or("Truncated chunk")?;
+    let content_size = u32_at(bytes, offset + 4)? as usize;
+    let children_size = u32_at(bytes, offset + 8)? as usize;
+    let content_start = offset + 12;
+    let children_start = content_start + content_size;
+    let content = bytes
+        .get(content_start..children_start)
+        .ok_or("Truncated chunk")?;
+    let children = bytes
+        .get(children_start..children_start + children_size)
+        .ok_or("Truncated chunk")?;
+    Ok(Chunk {
+        id,
+        content,
+        children,
+    })
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    let bytes = bytes.get(offset..offset + 4).ok_or("Truncated chunk")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
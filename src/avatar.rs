@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use nalgebra::{Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{preprocess, world::Sun};
+
+// Size of one voxel of the avatar model in world units
+const VOXEL_SIZE: f32 = 0.125;
+// Distance from the avatar's feet to the camera position it stands under
+const EYE_HEIGHT: f32 = 1.6;
+
+// Avatars drawn at once, the own one and those of other clients. Any more
+// aren't drawn.
+const MAX_AVATARS: usize = 64;
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+const SKIN: [f32; 3] = [0.9, 0.7, 0.55];
+const SHIRT: [f32; 3] = [0.7, 0.15, 0.15];
+const TROUSERS: [f32; 3] = [0.15, 0.25, 0.6];
+const HAIR: [f32; 3] = [0.3, 0.2, 0.1];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AvatarVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    color: [f32; 3],
+}
+
+impl AvatarVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x3];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<AvatarVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Voxels of the character, in model voxels with the origin between its feet
+// and the face looking towards +z
+fn avatar_voxels() -> HashMap<[i32; 3], [f32; 3]> {
+    let mut voxels = HashMap::new();
+    let mut fill = |min: [i32; 3], max: [i32; 3], color: [f32; 3]| {
+        for x in min[0]..max[0] {
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    voxels.insert([x, y, z], color);
+                }
+            }
+        }
+    };
+
+    // Legs
+    fill([-3, 0, -1], [-1, 6, 1], TROUSERS);
+    fill([1, 0, -1], [3, 6, 1], TROUSERS);
+    fill([-1, 5, -1], [1, 6, 1], TROUSERS);
+    // Body and arms
+    fill([-3, 6, -1], [3, 11, 1], SHIRT);
+    fill([-5, 6, -1], [-3, 11, 1], SKIN);
+    fill([3, 6, -1], [5, 11, 1], SKIN);
+    fill([-5, 10, -1], [-3, 11, 1], SHIRT);
+    fill([3, 10, -1], [5, 11, 1], SHIRT);
+    // Head
+    fill([-2, 11, -2], [2, 15, 2], SKIN);
+    fill([-2, 14, -2], [2, 15, 2], HAIR);
+    fill([-2, 11, -2], [2, 15, -1], HAIR);
+
+    voxels
+}
+
+// Two triangles for every voxel face that isn't covered by another voxel
+fn avatar_mesh() -> Vec<AvatarVertex> {
+    let voxels = avatar_voxels();
+    let mut vertices = Vec::new();
+
+    for (voxel, color) in &voxels {
+        for axis in 0..3 {
+            for side in [-1, 1] {
+                let mut neighbour = *voxel;
+                neighbour[axis] += side;
+                if voxels.contains_key(&neighbour) {
+                    continue;
+                }
+
+                let mut normal = [0.; 3];
+                normal[axis] = side as f32;
+                let u = (axis + 1) % 3;
+                let v = (axis + 2) % 3;
+
+                let corner = |a: f32, b: f32| {
+                    let mut position = voxel.map(|c| c as f32);
+                    position[axis] += if side > 0 { 1. } else { 0. };
+                    position[u] += a;
+                    position[v] += b;
+                    AvatarVertex {
+                        position: position.map(|c| c * VOXEL_SIZE),
+                        normal,
+                        color: *color,
+                    }
+                };
+                vertices.extend_from_slice(&[
+                    corner(0., 0.),
+                    corner(1., 0.),
+                    corner(1., 1.),
+                    corner(0., 0.),
+                    corner(1., 1.),
+                    corner(0., 1.),
+                ]);
+            }
+        }
+    }
+
+    vertices
+}
+
+// Model matrix of one avatar, a vertex buffer entry per instance
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AvatarInstance {
+    model: [[f32; 4]; 4],
+}
+
+impl AvatarInstance {
+    // Stands the avatar below a camera position, facing the camera direction
+    fn new(position: Point3<f32>, direction: Vector3<f32>) -> Self {
+        let yaw = direction.x.atan2(direction.z);
+        let feet = position - Vector3::new(0., EYE_HEIGHT, 0.);
+        let model = Matrix4::new_translation(&feet.coords)
+            * Matrix4::from_axis_angle(&Vector3::y_axis(), yaw);
+        Self {
+            model: model.into(),
+        }
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+            3 => Float32x4, 4 => Float32x4, 5 => Float32x4, 6 => Float32x4
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<AvatarInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AvatarUniform {
+    sun_direction: [f32; 4],
+}
+
+impl AvatarUniform {
+    fn new() -> Self {
+        Self {
+            sun_direction: Sun::new().direction().push(0.).into(),
+        }
+    }
+
+    pub fn update(&mut self, sun: &Sun) {
+        self.sun_direction = sun.direction().push(0.).into();
+    }
+}
+
+// Voxel characters drawn at the camera position in the third person mode and
+// at the cameras of other clients. They are rasterized on top of the ray
+// traced image, hidden behind its depth.
+pub struct AvatarPipeline {
+    pub uniform: AvatarUniform,
+    pub pipeline: wgpu::RenderPipeline,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub vertex_buffer: wgpu::Buffer,
+    pub vertex_count: u32,
+    pub instance_buffer: wgpu::Buffer,
+    // Avatars to draw, set by update
+    pub instance_count: u32,
+    pub depth_view: wgpu::TextureView,
+}
+
+impl AvatarPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        depth_texture: &wgpu::TextureView,
+    ) -> AvatarPipeline {
+        let shader = preprocess::shader_module(
+            device,
+            "Avatar shader",
+            include_str!("shaders/avatar.wgsl"),
+            &[],
+        );
+
+        let vertices = avatar_mesh();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Avatar vertex buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Avatar instance buffer"),
+            size: (MAX_AVATARS * std::mem::size_of::<AvatarInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform = AvatarUniform::new();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Avatar Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Avatar bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Avatar bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(depth_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Avatar Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Avatar Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[AvatarVertex::desc(), AvatarInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Faces are wound inconsistently, the depth test sorts them out
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        AvatarPipeline {
+            uniform,
+            pipeline,
+            buffer,
+            bind_group,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+            instance_buffer,
+            instance_count: 0,
+            depth_view: create_depth_view(device, config),
+        }
+    }
+
+    // The depth attachment has to match the swapchain size
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.depth_view = create_depth_view(device, config);
+    }
+
+    // Places an avatar at every camera position and direction
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        cameras: &[(Point3<f32>, Vector3<f32>)],
+        sun: &Sun,
+    ) {
+        let instances: Vec<_> = cameras
+            .iter()
+            .take(MAX_AVATARS)
+            .map(|&(position, direction)| AvatarInstance::new(position, direction))
+            .collect();
+        self.instance_count = instances.len() as u32;
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        self.uniform.update(sun);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+fn create_depth_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        label: Some("Avatar depth texture"),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
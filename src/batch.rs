@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::path::{CameraPath, CAMERA_PATH_PATH},
+    capture::AdaptiveSampling,
+    headless::HeadlessRenderer,
+};
+
+// Offline render of a camera path into numbered PNGs, e.g.
+//
+// path = "flythrough.toml"
+// width = 1920
+// height = 1080
+// samples = 64
+// noise_threshold = 0.02
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchSettings {
+    // Camera path recorded with F5
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    // Jittered frames averaged into every image
+    pub samples: u32,
+    // Samples after min_samples only go to the pixels whose standard error
+    // is above this fraction of their brightness, 0 samples every pixel
+    pub noise_threshold: f32,
+    pub min_samples: u32,
+    pub frame_rate: f32,
+    pub directory: String,
+}
+
+impl BatchSettings {
+    pub fn new() -> Self {
+        Self {
+            path: CAMERA_PATH_PATH.to_string(),
+            width: 1920,
+            height: 1080,
+            samples: 32,
+            noise_threshold: 0.02,
+            min_samples: 8,
+            frame_rate: 30.,
+            directory: "render".to_string(),
+        }
+    }
+
+    // Unlike the other settings files a batch render can't go on with
+    // defaults, so errors are returned
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        toml::from_str(&source).map_err(|error| format!("{}: {}", path, error))
+    }
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Renders every frame of the camera path, printing progress to stdout
+pub async fn render(settings: &BatchSettings) -> Result<(), String> {
+    let path = CameraPath::load(&settings.path)
+        .filter(|path| !path.keyframes.is_empty())
+        .ok_or_else(|| format!("No camera path in {}", settings.path))?;
+    std::fs::create_dir_all(&settings.directory)
+        .map_err(|error| format!("{}: {}", settings.directory, error))?;
+
+    let size = PhysicalSize::new(settings.width, settings.height);
+    let mut renderer = HeadlessRenderer::new(size)
+        .await
+        .map_err(|error| error.to_string())?;
+    renderer.samples = settings.samples;
+    renderer.adaptive = (settings.noise_threshold > 0.).then_some(AdaptiveSampling {
+        noise_threshold: settings.noise_threshold,
+        min_samples: settings.min_samples,
+    });
+
+    let frame_count = (path.duration() * settings.frame_rate).ceil() as u32 + 1;
+    let start = instant::Instant::now();
+    for frame in 0..frame_count {
+        if let Some(keyframe) = path.sample(frame as f32 / settings.frame_rate) {
+            keyframe.apply(&mut renderer.camera.camera);
+        }
+
+        let file = format!("{}/frame_{:05}.png", settings.directory, frame);
+        renderer.render_png(&file)?;
+
+        let elapsed = start.elapsed().as_secs_f32();
+        let remaining = elapsed / (frame + 1) as f32 * (frame_count - frame - 1) as f32;
+        println!(
+            "Frame {}/{} ({:.0}s elapsed, {:.0}s left)",
+            frame + 1,
+            frame_count,
+            elapsed,
+            remaining
+        );
+    }
+    Ok(())
+}
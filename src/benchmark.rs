@@ -0,0 +1,298 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use nalgebra::{Point3, Vector3};
+use serde::Serialize;
+use winit::{
+    dpi::PhysicalSize,
+    event::*,
+    window::{Window, WindowId},
+};
+
+use crate::{app::App, raytracing::Traversal, window::State, world::Sun};
+
+// Frames in the first second aren't measured, pipelines and caches warm up
+const WARMUP: f32 = 1.;
+// Orbit the camera flies around the origin
+const ORBIT_RADIUS: f32 = 48.;
+const ORBIT_HEIGHT: f32 = 14.;
+// Seconds per lap
+const ORBIT_PERIOD: f32 = 20.;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentiles {
+    pub mean: f32,
+    pub p50: f32,
+    pub p90: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl Percentiles {
+    // None without samples
+    pub fn new(samples: &[f32]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let at = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        Some(Self {
+            mean: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p50: at(0.5),
+            p90: at(0.9),
+            p95: at(0.95),
+            p99: at(0.99),
+            max: *sorted.last().unwrap(),
+        })
+    }
+}
+
+// Written as benchmark.json, and as benchmark.csv with one row per metric.
+// Times are in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkReport {
+    pub frames: usize,
+    pub seconds: f32,
+    pub render_size: [u32; 2],
+    // Runs with each traversal are compared by their pass timings
+    pub traversal: Traversal,
+    pub cpu_frame_time: Option<Percentiles>,
+    // Needs timestamp queries
+    pub gpu_frame_time: Option<Percentiles>,
+    pub passes: BTreeMap<&'static str, Percentiles>,
+    // Peak resident memory of the process, where the OS reports it
+    pub peak_memory_mb: Option<f32>,
+}
+
+impl BenchmarkReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("metric,mean,p50,p90,p95,p99,max\n");
+        let mut row = |name: &str, stats: &Percentiles| {
+            let _ = writeln!(
+                csv,
+                "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+                name, stats.mean, stats.p50, stats.p90, stats.p95, stats.p99, stats.max
+            );
+        };
+        if let Some(stats) = &self.cpu_frame_time {
+            row("cpu_frame_time", stats);
+        }
+        if let Some(stats) = &self.gpu_frame_time {
+            row("gpu_frame_time", stats);
+        }
+        for (name, stats) in &self.passes {
+            row(&format!("pass {}", name), stats);
+        }
+        csv
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|error| error.to_string())?;
+        let write = |path: String, contents: String| {
+            std::fs::write(&path, contents).map_err(|error| format!("{}: {}", path, error))
+        };
+        write(format!("{}.json", path), json)?;
+        write(format!("{}.csv", path), self.to_csv())
+    }
+}
+
+// Flies a fixed orbit over the terrain for a fixed time with fixed settings
+// and saves frame time statistics, then exits. Live input is ignored apart
+// from Escape.
+pub struct Benchmark {
+    state: State,
+    duration: f32,
+    // Written with .json and .csv appended
+    output: String,
+    elapsed: f32,
+    cpu_times: Vec<f32>,
+    gpu_times: Vec<f32>,
+    passes: BTreeMap<&'static str, Vec<f32>>,
+    done: bool,
+}
+
+impl Benchmark {
+    pub fn new(mut state: State, duration: f32, output: &str) -> Self {
+        state.render_scale.dynamic = false;
+        state.render_scale.set_scale(1.);
+        state.raytracing.settings.sun = Sun::new();
+        log::info!("Benchmarking for {} seconds", duration);
+        Self {
+            state,
+            duration,
+            output: output.to_string(),
+            elapsed: 0.,
+            cpu_times: Vec::new(),
+            gpu_times: Vec::new(),
+            passes: BTreeMap::new(),
+            done: false,
+        }
+    }
+
+    fn fly(&mut self) {
+        let angle = self.elapsed / ORBIT_PERIOD * std::f32::consts::TAU;
+        let camera = &mut self.state.camera.camera;
+        camera.position = Point3::new(
+            angle.cos() * ORBIT_RADIUS,
+            ORBIT_HEIGHT,
+            angle.sin() * ORBIT_RADIUS,
+        );
+        camera.direction = (Point3::origin() - camera.position)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or(Vector3::z());
+    }
+
+    // Timings of the previous frame, the GPU ones arrive a few frames late
+    fn measure(&mut self, dt: f32) {
+        self.cpu_times.push(dt * 1000.);
+        if let Some(time) = self
+            .state
+            .frame_timer
+            .as_ref()
+            .and_then(|timer| timer.last_frame_time)
+        {
+            self.gpu_times.push(time);
+        }
+        if let Some(timer) = &self.state.frame_timer {
+            for (name, time) in &timer.timings {
+                self.passes.entry(*name).or_default().push(*time);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        let render_size = self.state.render_size();
+        let report = BenchmarkReport {
+            frames: self.cpu_times.len(),
+            seconds: self.duration,
+            render_size: [render_size.width, render_size.height],
+            traversal: self.state.raytracing.settings.traversal,
+            cpu_frame_time: Percentiles::new(&self.cpu_times),
+            gpu_frame_time: Percentiles::new(&self.gpu_times),
+            passes: self
+                .passes
+                .iter()
+                .filter_map(|(name, times)| Some((*name, Percentiles::new(times)?)))
+                .collect(),
+            peak_memory_mb: peak_memory_mb(),
+        };
+        match report.save(&self.output) {
+            Ok(_) => log::info!("Saved {}.json and {}.csv", self.output, self.output),
+            Err(error) => log::error!("Couldn't save the benchmark: {}", error),
+        }
+    }
+}
+
+impl App for Benchmark {
+    fn window(&self) -> &Window {
+        self.state.window()
+    }
+
+    fn size(&self) -> PhysicalSize<u32> {
+        self.state.size
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        !matches!(
+            event,
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::Escape),
+                    ..
+                },
+                ..
+            } | WindowEvent::CloseRequested
+                | WindowEvent::Resized(_)
+                | WindowEvent::ScaleFactorChanged { .. }
+        )
+    }
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.state.resize(new_size)
+    }
+
+    fn update(&mut self, dt: instant::Duration) {
+        let dt = dt.as_secs_f32();
+        if self.elapsed >= WARMUP {
+            self.measure(dt);
+        }
+        self.elapsed += dt;
+        self.fly();
+        self.state.update(instant::Duration::from_secs_f32(dt));
+        if self.elapsed >= WARMUP + self.duration && !self.done {
+            self.finish();
+        }
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.state.render()
+    }
+
+    fn other_window_event(&mut self, window_id: WindowId, event: &WindowEvent) -> bool {
+        self.state.preview_event(window_id, event)
+    }
+
+    fn should_exit(&self) -> bool {
+        self.done
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn peak_memory_mb() -> Option<f32> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: f32 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes / 1024.)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_memory_mb() -> Option<f32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_of_a_hundred_samples() {
+        // 1 to 100 shuffled
+        let samples: Vec<f32> = (0..100).map(|i| ((i * 37) % 100 + 1) as f32).collect();
+        let stats = Percentiles::new(&samples).unwrap();
+        assert_eq!(stats.mean, 50.5);
+        assert_eq!(stats.p50, 51.);
+        assert_eq!(stats.p90, 90.);
+        assert_eq!(stats.p95, 95.);
+        assert_eq!(stats.p99, 99.);
+        assert_eq!(stats.max, 100.);
+    }
+
+    #[test]
+    fn percentiles_of_one_sample() {
+        let stats = Percentiles::new(&[4.]).unwrap();
+        assert_eq!([stats.p50, stats.p99, stats.max, stats.mean], [4.; 4]);
+        assert!(Percentiles::new(&[]).is_none());
+    }
+
+    #[test]
+    fn csv_has_a_row_per_metric() {
+        let stats = Percentiles::new(&[1., 2.]).unwrap();
+        let report = BenchmarkReport {
+            frames: 2,
+            seconds: 1.,
+            render_size: [64, 64],
+            traversal: Traversal::Descend,
+            cpu_frame_time: Some(stats.clone()),
+            gpu_frame_time: None,
+            passes: BTreeMap::from([("Ray tracing", stats)]),
+            peak_memory_mb: None,
+        };
+        let csv = report.to_csv();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("cpu_frame_time,1.500,"));
+        assert!(rows[2].starts_with("pass Ray tracing,"));
+    }
+}
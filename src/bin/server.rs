@@ -0,0 +1,40 @@
+use shaders::{net::server::NetServer, scene_file::WorldSource};
+
+// Headless world server: ticks the simulation, sends its world to rendering
+// clients and relays voxel edits and poses between them, e.g.
+// server 0.0.0.0:9001 castle.vox. Without a .vox model or .png heightmap the
+// world is the procedural terrain. Clients join with the connect console
+// command.
+fn main() {
+    env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| "0.0.0.0:9001".to_string());
+
+    let source = match args.next() {
+        Some(path) => WorldSource::from_path(&path),
+        None => Ok(WorldSource::Procedural),
+    };
+    let world = match source.and_then(|source| source.load()) {
+        Ok(world) => world,
+        Err(error) => {
+            eprintln!("Couldn't load the world: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut server = match NetServer::bind(&address, world) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("Couldn't serve on {}: {}", address, error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut last_poll = std::time::Instant::now();
+    loop {
+        let now = std::time::Instant::now();
+        server.poll((now - last_poll).as_secs_f32());
+        last_poll = now;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+}
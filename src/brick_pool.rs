@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::{
+    jobs::{Job, JobSystem},
+    palette,
+    residency::CHUNK_SIZE,
+    upload::Uploader,
+    world::{self, BrickWorld, World},
+};
+
+// Voxels per side of a brick
+const BRICK_SIZE: i32 = 8;
+// Bricks per side of the pool texture, must match POOL_BRICKS in
+// ray-tracing.wgsl
+const POOL_BRICKS: u32 = 32;
+const CAPACITY: u32 = POOL_BRICKS * POOL_BRICKS * POOL_BRICKS;
+// Bricks per side of the square of columns around the camera that the
+// indirection table covers, and bricks per column from TABLE_LAYER_OFFSET
+// below zero up. Must match the POOL_TABLE_* constants in ray-tracing.wgsl.
+const TABLE_BRICKS: i32 = 64;
+const TABLE_LAYERS: i32 = 32;
+const TABLE_LAYER_OFFSET: i32 = 16;
+const TABLE_ENTRIES: usize = (TABLE_BRICKS * TABLE_BRICKS * TABLE_LAYERS) as usize;
+// The table's first brick followed by an entry per brick
+pub const TABLE_SIZE: u64 = (4 + TABLE_ENTRIES as u64) * 4;
+// Bricks generated per job, and jobs in flight per worker
+const BATCH_BRICKS: usize = 32;
+const BATCHES_PER_WORKER: usize = 2;
+
+// Voxels of a brick, None when it is full
+type Generated = (Vector3<i32>, Option<Vec<u8>>);
+
+// The texture holding the voxels of pooled bricks, a brick per 8x8x8 region.
+// A byte per voxel indexing the material palette, palette::EMPTY for empty
+// ones.
+pub fn create_pool(device: &wgpu::Device) -> wgpu::Texture {
+    let size = POOL_BRICKS * BRICK_SIZE as u32;
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Brick pool"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R8Uint,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+// Keeps the voxels of the bricks around the camera that are partly solid or
+// edited in slots of one large 3D texture. An indirection table maps each
+// brick to its slot, so bricks stream in and out without anything being
+// rebound. Bricks that aren't pooled, like full ones or those still waiting
+// for a slot, are traced from the terrain function or the grid texture as
+// before. The table follows the camera a chunk at a time. Bricks are
+// generated in batches on the workers of a JobSystem.
+pub struct BrickPool {
+    // The table's first brick, None before the first update
+    origin: Option<Vector3<i32>>,
+    slots: HashMap<Vector3<i32>, u32>,
+    free: Vec<u32>,
+    // Slots of bricks that left the table, free once a table without them
+    // is uploaded
+    evicted: Vec<u32>,
+    // Bricks in range that may need a slot, nearest first
+    pending: VecDeque<Vector3<i32>>,
+    // Batches being generated, in the order they were taken from pending
+    batches: VecDeque<Job<Vec<Generated>>>,
+    // Generated bricks waiting for a slot and upload budget
+    generated: VecDeque<Generated>,
+    // Bricks in batches or generated, left out of pending
+    queued: HashSet<Vector3<i32>>,
+    // Bricks edited since their voxels were pooled or taken into a batch,
+    // generated again even with a slot
+    outdated: HashSet<Vector3<i32>>,
+    table_dirty: bool,
+}
+
+impl BrickPool {
+    pub fn new() -> Self {
+        Self {
+            origin: None,
+            slots: HashMap::new(),
+            free: (0..CAPACITY).rev().collect(),
+            evicted: Vec::new(),
+            pending: VecDeque::new(),
+            batches: VecDeque::new(),
+            generated: VecDeque::new(),
+            queued: HashSet::new(),
+            outdated: HashSet::new(),
+            table_dirty: false,
+        }
+    }
+
+    // The pool and table were recreated, e.g. with the device
+    pub fn invalidate(&mut self) {
+        *self = Self::new();
+    }
+
+    // The world changed, every brick is generated again. Their slots are
+    // freed like evicted ones, once a table without them is uploaded.
+    pub fn reset(&mut self) {
+        let free = std::mem::take(&mut self.free);
+        let mut evicted = std::mem::take(&mut self.evicted);
+        evicted.extend(self.slots.values());
+        *self = Self {
+            free,
+            evicted,
+            ..Self::new()
+        };
+    }
+
+    // A voxel was edited, its brick is generated again ahead of the other
+    // pending bricks. Its old voxels stay pooled until the new ones are.
+    pub fn edit(&mut self, voxel: Vector3<i32>) {
+        let brick = voxel.map(|v| v.div_euclid(BRICK_SIZE));
+        self.outdated.insert(brick);
+        let in_table = self
+            .origin
+            .is_some_and(|origin| table_index(origin, brick).is_some());
+        // Pending twice at most, the second is skipped once the first is
+        // queued
+        if in_table && !self.queued.contains(&brick) {
+            self.pending.push_front(brick);
+        }
+    }
+
+    // Centers the table on the chunk of eye. Bricks that left it give up
+    // their slots, the ones that entered it are queued.
+    pub fn update(&mut self, eye: Point3<f32>, world: &World) {
+        let chunk = Vector2::new(eye.x, eye.z).map(|v| (v / CHUNK_SIZE as f32).floor() as i32);
+        let corner = chunk * (CHUNK_SIZE / BRICK_SIZE) - Vector2::repeat(TABLE_BRICKS / 2);
+        let origin = Vector3::new(corner.x, -TABLE_LAYER_OFFSET, corner.y);
+        if self.origin == Some(origin) {
+            return;
+        }
+        self.origin = Some(origin);
+
+        let evicted = &mut self.evicted;
+        self.slots.retain(|brick, slot| {
+            let keep = table_index(origin, *brick).is_some();
+            if !keep {
+                evicted.push(*slot);
+            }
+            keep
+        });
+        self.table_dirty = true;
+        self.outdated
+            .retain(|brick| table_index(origin, *brick).is_some());
+
+        let needed = |brick: &Vector3<i32>| {
+            (!self.slots.contains_key(brick) || self.outdated.contains(brick))
+                && !self.queued.contains(brick)
+        };
+        let mut pending: Vec<_> = world
+            .edited_bricks()
+            .filter(|brick| table_index(origin, **brick).is_some() && needed(brick))
+            .copied()
+            .collect();
+        // Bricks entirely above the terrain are empty and those entirely
+        // below it full, neither is pooled. A grid is traced from its own
+        // texture, only its edited bricks are pooled.
+        if world.grid().is_none() {
+            let heights = world::height_ranges(corner * BRICK_SIZE, TABLE_BRICKS, BRICK_SIZE);
+            for z in 0..TABLE_BRICKS {
+                for x in 0..TABLE_BRICKS {
+                    let (low, high) = heights[(x + z * TABLE_BRICKS) as usize];
+                    for layer in 0..TABLE_LAYERS {
+                        let brick = origin + Vector3::new(x, layer, z);
+                        let bottom = (brick.y * BRICK_SIZE) as f32;
+                        let top = bottom + (BRICK_SIZE - 1) as f32;
+                        if bottom < high && top >= low && needed(&brick) {
+                            pending.push(brick);
+                        }
+                    }
+                }
+            }
+            pending.sort_unstable_by_key(|brick| (brick.x, brick.y, brick.z));
+            pending.dedup();
+        }
+        let eye_brick = eye.coords.map(|v| (v / BRICK_SIZE as f32).floor() as i32);
+        pending.sort_by_key(|brick| (brick - eye_brick).map(|v| v.abs()).sum());
+        self.pending = pending.into();
+    }
+
+    // Uploads the table when it changed, then streams generated bricks into
+    // free slots while the upload budget lasts. A brick only enters the table
+    // uploaded after its voxels, and a slot is only reused after a table
+    // without its old brick, so the GPU never reads a slot being rewritten.
+    pub fn upload(
+        &mut self,
+        world: &World,
+        jobs: &JobSystem,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+        table: &wgpu::Buffer,
+        pool: &wgpu::Texture,
+    ) {
+        let Some(origin) = self.origin else {
+            return;
+        };
+        if self.table_dirty {
+            let mut data = vec![0u32; 4 + TABLE_ENTRIES];
+            data[..4].copy_from_slice(&[origin.x as u32, origin.y as u32, origin.z as u32, 1]);
+            for (brick, slot) in &self.slots {
+                if let Some(index) = table_index(origin, *brick) {
+                    data[4 + index] = slot + 1;
+                }
+            }
+            if uploader.write_buffer(device, table, 0, bytemuck::cast_slice(&data)) {
+                self.free.append(&mut self.evicted);
+                self.table_dirty = false;
+            }
+        }
+
+        // Keeps every worker busy with the nearest pending bricks
+        while self.batches.len() < jobs.workers() * BATCHES_PER_WORKER && !self.pending.is_empty() {
+            let count = self.pending.len().min(BATCH_BRICKS);
+            let batch: Vec<_> = self
+                .pending
+                .drain(..count)
+                .filter(|brick| self.queued.insert(*brick))
+                .map(|brick| (brick, world.brick(brick)))
+                .collect();
+            for (brick, _) in &batch {
+                self.outdated.remove(brick);
+            }
+            self.batches.push_back(jobs.spawn(move || {
+                batch
+                    .into_iter()
+                    .map(|(brick, world)| generate(brick, &world))
+                    .collect()
+            }));
+        }
+        // Batches are taken in order, so nearer bricks go first
+        while let Some(batch) = self.batches.front().and_then(Job::poll) {
+            self.batches.pop_front();
+            self.generated.extend(batch);
+        }
+
+        while let Some((brick, voxels)) = self.generated.front() {
+            let brick = *brick;
+            // Edited while being generated, the voxels are out of date
+            if self.outdated.contains(&brick) {
+                self.queued.remove(&brick);
+                self.generated.pop_front();
+                self.pending.push_front(brick);
+                continue;
+            }
+            let voxels = match voxels {
+                Some(voxels) if table_index(origin, brick).is_some() => voxels,
+                // Full, or it left the table while being generated
+                _ => {
+                    self.queued.remove(&brick);
+                    self.generated.pop_front();
+                    continue;
+                }
+            };
+            let Some(&slot) = self.free.last() else {
+                break;
+            };
+            if !uploader.has_budget() {
+                break;
+            }
+            let base = Vector3::new(
+                slot % POOL_BRICKS,
+                slot / POOL_BRICKS % POOL_BRICKS,
+                slot / (POOL_BRICKS * POOL_BRICKS),
+            ) * BRICK_SIZE as u32;
+            let region = wgpu::Origin3d {
+                x: base.x,
+                y: base.y,
+                z: base.z,
+            };
+            let size = wgpu::Extent3d {
+                width: BRICK_SIZE as u32,
+                height: BRICK_SIZE as u32,
+                depth_or_array_layers: BRICK_SIZE as u32,
+            };
+            if !uploader.write_texture_region(device, pool, region, size, voxels) {
+                break;
+            }
+            self.free.pop();
+            // An edited brick replaces its old voxels
+            if let Some(old) = self.slots.insert(brick, slot) {
+                self.evicted.push(old);
+            }
+            self.queued.remove(&brick);
+            self.generated.pop_front();
+            self.table_dirty = true;
+        }
+    }
+}
+
+impl Default for BrickPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Entry of brick in the table starting at origin, x first, then z, then y
+fn table_index(origin: Vector3<i32>, brick: Vector3<i32>) -> Option<usize> {
+    let local = brick - origin;
+    let inside = |v: i32, size: i32| (0..size).contains(&v);
+    if inside(local.x, TABLE_BRICKS)
+        && inside(local.y, TABLE_LAYERS)
+        && inside(local.z, TABLE_BRICKS)
+    {
+        Some((local.x + (local.z + local.y * TABLE_BRICKS) * TABLE_BRICKS) as usize)
+    } else {
+        None
+    }
+}
+
+// Runs on a worker. Full bricks are as fast to trace from the terrain
+// function and aren't pooled, unless they were edited.
+fn generate(brick: Vector3<i32>, world: &BrickWorld) -> Generated {
+    let voxels = brick_voxels(brick, world);
+    let full = !world.edited() && voxels.iter().all(|&voxel| voxel != palette::EMPTY);
+    (brick, (!full).then_some(voxels))
+}
+
+// Voxels of a brick from the world, x first, then y, then z
+fn brick_voxels(brick: Vector3<i32>, world: &BrickWorld) -> Vec<u8> {
+    let mut voxels = Vec::with_capacity((BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize);
+    for z in 0..BRICK_SIZE {
+        for y in 0..BRICK_SIZE {
+            for x in 0..BRICK_SIZE {
+                let voxel = brick * BRICK_SIZE + Vector3::new(x, y, z);
+                voxels.push(world.material(voxel));
+            }
+        }
+    }
+    voxels
+}
@@ -2,6 +2,22 @@ use instant::Duration;
 use nalgebra::*;
 use winit::event::*;
 
+use crate::{
+    input::{Axis, Input},
+    keybindings::{Action, KeyBindings, KEYBINDINGS_PATH},
+    uniforms::UniformArena,
+    world::World,
+};
+
+pub mod bookmarks;
+pub mod path;
+pub mod presets;
+pub mod settings;
+
+use bookmarks::{Bookmarks, BOOKMARKS_PATH};
+use path::PathController;
+use settings::{CameraSettings, CAMERA_SETTINGS_PATH};
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: nalgebra::Matrix4<f32> = nalgebra::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -10,15 +26,112 @@ pub const OPENGL_TO_WGPU_MATRIX: nalgebra::Matrix4<f32> = nalgebra::Matrix4::new
     0.0, 0.0, 0.0, 1.0,
 );
 
-#[derive(Debug)]
+// How rays are generated from screen positions, must match the PROJECTION_*
+// constants in ray-tracing.wgsl
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    // Equidistant 180 degree fisheye
+    Fisheye,
+    // Full 360 degree panorama
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn next(self) -> Self {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Fisheye,
+            Projection::Fisheye => Projection::Equirectangular,
+            Projection::Equirectangular => Projection::Perspective,
+        }
+    }
+
+    // Whether straight lines stay straight, so geometry can be rasterized with view_proj
+    pub fn is_linear(self) -> bool {
+        matches!(self, Projection::Perspective | Projection::Orthographic)
+    }
+}
+
+// Whether the view is from the camera position or from behind an avatar
+// standing there
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CameraMode {
+    FirstPerson,
+    ThirdPerson,
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            CameraMode::FirstPerson => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::FirstPerson,
+        }
+    }
+}
+
+// Distance kept between the third person camera and the voxels its boom hits
+const BOOM_MARGIN: f32 = 0.2;
+
+const MIN_FOV: f32 = 10.;
+const MAX_FOV: f32 = 120.;
+const FOV_STEP: f32 = 5.;
+
+const MIN_SPEED: f32 = 0.1;
+const MAX_SPEED: f32 = 1000.;
+// Speed multiplier per scroll step
+const SPEED_STEP: f32 = 1.2;
+
+// Radians per second
+const ROLL_SPEED: f32 = 1.5;
+// How quickly auto-level returns the roll to zero, per second
+const AUTO_LEVEL_RATE: f32 = 3.;
+
+// Farthest point picked as a follow target
+const FOLLOW_DISTANCE: f32 = 64.;
+
+// Half size of the box kept clear of voxels with collision enabled
+const COLLISION_RADIUS: f32 = 0.3;
+
+// How quickly the eye follows head bob and crouch changes, per second
+const EYE_OFFSET_RATE: f32 = 12.;
+
+// Stops the FPS look mode just short of looking straight up or down, where
+// the view's up vector would be parallel to the direction
+const MAX_PITCH: f32 = 89. * std::f32::consts::PI / 180.;
+
+// Scroll wheel notches, touchpads scroll in pixels
+pub fn scroll_steps(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, y) => *y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.,
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub position: Point3<f32>,
     pub direction: Vector3<f32>,
+    // Vertical field of view in degrees
     pub fov: f32,
     pub near_clip: f32,
     pub far_clip: f32,
     pub yaw: f32,
     pub pitch: f32,
+    // Rotation around the view direction in radians
+    pub roll: f32,
+    pub projection: Projection,
+    // Height of the view in world units for the orthographic projection
+    pub ortho_height: f32,
+    pub mode: CameraMode,
+    // Distance from the position to the third person camera behind it
+    pub boom_length: f32,
+    // Length the boom is shortened to where it would go through voxels, see
+    // clip_boom
+    pub boom_clip: Option<f32>,
+    // Vertical offset of the first person eye, from head bob and crouching
+    pub eye_offset: f32,
 }
 
 impl Camera {
@@ -36,25 +149,139 @@ impl Camera {
             far_clip: far_clip.into(),
             yaw: 0.,
             pitch: 0.,
+            roll: 0.,
+            projection: Projection::Perspective,
+            ortho_height: 64.,
+            mode: CameraMode::FirstPerson,
+            boom_length: 6.,
+            boom_clip: None,
+            eye_offset: 0.,
+        }
+    }
+
+    // Where the view is rendered from
+    pub fn eye(&self) -> Point3<f32> {
+        match self.mode {
+            CameraMode::FirstPerson => self.position + Vector3::y() * self.eye_offset,
+            CameraMode::ThirdPerson => {
+                let length = self
+                    .boom_clip
+                    .map_or(self.boom_length, |clip| clip.min(self.boom_length));
+                self.position - self.direction.normalize() * length
+            }
         }
     }
 
+    // Shortens the third person boom when it would go through voxels, call
+    // after moving the camera
+    pub fn clip_boom(&mut self, world: &World) {
+        self.boom_clip = match self.mode {
+            CameraMode::FirstPerson => None,
+            CameraMode::ThirdPerson => world
+                .raycast(self.position, -self.direction, self.boom_length)
+                .map(|hit| (hit - BOOM_MARGIN).max(0.)),
+        };
+    }
+
     pub fn calc_view(&self) -> Matrix4<f32> {
-        let view = Matrix4::look_at_lh(
-            &self.position,
-            &(self.position + self.direction),
-            &Vector3::new(0., 1., 0.),
-        );
+        let up = Rotation3::from_axis_angle(&Unit::new_normalize(self.direction), self.roll)
+            * Vector3::new(0., 1., 0.);
+        let eye = self.eye();
+        let view = Matrix4::look_at_lh(&eye, &(eye + self.direction), &up);
 
         Matrix4::try_inverse(view).expect("Could not inverse view matrix") * OPENGL_TO_WGPU_MATRIX
     }
 
-    pub fn calc_proj(&self, width: u32, height: u32) -> Matrix4<f32> {
+    fn projection_matrix(&self, width: u32, height: u32) -> Matrix4<f32> {
         let aspect = width as f32 / height as f32;
-        let proj = Matrix4::new_perspective(aspect, self.fov, self.near_clip, self.far_clip);
+        match self.projection {
+            Projection::Perspective => Matrix4::new_perspective(
+                aspect,
+                self.fov.to_radians(),
+                self.near_clip,
+                self.far_clip,
+            ),
+            Projection::Orthographic => {
+                let top = self.ortho_height * 0.5;
+                let right = top * aspect;
+                Matrix4::new_orthographic(-right, right, -top, top, self.near_clip, self.far_clip)
+            }
+            // Not expressible as a matrix, the shader maps between camera space
+            // directions and the screen itself
+            Projection::Fisheye | Projection::Equirectangular => Matrix4::identity(),
+        }
+    }
+
+    pub fn calc_proj(&self, width: u32, height: u32) -> Matrix4<f32> {
+        let proj = self.projection_matrix(width, height);
 
         return Matrix4::try_inverse(proj).expect("Could not inverse projection matrix");
     }
+
+    // Forward projection of camera relative positions, the inverse of the ray generation
+    // done with calc_view and calc_proj. Used to reproject hits into previous frames.
+    pub fn calc_view_proj(&self, width: u32, height: u32) -> Matrix4<f32> {
+        self.calc_view_proj_with(self.projection_matrix(width, height))
+    }
+
+    // calc_view_proj with a projection the camera can't describe, e.g. the
+    // asymmetric per-eye frustums of VR headsets
+    pub fn calc_view_proj_with(&self, proj: Matrix4<f32>) -> Matrix4<f32> {
+        let mut view =
+            Matrix4::try_inverse(self.calc_view()).expect("Could not inverse view matrix");
+        view[(0, 3)] = 0.;
+        view[(1, 3)] = 0.;
+        view[(2, 3)] = 0.;
+
+        proj * view
+    }
+
+    // World space ray through a point on the screen in NDC, only for the
+    // projections view_proj can express
+    pub fn screen_ray(
+        &self,
+        ndc: [f32; 2],
+        width: u32,
+        height: u32,
+    ) -> Option<(Point3<f32>, Vector3<f32>)> {
+        if !self.projection.is_linear() {
+            return None;
+        }
+        let inverse = Matrix4::try_inverse(self.calc_view_proj(width, height))?;
+        let unproject = |z: f32| {
+            let p = inverse * Vector4::new(ndc[0], ndc[1], z, 1.);
+            p.xyz() / p.w
+        };
+        let near = unproject(-1.);
+        let far = unproject(1.);
+        Some((self.eye() + near, (far - near).try_normalize(f32::EPSILON)?))
+    }
+
+    // Scrolling up narrows the field of view
+    pub fn zoom(&mut self, steps: f32) {
+        self.fov = (self.fov - steps * FOV_STEP).clamp(MIN_FOV, MAX_FOV);
+        self.ortho_height = (self.ortho_height * (1. - steps * 0.1)).max(1.);
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::O => {
+                if state == ElementState::Pressed {
+                    self.projection = self.projection.next();
+                    log::info!("Projection: {:?}", self.projection);
+                }
+                true
+            }
+            VirtualKeyCode::P => {
+                if state == ElementState::Pressed {
+                    self.mode = self.mode.next();
+                    log::info!("Camera mode: {:?}", self.mode);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 #[repr(C)]
@@ -63,25 +290,108 @@ pub struct CameraUniform {
     view_position: [f32; 4],
     view: [[f32; 4]; 4],
     proj: [[f32; 4]; 4],
+    view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    // Previous frame's view and inverse projection, for passes that rebuild
+    // last frame's rays instead of projecting into it
+    prev_view: [[f32; 4]; 4],
+    prev_proj: [[f32; 4]; 4],
+    prev_view_position: [f32; 4],
+    // Sub-pixel offset in NDC, only xy are used
+    jitter: [f32; 4],
+    // Size of the rendered region in pixels, only xy are used
+    viewport: [u32; 4],
+    projection: u32,
+    _padding: [u32; 3],
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CameraUniform {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             view_position: [0.0; 4],
             view: nalgebra::Matrix4::identity().into(),
             proj: nalgebra::Matrix4::identity().into(),
+            view_proj: nalgebra::Matrix4::identity().into(),
+            prev_view_proj: nalgebra::Matrix4::identity().into(),
+            prev_view: nalgebra::Matrix4::identity().into(),
+            prev_proj: nalgebra::Matrix4::identity().into(),
+            prev_view_position: [0.0; 4],
+            jitter: [0.0; 4],
+            viewport: [0; 4],
+            projection: 0,
+            _padding: [0; 3],
         }
     }
 
-    fn update_view(&mut self, camera: &Camera) {
-        self.view_position = camera.position.to_homogeneous().into();
+    pub fn update_view(&mut self, camera: &Camera) {
+        self.prev_view_position = self.view_position;
+        self.prev_view = self.view;
+        self.view_position = camera.eye().to_homogeneous().into();
         self.view = camera.calc_view().into();
     }
 
     pub fn update_proj(&mut self, camera: &Camera, width: u32, height: u32) {
         self.proj = camera.calc_proj(width, height).into();
     }
+
+    // Must be called once per frame, after the camera has moved
+    pub fn update_view_proj(&mut self, camera: &Camera, width: u32, height: u32, jitter: [f32; 2]) {
+        self.prev_view_proj = self.view_proj;
+        self.prev_proj = self.proj;
+        self.view_proj = camera.calc_view_proj(width, height).into();
+        self.proj = camera.calc_proj(width, height).into();
+        self.projection = camera.projection as u32;
+        self.jitter = [jitter[0], jitter[1], 0., 0.];
+        self.viewport = [width, height, 0, 0];
+    }
+
+    // Perspective rendering with an OpenGL style projection matrix from outside
+    // the camera, no jitter
+    pub fn update_view_proj_with(
+        &mut self,
+        camera: &Camera,
+        proj: Matrix4<f32>,
+        width: u32,
+        height: u32,
+    ) {
+        self.prev_view_proj = self.view_proj;
+        self.prev_proj = self.proj;
+        self.view_proj = camera.calc_view_proj_with(proj).into();
+        self.proj = Matrix4::try_inverse(proj)
+            .expect("Could not inverse projection matrix")
+            .into();
+        self.projection = Projection::Perspective as u32;
+        self.jitter = [0.; 4];
+        self.viewport = [width, height, 0, 0];
+    }
+}
+
+// How mouse movement turns the camera
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LookMode {
+    // The last mouse delta of the frame is scaled by the frame time
+    Frame,
+    // Every mouse delta is added to absolute yaw and pitch angles, so the
+    // turn per mouse count doesn't depend on the frame rate
+    Fps,
+    // Like Fps, but turning moves the camera around a pivot point
+    Orbit,
+}
+
+impl LookMode {
+    pub fn next(self) -> Self {
+        match self {
+            LookMode::Frame => LookMode::Fps,
+            LookMode::Fps => LookMode::Orbit,
+            LookMode::Orbit => LookMode::Frame,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -94,13 +404,51 @@ pub struct CameraController {
     amount_down: f32,
     rotate_horizontal: f32,
     rotate_vertical: f32,
-    speed: f32,
-    sensitivity: f32,
-    last_mouse_pos: (f64, f64),
+    amount_roll_left: f32,
+    amount_roll_right: f32,
+    sprint: bool,
+    slow: bool,
+    crouch: bool,
+    // Speed multipliers while the sprint or slow keys are held
+    pub sprint_multiplier: f32,
+    pub slow_multiplier: f32,
+    // Level the horizon whenever the camera isn't being rolled
+    pub auto_level: bool,
+    // Stop movement against the voxel world instead of flying through it.
+    // Head bob and crouching only apply while walking like this.
+    pub collision: bool,
+    pub head_bob: bool,
+    // Height of the bob in voxels
+    pub bob_amplitude: f32,
+    // Distance walked per footstep, one bob per step
+    pub step_length: f32,
+    // How far crouching lowers the eye
+    pub crouch_height: f32,
+    pub crouch_multiplier: f32,
+    // Footsteps walked, the fraction is the progress through the current one
+    stride: f32,
+    pub look_mode: LookMode,
+    // World position kept in the center of the view while moving
+    pub follow: Option<Point3<f32>>,
+    // Point the orbit look mode turns around, picked when entering it if unset
+    pub pivot: Option<Point3<f32>>,
+    // Set by the follow key, resolved once the camera is known
+    follow_requested: bool,
+    // Radians turned per mouse count in the FPS look mode
+    pub fps_sensitivity: f32,
+    pub invert_x: bool,
+    pub invert_y: bool,
+    // Mouse movement accumulated since the last update
+    look_delta: (f64, f64),
+    pub speed: f32,
+    pub sensitivity: f32,
+    // One-off movement along (right, up, forward), e.g. from touch gestures
+    translation: Vector3<f32>,
+    pub bindings: KeyBindings,
 }
 
 impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
+    pub fn new(speed: f32, sensitivity: f32, bindings: KeyBindings) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -110,71 +458,199 @@ impl CameraController {
             amount_down: 0.0,
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
+            amount_roll_left: 0.0,
+            amount_roll_right: 0.0,
+            sprint: false,
+            slow: false,
+            crouch: false,
+            sprint_multiplier: 4.0,
+            slow_multiplier: 0.2,
+            auto_level: true,
+            collision: false,
+            head_bob: true,
+            bob_amplitude: 0.05,
+            step_length: 1.5,
+            crouch_height: 0.6,
+            crouch_multiplier: 0.5,
+            stride: 0.,
+            look_mode: LookMode::Frame,
+            follow: None,
+            pivot: None,
+            follow_requested: false,
+            fps_sensitivity: 0.002,
+            invert_x: false,
+            invert_y: false,
+            look_delta: (0., 0.),
             speed,
             sensitivity,
-            last_mouse_pos: (0., 0.),
+            translation: Vector3::zeros(),
+            bindings,
         }
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
-        let amount = if state == ElementState::Pressed {
-            1.0
-        } else {
-            0.0
+    // Reads the frame's input snapshot, call once per frame before update_camera
+    pub fn process_input(&mut self, input: &Input, camera: &mut Camera) {
+        let held = |action| input.action_held(&self.bindings, action) as u8 as f32;
+        // Keys and analog axes add up, clamped so both together aren't faster
+        let axis = |positive: Action, negative: Action, axis: Axis| {
+            let amount = (held(positive) - held(negative) + input.axis(axis)).clamp(-1., 1.);
+            (amount.max(0.), (-amount).max(0.))
         };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.amount_forward = amount;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.amount_backward = amount;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.amount_left = amount;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.amount_right = amount;
-                true
-            }
-            VirtualKeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.amount_down = amount;
-                true
+        (self.amount_forward, self.amount_backward) =
+            axis(Action::MoveForward, Action::MoveBackward, Axis::MoveZ);
+        (self.amount_right, self.amount_left) =
+            axis(Action::MoveRight, Action::MoveLeft, Axis::MoveX);
+        (self.amount_up, self.amount_down) = axis(Action::MoveUp, Action::MoveDown, Axis::MoveY);
+        self.amount_roll_left = held(Action::RollLeft);
+        self.amount_roll_right = held(Action::RollRight);
+        self.sprint = input.action_held(&self.bindings, Action::Sprint);
+        self.slow = input.action_held(&self.bindings, Action::Slow);
+        self.crouch = input.action_held(&self.bindings, Action::Crouch);
+
+        let pressed = |action| input.action_pressed(&self.bindings, action);
+        if pressed(Action::ToggleAutoLevel) {
+            self.auto_level = !self.auto_level;
+            log::info!("Auto level: {}", self.auto_level);
+        }
+        if pressed(Action::ToggleCollision) {
+            self.collision = !self.collision;
+            log::info!("Collision: {}", self.collision);
+        }
+        if pressed(Action::ToggleFollow) {
+            self.follow_requested = true;
+        }
+        if pressed(Action::ToggleLookMode) {
+            self.look_mode = self.look_mode.next();
+            log::info!("Look mode: {:?}", self.look_mode);
+        }
+
+        if input.mouse_delta != (0., 0.) {
+            self.process_mouse(input.mouse_delta);
+        }
+        if input.scroll != 0. {
+            if input.modifiers.ctrl() {
+                camera.zoom(input.scroll);
+            } else {
+                self.process_scroll(input.scroll);
             }
-            _ => false,
         }
     }
 
-    pub fn process_mouse(&mut self, mouse_pos: (f64, f64)) {
-        self.rotate_horizontal = (mouse_pos.0 - self.last_mouse_pos.0) as f32;
-        self.rotate_vertical = (mouse_pos.1 - self.last_mouse_pos.1) as f32;
+    // Relative mouse movement, several deltas can arrive in a frame
+    pub fn process_mouse(&mut self, delta: (f64, f64)) {
+        let delta = (
+            if self.invert_x { -delta.0 } else { delta.0 },
+            if self.invert_y { -delta.1 } else { delta.1 },
+        );
+        self.rotate_horizontal += delta.0 as f32;
+        self.rotate_vertical += delta.1 as f32;
+        self.look_delta.0 += delta.0;
+        self.look_delta.1 += delta.1;
+    }
+
+    pub fn process_scroll(&mut self, steps: f32) {
+        self.speed = (self.speed * SPEED_STEP.powf(steps)).clamp(MIN_SPEED, MAX_SPEED);
+        log::info!("Camera speed: {}", self.speed);
+    }
+
+    pub fn process_translation(&mut self, amount: Vector3<f32>) {
+        self.translation += amount;
     }
 
     pub fn update_camera(
         &mut self,
         camera: &mut Camera,
+        world: &World,
         dt: Duration,
         camera_unifrom: &mut CameraUniform,
     ) {
-        let dt = dt.as_secs_f32();
+        self.move_camera(camera, world, dt.as_secs_f32());
+        camera.clip_boom(world);
+        camera_unifrom.update_view(camera);
+    }
 
+    fn move_camera(&mut self, camera: &mut Camera, world: &World, dt: f32) {
         let up = Vector3::new(0., 1., 0.);
         let forward = Vector3::new(camera.direction.x, 0., camera.direction.z);
         let right = Matrix::cross(&up, &forward);
 
+        let mut speed = self.speed;
+        if self.sprint {
+            speed *= self.sprint_multiplier;
+        }
+        if self.slow {
+            speed *= self.slow_multiplier;
+        }
+        let crouching = self.crouch && self.collision;
+        if crouching {
+            speed *= self.crouch_multiplier;
+        }
+
         // Move forward/backward and left/right
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        let mut motion = forward * (self.amount_forward - self.amount_backward) * speed * dt;
+        motion += right * (self.amount_right - self.amount_left) * speed * dt;
 
-        // Move up/down. Since we don't use roll, we can just
+        // Move up/down. Movement ignores roll, so we can just
         // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        motion.y += (self.amount_up - self.amount_down) * speed * dt;
+
+        let flat = |v: Vector3<f32>| v.try_normalize(f32::EPSILON).unwrap_or(Vector3::zeros());
+        motion += flat(right) * self.translation.x
+            + up * self.translation.y
+            + flat(forward) * self.translation.z;
+        self.translation = Vector3::zeros();
+
+        let start = camera.position;
+        camera.position = if self.collision {
+            world.sweep(camera.position, motion, Vector3::repeat(COLLISION_RADIUS))
+        } else {
+            camera.position + motion
+        };
+        self.update_eye_offset(camera, start, crouching, dt);
+
+        let roll = self.amount_roll_right - self.amount_roll_left;
+        if roll != 0. {
+            camera.roll += roll * ROLL_SPEED * dt;
+        } else if self.auto_level {
+            camera.roll -= camera.roll * (AUTO_LEVEL_RATE * dt).min(1.);
+        }
+
+        if self.follow_requested {
+            self.follow_requested = false;
+            self.follow = match self.follow {
+                Some(_) => None,
+                None => Some(follow_target(camera, world)),
+            };
+            log::info!("Following: {:?}", self.follow);
+        }
+        // Following overrides mouse look
+        if let Some(target) = self.follow {
+            self.rotate_horizontal = 0.0;
+            self.rotate_vertical = 0.0;
+            self.look_delta = (0., 0.);
+            if let Some(direction) = (target - camera.position).try_normalize(f32::EPSILON) {
+                camera.direction = direction;
+            }
+            return;
+        }
+
+        if self.look_mode == LookMode::Fps {
+            self.look_fps(camera);
+            return;
+        }
+        if self.look_mode == LookMode::Orbit {
+            let pivot = *self
+                .pivot
+                .get_or_insert_with(|| follow_target(camera, world));
+            let offset = pivot - camera.position;
+            if let Some(direction) = offset.try_normalize(f32::EPSILON) {
+                camera.direction = direction;
+            }
+            self.look_fps(camera);
+            camera.position = pivot - camera.direction * offset.norm();
+            return;
+        }
+        self.look_delta = (0., 0.);
 
         // Rotate
         camera.yaw = self.rotate_horizontal * self.sensitivity * dt;
@@ -187,34 +663,99 @@ impl CameraController {
         self.rotate_vertical = 0.0;
 
         // Keep the camera's angle from going too high/low.
-        if camera.pitch < -90. {
-            camera.pitch = -90.;
-        } else if camera.pitch > 180. {
-            camera.pitch = 180.;
-        }
+        camera.pitch = camera.pitch.clamp(-90., 180.);
 
         camera.direction =
             Rotation::from_axis_angle(&Unit::new_normalize(right), camera.pitch) * camera.direction;
         camera.direction =
             Rotation::from_axis_angle(&Unit::new_normalize(up), camera.yaw) * camera.direction;
+    }
 
-        camera_unifrom.update_view(camera);
+    // The bob follows the distance actually walked, so it stops against walls
+    // and keeps in step with the footsteps at any speed
+    fn update_eye_offset(
+        &mut self,
+        camera: &mut Camera,
+        start: Point3<f32>,
+        crouching: bool,
+        dt: f32,
+    ) {
+        let walked = Vector3::new(camera.position.x - start.x, 0., camera.position.z - start.z);
+        let mut target = 0.;
+        if self.collision && self.head_bob && walked.norm() > 0. {
+            self.stride += walked.norm() / self.step_length;
+            // Lowest when a foot lands, highest between steps
+            target += self.bob_amplitude * ((self.stride * std::f32::consts::PI).sin().abs() - 1.);
+        } else {
+            // Start the next walk on a footstep
+            self.stride = 0.;
+        }
+        if crouching {
+            target -= self.crouch_height;
+        }
+
+        camera.eye_offset += (target - camera.eye_offset) * (EYE_OFFSET_RATE * dt).min(1.);
+    }
+
+    // Yaw and pitch are taken from the current direction, so bookmarks and
+    // camera paths that set it directly are picked up
+    fn look_fps(&mut self, camera: &mut Camera) {
+        let (dx, dy) = self.look_delta;
+        self.look_delta = (0., 0.);
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        let direction = camera.direction.normalize();
+        camera.yaw = direction.x.atan2(direction.z) + dx as f32 * self.fps_sensitivity;
+        camera.pitch = (direction.y.clamp(-1., 1.).asin() - dy as f32 * self.fps_sensitivity)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+
+        camera.direction = Vector3::new(
+            camera.pitch.cos() * camera.yaw.sin(),
+            camera.pitch.sin(),
+            camera.pitch.cos() * camera.yaw.cos(),
+        );
     }
 }
 
+// Follows the voxel in the center of the view, or a point in front of the
+// camera when looking at the sky
+fn follow_target(camera: &Camera, world: &World) -> Point3<f32> {
+    let eye = camera.eye();
+    let direction = camera.direction.normalize();
+    let distance = world
+        .raycast(eye, direction, FOLLOW_DISTANCE)
+        .unwrap_or(FOLLOW_DISTANCE);
+    eye + direction * distance
+}
+
 pub struct CameraPipeline {
+    pub settings: CameraSettings,
     pub camera: Camera,
     pub controller: CameraController,
+    pub bookmarks: Bookmarks,
+    pub path: PathController,
     pub uniform: CameraUniform,
     pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    // Offset of the uniform in a UniformArena once bound to one, buffer is
+    // unused then
+    arena_slot: Option<u32>,
 }
 
 impl CameraPipeline {
     pub fn new(device: &wgpu::Device) -> CameraPipeline {
-        let camera = Camera::new(Vector3::new(0.0, 2.0, -12.0), 45., 1., 100.);
-        let controller = CameraController::new(10.0, 1.0);
+        let settings = CameraSettings::load(CAMERA_SETTINGS_PATH);
+        let camera = Camera::new(
+            settings.position,
+            settings.fov,
+            settings.near_clip,
+            settings.far_clip,
+        );
+        let bindings = KeyBindings::load(KEYBINDINGS_PATH);
+        let controller = CameraController::new(settings.speed, settings.sensitivity, bindings);
+        let bookmarks = Bookmarks::load(BOOKMARKS_PATH);
 
         let uniform = CameraUniform::new();
 
@@ -230,7 +771,9 @@ impl CameraPipeline {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
+                visibility: wgpu::ShaderStages::COMPUTE
+                    | wgpu::ShaderStages::VERTEX
+                    | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -250,13 +793,85 @@ impl CameraPipeline {
             label: Some("camera_bind_group"),
         });
 
-        return CameraPipeline {
+        let mut camera_pipeline = CameraPipeline {
+            settings,
             camera,
             controller,
+            bookmarks,
+            path: PathController::new(),
             uniform,
             buffer,
             bind_group,
             bind_group_layout,
+            arena_slot: None,
         };
+        camera_pipeline.apply_settings();
+        camera_pipeline
+    }
+
+    // Recreates the buffer and bind group on a new device, the camera and its
+    // settings are kept
+    pub fn recreate(&mut self, device: &wgpu::Device) {
+        let pipeline = CameraPipeline::new(device);
+        self.buffer = pipeline.buffer;
+        self.bind_group = pipeline.bind_group;
+        self.bind_group_layout = pipeline.bind_group_layout;
+        self.arena_slot = None;
+    }
+
+    // Moves the uniform into a slot of arena, so it's uploaded together with
+    // the uniforms pushed there. The layout is unchanged, only the bind group
+    // is rebuilt. Call again once the arena is recreated.
+    pub fn bind_to_arena(&mut self, device: &wgpu::Device, arena: &mut UniformArena) {
+        let slot = arena.reserve::<CameraUniform>();
+        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: arena.slot_binding::<CameraUniform>(slot),
+            }],
+            label: Some("camera_bind_group"),
+        });
+        self.arena_slot = Some(slot);
+    }
+
+    // Writes uniform into the arena slot, uploaded by the arena's next flush,
+    // or straight into buffer when not bound to an arena
+    pub fn write_uniform(
+        &self,
+        queue: &wgpu::Queue,
+        arena: &mut UniformArena,
+        uniform: &CameraUniform,
+    ) {
+        match self.arena_slot {
+            Some(slot) => arena.write(slot, uniform),
+            None => queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(uniform)),
+        }
+    }
+
+    // Applies changed settings at runtime. The projection uniform is rebuilt
+    // from the camera every frame, so fov and clip changes show immediately.
+    pub fn apply_settings(&mut self) {
+        let settings = &self.settings;
+        self.camera.fov = settings.fov.clamp(MIN_FOV, MAX_FOV);
+        self.camera.near_clip = settings.near_clip;
+        self.camera.far_clip = settings.far_clip.max(settings.near_clip);
+        self.controller.speed = settings.speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.controller.sensitivity = settings.sensitivity;
+        self.controller.fps_sensitivity = settings.fps_sensitivity;
+        self.controller.invert_x = settings.invert_x;
+        self.controller.invert_y = settings.invert_y;
+        self.controller.sprint_multiplier = settings.sprint_multiplier;
+        self.controller.slow_multiplier = settings.slow_multiplier;
+        self.controller.head_bob = settings.head_bob;
+        self.controller.bob_amplitude = settings.bob_amplitude;
+        self.controller.step_length = settings.step_length.max(f32::EPSILON);
+        self.controller.crouch_height = settings.crouch_height;
+        self.controller.crouch_multiplier = settings.crouch_multiplier;
+    }
+
+    // Keeps settings changed at runtime for the next start
+    pub fn save_settings(&self) {
+        self.settings.save(CAMERA_SETTINGS_PATH);
     }
 }
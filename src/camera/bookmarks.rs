@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, ModifiersState, VirtualKeyCode};
+
+use super::Camera;
+
+pub const BOOKMARKS_PATH: &str = "bookmarks.toml";
+
+// Seconds it takes to fly to a recalled bookmark
+const TRANSITION_TIME: f32 = 0.75;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+    pub roll: f32,
+    pub fov: f32,
+}
+
+impl Bookmark {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            position: camera.position.into(),
+            direction: camera.direction.into(),
+            roll: camera.roll,
+            fov: camera.fov,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = Point3::from(self.position);
+        camera.direction = Vector3::from(self.direction);
+        camera.roll = self.roll;
+        camera.fov = self.fov;
+    }
+
+    fn lerp(&self, other: &Bookmark, t: f32) -> Bookmark {
+        let position = Vector3::from(self.position).lerp(&Vector3::from(other.position), t);
+        let direction = Vector3::from(self.direction)
+            .lerp(&Vector3::from(other.direction), t)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or(Vector3::from(other.direction));
+
+        Bookmark {
+            position: position.into(),
+            direction: direction.into(),
+            roll: self.roll + (other.roll - self.roll) * t,
+            fov: self.fov + (other.fov - self.fov) * t,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BookmarksFile {
+    // Keyed by slot number, TOML only allows string keys
+    bookmarks: BTreeMap<String, Bookmark>,
+}
+
+#[derive(Debug)]
+struct Transition {
+    from: Bookmark,
+    to: Bookmark,
+    time: f32,
+}
+
+// Camera viewpoints saved with Ctrl+1..9 and recalled with 1..9
+#[derive(Debug)]
+pub struct Bookmarks {
+    slots: [Option<Bookmark>; 9],
+    // Fly to recalled bookmarks instead of jumping
+    pub smooth: bool,
+    transition: Option<Transition>,
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self {
+            slots: [None; 9],
+            smooth: true,
+            transition: None,
+        }
+    }
+
+    pub fn load(path: &str) -> Self {
+        let mut bookmarks = Self::new();
+        let Ok(source) = std::fs::read_to_string(path) else {
+            return bookmarks;
+        };
+
+        match toml::from_str::<BookmarksFile>(&source) {
+            Ok(file) => {
+                for (slot, bookmark) in file.bookmarks {
+                    match slot.parse::<usize>() {
+                        Ok(slot @ 1..=9) => bookmarks.slots[slot - 1] = Some(bookmark),
+                        _ => log::warn!("Invalid bookmark slot {} in {}", slot, path),
+                    }
+                }
+            }
+            Err(error) => log::warn!("Invalid bookmarks in {}: {}", path, error),
+        }
+        bookmarks
+    }
+
+    pub fn save_to(&self, path: &str) {
+        let file = BookmarksFile {
+            bookmarks: self
+                .slots
+                .iter()
+                .enumerate()
+                .filter_map(|(i, slot)| slot.map(|bookmark| ((i + 1).to_string(), bookmark)))
+                .collect(),
+        };
+
+        let result = toml::to_string(&file)
+            .map_err(|error| error.to_string())
+            .and_then(|source| std::fs::write(path, source).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            log::warn!("Couldn't save bookmarks to {}: {}", path, error);
+        }
+    }
+
+    // slot is 1 to 9
+    pub fn save(&mut self, slot: usize, camera: &Camera) {
+        self.slots[slot - 1] = Some(Bookmark::from_camera(camera));
+        self.save_to(BOOKMARKS_PATH);
+        log::info!("Saved bookmark {}", slot);
+    }
+
+    pub fn recall(&mut self, slot: usize, camera: &mut Camera) {
+        if let Some(bookmark) = self.slots[slot - 1] {
+            self.fly_to(bookmark, camera);
+        }
+    }
+
+    // Moves the camera to a viewpoint, smoothly if enabled
+    pub fn fly_to(&mut self, bookmark: Bookmark, camera: &mut Camera) {
+        if self.smooth {
+            self.transition = Some(Transition {
+                from: Bookmark::from_camera(camera),
+                to: bookmark,
+                time: 0.,
+            });
+        } else {
+            bookmark.apply(camera);
+        }
+    }
+
+    pub fn process_keyboard(
+        &mut self,
+        key: VirtualKeyCode,
+        state: ElementState,
+        modifiers: ModifiersState,
+        camera: &mut Camera,
+    ) -> bool {
+        let slot = match key {
+            VirtualKeyCode::Key1 => 1,
+            VirtualKeyCode::Key2 => 2,
+            VirtualKeyCode::Key3 => 3,
+            VirtualKeyCode::Key4 => 4,
+            VirtualKeyCode::Key5 => 5,
+            VirtualKeyCode::Key6 => 6,
+            VirtualKeyCode::Key7 => 7,
+            VirtualKeyCode::Key8 => 8,
+            VirtualKeyCode::Key9 => 9,
+            _ => return false,
+        };
+
+        if state == ElementState::Pressed {
+            if modifiers.ctrl() {
+                self.save(slot, camera);
+            } else {
+                self.recall(slot, camera);
+            }
+        }
+        true
+    }
+
+    // Advances a running transition, call before the controller moves the camera
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        let Some(transition) = &mut self.transition else {
+            return;
+        };
+
+        transition.time += dt;
+        let t = (transition.time / TRANSITION_TIME).min(1.);
+        // Ease in and out
+        let t = t * t * (3. - 2. * t);
+        transition.from.lerp(&transition.to, t).apply(camera);
+
+        if transition.time >= TRANSITION_TIME {
+            self.transition = None;
+        }
+    }
+}
+
+impl Default for Bookmarks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,342 @@
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, VirtualKeyCode};
+
+use super::Camera;
+
+pub const CAMERA_PATH_PATH: &str = "camera_path.toml";
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    // Seconds since the start of the path
+    pub time: f32,
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+    pub roll: f32,
+    pub fov: f32,
+}
+
+impl Keyframe {
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            position: camera.position.into(),
+            direction: camera.direction.into(),
+            roll: camera.roll,
+            fov: camera.fov,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = Point3::from(self.position);
+        camera.direction = Vector3::from(self.direction);
+        camera.roll = self.roll;
+        camera.fov = self.fov;
+    }
+}
+
+// Uniform Catmull-Rom spline through p1 and p2
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2. * p1
+        + (p2 - p0) * t
+        + (2. * p0 - 5. * p1 + 4. * p2 - p3) * t2
+        + (3. * p1 - p0 - 3. * p2 + p3) * t3)
+}
+
+fn catmull_rom_3(p0: [f32; 3], p1: [f32; 3], p2: [f32; 3], p3: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| catmull_rom(p0[i], p1[i], p2[i], p3[i], t))
+}
+
+// Keyframes sorted by time, sampled with a Catmull-Rom spline. Sampling only
+// depends on the time, so playback is deterministic.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: &str) -> Option<Self> {
+        let source = std::fs::read_to_string(path).ok()?;
+        match toml::from_str(&source) {
+            Ok(camera_path) => Some(camera_path),
+            Err(error) => {
+                log::warn!("Invalid camera path in {}: {}", path, error);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        let result = toml::to_string(self)
+            .map_err(|error| error.to_string())
+            .and_then(|source| std::fs::write(path, source).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            log::warn!("Couldn't save camera path to {}: {}", path, error);
+        }
+    }
+
+    // Keyframes must be pushed in time order
+    pub fn push(&mut self, keyframe: Keyframe) {
+        self.keyframes.push(keyframe);
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0., |keyframe| keyframe.time)
+    }
+
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        // Index of the keyframe starting the segment containing time
+        let segment = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time)
+            .saturating_sub(1)
+            .min(last.saturating_sub(1));
+
+        let k1 = &self.keyframes[segment];
+        let k2 = &self.keyframes[(segment + 1).min(last)];
+        let k0 = &self.keyframes[segment.saturating_sub(1)];
+        let k3 = &self.keyframes[(segment + 2).min(last)];
+
+        let span = k2.time - k1.time;
+        let t = if span > 0. {
+            ((time - k1.time) / span).clamp(0., 1.)
+        } else {
+            0.
+        };
+
+        let direction = Vector3::from(catmull_rom_3(
+            k0.direction,
+            k1.direction,
+            k2.direction,
+            k3.direction,
+            t,
+        ))
+        .try_normalize(f32::EPSILON)
+        .unwrap_or(Vector3::from(k1.direction));
+
+        Some(Keyframe {
+            time,
+            position: catmull_rom_3(k0.position, k1.position, k2.position, k3.position, t),
+            direction: direction.into(),
+            roll: catmull_rom(k0.roll, k1.roll, k2.roll, k3.roll, t),
+            fov: catmull_rom(k0.fov, k1.fov, k2.fov, k3.fov, t),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PathState {
+    Idle,
+    Recording { time: f32, since_keyframe: f32 },
+    Playing { time: f32 },
+}
+
+// Records the camera into a path (F5) and plays it back (F6)
+#[derive(Debug)]
+pub struct PathController {
+    pub path: CameraPath,
+    pub state: PathState,
+    // Seconds between keyframes while recording
+    pub keyframe_interval: f32,
+    pub looping: bool,
+}
+
+impl PathController {
+    pub fn new() -> Self {
+        Self {
+            path: CameraPath::load(CAMERA_PATH_PATH).unwrap_or_default(),
+            state: PathState::Idle,
+            keyframe_interval: 0.5,
+            looping: false,
+        }
+    }
+
+    pub fn playing(&self) -> bool {
+        matches!(self.state, PathState::Playing { .. })
+    }
+
+    pub fn start_recording(&mut self, camera: &Camera) {
+        self.path = CameraPath::new();
+        self.path.push(Keyframe::from_camera(camera, 0.));
+        self.state = PathState::Recording {
+            time: 0.,
+            since_keyframe: 0.,
+        };
+        log::info!("Recording camera path");
+    }
+
+    pub fn stop_recording(&mut self, camera: &Camera) {
+        if let PathState::Recording { time, .. } = self.state {
+            self.path.push(Keyframe::from_camera(camera, time));
+            self.path.save(CAMERA_PATH_PATH);
+            log::info!(
+                "Recorded {} keyframes over {:.1}s",
+                self.path.keyframes.len(),
+                time
+            );
+        }
+        self.state = PathState::Idle;
+    }
+
+    pub fn play(&mut self) {
+        if self.path.keyframes.is_empty() {
+            log::warn!("No camera path to play");
+            return;
+        }
+        self.state = PathState::Playing { time: 0. };
+    }
+
+    pub fn process_keyboard(
+        &mut self,
+        key: VirtualKeyCode,
+        state: ElementState,
+        camera: &Camera,
+    ) -> bool {
+        let pressed = state == ElementState::Pressed;
+        match key {
+            VirtualKeyCode::F5 => {
+                if pressed {
+                    match self.state {
+                        PathState::Recording { .. } => self.stop_recording(camera),
+                        _ => self.start_recording(camera),
+                    }
+                }
+                true
+            }
+            VirtualKeyCode::F6 => {
+                if pressed {
+                    match self.state {
+                        PathState::Playing { .. } => self.state = PathState::Idle,
+                        _ => self.play(),
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Call before the controller moves the camera
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        match &mut self.state {
+            PathState::Idle => {}
+            PathState::Recording {
+                time,
+                since_keyframe,
+            } => {
+                *time += dt;
+                *since_keyframe += dt;
+                if *since_keyframe >= self.keyframe_interval {
+                    *since_keyframe = 0.;
+                    self.path.push(Keyframe::from_camera(camera, *time));
+                }
+            }
+            PathState::Playing { time } => {
+                if let Some(keyframe) = self.path.sample(*time) {
+                    keyframe.apply(camera);
+                }
+
+                *time += dt;
+                if *time > self.path.duration() {
+                    if self.looping {
+                        *time = 0.;
+                    } else {
+                        self.state = PathState::Idle;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for PathController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn keyframe(time: f32, x: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position: [x, 2. * x, 0.],
+            direction: [1., 0., 0.],
+            roll: 0.,
+            fov: 60. + x,
+        }
+    }
+
+    fn path(keyframes: &[(f32, f32)]) -> CameraPath {
+        CameraPath {
+            keyframes: keyframes
+                .iter()
+                .map(|&(time, x)| keyframe(time, x))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        let path = path(&[(0., 0.), (1., 3.), (2., -1.), (4., 5.)]);
+        for keyframe in &path.keyframes {
+            let sample = path.sample(keyframe.time).unwrap();
+            assert!((sample.position[0] - keyframe.position[0]).abs() < EPSILON);
+            assert!((sample.fov - keyframe.fov).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn evenly_spaced_lines_stay_linear() {
+        // The first and last segments repeat their end keyframe and bend
+        let path = path(&[(0., 0.), (1., 1.), (2., 2.), (3., 3.), (4., 4.)]);
+        for time in [1.25, 2., 2.75] {
+            let sample = path.sample(time).unwrap();
+            assert!((sample.position[0] - time).abs() < EPSILON);
+            assert!((sample.position[1] - 2. * time).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn clamps_outside_of_the_path() {
+        let path = path(&[(1., 2.), (3., 4.)]);
+        assert_eq!(path.sample(0.).unwrap().position, [2., 4., 0.]);
+        assert_eq!(path.sample(5.).unwrap().position, [4., 8., 0.]);
+        assert_eq!(path.duration(), 3.);
+    }
+
+    #[test]
+    fn samples_single_and_empty_paths() {
+        assert!(CameraPath::new().sample(0.).is_none());
+        let sample = path(&[(0., 7.)]).sample(2.).unwrap();
+        assert_eq!(sample.position, [7., 14., 0.]);
+        assert_eq!(sample.time, 2.);
+    }
+
+    #[test]
+    fn directions_stay_normalized() {
+        let mut path = path(&[(0., 0.), (1., 1.)]);
+        path.keyframes[1].direction = [0., 0., 1.];
+        let direction = Vector3::from(path.sample(0.5).unwrap().direction);
+        assert!((direction.norm() - 1.).abs() < EPSILON);
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let path = path(&[(0., 1.), (2., 3.)]);
+        let loaded: CameraPath = toml::from_str(&toml::to_string(&path).unwrap()).unwrap();
+        assert_eq!(loaded.keyframes.len(), 2);
+        assert_eq!(loaded.keyframes[1].position, path.keyframes[1].position);
+    }
+}
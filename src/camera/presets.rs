@@ -0,0 +1,90 @@
+use nalgebra::{Point3, Vector3};
+use winit::event::{ElementState, ModifiersState, VirtualKeyCode};
+
+use super::{
+    bookmarks::{Bookmark, Bookmarks},
+    Camera,
+};
+use crate::world;
+
+// Canonical views around the scene bounds, on the numpad like in Blender
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PresetView {
+    Front,
+    Back,
+    Right,
+    Left,
+    Top,
+    Bottom,
+    Isometric,
+}
+
+impl PresetView {
+    // Ctrl selects the opposite view
+    pub fn from_key(key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Self> {
+        let opposite = modifiers.ctrl();
+        let view = match key {
+            VirtualKeyCode::Numpad1 if opposite => PresetView::Back,
+            VirtualKeyCode::Numpad1 => PresetView::Front,
+            VirtualKeyCode::Numpad3 if opposite => PresetView::Left,
+            VirtualKeyCode::Numpad3 => PresetView::Right,
+            VirtualKeyCode::Numpad7 if opposite => PresetView::Bottom,
+            VirtualKeyCode::Numpad7 => PresetView::Top,
+            VirtualKeyCode::Numpad5 => PresetView::Isometric,
+            _ => return None,
+        };
+        Some(view)
+    }
+
+    // Direction the camera looks in
+    fn direction(self) -> Vector3<f32> {
+        // Looking exactly up or down would leave the view without an up
+        // vector, so vertical views lean slightly towards +z
+        match self {
+            PresetView::Front => Vector3::new(0., 0., 1.),
+            PresetView::Back => Vector3::new(0., 0., -1.),
+            PresetView::Right => Vector3::new(-1., 0., 0.),
+            PresetView::Left => Vector3::new(1., 0., 0.),
+            PresetView::Top => Vector3::new(0., -1., 0.001),
+            PresetView::Bottom => Vector3::new(0., 1., 0.001),
+            PresetView::Isometric => Vector3::new(-1., -1., -1.),
+        }
+        .normalize()
+    }
+
+    // Looks at the center of the scene from far enough away to fit all of it
+    pub fn bookmark(self, camera: &Camera) -> Bookmark {
+        let min = Point3::from(world::SCENE_MIN);
+        let max = Point3::from(world::SCENE_MAX);
+        let center = nalgebra::center(&min, &max);
+        let radius = nalgebra::distance(&min, &max) * 0.5;
+
+        let direction = self.direction();
+        let distance = radius / (camera.fov.to_radians() * 0.5).sin();
+
+        Bookmark {
+            position: (center - direction * distance).into(),
+            direction: direction.into(),
+            roll: 0.,
+            fov: camera.fov,
+        }
+    }
+}
+
+pub fn process_keyboard(
+    key: VirtualKeyCode,
+    state: ElementState,
+    modifiers: ModifiersState,
+    camera: &mut Camera,
+    bookmarks: &mut Bookmarks,
+) -> bool {
+    let Some(view) = PresetView::from_key(key, modifiers) else {
+        return false;
+    };
+
+    if state == ElementState::Pressed {
+        bookmarks.fly_to(view.bookmark(camera), camera);
+        log::info!("View: {:?}", view);
+    }
+    true
+}
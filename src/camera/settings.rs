@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+pub const CAMERA_SETTINGS_PATH: &str = "camera.toml";
+
+// Startup and tuning parameters of the camera and its controller. Missing
+// fields keep their defaults, e.g.
+//
+// fov = 60.0
+// speed = 20.0
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    // Only used when the camera is created
+    pub position: [f32; 3],
+    // Vertical field of view in degrees
+    pub fov: f32,
+    pub near_clip: f32,
+    pub far_clip: f32,
+    // Movement speed in voxels per second
+    pub speed: f32,
+    pub sensitivity: f32,
+    // Radians per mouse count in the FPS and orbit look modes
+    pub fps_sensitivity: f32,
+    // Flip the mouse look axes
+    pub invert_x: bool,
+    pub invert_y: bool,
+    pub sprint_multiplier: f32,
+    pub slow_multiplier: f32,
+    // Walking feel with collision enabled, distances in voxels
+    pub head_bob: bool,
+    pub bob_amplitude: f32,
+    pub step_length: f32,
+    pub crouch_height: f32,
+    pub crouch_multiplier: f32,
+}
+
+impl CameraSettings {
+    pub fn new() -> Self {
+        Self {
+            position: [0.0, 2.0, -12.0],
+            fov: 45.,
+            near_clip: 1.,
+            far_clip: 100.,
+            speed: 10.0,
+            sensitivity: 1.0,
+            fps_sensitivity: 0.002,
+            invert_x: false,
+            invert_y: false,
+            sprint_multiplier: 4.0,
+            slow_multiplier: 0.2,
+            head_bob: true,
+            bob_amplitude: 0.05,
+            step_length: 1.5,
+            crouch_height: 0.6,
+            crouch_multiplier: 0.5,
+        }
+    }
+
+    // Falls back to the defaults if the file is missing or invalid
+    pub fn load(path: &str) -> Self {
+        let Ok(source) = std::fs::read_to_string(path) else {
+            return Self::new();
+        };
+
+        match toml::from_str(&source) {
+            Ok(settings) => settings,
+            Err(error) => {
+                log::warn!("Invalid camera settings in {}: {}", path, error);
+                Self::new()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        let result = toml::to_string(self)
+            .map_err(|error| error.to_string())
+            .and_then(|source| std::fs::write(path, source).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            log::warn!("Couldn't save camera settings to {}: {}", path, error);
+        }
+    }
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,688 @@
+use winit::{dpi::PhysicalSize, event::*};
+
+use crate::{camera::path::CameraPath, raytracing, render};
+
+// Format of the captured images, tonemapped like the swapchain
+const CAPTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Debug)]
+pub struct CaptureSettings {
+    // Samples accumulated into every captured frame
+    pub samples: u32,
+    pub frame_rate: f32,
+    pub directory: String,
+}
+
+impl CaptureSettings {
+    pub fn new() -> Self {
+        Self {
+            samples: 32,
+            frame_rate: 30.,
+            directory: "flythrough".to_string(),
+        }
+    }
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Variance-guided sampling of still images. Every pixel tracks the variance
+// of its luminance, and once min_samples are in only the blocks of the noise
+// mask with a pixel whose standard error is above noise_threshold times its
+// luminance are traced and accumulated again. Converged blocks drop out of
+// the tile lists, see raytracing::RaytracingSettings::adaptive_sampling.
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveSampling {
+    pub noise_threshold: f32,
+    pub min_samples: u32,
+}
+
+impl AdaptiveSampling {
+    pub fn new() -> Self {
+        Self {
+            noise_threshold: 0.02,
+            min_samples: 8,
+        }
+    }
+
+    // Whether sample only traces the noisy blocks, the mask needs a sample
+    // to be written first
+    pub fn active(&self, sample: u32) -> bool {
+        sample >= self.min_samples.max(1)
+    }
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AccumulateUniform {
+    size: [u32; 2],
+    sample_index: u32,
+    // Only pixels of blocks in the noise mask were traced
+    adaptive: u32,
+    // The noise mask is written after accumulating when above 0
+    noise_threshold: f32,
+    _padding: [u32; 3],
+}
+
+impl AccumulateUniform {
+    fn new() -> Self {
+        Self {
+            size: [0; 2],
+            sample_index: 0,
+            adaptive: 0,
+            noise_threshold: 0.,
+            _padding: [0; 3],
+        }
+    }
+}
+
+// Progress through the camera path, advanced once per rendered sample
+#[derive(Debug, Copy, Clone)]
+pub struct Flythrough {
+    pub frame: u32,
+    pub frame_count: u32,
+    pub sample: u32,
+}
+
+// Renders a camera path offline: every frame of the path accumulates
+// `samples` jittered frames and is written out as a numbered PNG
+pub struct CapturePipeline {
+    pub settings: CaptureSettings,
+    pub uniform: AccumulateUniform,
+    pub pipeline: wgpu::ComputePipeline,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub output: wgpu::Texture,
+    pub output_view: wgpu::TextureView,
+    pub history: wgpu::Texture,
+    // Luminance moments and sample count of every pixel, see accumulate.wgsl
+    pub moments: wgpu::Texture,
+    pub moments_history: wgpu::Texture,
+    // Writes raytracing::RaytracingPipeline::noise_mask from the moments
+    pub noise_pipeline: wgpu::ComputePipeline,
+    pub noise_bind_group: wgpu::BindGroup,
+    // Tonemaps the accumulated image into the capture target
+    pub render: render::RenderPipeline,
+    pub target: wgpu::Texture,
+    pub readback: wgpu::Buffer,
+    pub flythrough: Option<Flythrough>,
+}
+
+impl CapturePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        output_size: &PhysicalSize<u32>,
+        color_texture: &wgpu::TextureView,
+        noise_mask: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        tonemap_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> CapturePipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Accumulation shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/accumulate.wgsl").into()),
+        });
+
+        let settings = CaptureSettings::new();
+        let uniform = AccumulateUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Accumulation Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let extent = wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        };
+
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Accumulation output texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let output_view = output.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let history = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("Accumulation history texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let history_view = history.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let moments = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Accumulation moments texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let moments_view = moments.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let moments_history = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("Accumulation moments history texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let moments_history_view =
+            moments_history.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let storage_entry =
+            |binding: u32, format: wgpu::TextureFormat| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            };
+        let uniform_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                storage_entry(2, wgpu::TextureFormat::Rgba16Float),
+                uniform_entry(3),
+                texture_entry(4),
+                storage_entry(5, wgpu::TextureFormat::Rgba32Float),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("accumulation_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&moments_history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&moments_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(noise_mask),
+                },
+            ],
+            label: Some("accumulation_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Accumulation Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Accumulation pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let noise_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Noise mask shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/noise-mask.wgsl").into()),
+        });
+
+        let noise_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0),
+                    storage_entry(1, wgpu::TextureFormat::R32Uint),
+                    uniform_entry(2),
+                ],
+                label: Some("noise_mask_bind_group_layout"),
+            });
+
+        let noise_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &noise_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&moments_history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(noise_mask),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("noise_mask_bind_group"),
+        });
+
+        let noise_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Noise Mask Pipeline Layout"),
+                bind_group_layouts: &[&noise_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let noise_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Noise mask pipeline"),
+            layout: Some(&noise_pipeline_layout),
+            module: &noise_shader,
+            entry_point: "main",
+        });
+
+        let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
+        });
+
+        let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fragment shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/frag.wgsl").into()),
+        });
+
+        // Only the format is used when creating the pipeline
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: CAPTURE_FORMAT,
+            width: output_size.width,
+            height: output_size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+
+        let render = render::RenderPipeline::new(
+            device,
+            vert_shader,
+            frag_shader,
+            &config,
+            sampler,
+            &output_view,
+            tonemap_bind_group_layout,
+        );
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: output_size.width,
+                height: output_size.height,
+                depth_or_array_layers: 1,
+            },
+            format: CAPTURE_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Capture target texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Readback Buffer"),
+            size: (padded_bytes_per_row(output_size.width) * output_size.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        CapturePipeline {
+            settings,
+            uniform,
+            pipeline,
+            buffer,
+            bind_group,
+            output,
+            output_view,
+            history,
+            moments,
+            moments_history,
+            noise_pipeline,
+            noise_bind_group,
+            render,
+            target,
+            readback,
+            flythrough: None,
+        }
+    }
+
+    pub fn capturing(&self) -> bool {
+        self.flythrough.is_some()
+    }
+
+    pub fn start(&mut self, path: &CameraPath) {
+        if path.keyframes.is_empty() {
+            log::warn!("No camera path to capture");
+            return;
+        }
+        if let Err(error) = std::fs::create_dir_all(&self.settings.directory) {
+            log::warn!("Couldn't create {}: {}", self.settings.directory, error);
+            return;
+        }
+
+        let frame_count = (path.duration() * self.settings.frame_rate).ceil() as u32 + 1;
+        log::info!(
+            "Capturing {} frames with {} samples to {}",
+            frame_count,
+            self.settings.samples,
+            self.settings.directory
+        );
+        self.flythrough = Some(Flythrough {
+            frame: 0,
+            frame_count,
+            sample: 0,
+        });
+    }
+
+    pub fn stop(&mut self) {
+        if self.flythrough.take().is_some() {
+            log::info!("Capture stopped");
+        }
+    }
+
+    pub fn process_keyboard(
+        &mut self,
+        key: VirtualKeyCode,
+        state: ElementState,
+        path: &CameraPath,
+    ) -> bool {
+        match key {
+            VirtualKeyCode::F7 => {
+                if state == ElementState::Pressed {
+                    if self.capturing() {
+                        self.stop();
+                    } else {
+                        self.start(path);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Path time of the frame being captured. It only depends on the frame
+    // number, so captures don't depend on how long frames take to render.
+    pub fn time(&self) -> Option<f32> {
+        self.flythrough
+            .map(|flythrough| flythrough.frame as f32 / self.settings.frame_rate)
+    }
+
+    // Whether the sample rendered this frame completes a captured frame
+    pub fn last_sample(&self) -> bool {
+        self.flythrough
+            .is_some_and(|flythrough| flythrough.sample + 1 >= self.settings.samples)
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, render_size: PhysicalSize<u32>) {
+        if let Some(flythrough) = self.flythrough {
+            self.update_sample(queue, render_size, flythrough.sample, None);
+        }
+    }
+
+    // Sample 0 starts a new image, later ones are averaged into it. With
+    // adaptive sampling the ray tracing of the sample has to be limited to
+    // the noise mask when AdaptiveSampling::active.
+    pub fn update_sample(
+        &mut self,
+        queue: &wgpu::Queue,
+        render_size: PhysicalSize<u32>,
+        sample: u32,
+        adaptive: Option<AdaptiveSampling>,
+    ) {
+        self.uniform.size = [render_size.width, render_size.height];
+        self.uniform.sample_index = sample;
+        self.uniform.adaptive = adaptive.map_or(0, |adaptive| adaptive.active(sample) as u32);
+        self.uniform.noise_threshold = adaptive.map_or(0., |adaptive| adaptive.noise_threshold);
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Averages the current color into the accumulated image, then marks the
+    // blocks that are still noisy when sampling adaptively
+    pub fn accumulate(&self, encoder: &mut wgpu::CommandEncoder, render_size: PhysicalSize<u32>) {
+        {
+            let mut accumulate_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Accumulation pass"),
+            });
+
+            accumulate_pass.set_pipeline(&self.pipeline);
+            accumulate_pass.set_bind_group(0, &self.bind_group, &[]);
+            accumulate_pass.dispatch_workgroups(
+                render_size.width.div_ceil(16),
+                render_size.height.div_ceil(16),
+                1,
+            );
+        }
+        encoder.copy_texture_to_texture(
+            self.output.as_image_copy(),
+            self.history.as_image_copy(),
+            self.output.size(),
+        );
+        encoder.copy_texture_to_texture(
+            self.moments.as_image_copy(),
+            self.moments_history.as_image_copy(),
+            self.moments.size(),
+        );
+
+        if self.uniform.noise_threshold > 0. {
+            let mut noise_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Noise mask pass"),
+            });
+            noise_pass.set_pipeline(&self.noise_pipeline);
+            noise_pass.set_bind_group(0, &self.noise_bind_group, &[]);
+            let blocks = raytracing::noise_mask_size(&render_size);
+            noise_pass.dispatch_workgroups(blocks.width.div_ceil(8), blocks.height.div_ceil(8), 1);
+        }
+    }
+
+    // Tonemaps the accumulated image into the target and copies it into the
+    // readback buffer
+    pub fn resolve(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        tonemap_bind_group: &wgpu::BindGroup,
+    ) {
+        let target_view = self
+            .target
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        {
+            let mut capture_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Capture Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            capture_pass.set_pipeline(&self.render.pipeline);
+            capture_pass.set_bind_group(0, &self.render.bind_group, &[]);
+            capture_pass.set_bind_group(1, tonemap_bind_group, &[]);
+            capture_pass.draw(0..3, 0..1);
+        }
+        self.copy_target(encoder);
+    }
+
+    // Copies the tonemapped capture target into the readback buffer
+    pub fn copy_target(&self, encoder: &mut wgpu::CommandEncoder) {
+        let size = self.target.size();
+        encoder.copy_texture_to_buffer(
+            self.target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row(size.width)),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+    }
+
+    // Called after submitting the frame. Writes out the captured frame once
+    // all of its samples are accumulated and moves on to the next one.
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        let last_sample = self.last_sample();
+        let Some(flythrough) = &mut self.flythrough else {
+            return;
+        };
+
+        if !last_sample {
+            flythrough.sample += 1;
+            return;
+        }
+
+        let path = format!(
+            "{}/frame_{:05}.png",
+            self.settings.directory, flythrough.frame
+        );
+        if let Err(error) = save_png(device, &self.readback, self.target.size(), &path) {
+            log::warn!("Couldn't save {}: {}", path, error);
+        }
+
+        flythrough.sample = 0;
+        flythrough.frame += 1;
+        if flythrough.frame >= flythrough.frame_count {
+            log::info!(
+                "Captured {} frames to {}",
+                flythrough.frame_count,
+                self.settings.directory
+            );
+            self.flythrough = None;
+        }
+    }
+}
+
+// Rows of a texture to buffer copy have to be aligned to 256 bytes
+pub(crate) fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+pub(crate) fn save_png(
+    device: &wgpu::Device,
+    buffer: &wgpu::Buffer,
+    size: wgpu::Extent3d,
+    path: &str,
+) -> Result<(), String> {
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| ());
+    device.poll(wgpu::Maintain::Wait);
+
+    let pixels = read_pixels(buffer, size);
+    write_png(&pixels, size, path)
+}
+
+// Copies the rows of a mapped readback buffer without their padding and
+// unmaps it
+pub(crate) fn read_pixels(buffer: &wgpu::Buffer, size: wgpu::Extent3d) -> Vec<u8> {
+    let pixels = unpad_rows(&buffer.slice(..).get_mapped_range(), size);
+    buffer.unmap();
+    pixels
+}
+
+// The rows of a copied texture without their padding
+pub(crate) fn unpad_rows(data: &[u8], size: wgpu::Extent3d) -> Vec<u8> {
+    let padded_row = padded_bytes_per_row(size.width) as usize;
+    let row = size.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row * size.height as usize);
+    for y in 0..size.height as usize {
+        pixels.extend_from_slice(&data[y * padded_row..y * padded_row + row]);
+    }
+    pixels
+}
+
+pub(crate) fn write_png(pixels: &[u8], size: wgpu::Extent3d, path: &str) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|error| error.to_string())?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|error| error.to_string())
+}
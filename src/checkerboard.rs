@@ -0,0 +1,334 @@
+use serde::Deserialize;
+use winit::{dpi::PhysicalSize, event::*};
+
+// Which half of the pixels is traced on alternating frames, must match the
+// INTERLEAVE_* flags in ray-tracing.wgsl and PATTERN_* in checkerboard.wgsl
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    Checkerboard,
+    // Every other row, reconstructed from the rows above and below. Cheaper
+    // to trace than a checkerboard as whole rows of a workgroup stay coherent.
+    Rows,
+    Columns,
+}
+
+impl Pattern {
+    // Pixels of size traced per frame, what the ray tracing pass is
+    // dispatched over
+    pub fn traced_size(self, size: PhysicalSize<u32>) -> PhysicalSize<u32> {
+        match self {
+            Pattern::Checkerboard | Pattern::Columns => {
+                PhysicalSize::new(size.width.div_ceil(2), size.height)
+            }
+            Pattern::Rows => PhysicalSize::new(size.width, size.height.div_ceil(2)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CheckerboardSettings {
+    // Trace half of the pixels every frame and reconstruct the rest from the
+    // previous frame
+    pub enabled: bool,
+    pub pattern: Pattern,
+    // Trace the center of the screen at full rate and checkerboard only the
+    // periphery, for high resolution displays and headsets
+    pub foveated: bool,
+    // Width and height of the fully traced center as a fraction of the screen
+    pub fovea_size: f32,
+}
+
+impl CheckerboardSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            pattern: Pattern::Checkerboard,
+            foveated: false,
+            fovea_size: 0.5,
+        }
+    }
+
+    // Whether any pixels are left out, foveated rendering checkerboards the
+    // periphery
+    pub fn active(&self) -> bool {
+        self.enabled || self.foveated
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::I => {
+                if state == ElementState::Pressed {
+                    self.enabled = !self.enabled;
+                    log::info!("Checkerboard rendering: {}", self.enabled);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for CheckerboardSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CheckerboardUniform {
+    size: [u32; 2],
+    history_size: [u32; 2],
+    enabled: u32,
+    // Pixels with (x + y + parity) even were traced this frame, or with
+    // (y + parity) even for rows and (x + parity) even for columns
+    parity: u32,
+    reset: u32,
+    pattern: u32,
+    // Pixels from xy up to zw are all traced
+    fovea: [u32; 4],
+}
+
+impl CheckerboardUniform {
+    fn new() -> Self {
+        Self {
+            size: [0; 2],
+            history_size: [0; 2],
+            enabled: 0,
+            parity: 0,
+            reset: 1,
+            pattern: 0,
+            fovea: [0; 4],
+        }
+    }
+}
+
+pub struct CheckerboardPipeline {
+    pub settings: CheckerboardSettings,
+    pub uniform: CheckerboardUniform,
+    pub pipeline: wgpu::ComputePipeline,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub output: wgpu::Texture,
+    pub output_view: wgpu::TextureView,
+    pub history: wgpu::Texture,
+    pub frame: u32,
+    pub size: PhysicalSize<u32>,
+    // The history is invalid on the first frame after enabling
+    was_enabled: bool,
+}
+
+impl CheckerboardPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        color_texture: &wgpu::TextureView,
+        motion_texture: &wgpu::TextureView,
+    ) -> CheckerboardPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Checkerboard reconstruction shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/checkerboard.wgsl").into()),
+        });
+
+        let settings = CheckerboardSettings::new();
+        let uniform = CheckerboardUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Checkerboard Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let extent = wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        };
+
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Checkerboard output texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let output_view = output.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let history = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("Checkerboard history texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let history_view = history.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let history_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Checkerboard history sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding: u32, filterable: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, false),
+                texture_entry(1, false),
+                texture_entry(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("checkerboard_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(motion_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&history_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("checkerboard_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Checkerboard Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Checkerboard reconstruction pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        CheckerboardPipeline {
+            settings,
+            uniform,
+            pipeline,
+            buffer,
+            bind_group,
+            output,
+            output_view,
+            history,
+            frame: 0,
+            size: *size,
+            was_enabled: false,
+        }
+    }
+
+    // Which half of the pixels the ray tracing pass traces this frame, None
+    // when every pixel is traced
+    pub fn parity(&self) -> Option<(u32, Pattern)> {
+        self.settings
+            .active()
+            .then_some((self.frame & 1, self.settings.pattern))
+    }
+
+    // The centered pixels the ray tracing pass also traces at the other
+    // parity with foveated rendering, from xy up to zw. Starts on an even
+    // row and column, see fovea in ray-tracing.wgsl.
+    pub fn fovea(&self, render_size: PhysicalSize<u32>) -> Option<[u32; 4]> {
+        if !self.settings.foveated {
+            return None;
+        }
+        let size = self.settings.fovea_size.clamp(0., 1.);
+        let extent = |length: u32| {
+            let inner = (length as f32 * size) as u32;
+            let start = (length - inner) / 2;
+            (start, start + inner)
+        };
+        let (left, right) = extent(render_size.width);
+        let (top, bottom) = extent(render_size.height);
+        Some([left & !1, top & !1, right, bottom])
+    }
+
+    // Must run before the ray tracing uniform is updated with parity()
+    pub fn update(&mut self, queue: &wgpu::Queue, render_size: PhysicalSize<u32>) {
+        self.frame = self.frame.wrapping_add(1);
+        self.uniform.history_size = [self.size.width, self.size.height];
+        self.size = render_size;
+
+        self.uniform.size = [self.size.width, self.size.height];
+        self.uniform.enabled = self.settings.active() as u32;
+        self.uniform.parity = self.frame & 1;
+        self.uniform.reset = (!self.was_enabled) as u32;
+        self.uniform.pattern = self.settings.pattern as u32;
+        self.uniform.fovea = self.fovea(render_size).unwrap_or([0; 4]);
+        self.was_enabled = self.settings.active();
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    camera::settings::CameraSettings,
+    checkerboard::Pattern,
+    keybindings::Action,
+    present::PresentMode,
+    raytracing::{Traversal, WorkgroupSize},
+    watcher::FileWatcher,
+};
+
+pub const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub vsync: Option<bool>,
+    // Takes precedence over vsync
+    pub present_mode: Option<PresentMode>,
+}
+
+// Missing fields keep what is currently set, so keys toggled at runtime
+// aren't reset by unrelated edits
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    pub render_scale: Option<f32>,
+    pub dynamic_resolution: Option<bool>,
+    // Milliseconds, for dynamic resolution
+    pub target_frame_time: Option<f32>,
+    pub supersampling: Option<bool>,
+    pub checkerboard: Option<bool>,
+    // Pixels traced on alternating frames with checkerboard = true,
+    // "checkerboard", "rows" or "columns"
+    pub interleave_pattern: Option<Pattern>,
+    // Checkerboards only the periphery, fovea_size is the fraction of the
+    // screen traced at full rate
+    pub foveated: Option<bool>,
+    pub fovea_size: Option<f32>,
+    pub reprojection: Option<bool>,
+    pub taa: Option<bool>,
+    pub motion_blur: Option<bool>,
+    pub beam_optimization: Option<bool>,
+    pub half_res_lighting: Option<bool>,
+    pub shadows: Option<bool>,
+    pub wavefront: Option<bool>,
+    pub tiled_dispatch: Option<bool>,
+    pub sky_tiles: Option<bool>,
+    // "8x8", "16x16" or "8x4", picked by timing them at startup if left out
+    pub workgroup_size: Option<WorkgroupSize>,
+    // "descend" or "stackless"
+    pub traversal: Option<Traversal>,
+    // Limits of the traversal, see raytracing::RenderSettings
+    pub max_distance: Option<f32>,
+    pub max_steps: Option<u32>,
+    pub lod_bias: Option<f32>,
+    pub chunk_culling: Option<bool>,
+    pub occlusion_culling: Option<bool>,
+    // In chunks
+    pub view_distance: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    // GPU memory that streaming may fill, unlimited if left out
+    pub budget_mb: Option<u64>,
+    // Data streamed to the GPU per frame, 32 if left out
+    pub upload_budget_mb: Option<u64>,
+    pub max_resident_chunks: Option<usize>,
+}
+
+// Startup configuration, reloaded while running when the file changes, e.g.
+//
+// [window]
+// width = 1600
+// height = 900
+// vsync = false
+// present_mode = "mailbox"
+//
+// [camera]
+// fov = 60.0
+//
+// [bindings]
+// move_forward = ["Z", "Up"]
+//
+// [quality]
+// render_scale = 0.75
+// half_res_lighting = true
+// workgroup_size = "8x8"
+//
+// [memory]
+// budget_mb = 2048
+// upload_budget_mb = 16
+// max_resident_chunks = 16384
+//
+// Without a [camera] or [bindings] table camera.toml and keybindings.toml
+// are used.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub camera: Option<CameraSettings>,
+    pub bindings: Option<HashMap<Action, Vec<VirtualKeyCode>>>,
+    pub quality: QualityConfig,
+    pub memory: MemoryConfig,
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        toml::from_str(&source).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    // Falls back to the defaults if the file is missing or invalid
+    pub fn load(path: &str) -> Self {
+        if std::fs::metadata(path).is_err() {
+            return Self::default();
+        }
+
+        Self::from_file(path).unwrap_or_else(|error| {
+            log::warn!("Invalid config: {}", error);
+            Self::default()
+        })
+    }
+}
+
+// Reloads the config file when it changes
+pub struct ConfigWatcher {
+    watcher: FileWatcher,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &str) -> Self {
+        Self {
+            watcher: FileWatcher::new(path),
+        }
+    }
+
+    // The new config if the file changed and is valid. An invalid file keeps
+    // the current config until it is fixed.
+    pub fn poll(&mut self, dt: f32) -> Option<Config> {
+        if !self.watcher.poll(dt) {
+            return None;
+        }
+
+        match Config::from_file(&self.watcher.path) {
+            Ok(config) => {
+                log::info!("Reloaded {}", self.watcher.path);
+                Some(config)
+            }
+            Err(error) => {
+                log::warn!("Invalid config: {}", error);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_table() {
+        let config: Config = toml::from_str(
+            r#"
+            [window]
+            width = 1600
+            height = 900
+            vsync = false
+            present_mode = "mailbox"
+
+            [camera]
+            fov = 60.0
+
+            [bindings]
+            move_forward = ["Z", "Up"]
+
+            [quality]
+            render_scale = 0.75
+            half_res_lighting = true
+            workgroup_size = "8x8"
+            traversal = "stackless"
+
+            [memory]
+            budget_mb = 2048
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.window,
+            WindowConfig {
+                width: Some(1600),
+                height: Some(900),
+                vsync: Some(false),
+                present_mode: Some(PresentMode::Mailbox),
+            }
+        );
+        assert_eq!(config.camera.unwrap().fov, 60.);
+        assert_eq!(
+            config.bindings.unwrap()[&Action::MoveForward],
+            [VirtualKeyCode::Z, VirtualKeyCode::Up]
+        );
+        assert_eq!(config.quality.render_scale, Some(0.75));
+        assert_eq!(config.quality.half_res_lighting, Some(true));
+        assert_eq!(config.quality.workgroup_size, Some(WorkgroupSize::Size8x8));
+        assert_eq!(config.quality.traversal, Some(Traversal::Stackless));
+        assert_eq!(config.memory.budget_mb, Some(2048));
+    }
+
+    #[test]
+    fn missing_keys_are_left_unset() {
+        let config: Config = toml::from_str("[quality]\ntaa = false").unwrap();
+        assert_eq!(config.window, WindowConfig::default());
+        assert!(config.camera.is_none() && config.bindings.is_none());
+        assert_eq!(config.quality.taa, Some(false));
+        assert_eq!(config.quality.shadows, None);
+        assert_eq!(config.memory.upload_budget_mb, None);
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        assert!(toml::from_str::<Config>("[quality]\nworkgroup_size = \"7x7\"").is_err());
+        assert!(toml::from_str::<Config>("[window]\nwidth = \"wide\"").is_err());
+        assert!(Config::from_file("missing-config.toml").is_err());
+        assert!(Config::load("missing-config.toml").camera.is_none());
+    }
+}
@@ -0,0 +1,202 @@
+use nalgebra::Point3;
+use winit::{event::*, window::Window};
+
+use crate::world::Sun;
+
+const HELP: &str = "Commands: tp x y z, look x y z, load file.vox, \
+                    set sun azimuth elevation, set time hours, set voxel x y z material, \
+                    set fov degrees, set speed units, connect ws://host:port, help";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Teleport(Point3<f32>),
+    LookAt(Point3<f32>),
+    // MagicaVoxel file replacing the world
+    Load(String),
+    SetSun(Sun),
+    // Hours since midnight, moves the sun
+    SetTime(f32),
+    // Palette index, 0 removes the voxel
+    SetVoxel(Point3<i32>, u8),
+    SetFov(f32),
+    SetSpeed(f32),
+    // WebSocket url of a world server
+    Connect(String),
+    Help,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["tp", x, y, z] => Ok(Command::Teleport(Point3::new(
+                number(x)?,
+                number(y)?,
+                number(z)?,
+            ))),
+            ["look", x, y, z] => Ok(Command::LookAt(Point3::new(
+                number(x)?,
+                number(y)?,
+                number(z)?,
+            ))),
+            ["load", path] => Ok(Command::Load(path.to_string())),
+            ["set", "sun", azimuth, elevation] => Ok(Command::SetSun(Sun {
+                azimuth: number(azimuth)?,
+                elevation: number(elevation)?,
+            })),
+            ["set", "time", hours] => Ok(Command::SetTime(number(hours)?)),
+            ["set", "voxel", x, y, z, material] => Ok(Command::SetVoxel(
+                Point3::new(integer(x)?, integer(y)?, integer(z)?),
+                material
+                    .parse()
+                    .map_err(|_| format!("Invalid material: {}", material))?,
+            )),
+            ["set", "fov", fov] => Ok(Command::SetFov(number(fov)?)),
+            ["set", "speed", speed] => Ok(Command::SetSpeed(number(speed)?)),
+            ["connect", url] => Ok(Command::Connect(url.to_string())),
+            ["help"] => Ok(Command::Help),
+            _ => Err(format!("Unknown command: {}. {}", line.trim(), HELP)),
+        }
+    }
+
+    pub fn help() -> &'static str {
+        HELP
+    }
+}
+
+fn number(word: &str) -> Result<f32, String> {
+    word.parse()
+        .ok()
+        .filter(|value: &f32| value.is_finite())
+        .ok_or_else(|| format!("Invalid number: {}", word))
+}
+
+fn integer(word: &str) -> Result<i32, String> {
+    word.parse()
+        .map_err(|_| format!("Invalid integer: {}", word))
+}
+
+// Command line toggled with the backtick key. While it is open every key goes
+// to the line being typed instead of the camera and render bindings. Without
+// text rendering the line is shown in the window title and results are
+// logged.
+pub struct Console {
+    pub open: bool,
+    line: String,
+    history: Vec<String>,
+    // Position while browsing the history, history.len() is the new line
+    history_index: usize,
+    // Entered lines waiting to be run
+    submitted: Vec<String>,
+    // Restored when the console closes
+    title: String,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            line: String::new(),
+            history: Vec::new(),
+            history_index: 0,
+            submitted: Vec::new(),
+            title: String::new(),
+        }
+    }
+
+    // True if the console used the event
+    pub fn input(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                if *state == ElementState::Pressed {
+                    self.process_key(window, *key);
+                }
+                self.open || *key == VirtualKeyCode::Grave
+            }
+            WindowEvent::ReceivedCharacter(character) if self.open => {
+                // The backtick that opened the console arrives after it is open
+                if !character.is_control() && *character != '`' {
+                    self.line.push(*character);
+                    self.show(window);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn process_key(&mut self, window: &Window, key: VirtualKeyCode) {
+        match key {
+            VirtualKeyCode::Grave => {
+                if self.open {
+                    self.close(window);
+                } else {
+                    self.open = true;
+                    self.title = window.title();
+                    self.history_index = self.history.len();
+                    self.show(window);
+                }
+            }
+            _ if !self.open => {}
+            VirtualKeyCode::Escape => self.close(window),
+            VirtualKeyCode::Back => {
+                self.line.pop();
+                self.show(window);
+            }
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                let line = std::mem::take(&mut self.line);
+                if !line.trim().is_empty() {
+                    if self.history.last() != Some(&line) {
+                        self.history.push(line.clone());
+                    }
+                    self.submitted.push(line);
+                }
+                self.history_index = self.history.len();
+                self.show(window);
+            }
+            VirtualKeyCode::Up | VirtualKeyCode::Down => {
+                if key == VirtualKeyCode::Up {
+                    self.history_index = self.history_index.saturating_sub(1);
+                } else {
+                    self.history_index = (self.history_index + 1).min(self.history.len());
+                }
+                self.line = self
+                    .history
+                    .get(self.history_index)
+                    .cloned()
+                    .unwrap_or_default();
+                self.show(window);
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, window: &Window) {
+        self.open = false;
+        self.line.clear();
+        window.set_title(&self.title);
+    }
+
+    fn show(&self, window: &Window) {
+        window.set_title(&format!("> {}_", self.line));
+    }
+
+    // Lines entered since the last call, oldest first
+    pub fn take_submitted(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.submitted)
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
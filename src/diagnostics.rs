@@ -0,0 +1,118 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+// Errors kept for the overlay, the oldest is dropped first
+const MAX_ERRORS: usize = 8;
+
+// How wgpu-core words a lost device, wgpu 0.16 has no callback for it
+const DEVICE_LOST: &str = "device is lost";
+
+#[derive(Default)]
+struct Reported {
+    // Message and how many times in a row it was reported
+    errors: VecDeque<(String, u32)>,
+    device_lost: bool,
+}
+
+// Validation and out of memory errors of the device, logged and kept for the
+// overlay instead of panicking like wgpu does by default. Frames run inside
+// error scopes so their errors are reported with a label, anything else
+// reaches the uncaptured error handler. A lost device shows up as one of
+// these errors and is recreated by State.
+#[derive(Clone, Default)]
+pub struct GpuDiagnostics {
+    reported: Arc<Mutex<Reported>>,
+}
+
+impl GpuDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Has to be called again for a recreated device
+    pub fn install(&self, device: &wgpu::Device) {
+        let diagnostics = self.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            diagnostics.report("Uncaptured", &error)
+        }));
+    }
+
+    // Error scopes can't be waited on on the web, there everything goes to
+    // the uncaptured error handler
+    pub fn push_scope(&self, device: &wgpu::Device) {
+        if cfg!(not(target_arch = "wasm32")) {
+            device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+        }
+    }
+
+    // Pops the scopes of push_scope, label names the work inside them
+    pub fn pop_scope(&self, device: &wgpu::Device, label: &str) {
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        for _ in 0..2 {
+            if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+                self.report(label, &error);
+            }
+        }
+    }
+
+    // Errors repeated every frame are only logged the first time
+    pub fn report(&self, label: &str, error: &wgpu::Error) {
+        let message = format!("{}: {}", label, error);
+        let mut reported = self.reported.lock().unwrap();
+        if is_device_lost(error) {
+            reported.device_lost = true;
+        }
+        if let Some((last, count)) = reported.errors.back_mut() {
+            if *last == message {
+                *count += 1;
+                return;
+            }
+        }
+        log::error!("{}", message);
+        if reported.errors.len() == MAX_ERRORS {
+            reported.errors.pop_front();
+        }
+        reported.errors.push_back((message, 1));
+    }
+
+    pub fn device_lost(&self) -> bool {
+        self.reported.lock().unwrap().device_lost
+    }
+
+    // The device was recreated, errors of the old one no longer apply
+    pub fn recovered(&self) {
+        let mut reported = self.reported.lock().unwrap();
+        reported.device_lost = false;
+        reported.errors.clear();
+    }
+
+    // Oldest first, with the repeat count of errors reported more than once
+    pub fn errors(&self) -> Vec<String> {
+        self.reported
+            .lock()
+            .unwrap()
+            .errors
+            .iter()
+            .map(|(message, count)| match count {
+                1 => message.clone(),
+                _ => format!("{} (x{})", message, count),
+            })
+            .collect()
+    }
+}
+
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if error.to_string().contains(DEVICE_LOST) {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
@@ -0,0 +1,252 @@
+use winit::{dpi::PhysicalSize, event::*};
+
+// Byte offset of `exposure` inside the GPU side ExposureState
+pub const EXPOSURE_OFFSET: wgpu::BufferAddress = 4;
+
+#[derive(Debug)]
+pub struct ExposureSettings {
+    pub enabled: bool,
+    // Average scene luminance is mapped to this value (middle grey)
+    pub key_value: f32,
+    pub adaptation_speed: f32,
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+}
+
+impl ExposureSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            key_value: 0.18,
+            adaptation_speed: 1.5,
+            min_exposure: 0.05,
+            max_exposure: 20.,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::X => {
+                if state == ElementState::Pressed {
+                    self.enabled = !self.enabled;
+                    log::info!("Auto exposure: {}", self.enabled);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ExposureUniform {
+    dt: f32,
+    speed: f32,
+    key_value: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+    compensation: f32,
+    size: [u32; 2],
+}
+
+impl ExposureUniform {
+    fn new() -> Self {
+        Self {
+            dt: 0.,
+            speed: 0.,
+            key_value: 0.18,
+            min_exposure: 1.,
+            max_exposure: 1.,
+            compensation: 1.,
+            size: [0; 2],
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        settings: &ExposureSettings,
+        dt: f32,
+        compensation: f32,
+        size: PhysicalSize<u32>,
+    ) {
+        self.dt = dt;
+        self.speed = settings.adaptation_speed;
+        self.key_value = settings.key_value;
+        self.min_exposure = settings.min_exposure;
+        self.max_exposure = settings.max_exposure;
+        self.compensation = compensation;
+        self.size = [size.width, size.height];
+    }
+}
+
+pub struct ExposurePipeline {
+    pub settings: ExposureSettings,
+    pub uniform: ExposureUniform,
+    pub reduce_pipeline: wgpu::ComputePipeline,
+    pub adapt_pipeline: wgpu::ComputePipeline,
+    pub buffer: wgpu::Buffer,
+    pub partials: wgpu::Buffer,
+    pub state: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ExposurePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        hdr_texture: &wgpu::TextureView,
+    ) -> ExposurePipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Luminance shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/luminance.wgsl").into()),
+        });
+
+        let settings = ExposureSettings::new();
+        let uniform = ExposureUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Exposure Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        // One partial sum per 16x16 tile of the HDR image
+        let partials = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance partial sums"),
+            size: (size.width.div_ceil(16) * size.height.div_ceil(16)) as u64 * 8,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let state = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Exposure state"),
+                contents: bytemuck::cast_slice(&[1f32, 1., 0., 0.]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            },
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("exposure_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: partials.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: state.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("exposure_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let reduce_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Luminance reduce pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "reduce",
+        });
+
+        let adapt_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Exposure adapt pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "adapt",
+        });
+
+        ExposurePipeline {
+            settings,
+            uniform,
+            reduce_pipeline,
+            adapt_pipeline,
+            buffer,
+            partials,
+            state,
+            bind_group,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        dt: instant::Duration,
+        compensation: f32,
+        size: PhysicalSize<u32>,
+    ) {
+        self.uniform
+            .update(&self.settings, dt.as_secs_f32(), compensation, size);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
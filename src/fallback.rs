@@ -0,0 +1,330 @@
+use std::iter;
+
+use winit::{event::*, window::Window};
+
+use crate::{
+    app, camera, input,
+    overlay::{ChunkBounds, OverlaySettings},
+    preprocess,
+    raytracing::{RaytracingSettings, RaytracingUniform},
+    touch,
+    window::{GpuContext, InitError},
+    world,
+};
+
+// Renders on devices without compute shaders, i.e. WebGL2. The ray tracing
+// shader is built with FRAGMENT_FALLBACK and traces every pixel in a
+// fragment shader straight to the surface. There are no post passes, so only
+// the camera can be controlled.
+pub struct FallbackState {
+    surface: wgpu::Surface,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    pub size: winit::dpi::PhysicalSize<u32>,
+    pub camera: camera::CameraPipeline,
+    pub settings: RaytracingSettings,
+    uniform: RaytracingUniform,
+    buffer: wgpu::Buffer,
+    render_settings_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    pub input: input::Input,
+    touch: touch::TouchController,
+    // The terrain the camera collides with, the fallback shader draws
+    // neither grids nor edits
+    world: world::World,
+    // Declared last so the surface is dropped before its window
+    window: Window,
+}
+
+impl FallbackState {
+    // context has to be created for this window
+    pub fn new(window: Window, context: GpuContext) -> Result<Self, InitError> {
+        let GpuContext {
+            surface,
+            adapter,
+            device,
+            queue,
+            ..
+        } = context;
+        let size = window.inner_size();
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        let format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .or_else(|| surface_caps.formats.first().copied())
+            .ok_or(InitError::UnsupportedSurface)?;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(&device, &config);
+        log::info!("Compute shaders aren't supported, ray tracing in a fragment shader");
+
+        let camera = camera::CameraPipeline::new(&device);
+        let settings = RaytracingSettings::new();
+        let uniform = RaytracingUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Fallback ray tracing Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        let render_settings_buffer = wgpu::util::DeviceExt::create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Fallback render settings Buffer"),
+                contents: bytemuck::cast_slice(&[settings.render]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let uniform_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(5), uniform_entry(16)],
+            label: Some("fallback bind group layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fallback bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: render_settings_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
+        });
+        let frag_shader = preprocess::shader_module(
+            &device,
+            "Fallback ray tracing shader",
+            include_str!("shaders/ray-tracing.wgsl"),
+            &[("FRAGMENT_FALLBACK", ""), ("SHADOWS", "")],
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fallback Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &camera.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fallback Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vert_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &frag_shader,
+                entry_point: "fallback_fragment",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            surface,
+            device,
+            queue,
+            config,
+            size,
+            camera,
+            settings,
+            uniform,
+            buffer,
+            render_settings_buffer,
+            bind_group,
+            pipeline,
+            input: input::Input::new(),
+            touch: touch::TouchController::new(),
+            world: world::World::default(),
+            window,
+        })
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+        }
+    }
+
+    // Camera controls only
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                self.input.process_key(*key, *state);
+                self.camera.controller.bindings.action(*key).is_some()
+            }
+            #[cfg(target_arch = "wasm32")]
+            WindowEvent::Touch(touch) => {
+                self.touch.process_touch(touch);
+                true
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.input.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.input.process_scroll(camera::scroll_steps(delta));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn update(&mut self, dt: instant::Duration) {
+        self.camera
+            .controller
+            .process_input(&self.input, &mut self.camera.camera);
+        self.input.end_frame();
+        self.touch.apply(&mut self.camera.controller);
+        self.camera.controller.update_camera(
+            &mut self.camera.camera,
+            &self.world,
+            dt,
+            &mut self.camera.uniform,
+        );
+        self.camera.uniform.update_view_proj(
+            &self.camera.camera,
+            self.size.width,
+            self.size.height,
+            [0., 0.],
+        );
+        self.queue.write_buffer(
+            &self.camera.buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera.uniform]),
+        );
+
+        let overlay = OverlaySettings {
+            crosshair: false,
+            highlight_picked: false,
+            chunk_bounds: ChunkBounds::Off,
+            grid: false,
+        };
+        self.uniform.update(&self.settings, &overlay);
+        self.queue
+            .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+        self.queue.write_buffer(
+            &self.render_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.settings.render]),
+        );
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Fallback Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Fallback Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_bind_group(1, &self.camera.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        self.queue.submit(iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+}
+
+impl app::App for FallbackState {
+    fn window(&self) -> &Window {
+        FallbackState::window(self)
+    }
+
+    fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        FallbackState::input(self, event)
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        FallbackState::resize(self, new_size)
+    }
+
+    fn update(&mut self, dt: instant::Duration) {
+        FallbackState::update(self, dt)
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        FallbackState::render(self)
+    }
+}
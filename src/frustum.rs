@@ -0,0 +1,113 @@
+use nalgebra::{Matrix4, Vector4};
+use winit::event::*;
+
+use crate::{camera::Camera, grid::LineVertex};
+
+// Twelve edges of the frustum box
+const VERTEX_COUNT: u32 = 24;
+const FRUSTUM_COLOR: [f32; 4] = [1., 0.8, 0.2, 1.];
+
+// Freezes the camera used for culling and LOD selection so the frozen frustum
+// can be inspected from outside while the view camera keeps moving. The frozen
+// frustum is drawn with the grid pipeline.
+pub struct FrustumFreeze {
+    pub frozen: Option<Camera>,
+    // Aspect ratio of the render region when the camera was frozen
+    aspect: f32,
+    pub vertex_buffer: wgpu::Buffer,
+}
+
+impl FrustumFreeze {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frustum vertex buffer"),
+            size: (VERTEX_COUNT as usize * std::mem::size_of::<LineVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            frozen: None,
+            aspect: 1.,
+            vertex_buffer,
+        }
+    }
+
+    // The vertices are written again in update
+    pub fn recreate(&mut self, device: &wgpu::Device) {
+        self.vertex_buffer = Self::new(device).vertex_buffer;
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        VERTEX_COUNT
+    }
+
+    pub fn process_keyboard(
+        &mut self,
+        key: VirtualKeyCode,
+        state: ElementState,
+        camera: &Camera,
+        aspect: f32,
+    ) -> bool {
+        match key {
+            VirtualKeyCode::F8 => {
+                if state == ElementState::Pressed {
+                    self.frozen = match self.frozen {
+                        Some(_) => None,
+                        None => Some(camera.clone()),
+                    };
+                    self.aspect = aspect;
+                    log::info!("Frustum frozen: {}", self.frozen.is_some());
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Camera that culling and LOD selection should use
+    pub fn culling_camera<'a>(&'a self, camera: &'a Camera) -> &'a Camera {
+        self.frozen.as_ref().unwrap_or(camera)
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let Some(camera) = &self.frozen else {
+            return;
+        };
+        // Only the aspect ratio matters to the frustum shape
+        let height = 1000;
+        let width = (height as f32 * self.aspect) as u32;
+        let vertices = frustum_lines(camera, width, height);
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+}
+
+// Corners of the clip space box moved back into world space
+fn frustum_lines(camera: &Camera, width: u32, height: u32) -> Vec<LineVertex> {
+    let inverse =
+        Matrix4::try_inverse(camera.calc_view_proj(width, height)).unwrap_or(Matrix4::identity());
+    let eye = camera.eye();
+
+    // view_proj produces OpenGL style -1..1 depth
+    let corner = |x: f32, y: f32, z: f32| {
+        let p = inverse * Vector4::new(x, y, z, 1.);
+        let p = p.xyz() / p.w + eye.coords;
+        LineVertex {
+            position: p.into(),
+            color: FRUSTUM_COLOR,
+        }
+    };
+
+    let mut vertices = Vec::with_capacity(VERTEX_COUNT as usize);
+    let square = [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)];
+    for i in 0..4 {
+        let (x0, y0) = square[i];
+        let (x1, y1) = square[(i + 1) % 4];
+        // Near and far rectangles
+        vertices.extend([corner(x0, y0, -1.), corner(x1, y1, -1.)]);
+        vertices.extend([corner(x0, y0, 1.), corner(x1, y1, 1.)]);
+        // Side edges
+        vertices.extend([corner(x0, y0, -1.), corner(x0, y0, 1.)]);
+    }
+    vertices
+}
@@ -0,0 +1,84 @@
+// Adapter chosen with --gpu, by its index in list_adapters or by part of its
+// name, e.g. --gpu 1 or --gpu nvidia
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpuSelection {
+    Index(usize),
+    Name(String),
+}
+
+impl GpuSelection {
+    pub fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => GpuSelection::Index(index),
+            Err(_) => GpuSelection::Name(value.to_lowercase()),
+        }
+    }
+
+    fn matches(&self, index: usize, info: &wgpu::AdapterInfo) -> bool {
+        match self {
+            GpuSelection::Index(selected) => *selected == index,
+            GpuSelection::Name(name) => info.name.to_lowercase().contains(name.as_str()),
+        }
+    }
+}
+
+// Every adapter of every backend, in the order used by GpuSelection::Index.
+// Browsers only hand out one adapter on request, so the web has none.
+pub fn list_adapters(instance: &wgpu::Instance) -> Vec<wgpu::AdapterInfo> {
+    adapters(instance)
+        .iter()
+        .map(wgpu::Adapter::get_info)
+        .collect()
+}
+
+// One line per adapter for --list-gpus
+pub fn describe(index: usize, info: &wgpu::AdapterInfo) -> String {
+    format!(
+        "{}: {} ({:?}, {:?})",
+        index, info.name, info.backend, info.device_type
+    )
+}
+
+// The selected adapter, if it exists and can render to the surface
+pub fn select_adapter(
+    instance: &wgpu::Instance,
+    selection: &GpuSelection,
+    compatible_surface: Option<&wgpu::Surface>,
+) -> Option<wgpu::Adapter> {
+    let adapter = adapters(instance)
+        .into_iter()
+        .enumerate()
+        .find(|(index, adapter)| selection.matches(*index, &adapter.get_info()))
+        .map(|(_, adapter)| adapter)?;
+
+    if let Some(surface) = compatible_surface {
+        if !adapter.is_surface_supported(surface) {
+            log::warn!("{} can't render to the window", adapter.get_info().name);
+            return None;
+        }
+    }
+    Some(adapter)
+}
+
+// Every adapter that can render to the surface, if there is one
+pub fn compatible_adapters(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+) -> Vec<wgpu::Adapter> {
+    adapters(instance)
+        .into_iter()
+        .filter(|adapter| {
+            compatible_surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+        })
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn adapters(instance: &wgpu::Instance) -> Vec<wgpu::Adapter> {
+    instance.enumerate_adapters(wgpu::Backends::all()).collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn adapters(_instance: &wgpu::Instance) -> Vec<wgpu::Adapter> {
+    Vec::new()
+}
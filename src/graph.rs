@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use crate::memory;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransientDesc {
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+struct PooledTexture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+// Transient textures kept between frames. Textures a frame doesn't use are
+// freed, so resizing drops the old sizes after one frame.
+#[derive(Default)]
+pub struct TexturePool {
+    textures: HashMap<TransientDesc, Vec<PooledTexture>>,
+}
+
+impl TexturePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.textures
+            .values()
+            .flatten()
+            .map(|pooled| memory::texture_bytes(&pooled.texture))
+            .sum()
+    }
+}
+
+enum Resource {
+    // Owned by a pipeline, only declared for ordering
+    Imported,
+    Transient(TransientDesc),
+}
+
+type Execute<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &Resources) + 'a>;
+
+struct Node<'a> {
+    name: &'static str,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    execute: Execute<'a>,
+}
+
+// Views of the transient textures while the graph executes
+pub struct Resources {
+    views: Vec<Option<wgpu::TextureView>>,
+}
+
+impl Resources {
+    pub fn view(&self, id: ResourceId) -> &wgpu::TextureView {
+        self.views[id.0]
+            .as_ref()
+            .expect("Only transient resources have views")
+    }
+}
+
+// Passes of one frame. Nodes declare the resources they read and write and
+// run after the nodes they depend on, otherwise in the order they were
+// added. Built again every frame, so disabled passes simply aren't added.
+pub struct RenderGraph<'a> {
+    resources: Vec<(&'static str, Resource)>,
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    pub fn import(&mut self, name: &'static str) -> ResourceId {
+        self.resources.push((name, Resource::Imported));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    // A texture created by the graph, or reused from the pool
+    pub fn transient(&mut self, name: &'static str, desc: TransientDesc) -> ResourceId {
+        self.resources.push((name, Resource::Transient(desc)));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn add_node(
+        &mut self,
+        name: &'static str,
+        reads: &[ResourceId],
+        writes: &[ResourceId],
+        execute: impl FnOnce(&mut wgpu::CommandEncoder, &Resources) + 'a,
+    ) {
+        self.nodes.push(Node {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            execute: Box::new(execute),
+        });
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Node indices in execution order. A node waits for the last writer of
+    // everything it reads, and for earlier readers and writers of everything
+    // it writes.
+    fn order(&self) -> Vec<usize> {
+        let mut dependencies = vec![Vec::new(); self.nodes.len()];
+        let mut last_writer: HashMap<ResourceId, usize> = HashMap::new();
+        let mut readers: HashMap<ResourceId, Vec<usize>> = HashMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            for resource in &node.reads {
+                dependencies[index].extend(last_writer.get(resource));
+                readers.entry(*resource).or_default().push(index);
+            }
+            for resource in &node.writes {
+                dependencies[index].extend(last_writer.get(resource));
+                let earlier = readers.remove(resource).unwrap_or_default();
+                dependencies[index].extend(earlier.into_iter().filter(|&reader| reader != index));
+                last_writer.insert(*resource, index);
+            }
+        }
+
+        // Dependencies always point to earlier nodes, so picking the first
+        // ready node repeatedly keeps the insertion order where it can
+        let mut done = vec![false; self.nodes.len()];
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len())
+                .find(|&index| {
+                    !done[index]
+                        && dependencies[index]
+                            .iter()
+                            .all(|&dependency| done[dependency])
+                })
+                .expect("Render graph dependencies are acyclic");
+            done[next] = true;
+            order.push(next);
+        }
+        order
+    }
+
+    // Records every node into the encoder. after_node runs between nodes,
+    // e.g. to write profiler timestamps.
+    pub fn execute(
+        self,
+        device: &wgpu::Device,
+        pool: &mut TexturePool,
+        encoder: &mut wgpu::CommandEncoder,
+        mut after_node: impl FnMut(&mut wgpu::CommandEncoder, &'static str),
+    ) {
+        let order = self.order();
+
+        let mut available = std::mem::take(&mut pool.textures);
+        let mut used: Vec<(TransientDesc, wgpu::Texture)> = Vec::new();
+        let views = self
+            .resources
+            .iter()
+            .map(|(name, resource)| {
+                let Resource::Transient(desc) = resource else {
+                    return None;
+                };
+                let pooled = available
+                    .get_mut(desc)
+                    .and_then(Vec::pop)
+                    .unwrap_or_else(|| create_texture(device, name, desc));
+                used.push((*desc, pooled.texture));
+                Some(pooled.view)
+            })
+            .collect();
+        let resources = Resources { views };
+
+        let mut nodes: Vec<Option<Node>> = self.nodes.into_iter().map(Some).collect();
+        for index in order {
+            let node = nodes[index].take().unwrap();
+            (node.execute)(encoder, &resources);
+            after_node(encoder, node.name);
+        }
+
+        for ((desc, texture), view) in used.into_iter().zip(resources.views.into_iter().flatten()) {
+            pool.textures
+                .entry(desc)
+                .or_default()
+                .push(PooledTexture { texture, view });
+        }
+    }
+}
+
+impl Default for RenderGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn create_texture(device: &wgpu::Device, name: &str, desc: &TransientDesc) -> PooledTexture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(name),
+        size: desc.size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: desc.format,
+        usage: desc.usage,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    PooledTexture { texture, view }
+}
@@ -0,0 +1,173 @@
+use wgpu::util::DeviceExt;
+
+use crate::preprocess;
+
+// Half extent of the ground grid in voxels
+const GRID_EXTENT: i32 = 256;
+const GRID_SPACING: i32 = 8;
+const MAJOR_SPACING: i32 = 64;
+const AXIS_LENGTH: f32 = 32.;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Ground grid on the y = 0 plane plus an XYZ axis gizmo at the origin
+fn grid_lines() -> Vec<LineVertex> {
+    let mut vertices = Vec::new();
+    let mut line = |from: [f32; 3], to: [f32; 3], color: [f32; 4]| {
+        vertices.push(LineVertex {
+            position: from,
+            color,
+        });
+        vertices.push(LineVertex {
+            position: to,
+            color,
+        });
+    };
+
+    let extent = GRID_EXTENT as f32;
+    for i in (-GRID_EXTENT..=GRID_EXTENT).step_by(GRID_SPACING as usize) {
+        // The axes are drawn separately
+        if i == 0 {
+            continue;
+        }
+        let color = if i % MAJOR_SPACING == 0 {
+            [0.8, 0.8, 0.8, 0.6]
+        } else {
+            [0.6, 0.6, 0.6, 0.25]
+        };
+        let offset = i as f32;
+        line([offset, 0., -extent], [offset, 0., extent], color);
+        line([-extent, 0., offset], [extent, 0., offset], color);
+    }
+
+    let grey = [0.8, 0.8, 0.8, 0.6];
+    line([-extent, 0., 0.], [0., 0., 0.], grey);
+    line([0., 0., -extent], [0., 0., 0.], grey);
+    line([AXIS_LENGTH, 0., 0.], [extent, 0., 0.], grey);
+    line([0., 0., AXIS_LENGTH], [0., 0., extent], grey);
+
+    line([0., 0., 0.], [AXIS_LENGTH, 0., 0.], [1., 0.2, 0.2, 1.]);
+    line([0., 0., 0.], [0., AXIS_LENGTH, 0.], [0.2, 1., 0.2, 1.]);
+    line([0., 0., 0.], [0., 0., AXIS_LENGTH], [0.2, 0.4, 1., 1.]);
+
+    vertices
+}
+
+pub struct GridPipeline {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group: wgpu::BindGroup,
+    pub vertex_buffer: wgpu::Buffer,
+    pub vertex_count: u32,
+}
+
+impl GridPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        depth_texture: &wgpu::TextureView,
+    ) -> GridPipeline {
+        let shader = preprocess::shader_module(
+            device,
+            "Grid shader",
+            include_str!("shaders/grid.wgsl"),
+            &[],
+        );
+
+        let vertices = grid_lines();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid vertex buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth_texture),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grid Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        GridPipeline {
+            pipeline,
+            bind_group,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use winit::{event::*, window::Window};
+
+use crate::{
+    camera::{CameraPipeline, LookMode},
+    memory::{megabytes, MemoryCategory},
+    raytracing::{
+        DebugView, RaytracingSettings, RenderSettings, StereoMode, Traversal, WorkgroupSize,
+    },
+    tonemap::{TonemapSettings, Tonemapper},
+};
+
+// Frame statistics shown in the overlay
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    pub cpu_time: f32,
+    pub gpu_time: Option<f32>,
+    // GPU milliseconds of each group of passes, empty without timestamp queries
+    pub passes: Vec<(&'static str, f32)>,
+    pub render_size: winit::dpi::PhysicalSize<u32>,
+    // Bytes per subsystem, see memory::MemoryTracker
+    pub memory: Vec<(MemoryCategory, u64)>,
+    pub memory_budget: Option<u64>,
+    // Recent validation and out of memory errors of the device
+    pub errors: Vec<String>,
+}
+
+// Debug and settings overlay drawn with egui on top of the final image,
+// toggled with F1. Settings edited here apply on the same frame.
+pub struct Gui {
+    pub visible: bool,
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+}
+
+impl Gui {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, window: &Window) -> Gui {
+        let context = egui::Context::default();
+        let mut state = egui_winit::State::new(window);
+        state.set_pixels_per_point(window.scale_factor() as f32);
+
+        Gui {
+            visible: false,
+            context,
+            state,
+            renderer: egui_wgpu::Renderer::new(device, format, None, 1),
+            paint_jobs: Vec::new(),
+            textures_delta: egui::TexturesDelta::default(),
+        }
+    }
+
+    // The renderer depends on the swapchain format
+    pub fn configure(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        self.renderer = egui_wgpu::Renderer::new(device, format, None, 1);
+    }
+
+    // True if the overlay used the event, e.g. typing into a text field
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::F1),
+                    state: ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.visible = !self.visible;
+            return true;
+        }
+
+        self.visible && self.state.on_event(&self.context, event).consumed
+    }
+
+    // Builds this frame's UI, call before the settings are used
+    pub fn update(
+        &mut self,
+        window: &Window,
+        stats: FrameStats,
+        camera: &mut CameraPipeline,
+        tonemap: &mut TonemapSettings,
+        raytracing: &mut RaytracingSettings,
+    ) {
+        if !self.visible {
+            self.paint_jobs.clear();
+            return;
+        }
+
+        let input = self.state.take_egui_input(window);
+        let output = self.context.run(input, |context| {
+            egui::Window::new("Settings").show(context, |ui| {
+                stats_ui(ui, &stats);
+                ui.collapsing("Camera", |ui| camera_ui(ui, camera));
+                ui.collapsing("Image", |ui| tonemap_ui(ui, tonemap));
+                ui.collapsing("Ray tracing", |ui| raytracing_ui(ui, raytracing));
+            });
+        });
+
+        self.state
+            .handle_platform_output(window, &self.context, output.platform_output);
+        self.paint_jobs = self.context.tessellate(output.shapes);
+        self.textures_delta.append(output.textures_delta);
+    }
+
+    // Draws the overlay over the already rendered view
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        let textures_delta = std::mem::take(&mut self.textures_delta);
+        for (id, image_delta) in &textures_delta.set {
+            self.renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+
+        if !self.paint_jobs.is_empty() {
+            let screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
+                size_in_pixels: [size.width, size.height],
+                pixels_per_point: self.context.pixels_per_point(),
+            };
+            // Returns no extra command buffers without paint callbacks
+            self.renderer.update_buffers(
+                device,
+                queue,
+                encoder,
+                &self.paint_jobs,
+                &screen_descriptor,
+            );
+
+            let mut gui_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("GUI Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer
+                .render(&mut gui_pass, &self.paint_jobs, &screen_descriptor);
+        }
+
+        for id in &textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+fn stats_ui(ui: &mut egui::Ui, stats: &FrameStats) {
+    ui.label(format!(
+        "CPU {:.2} ms ({:.0} fps)",
+        stats.cpu_time,
+        1000. / stats.cpu_time.max(f32::EPSILON)
+    ));
+    if let Some(gpu_time) = stats.gpu_time {
+        ui.label(format!("GPU {:.2} ms", gpu_time));
+    }
+    for (name, time) in &stats.passes {
+        ui.label(format!("  {} {:.2} ms", name, time));
+    }
+    ui.label(format!(
+        "Render size {}x{}",
+        stats.render_size.width, stats.render_size.height
+    ));
+    let used: u64 = stats.memory.iter().map(|(_, bytes)| bytes).sum();
+    match stats.memory_budget {
+        Some(budget) => ui.label(format!(
+            "GPU memory {:.1} / {:.0} MB",
+            megabytes(used),
+            megabytes(budget)
+        )),
+        None => ui.label(format!("GPU memory {:.1} MB", megabytes(used))),
+    };
+    for (category, bytes) in &stats.memory {
+        ui.label(format!("  {:?} {:.1} MB", category, megabytes(*bytes)));
+    }
+    if !stats.errors.is_empty() {
+        ui.collapsing(format!("GPU errors ({})", stats.errors.len()), |ui| {
+            for error in &stats.errors {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+        });
+    }
+}
+
+fn camera_ui(ui: &mut egui::Ui, camera: &mut CameraPipeline) {
+    let settings = &mut camera.settings;
+    let mut changed = false;
+    changed |= ui
+        .add(egui::Slider::new(&mut settings.fov, 10.0..=120.0).text("FOV"))
+        .changed();
+    changed |= ui
+        .add(
+            egui::Slider::new(&mut settings.speed, 0.1..=1000.0)
+                .logarithmic(true)
+                .text("Speed"),
+        )
+        .changed();
+    changed |= ui
+        .add(egui::Slider::new(&mut settings.sensitivity, 0.1..=10.0).text("Sensitivity"))
+        .changed();
+    changed |= ui
+        .add(
+            egui::Slider::new(&mut settings.fps_sensitivity, 0.0001..=0.01)
+                .logarithmic(true)
+                .text("FPS sensitivity"),
+        )
+        .changed();
+    changed |= ui.checkbox(&mut settings.invert_x, "Invert X").changed();
+    changed |= ui.checkbox(&mut settings.invert_y, "Invert Y").changed();
+    changed |= ui.checkbox(&mut settings.head_bob, "Head bob").changed();
+    if changed {
+        camera.apply_settings();
+    }
+    if ui.button("Save").clicked() {
+        camera.save_settings();
+    }
+
+    let controller = &mut camera.controller;
+    ui.checkbox(&mut controller.collision, "Collision");
+    ui.checkbox(&mut controller.auto_level, "Auto level");
+    egui::ComboBox::from_label("Look mode")
+        .selected_text(format!("{:?}", controller.look_mode))
+        .show_ui(ui, |ui| {
+            for mode in [LookMode::Frame, LookMode::Fps, LookMode::Orbit] {
+                ui.selectable_value(&mut controller.look_mode, mode, format!("{:?}", mode));
+            }
+        });
+}
+
+fn tonemap_ui(ui: &mut egui::Ui, tonemap: &mut TonemapSettings) {
+    egui::ComboBox::from_label("Tonemapper")
+        .selected_text(format!("{:?}", tonemap.tonemapper))
+        .show_ui(ui, |ui| {
+            for tonemapper in [Tonemapper::None, Tonemapper::Reinhard, Tonemapper::Aces] {
+                ui.selectable_value(
+                    &mut tonemap.tonemapper,
+                    tonemapper,
+                    format!("{:?}", tonemapper),
+                );
+            }
+        });
+    ui.add(egui::Slider::new(&mut tonemap.exposure, -8.0..=8.0).text("Exposure (EV)"));
+    ui.checkbox(&mut tonemap.hdr_output, "HDR output");
+}
+
+fn raytracing_ui(ui: &mut egui::Ui, raytracing: &mut RaytracingSettings) {
+    egui::ComboBox::from_label("Debug view")
+        .selected_text(format!("{:?}", raytracing.debug_view))
+        .show_ui(ui, |ui| {
+            for view in [
+                DebugView::None,
+                DebugView::Normals,
+                DebugView::Depth,
+                DebugView::StepCount,
+                DebugView::ChunkId,
+                DebugView::Residency,
+            ] {
+                ui.selectable_value(&mut raytracing.debug_view, view, format!("{:?}", view));
+            }
+        });
+    egui::ComboBox::from_label("Stereo")
+        .selected_text(format!("{:?}", raytracing.stereo))
+        .show_ui(ui, |ui| {
+            for stereo in [
+                StereoMode::Off,
+                StereoMode::SideBySide,
+                StereoMode::Anaglyph,
+            ] {
+                ui.selectable_value(&mut raytracing.stereo, stereo, format!("{:?}", stereo));
+            }
+        });
+    egui::ComboBox::from_label("Workgroup size")
+        .selected_text(format!("{:?}", raytracing.workgroup_size))
+        .show_ui(ui, |ui| {
+            for size in WorkgroupSize::ALL {
+                ui.selectable_value(&mut raytracing.workgroup_size, size, format!("{:?}", size));
+            }
+        });
+    egui::ComboBox::from_label("Traversal")
+        .selected_text(format!("{:?}", raytracing.traversal))
+        .show_ui(ui, |ui| {
+            for traversal in Traversal::ALL {
+                ui.selectable_value(
+                    &mut raytracing.traversal,
+                    traversal,
+                    format!("{:?}", traversal),
+                );
+            }
+        });
+    ui.checkbox(&mut raytracing.beam_optimization, "Beam optimization");
+    ui.checkbox(
+        &mut raytracing.half_res_lighting,
+        "Half resolution lighting",
+    );
+    ui.checkbox(&mut raytracing.shadows, "Shadows");
+    ui.checkbox(&mut raytracing.wavefront, "Wavefront passes");
+    ui.checkbox(&mut raytracing.tiled, "Tiled dispatch");
+    ui.add_enabled(
+        !raytracing.tiled,
+        egui::Checkbox::new(&mut raytracing.sky_tiles, "Sky tiles"),
+    );
+    ui.add_enabled(
+        raytracing.tiled,
+        egui::Slider::new(&mut raytracing.far_distance, 64.0..=4096.0)
+            .logarithmic(true)
+            .text("Far tile distance"),
+    );
+    let reach = RenderSettings::new();
+    ui.add(
+        egui::Slider::new(
+            &mut raytracing.render.max_distance,
+            64.0..=reach.max_distance,
+        )
+        .logarithmic(true)
+        .text("Max distance"),
+    );
+    ui.add(
+        egui::Slider::new(&mut raytracing.render.max_steps, 16..=reach.max_steps)
+            .logarithmic(true)
+            .text("Max steps"),
+    );
+    ui.add(egui::Slider::new(&mut raytracing.render.lod_bias, 0.0..=4.0).text("LOD bias"));
+    ui.checkbox(&mut raytracing.write_albedo, "Write albedo");
+    ui.checkbox(&mut raytracing.write_normal, "Write normal");
+    ui.checkbox(&mut raytracing.write_depth, "Write depth");
+}
@@ -0,0 +1,149 @@
+use std::iter;
+
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera, capture,
+    gpu::GpuSelection,
+    overlay::{ChunkBounds, OverlaySettings},
+    raytracing, taa, tonemap,
+    window::{request_adapter_and_device, InitError},
+};
+
+// Renders without a window or surface, e.g. for golden image tests in CI and
+// batch rendering on servers without displays
+pub struct HeadlessRenderer {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub size: PhysicalSize<u32>,
+    pub camera: camera::CameraPipeline,
+    pub raytracing: raytracing::RaytracingPipeline,
+    pub tonemap: tonemap::TonemapPipeline,
+    // Accumulates the samples of an image and reads it back
+    pub capture: capture::CapturePipeline,
+    // Jittered frames averaged into every image
+    pub samples: u32,
+    // Spend the samples after the first ones on the noisy parts of the image
+    pub adaptive: Option<capture::AdaptiveSampling>,
+}
+
+impl HeadlessRenderer {
+    pub async fn new(size: PhysicalSize<u32>) -> Result<HeadlessRenderer, InitError> {
+        Self::with_gpu(size, None).await
+    }
+
+    pub async fn with_gpu(
+        size: PhysicalSize<u32>,
+        gpu: Option<GpuSelection>,
+    ) -> Result<HeadlessRenderer, InitError> {
+        let size = PhysicalSize::new(size.width.max(1), size.height.max(1));
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+        let (_, device, queue) = request_adapter_and_device(&instance, None, gpu.as_ref()).await?;
+
+        let camera = camera::CameraPipeline::new(&device);
+        let raytracing =
+            raytracing::RaytracingPipeline::new(&device, &size, &camera.bind_group_layout);
+        let tonemap = tonemap::TonemapPipeline::new(&device);
+
+        let capture = capture::CapturePipeline::new(
+            &device,
+            &size,
+            &size,
+            &raytracing.texture,
+            &raytracing.noise_mask,
+            &raytracing.sampler,
+            &tonemap.bind_group_layout,
+        );
+
+        Ok(HeadlessRenderer {
+            device,
+            queue,
+            size,
+            camera,
+            raytracing,
+            tonemap,
+            capture,
+            samples: 1,
+            adaptive: None,
+        })
+    }
+
+    // Ray traces self.samples jittered frames from self.camera.camera,
+    // tonemaps their average and writes it to a PNG. Blocks until the image is
+    // read back.
+    pub fn render_png(&mut self, path: &str) -> Result<(), String> {
+        let overlay = OverlaySettings {
+            crosshair: false,
+            highlight_picked: false,
+            chunk_bounds: ChunkBounds::Off,
+            grid: false,
+        };
+
+        self.tonemap
+            .uniform
+            .update_uv_scale(self.size, self.raytracing.size);
+        self.tonemap.uniform.update_crosshair(false);
+        self.tonemap.uniform.update_hdr_output(false);
+        self.tonemap.update(&self.queue, false);
+
+        let samples = self.samples.max(1);
+        for sample in 0..samples {
+            // A single sample stays centered on the pixels
+            let jitter = if samples == 1 {
+                [0., 0.]
+            } else {
+                [
+                    (taa::halton(sample + 1, 2) - 0.5) * 2. / self.size.width as f32,
+                    (taa::halton(sample + 1, 3) - 0.5) * 2. / self.size.height as f32,
+                ]
+            };
+            let camera = &mut self.camera;
+            camera.uniform.update_view(&camera.camera);
+            camera.uniform.update_view_proj(
+                &camera.camera,
+                self.size.width,
+                self.size.height,
+                jitter,
+            );
+            self.queue
+                .write_buffer(&camera.buffer, 0, bytemuck::cast_slice(&[camera.uniform]));
+            self.raytracing.settings.adaptive_sampling = self
+                .adaptive
+                .is_some_and(|adaptive| adaptive.active(sample));
+            self.raytracing
+                .update(&self.device, &self.queue, &overlay, [0., 0.], None);
+            self.capture
+                .update_sample(&self.queue, self.size, sample, self.adaptive);
+
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Headless Encoder"),
+                });
+            {
+                let mut ray_tracing_pass =
+                    encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("Headless ray tracing pass"),
+                    });
+                self.raytracing
+                    .dispatch(&mut ray_tracing_pass, &self.camera.bind_group);
+            }
+            self.capture.accumulate(&mut encoder, self.size);
+            if sample + 1 == samples {
+                self.capture.resolve(&mut encoder, &self.tonemap.bind_group);
+            }
+            self.queue.submit(iter::once(encoder.finish()));
+        }
+
+        capture::save_png(
+            &self.device,
+            &self.capture.readback,
+            self.capture.target.size(),
+            path,
+        )
+    }
+}
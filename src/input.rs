@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use winit::event::{ElementState, ModifiersState, VirtualKeyCode};
+
+use crate::keybindings::{Action, KeyBindings};
+
+// Analog inputs, e.g. gamepad sticks, in -1..1
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Axis {
+    // Right is positive
+    MoveX,
+    // Up is positive
+    MoveY,
+    // Forward is positive
+    MoveZ,
+}
+
+// Snapshot of the input for one frame. Window events, replays or headless
+// drivers feed it, the camera controller and tools read it instead of
+// handling events themselves.
+#[derive(Debug, Default)]
+pub struct Input {
+    held: HashSet<VirtualKeyCode>,
+    // Keys that went down this frame, key repeats excluded
+    pressed: HashSet<VirtualKeyCode>,
+    pub modifiers: ModifiersState,
+    // Relative mouse movement this frame, only while the mouse is grabbed
+    pub mouse_delta: (f64, f64),
+    // Scroll wheel notches this frame
+    pub scroll: f32,
+    axes: HashMap<Axis, f32>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process_key(&mut self, key: VirtualKeyCode, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.held.insert(key) {
+                    self.pressed.insert(key);
+                }
+            }
+            ElementState::Released => {
+                self.held.remove(&key);
+            }
+        }
+    }
+
+    pub fn process_mouse_motion(&mut self, delta: (f64, f64)) {
+        self.mouse_delta.0 += delta.0;
+        self.mouse_delta.1 += delta.1;
+    }
+
+    pub fn process_scroll(&mut self, steps: f32) {
+        self.scroll += steps;
+    }
+
+    // Axes keep their value until changed, like a stick held in place
+    pub fn set_axis(&mut self, axis: Axis, value: f32) {
+        self.axes.insert(axis, value.clamp(-1., 1.));
+    }
+
+    pub fn held(&self, key: VirtualKeyCode) -> bool {
+        self.held.contains(&key)
+    }
+
+    pub fn pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    pub fn action_held(&self, bindings: &KeyBindings, action: Action) -> bool {
+        bindings.keys(action).iter().any(|key| self.held(*key))
+    }
+
+    pub fn action_pressed(&self, bindings: &KeyBindings, action: Action) -> bool {
+        bindings.keys(action).iter().any(|key| self.pressed(*key))
+    }
+
+    pub fn axis(&self, axis: Axis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.)
+    }
+
+    // Releases everything, e.g. when the window loses focus and key releases
+    // would be missed
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.pressed.clear();
+        self.axes.clear();
+    }
+
+    // Call once the frame's input has been consumed
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.mouse_delta = (0., 0.);
+        self.scroll = 0.;
+    }
+}
@@ -0,0 +1,251 @@
+use nalgebra::Vector3;
+use winit::{dpi::PhysicalSize, event::*};
+
+use crate::{
+    camera::{Camera, CameraUniform, Projection},
+    overlay::{ChunkBounds, OverlaySettings},
+    raytracing, render, tonemap,
+    world::{Sun, World},
+};
+
+// Size of the inset in pixels
+pub const INSET_SIZE: PhysicalSize<u32> = PhysicalSize::new(384, 224);
+// Gap between the inset and the corner of the window
+const INSET_MARGIN: u32 = 16;
+// Height of the top-down camera above the main one
+const TOP_DOWN_HEIGHT: f32 = 48.;
+
+// What the second camera shows
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InsetView {
+    Off,
+    // Orthographic map above the main camera, rotated with its heading
+    TopDown,
+    // Stays where the main camera was when it was selected
+    Fixed,
+}
+
+impl InsetView {
+    pub fn next(self) -> Self {
+        match self {
+            InsetView::Off => InsetView::TopDown,
+            InsetView::TopDown => InsetView::Fixed,
+            InsetView::Fixed => InsetView::Off,
+        }
+    }
+}
+
+// A second camera ray traced into a small texture and composited into the
+// top right corner of the window
+pub struct InsetPipeline {
+    pub view: InsetView,
+    pub camera: Camera,
+    pub camera_uniform: CameraUniform,
+    pub camera_buffer: wgpu::Buffer,
+    pub camera_bind_group: wgpu::BindGroup,
+    pub raytracing: raytracing::RaytracingPipeline,
+    // Own tonemap uniform, the main one rescales to the main render size
+    pub tonemap: tonemap::TonemapPipeline,
+    pub render: render::RenderPipeline,
+}
+
+impl InsetPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> InsetPipeline {
+        let camera = Camera::new(Vector3::new(0.0, TOP_DOWN_HEIGHT, 0.0), 45., 1., 100.);
+        let camera_uniform = CameraUniform::new();
+
+        let camera_buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Inset Camera Buffer"),
+                contents: bytemuck::cast_slice(&[camera_uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("inset_camera_bind_group"),
+        });
+
+        let raytracing =
+            raytracing::RaytracingPipeline::new(device, &INSET_SIZE, camera_bind_group_layout);
+        let tonemap = tonemap::TonemapPipeline::new(device);
+        let render = create_render_pipeline(device, config, &raytracing, &tonemap);
+
+        InsetPipeline {
+            view: InsetView::Off,
+            camera,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            raytracing,
+            tonemap,
+            render,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.view != InsetView::Off
+    }
+
+    // The composite pipeline depends on the swapchain format
+    pub fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.render = create_render_pipeline(device, config, &self.raytracing, &self.tonemap);
+    }
+
+    // Draws the main view's world, see RaytracingPipeline::set_world
+    pub fn set_world(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        world: &World,
+    ) {
+        self.raytracing.set_world(device, queue, world);
+        self.configure(device, config);
+    }
+
+    // Recreates everything on a new device, keeping the view and its camera
+    pub fn recreate(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        let mut inset = InsetPipeline::new(device, config, camera_bind_group_layout);
+        inset.view = self.view;
+        std::mem::swap(&mut inset.camera, &mut self.camera);
+        std::mem::swap(&mut inset.tonemap.settings, &mut self.tonemap.settings);
+        self.raytracing.recreate(device, camera_bind_group_layout);
+        std::mem::swap(&mut inset.raytracing, &mut self.raytracing);
+        inset.configure(device, config);
+        *self = inset;
+    }
+
+    pub fn process_keyboard(
+        &mut self,
+        key: VirtualKeyCode,
+        state: ElementState,
+        main_camera: &Camera,
+    ) -> bool {
+        match key {
+            VirtualKeyCode::Z => {
+                if state == ElementState::Pressed {
+                    self.view = self.view.next();
+                    if self.view == InsetView::Fixed {
+                        self.camera.position = main_camera.eye();
+                        self.camera.direction = main_camera.direction;
+                        self.camera.fov = main_camera.fov;
+                        self.camera.projection = Projection::Perspective;
+                    }
+                    log::info!("Inset view: {:?}", self.view);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        main_camera: &Camera,
+        sun: &Sun,
+        main_tonemap: &tonemap::TonemapPipeline,
+        hdr_output: bool,
+    ) {
+        if self.view == InsetView::TopDown {
+            // Looking exactly down would leave the view without an up vector
+            let heading = Vector3::new(main_camera.direction.x, 0., main_camera.direction.z)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or(Vector3::z());
+            self.camera.position = main_camera.position + Vector3::y() * TOP_DOWN_HEIGHT;
+            self.camera.direction = (heading * 0.01 - Vector3::y()).normalize();
+            self.camera.projection = Projection::Orthographic;
+            self.camera.ortho_height = 96.;
+        }
+
+        self.camera_uniform.update_view(&self.camera);
+        self.camera_uniform.update_view_proj(
+            &self.camera,
+            INSET_SIZE.width,
+            INSET_SIZE.height,
+            [0., 0.],
+        );
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+
+        let overlay = OverlaySettings {
+            crosshair: false,
+            highlight_picked: false,
+            chunk_bounds: ChunkBounds::Off,
+            grid: false,
+        };
+        self.raytracing.settings.sun = *sun;
+        self.raytracing
+            .update(device, queue, &overlay, [0., 0.], None);
+
+        // Same look as the main view. Auto exposure is copied over on the GPU
+        // like for the main view.
+        self.tonemap.settings.tonemapper = main_tonemap.settings.tonemapper;
+        self.tonemap.settings.exposure = main_tonemap.settings.exposure;
+        self.tonemap.uniform.update_hdr_output(hdr_output);
+        self.tonemap.update(queue, false);
+    }
+
+    // Top right corner of the window, None when it doesn't fit
+    pub fn viewport(&self, window_size: PhysicalSize<u32>) -> Option<(f32, f32, f32, f32)> {
+        if window_size.width < INSET_SIZE.width + INSET_MARGIN * 2
+            || window_size.height < INSET_SIZE.height + INSET_MARGIN * 2
+        {
+            return None;
+        }
+        Some((
+            (window_size.width - INSET_SIZE.width - INSET_MARGIN) as f32,
+            INSET_MARGIN as f32,
+            INSET_SIZE.width as f32,
+            INSET_SIZE.height as f32,
+        ))
+    }
+}
+
+// Also used by preview windows
+pub(crate) fn create_render_pipeline(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    raytracing: &raytracing::RaytracingPipeline,
+    tonemap: &tonemap::TonemapPipeline,
+) -> render::RenderPipeline {
+    let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Vertex shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
+    });
+
+    let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fragment shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/frag.wgsl").into()),
+    });
+
+    render::RenderPipeline::new(
+        device,
+        vert_shader,
+        frag_shader,
+        config,
+        &raytracing.sampler,
+        &raytracing.texture,
+        &tonemap.bind_group_layout,
+    )
+}
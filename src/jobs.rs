@@ -0,0 +1,92 @@
+use std::sync::{mpsc, Arc, Mutex};
+
+type Task = Box<dyn FnOnce() + Send>;
+
+// A pool of worker threads that world generation runs on, so streaming
+// keeps up with a fast moving camera without stalling the frame. Results are
+// handed back to the main thread, which uploads them. On the web, where there
+// are no threads, jobs run right away on the calling thread.
+pub struct JobSystem {
+    sender: Option<mpsc::Sender<Task>>,
+    workers: usize,
+}
+
+impl JobSystem {
+    // A worker per core, minus the one the render loop runs on
+    pub fn new() -> Self {
+        if cfg!(target_arch = "wasm32") {
+            return Self {
+                sender: None,
+                workers: 1,
+            };
+        }
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |cores| cores.get().saturating_sub(1))
+            .max(1);
+        let (sender, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..workers {
+            let receiver = receiver.clone();
+            let spawned = std::thread::Builder::new()
+                .name(format!("Job worker {}", index))
+                .spawn(move || loop {
+                    // The lock is released before the task runs
+                    let task = receiver.lock().unwrap().recv();
+                    match task {
+                        Ok(task) => task(),
+                        // The system was dropped
+                        Err(_) => break,
+                    }
+                });
+            if let Err(error) = spawned {
+                log::warn!("Couldn't start a job worker: {}", error);
+            }
+        }
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    // Jobs that can run at once, to size batches by
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    // Runs job on a worker, its result is taken with Job::poll
+    pub fn spawn<T: Send + 'static>(&self, job: impl FnOnce() -> T + Send + 'static) -> Job<T> {
+        let (sender, receiver) = mpsc::channel();
+        let task = move || {
+            // Nobody waits for the result anymore when the receiver is gone
+            let _ = sender.send(job());
+        };
+        match &self.sender {
+            Some(sender) => {
+                if let Err(mpsc::SendError(task)) = sender.send(Box::new(task)) {
+                    task();
+                }
+            }
+            None => task(),
+        }
+        Job { receiver }
+    }
+}
+
+impl Default for JobSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The pending result of a job. Dropping it discards the result, the job
+// still runs.
+pub struct Job<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> Job<T> {
+    // The result once the job finished, only returned once
+    pub fn poll(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
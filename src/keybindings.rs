@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+pub const KEYBINDINGS_PATH: &str = "keybindings.toml";
+
+// Everything that can be bound to a key
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Sprint,
+    Slow,
+    Crouch,
+    RollLeft,
+    RollRight,
+    ToggleAutoLevel,
+    ToggleCollision,
+    ToggleLookMode,
+    ToggleFollow,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyBindingsFile {
+    #[serde(default)]
+    bindings: HashMap<Action, Vec<VirtualKeyCode>>,
+}
+
+// Maps actions to the keys that trigger them, several keys can trigger the
+// same action
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    bindings: HashMap<Action, Vec<VirtualKeyCode>>,
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        let bindings = HashMap::from([
+            (
+                Action::MoveForward,
+                vec![VirtualKeyCode::W, VirtualKeyCode::Up],
+            ),
+            (
+                Action::MoveBackward,
+                vec![VirtualKeyCode::S, VirtualKeyCode::Down],
+            ),
+            (
+                Action::MoveLeft,
+                vec![VirtualKeyCode::A, VirtualKeyCode::Left],
+            ),
+            (
+                Action::MoveRight,
+                vec![VirtualKeyCode::D, VirtualKeyCode::Right],
+            ),
+            (Action::MoveUp, vec![VirtualKeyCode::Space]),
+            (Action::MoveDown, vec![VirtualKeyCode::C]),
+            (Action::Sprint, vec![VirtualKeyCode::LShift]),
+            (Action::Slow, vec![VirtualKeyCode::LAlt]),
+            (Action::Crouch, vec![VirtualKeyCode::RShift]),
+            (Action::RollLeft, vec![VirtualKeyCode::Q]),
+            (Action::RollRight, vec![VirtualKeyCode::E]),
+            (Action::ToggleAutoLevel, vec![VirtualKeyCode::R]),
+            (Action::ToggleCollision, vec![VirtualKeyCode::F]),
+            (Action::ToggleLookMode, vec![VirtualKeyCode::M]),
+            (Action::ToggleFollow, vec![VirtualKeyCode::Tab]),
+        ]);
+
+        Self { bindings }
+    }
+
+    // Actions missing from the file keep their default keys, e.g.
+    //
+    // [bindings]
+    // move_forward = ["Z", "Up"]
+    // move_left = ["Q"]
+    pub fn from_toml(source: &str) -> Result<Self, toml::de::Error> {
+        let file: KeyBindingsFile = toml::from_str(source)?;
+        Ok(Self::with_bindings(file.bindings))
+    }
+
+    // The defaults with the keys of some actions replaced
+    pub fn with_bindings(bindings: HashMap<Action, Vec<VirtualKeyCode>>) -> Self {
+        let mut key_bindings = Self::new();
+        for (action, keys) in bindings {
+            key_bindings.bind(action, keys);
+        }
+        key_bindings
+    }
+
+    // Falls back to the defaults if the file is missing or invalid
+    pub fn load(path: &str) -> Self {
+        let Ok(source) = std::fs::read_to_string(path) else {
+            return Self::new();
+        };
+
+        match Self::from_toml(&source) {
+            Ok(key_bindings) => key_bindings,
+            Err(error) => {
+                log::warn!("Invalid key bindings in {}: {}", path, error);
+                Self::new()
+            }
+        }
+    }
+
+    // Replaces the keys of an action, taking them away from any other action
+    pub fn bind(&mut self, action: Action, keys: Vec<VirtualKeyCode>) {
+        for bound in self.bindings.values_mut() {
+            bound.retain(|key| !keys.contains(key));
+        }
+        self.bindings.insert(action, keys);
+    }
+
+    pub fn keys(&self, action: Action) -> &[VirtualKeyCode] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_actions_keep_their_defaults() {
+        let bindings = KeyBindings::from_toml(
+            r#"
+            [bindings]
+            move_forward = ["Z", "Up"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            bindings.keys(Action::MoveForward),
+            [VirtualKeyCode::Z, VirtualKeyCode::Up]
+        );
+        assert_eq!(bindings.keys(Action::MoveLeft)[0], VirtualKeyCode::A);
+        assert_eq!(bindings.action(VirtualKeyCode::W), None);
+    }
+
+    #[test]
+    fn rebound_keys_leave_their_old_action() {
+        let bindings = KeyBindings::from_toml(
+            r#"
+            [bindings]
+            move_left = ["Q"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bindings.action(VirtualKeyCode::Q), Some(Action::MoveLeft));
+        assert!(bindings.keys(Action::RollLeft).is_empty());
+    }
+
+    #[test]
+    fn empty_files_are_the_defaults() {
+        let bindings = KeyBindings::from_toml("").unwrap();
+        assert_eq!(bindings.action(VirtualKeyCode::C), Some(Action::MoveDown));
+    }
+
+    #[test]
+    fn unknown_actions_and_keys_are_errors() {
+        assert!(KeyBindings::from_toml("[bindings]\nfly = [\"F\"]").is_err());
+        assert!(KeyBindings::from_toml("[bindings]\nmove_up = [\"Nope\"]").is_err());
+    }
+}
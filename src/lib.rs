@@ -1,4 +1,70 @@
+pub mod app;
+pub mod assets;
+pub mod avatar;
+pub mod batch;
+pub mod benchmark;
+pub mod brick_pool;
 pub mod camera;
+pub mod capture;
+pub mod checkerboard;
+pub mod config;
+pub mod console;
+pub mod diagnostics;
+pub mod exposure;
+pub mod fallback;
+pub mod frustum;
+pub mod gpu;
+pub mod graph;
+pub mod grid;
+#[cfg(feature = "egui")]
+pub mod gui;
+pub mod headless;
+pub mod input;
+pub mod inset;
+pub mod jobs;
+pub mod keybindings;
+pub mod memory;
+pub mod motion_blur;
+pub mod mouse;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod occlusion;
+pub mod occupancy;
+pub mod overlay;
+pub mod palette;
+pub mod pass;
+pub mod picking;
+pub mod preprocess;
+pub mod present;
+pub mod preview;
+pub mod profiling;
 pub mod raytracing;
+pub mod readback;
+pub mod reference;
 pub mod render;
+pub mod renderer;
+pub mod replay;
+pub mod reprojection;
+pub mod residency;
+pub mod resolution;
+#[cfg(feature = "ecs")]
+pub mod scene;
+pub mod scene_file;
+pub mod screenshot;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod shader_reload;
+pub mod sim;
+pub mod taa;
+pub mod tonemap;
+pub mod touch;
+pub mod uniforms;
+pub mod upload;
+pub mod video;
+pub mod watcher;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
 pub mod window;
+pub mod world;
+#[cfg(feature = "xr")]
+pub mod xr;
@@ -1,20 +1,113 @@
-use winit::{
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-};
+use winit::{event_loop::EventLoop, window::WindowBuilder};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use shaders::window;
+use shaders::{app, fallback::FallbackState, gpu::GpuSelection, window};
+#[cfg(not(target_arch = "wasm32"))]
+use shaders::{benchmark::Benchmark, replay};
 
 fn main() {
-    pollster::block_on(run());
+    // Offline modes that don't open a window:
+    // --headless image.png renders a single frame,
+    // --render batch.toml renders a camera path into an image sequence,
+    // --list-gpus prints the adapters --gpu can pick by index or name.
+    // --record input.jsonl and --replay input.jsonl save and play back the
+    // input of a session.
+    // --benchmark 30 flies a fixed camera path for that many seconds and
+    // writes frame time statistics to benchmark.json and benchmark.csv.
+    let args: Vec<String> = std::env::args().collect();
+    let flag = |name: &str| {
+        let i = args.iter().position(|arg| arg == name)?;
+        Some(args.get(i + 1).cloned())
+    };
+    let gpu = flag("--gpu")
+        .flatten()
+        .map(|value| GpuSelection::parse(&value));
+
+    let result = if args.iter().any(|arg| arg == "--list-gpus") {
+        list_gpus();
+        Ok(())
+    } else if let Some(path) = flag("--headless") {
+        env_logger::init();
+        pollster::block_on(render_headless(
+            path.as_deref().unwrap_or("render.png"),
+            gpu,
+        ))
+    } else if let Some(path) = flag("--render") {
+        env_logger::init();
+        match path {
+            Some(path) => pollster::block_on(render_batch(&path)),
+            None => Err("--render needs a settings file".to_string()),
+        }
+    } else {
+        let mode = match (flag("--record"), flag("--replay"), flag("--benchmark")) {
+            (Some(path), _, _) => Some(RunMode::Record(
+                path.unwrap_or_else(|| "input.jsonl".to_string()),
+            )),
+            (_, Some(path), _) => Some(RunMode::Replay(
+                path.unwrap_or_else(|| "input.jsonl".to_string()),
+            )),
+            (_, _, Some(seconds)) => Some(RunMode::Benchmark(
+                seconds
+                    .and_then(|seconds| seconds.parse().ok())
+                    .unwrap_or(30.),
+            )),
+            _ => None,
+        };
+        pollster::block_on(run_with_gpu(gpu, mode));
+        return;
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+fn list_gpus() {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        dx12_shader_compiler: Default::default(),
+    });
+    for (index, info) in shaders::gpu::list_adapters(&instance).iter().enumerate() {
+        println!("{}", shaders::gpu::describe(index, info));
+    }
+}
+
+async fn render_headless(path: &str, gpu: Option<GpuSelection>) -> Result<(), String> {
+    let size = winit::dpi::PhysicalSize::new(1280, 720);
+    let mut renderer = shaders::headless::HeadlessRenderer::with_gpu(size, gpu)
+        .await
+        .map_err(|error| error.to_string())?;
+    renderer.render_png(path)?;
+    log::info!("Saved {}", path);
+    Ok(())
+}
+
+async fn render_batch(path: &str) -> Result<(), String> {
+    let settings = shaders::batch::BatchSettings::load(path)?;
+    shaders::batch::render(&settings).await
 }
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(start)]
 pub async fn run() {
+    run_with_gpu(None).await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+enum RunMode {
+    Record(String),
+    Replay(String),
+    // Seconds to measure for
+    Benchmark(f32),
+}
+
+async fn run_with_gpu(
+    gpu: Option<GpuSelection>,
+    #[cfg(not(target_arch = "wasm32"))] mode: Option<RunMode>,
+) {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -23,89 +116,109 @@ pub async fn run() {
             env_logger::init();
         }
     }
+    #[cfg(feature = "tracy")]
+    tracy_client::Client::start();
+    // Stops serving when dropped, so it lives as long as the event loop
+    #[cfg(feature = "puffin")]
+    let _puffin_server = start_puffin();
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
     #[cfg(target_arch = "wasm32")]
-    {
-        // Winit prevents sizing with CSS, so we have to set
-        // the size manually when on web.
-        use winit::dpi::PhysicalSize;
-        window.set_inner_size(PhysicalSize::new(450, 400));
-
+    let resizer = {
         use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
+        let container = web_sys::window()
             .and_then(|win| win.document())
             .and_then(|doc| {
                 let dst = doc.get_element_by_id("wasm-example")?;
                 let canvas = web_sys::Element::from(window.canvas());
                 dst.append_child(&canvas).ok()?;
-                Some(())
+                Some(dst)
             })
             .expect("Couldn't append canvas to document body.");
-    }
+        // Winit prevents sizing with CSS, so the canvas follows the size of
+        // its container instead
+        let resizer = shaders::web::CanvasResizer::new(&container);
+        if resizer.is_none() {
+            log::warn!("ResizeObserver isn't supported, the canvas keeps its size");
+        }
+        resizer
+    };
 
-    let mut state = window::State::new(window).await;
-    let mut last_render_time = instant::Instant::now();
-
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
-        match event {
-            Event::MainEventsCleared => state.window().request_redraw(),
-
-            Event::DeviceEvent {
-                event: DeviceEvent::MouseMotion { delta },
-                ..
-            } => {
-                if state.mouse_pressed {
-                    state.camera.controller.process_mouse(delta)
-                }
+    let context = match window::GpuContext::new(&window, gpu.as_ref()).await {
+        Ok(context) => context,
+        Err(error) => {
+            show_error(&error);
+            return;
+        }
+    };
+    if !context.supports_compute() {
+        match FallbackState::new(window, context) {
+            #[cfg(not(target_arch = "wasm32"))]
+            Ok(state) => run_app(event_loop, state, mode),
+            #[cfg(target_arch = "wasm32")]
+            Ok(state) => app::run(event_loop, state, resizer),
+            Err(error) => show_error(&error),
+        }
+        return;
+    }
+    match window::State::from_context(window, context) {
+        #[cfg(not(target_arch = "wasm32"))]
+        Ok(state) => match mode {
+            Some(RunMode::Benchmark(seconds)) => {
+                app::run(event_loop, Benchmark::new(state, seconds, "benchmark"))
             }
+            mode => run_app(event_loop, state, mode),
+        },
+        #[cfg(target_arch = "wasm32")]
+        Ok(state) => app::run(event_loop, state, resizer),
+        Err(error) => show_error(&error),
+    }
+}
 
-            Event::WindowEvent {
-                ref event,
-                window_id,
-            } if window_id == state.window().id() && !state.input(event) => match event {
-                #[cfg(not(target_arch = "wasm32"))]
-                WindowEvent::CloseRequested
-                | WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        },
-                    ..
-                } => *control_flow = ControlFlow::Exit,
-                WindowEvent::Resized(physical_size) => {
-                    state.resize(*physical_size);
-                }
-                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                    state.resize(**new_inner_size);
-                }
-                _ => {}
-            },
-
-            Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-                let now = instant::Instant::now();
-                let dt = now - last_render_time;
-                // println!("{:#?}", dt);
-                last_render_time = now;
-                state.update(dt);
-                match state.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.size)
-                    }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // We're ignoring timeouts
-                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
-                }
-            }
-            _ => {}
+// Wraps the app to record or replay its input. Benchmarks need the compute
+// renderer and are handled before this.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_app<A: app::App + 'static>(event_loop: EventLoop<()>, app: A, mode: Option<RunMode>) {
+    let result = match mode {
+        None => app::run(event_loop, app),
+        Some(RunMode::Record(path)) => {
+            replay::Recording::new(app, &path).map(|app| app::run(event_loop, app))
         }
-    });
+        Some(RunMode::Replay(path)) => {
+            replay::Replay::new(app, &path).map(|app| app::run(event_loop, app))
+        }
+        Some(RunMode::Benchmark(_)) => {
+            log::warn!("Benchmarks need compute shaders, running normally");
+            app::run(event_loop, app)
+        }
+    };
+    if let Err(error) = result {
+        log::error!("{}", error);
+    }
+}
+
+// Serves the scopes to puffin_viewer on the default port
+#[cfg(feature = "puffin")]
+fn start_puffin() -> Option<puffin_http::Server> {
+    puffin::set_scopes_on(true);
+    let address = format!("0.0.0.0:{}", puffin_http::DEFAULT_PORT);
+    puffin_http::Server::new(&address)
+        .map_err(|error| log::warn!("Couldn't start the puffin server: {}", error))
+        .ok()
+}
+
+// Startup errors happen before anything is drawn, so with the dialog
+// feature the user is told in a dialog rather than only in the log
+fn show_error(error: &window::InitError) {
+    log::error!("{}", error);
+
+    #[cfg(all(feature = "dialog", not(target_arch = "wasm32")))]
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title("Couldn't start the renderer")
+        .set_description(&error.to_string())
+        .set_buttons(rfd::MessageButtons::Ok)
+        .show();
 }
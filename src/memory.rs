@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+// Smallest pooled buffer, smaller chunks round up to it
+const MIN_BUFFER_SIZE: u64 = 64 * 1024;
+
+// Subsystems GPU memory is accounted to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    // Streamed voxel data and assets
+    World,
+    // History and accumulation targets of TAA, checkerboarding and captures
+    Accumulation,
+    // Everything else the frame is rendered through, from the ray traced
+    // image to the swapchain blits
+    Post,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 3] = [
+        MemoryCategory::World,
+        MemoryCategory::Accumulation,
+        MemoryCategory::Post,
+    ];
+}
+
+// Bytes of the first mip level, wgpu doesn't report the real allocation size
+pub fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let size = texture.size();
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_size(None).unwrap_or(4);
+    size.width.div_ceil(block_width) as u64
+        * size.height.div_ceil(block_height) as u64
+        * size.depth_or_array_layers as u64
+        * block_size as u64
+}
+
+pub fn megabytes(bytes: u64) -> f32 {
+    bytes as f32 / (1024. * 1024.)
+}
+
+// GPU memory in use per subsystem, against an optional budget. wgpu can't
+// query how much memory the driver has left, so allocations are reported
+// here by whoever makes them.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    used: HashMap<MemoryCategory, u64>,
+    // Bytes that streaming may fill, None for no limit
+    pub budget: Option<u64>,
+}
+
+impl MemoryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allocate(&mut self, category: MemoryCategory, bytes: u64) {
+        *self.used.entry(category).or_default() += bytes;
+    }
+
+    pub fn free(&mut self, category: MemoryCategory, bytes: u64) {
+        let used = self.used.entry(category).or_default();
+        *used = used.saturating_sub(bytes);
+    }
+
+    // For allocations that are measured as a whole, like the render targets
+    pub fn set(&mut self, category: MemoryCategory, bytes: u64) {
+        self.used.insert(category, bytes);
+    }
+
+    pub fn used(&self, category: MemoryCategory) -> u64 {
+        self.used.get(&category).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.used.values().sum()
+    }
+
+    // Whether bytes more can be allocated within the budget
+    pub fn fits(&self, bytes: u64) -> bool {
+        match self.budget {
+            Some(budget) => self.total() + bytes <= budget,
+            None => true,
+        }
+    }
+
+    pub fn usage(&self) -> Vec<(MemoryCategory, u64)> {
+        MemoryCategory::ALL
+            .iter()
+            .map(|&category| (category, self.used(category)))
+            .collect()
+    }
+}
+
+// Chunk buffers freed by streaming, kept for the next chunk of the same size
+// class instead of being destroyed and allocated again. Pooled buffers stay
+// counted as world memory until trim destroys them.
+#[derive(Default)]
+pub struct BufferPool {
+    free: HashMap<(u64, wgpu::BufferUsages), Vec<wgpu::Buffer>>,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A buffer of at least size bytes, None if a new one would exceed the
+    // budget even after trimming the pool
+    pub fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        memory: &mut MemoryTracker,
+        size: u64,
+        usage: wgpu::BufferUsages,
+    ) -> Option<wgpu::Buffer> {
+        let size = size.next_power_of_two().max(MIN_BUFFER_SIZE);
+        if let Some(buffer) = self.free.get_mut(&(size, usage)).and_then(Vec::pop) {
+            return Some(buffer);
+        }
+
+        if !memory.fits(size) {
+            self.trim(memory);
+            if !memory.fits(size) {
+                return None;
+            }
+        }
+        memory.allocate(MemoryCategory::World, size);
+        Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Chunk buffer"),
+            size,
+            usage,
+            mapped_at_creation: false,
+        }))
+    }
+
+    pub fn release(&mut self, buffer: wgpu::Buffer) {
+        self.free
+            .entry((buffer.size(), buffer.usage()))
+            .or_default()
+            .push(buffer);
+    }
+
+    pub fn pooled_bytes(&self) -> u64 {
+        self.free
+            .iter()
+            .map(|((size, _), buffers)| size * buffers.len() as u64)
+            .sum()
+    }
+
+    // Destroys pooled buffers, largest first, until memory is within its
+    // budget again
+    pub fn trim(&mut self, memory: &mut MemoryTracker) {
+        let mut classes: Vec<_> = self.free.keys().copied().collect();
+        classes.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+        for class in classes {
+            let buffers = self.free.get_mut(&class).unwrap();
+            while !memory.fits(0) {
+                let Some(buffer) = buffers.pop() else {
+                    break;
+                };
+                buffer.destroy();
+                memory.free(MemoryCategory::World, class.0);
+            }
+        }
+        self.free.retain(|_, buffers| !buffers.is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+use winit::{dpi::PhysicalSize, event::*};
+
+#[derive(Debug)]
+pub struct MotionBlurSettings {
+    pub enabled: bool,
+    // Fraction of the frame the virtual shutter is open for
+    pub shutter: f32,
+    pub samples: u32,
+    // Blur length limit in pixels
+    pub max_length: f32,
+}
+
+impl MotionBlurSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            shutter: 0.5,
+            samples: 12,
+            max_length: 48.,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::B => {
+                if state == ElementState::Pressed {
+                    self.enabled = !self.enabled;
+                    log::info!("Motion blur: {}", self.enabled);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MotionBlurUniform {
+    size: [u32; 2],
+    shutter: f32,
+    samples: u32,
+    max_length: f32,
+    _padding: u32,
+}
+
+impl MotionBlurUniform {
+    fn new() -> Self {
+        Self {
+            size: [0; 2],
+            shutter: 0.,
+            samples: 1,
+            max_length: 0.,
+            _padding: 0,
+        }
+    }
+
+    pub fn update(&mut self, settings: &MotionBlurSettings, render_size: PhysicalSize<u32>) {
+        self.size = [render_size.width, render_size.height];
+        self.shutter = settings.shutter;
+        self.samples = settings.samples;
+        self.max_length = settings.max_length;
+    }
+}
+
+pub struct MotionBlurPipeline {
+    pub settings: MotionBlurSettings,
+    pub uniform: MotionBlurUniform,
+    pub pipeline: wgpu::ComputePipeline,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub output: wgpu::Texture,
+    pub output_view: wgpu::TextureView,
+}
+
+impl MotionBlurPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        color_texture: &wgpu::TextureView,
+        motion_texture: &wgpu::TextureView,
+    ) -> MotionBlurPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Motion blur shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/motion-blur.wgsl").into()),
+        });
+
+        let settings = MotionBlurSettings::new();
+        let uniform = MotionBlurUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Motion blur Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            label: Some("Motion blur output texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let output_view = output.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Motion blur sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("motion_blur_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(motion_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("motion_blur_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Motion blur Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Motion blur pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        MotionBlurPipeline {
+            settings,
+            uniform,
+            pipeline,
+            buffer,
+            bind_group,
+            output,
+            output_view,
+        }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, render_size: PhysicalSize<u32>) {
+        self.uniform.update(&self.settings, render_size);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
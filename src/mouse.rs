@@ -0,0 +1,138 @@
+use instant::{Duration, Instant};
+use winit::{
+    dpi::PhysicalPosition,
+    window::{CursorGrabMode, Window},
+};
+
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(300);
+
+// Grabs the cursor for mouse look. Raw motion deltas are used when the
+// platform sends them, otherwise the cursor is confined to the window and
+// recentered after every move.
+#[derive(Debug)]
+pub struct MouseLook {
+    pub grabbed: bool,
+    // Cursor position before grabbing, restored on release
+    saved_position: Option<PhysicalPosition<f64>>,
+    // The first delta after grabbing contains the jump to the grab position
+    skip_delta: bool,
+    // Whether DeviceEvent::MouseMotion deltas have been received
+    raw_input: bool,
+    // Locking isn't supported everywhere, confining the cursor is the fallback
+    locked: bool,
+    last_click: Option<Instant>,
+}
+
+impl MouseLook {
+    pub fn new() -> Self {
+        Self {
+            grabbed: false,
+            saved_position: None,
+            skip_delta: false,
+            raw_input: false,
+            locked: false,
+            last_click: None,
+        }
+    }
+
+    pub fn grab(&mut self, window: &Window, cursor_position: PhysicalPosition<f64>) {
+        self.locked = window.set_cursor_grab(CursorGrabMode::Locked).is_ok();
+        if !self.locked {
+            if let Err(error) = window.set_cursor_grab(CursorGrabMode::Confined) {
+                log::warn!("Couldn't grab the cursor: {}", error);
+                return;
+            }
+        }
+        window.set_cursor_visible(false);
+
+        self.saved_position = Some(cursor_position);
+        self.grabbed = true;
+        self.skip_delta = true;
+        recenter(window);
+    }
+
+    pub fn release(&mut self, window: &Window) {
+        if !self.grabbed {
+            return;
+        }
+        self.grabbed = false;
+
+        let _ = window.set_cursor_grab(CursorGrabMode::None);
+        window.set_cursor_visible(true);
+        if let Some(position) = self.saved_position.take() {
+            let _ = window.set_cursor_position(position);
+        }
+    }
+
+    pub fn toggle(&mut self, window: &Window, cursor_position: PhysicalPosition<f64>) {
+        if self.grabbed {
+            self.release(window);
+        } else {
+            self.grab(window, cursor_position);
+        }
+    }
+
+    // Call on every left click, true when it completes a double click
+    pub fn double_click(&mut self) -> bool {
+        let now = Instant::now();
+        let double = self
+            .last_click
+            .is_some_and(|last| now - last < DOUBLE_CLICK_TIME);
+        // A third click starts a new double click
+        self.last_click = if double { None } else { Some(now) };
+        double
+    }
+
+    // Delta to look with from a DeviceEvent::MouseMotion
+    pub fn raw_motion(&mut self, delta: (f64, f64)) -> Option<(f64, f64)> {
+        self.raw_input = true;
+        if !self.grabbed {
+            return None;
+        }
+        if self.skip_delta {
+            self.skip_delta = false;
+            return None;
+        }
+        Some(delta)
+    }
+
+    // Delta to look with from a WindowEvent::CursorMoved, only used without
+    // raw input
+    pub fn cursor_moved(
+        &mut self,
+        window: &Window,
+        position: PhysicalPosition<f64>,
+    ) -> Option<(f64, f64)> {
+        if !self.grabbed || self.raw_input || self.locked {
+            return None;
+        }
+
+        let center = center(window);
+        let delta = (position.x - center.x, position.y - center.y);
+        if delta == (0., 0.) {
+            return None;
+        }
+        // Keep the cursor away from the borders it's confined to
+        recenter(window);
+        if self.skip_delta {
+            self.skip_delta = false;
+            return None;
+        }
+        Some(delta)
+    }
+}
+
+impl Default for MouseLook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn center(window: &Window) -> PhysicalPosition<f64> {
+    let size = window.inner_size();
+    PhysicalPosition::new(size.width as f64 / 2., size.height as f64 / 2.)
+}
+
+fn recenter(window: &Window) {
+    let _ = window.set_cursor_position(center(window));
+}
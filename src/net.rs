@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use nalgebra::Vector3;
+
+use crate::{
+    camera::Camera,
+    palette,
+    world::{VoxelGrid, World},
+};
+
+pub mod journal;
+pub mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+pub mod transport;
+
+use journal::EditJournal;
+use protocol::{ClientId, GridHeader, Message, Pose, VoxelEdit};
+
+// Seconds between pose updates sent to the server
+const POSE_INTERVAL: f32 = 0.05;
+
+// The server's grid while its chunks arrive
+struct IncomingGrid {
+    header: GridHeader,
+    voxels: Vec<u8>,
+    chunks: u32,
+}
+
+impl IncomingGrid {
+    fn new(header: GridHeader) -> Self {
+        let count = header.size.iter().map(|&v| v as usize).product();
+        Self {
+            header,
+            voxels: vec![palette::EMPTY; count],
+            chunks: 0,
+        }
+    }
+
+    fn insert(&mut self, origin: [i32; 3], size: [u32; 3], voxels: &[u8]) {
+        let [width, height, _] = self.header.size.map(|v| v as i32);
+        let start = Vector3::from(origin) - Vector3::from(self.header.origin);
+        let size = size.map(|v| v as usize);
+        for (i, &voxel) in voxels.iter().enumerate() {
+            let local = start
+                + Vector3::new(i % size[0], i / size[0] % size[1], i / (size[0] * size[1]))
+                    .map(|v| v as i32);
+            if (0..3).all(|axis| local[axis] >= 0 && local[axis] < self.header.size[axis] as i32) {
+                self.voxels[(local.x + (local.y + local.z * height) * width) as usize] = voxel;
+            }
+        }
+        self.chunks += 1;
+    }
+
+    fn finish(self) -> Result<World, String> {
+        let grid = VoxelGrid::new(
+            self.header.origin.into(),
+            self.header.size.into(),
+            self.voxels,
+        )?;
+        let mut colors = [[0.; 4]; palette::SIZE];
+        for (color, &entry) in colors.iter_mut().zip(&self.header.palette) {
+            *color = entry;
+        }
+        Ok(World::new(Some(grid)).with_palette(colors))
+    }
+}
+
+// Connection of a client to a NetServer. Voxel edits are journaled so
+// concurrent edits of the same voxel resolve the same way everywhere, and the
+// camera of every other client is tracked as its pose.
+pub struct NetSession {
+    connection: transport::Connection,
+    // Assigned by the server in its welcome
+    pub client: Option<ClientId>,
+    pub journal: EditJournal,
+    pub peers: HashMap<ClientId, Pose>,
+    // Time of day of the server's simulation, until taken
+    clock: Option<f32>,
+    grid: Option<IncomingGrid>,
+    // The server's world once all of it arrived, until taken
+    world: Option<World>,
+    // Edits made before the welcome, stamped once the id is known
+    pending: Vec<([i32; 3], u32)>,
+    pose_timer: f32,
+}
+
+impl NetSession {
+    pub fn connect(url: &str) -> Result<NetSession, String> {
+        let connection = transport::Connection::connect(url)?;
+        log::info!("Connecting to {}", url);
+        Ok(NetSession {
+            connection,
+            client: None,
+            journal: EditJournal::new(),
+            peers: HashMap::new(),
+            clock: None,
+            grid: None,
+            world: None,
+            pending: Vec::new(),
+            pose_timer: 0.,
+        })
+    }
+
+    // Time of day the server moved to since the last call
+    pub fn take_clock(&mut self) -> Option<f32> {
+        self.clock.take()
+    }
+
+    // World of the server once it arrived. The journal's edits have to be
+    // applied to it again.
+    pub fn take_world(&mut self) -> Option<World> {
+        self.world.take()
+    }
+
+    pub fn closed(&self) -> bool {
+        self.connection.closed()
+    }
+
+    pub fn edit(&mut self, position: [i32; 3], material: u32) {
+        match self.client {
+            Some(client) => {
+                let edit = self.journal.local_edit(position, material, client);
+                self.connection.send(Message::Edit { edit }.encode());
+            }
+            None => self.pending.push((position, material)),
+        }
+    }
+
+    // Sends the camera pose and handles what arrived, returns the edits that
+    // changed voxels
+    pub fn update(&mut self, dt: f32, camera: &Camera) -> Vec<VoxelEdit> {
+        let mut changed = Vec::new();
+        for text in self.connection.receive() {
+            let message = match Message::decode(&text) {
+                Ok(message) => message,
+                Err(error) => {
+                    log::warn!("{}", error);
+                    continue;
+                }
+            };
+            match message {
+                Message::Welcome {
+                    client,
+                    grid,
+                    journal,
+                    hours,
+                    ..
+                } => {
+                    log::info!("Joined as client {} with {} edits", client, journal.len());
+                    self.client = Some(client);
+                    self.clock = Some(hours);
+                    match grid {
+                        Some(header) => self.receive_grid(IncomingGrid::new(header)),
+                        None => self.world = Some(World::default()),
+                    }
+                    for edit in journal {
+                        if self.journal.apply(edit) {
+                            changed.push(edit);
+                        }
+                    }
+                    for (position, material) in std::mem::take(&mut self.pending) {
+                        self.edit(position, material);
+                    }
+                }
+                Message::Edit { edit } => {
+                    if self.journal.apply(edit) {
+                        changed.push(edit);
+                    }
+                }
+                Message::Chunk {
+                    origin,
+                    size,
+                    voxels,
+                } => match self.grid.take() {
+                    Some(mut grid) => {
+                        grid.insert(origin, size, &voxels);
+                        self.receive_grid(grid);
+                    }
+                    None => log::warn!("Chunk at {:?} outside of a world", origin),
+                },
+                Message::Clock { hours, .. } => self.clock = Some(hours),
+                Message::Pose { client, pose } => {
+                    self.peers.insert(client, pose);
+                }
+                Message::Left { client } => {
+                    self.peers.remove(&client);
+                }
+            }
+        }
+
+        self.pose_timer += dt;
+        if let Some(client) = self.client {
+            if self.pose_timer >= POSE_INTERVAL {
+                self.pose_timer = 0.;
+                let pose = Pose {
+                    position: camera.position.into(),
+                    direction: camera.direction.into(),
+                };
+                self.connection
+                    .send(Message::Pose { client, pose }.encode());
+            }
+        }
+        changed
+    }
+    // Keeps a grid until its last chunk arrived
+    fn receive_grid(&mut self, grid: IncomingGrid) {
+        if grid.chunks < grid.header.chunks {
+            self.grid = Some(grid);
+            return;
+        }
+        match grid.finish() {
+            Ok(world) => self.world = Some(world),
+            Err(error) => log::warn!("Invalid world from the server: {}", error),
+        }
+    }
+}
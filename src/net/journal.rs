@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use super::protocol::{ClientId, Stamp, VoxelEdit};
+
+// Latest edit of every edited voxel. Edits can arrive in any order and more
+// than once, keeping the one with the highest stamp makes every peer end up
+// with the same voxels.
+#[derive(Debug, Default)]
+pub struct EditJournal {
+    edits: HashMap<[i32; 3], VoxelEdit>,
+    // Lamport clock, ahead of every stamp seen
+    clock: u64,
+}
+
+impl EditJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Stamps an edit made by this peer
+    pub fn local_edit(&mut self, position: [i32; 3], material: u32, client: ClientId) -> VoxelEdit {
+        self.clock += 1;
+        let edit = VoxelEdit {
+            position,
+            material,
+            stamp: Stamp {
+                clock: self.clock,
+                client,
+            },
+        };
+        self.apply(edit);
+        edit
+    }
+
+    // True if the edit won against what was there and changed the voxel
+    pub fn apply(&mut self, edit: VoxelEdit) -> bool {
+        self.clock = self.clock.max(edit.stamp.clock);
+        match self.edits.get(&edit.position) {
+            Some(current) if current.stamp >= edit.stamp => false,
+            _ => {
+                self.edits.insert(edit.position, edit);
+                true
+            }
+        }
+    }
+
+    // None if the voxel was never edited
+    pub fn material_at(&self, position: [i32; 3]) -> Option<u32> {
+        self.edits.get(&position).map(|edit| edit.material)
+    }
+
+    pub fn snapshot(&self) -> Vec<VoxelEdit> {
+        let mut edits: Vec<_> = self.edits.values().copied().collect();
+        edits.sort_by_key(|edit| edit.stamp);
+        edits
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(material: u32, clock: u64, client: ClientId) -> VoxelEdit {
+        VoxelEdit {
+            position: [1, 2, 3],
+            material,
+            stamp: Stamp { clock, client },
+        }
+    }
+
+    #[test]
+    fn later_edits_win_in_any_order() {
+        let edits = [edit(4, 1, 1), edit(5, 2, 1), edit(6, 3, 2)];
+        for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0]] {
+            let mut journal = EditJournal::new();
+            for i in order {
+                journal.apply(edits[i]);
+            }
+            assert_eq!(journal.material_at([1, 2, 3]), Some(6));
+            assert_eq!(journal.len(), 1);
+        }
+    }
+
+    #[test]
+    fn ties_go_to_the_higher_client() {
+        let mut journal = EditJournal::new();
+        assert!(journal.apply(edit(7, 3, 2)));
+        assert!(!journal.apply(edit(8, 3, 1)));
+        assert_eq!(journal.material_at([1, 2, 3]), Some(7));
+
+        let mut journal = EditJournal::new();
+        assert!(journal.apply(edit(8, 3, 1)));
+        assert!(journal.apply(edit(7, 3, 2)));
+        assert_eq!(journal.material_at([1, 2, 3]), Some(7));
+    }
+
+    #[test]
+    fn repeated_edits_change_nothing() {
+        let mut journal = EditJournal::new();
+        assert!(journal.apply(edit(4, 1, 1)));
+        assert!(!journal.apply(edit(4, 1, 1)));
+    }
+
+    #[test]
+    fn local_edits_follow_the_clock() {
+        let mut journal = EditJournal::new();
+        journal.apply(edit(4, 10, 2));
+        // Stamped after everything seen, so it wins over a lower client
+        let local = journal.local_edit([1, 2, 3], 9, 1);
+        assert_eq!(local.stamp.clock, 11);
+        assert_eq!(journal.material_at([1, 2, 3]), Some(9));
+    }
+
+    #[test]
+    fn snapshots_are_in_stamp_order() {
+        let mut journal = EditJournal::new();
+        journal.local_edit([0, 0, 0], 1, 1);
+        journal.apply(VoxelEdit {
+            position: [5, 0, 0],
+            ..edit(2, 7, 3)
+        });
+        journal.local_edit([1, 0, 0], 3, 1);
+        let clocks: Vec<_> = journal
+            .snapshot()
+            .iter()
+            .map(|edit| edit.stamp.clock)
+            .collect();
+        assert_eq!(clocks, [1, 7, 8]);
+        assert_eq!(journal.material_at([9, 9, 9]), None);
+    }
+}
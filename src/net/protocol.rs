@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+pub type ClientId = u32;
+
+// Orders edits of the same voxel the same way on every peer: the higher
+// Lamport clock wins and ties go to the higher client id
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Stamp {
+    pub clock: u64,
+    pub client: ClientId,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoxelEdit {
+    pub position: [i32; 3],
+    // 0 removes the voxel
+    pub material: u32,
+    pub stamp: Stamp,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pose {
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+}
+
+// Grid of the server's world, see world::VoxelGrid. Its voxels follow the
+// welcome in chunks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridHeader {
+    pub origin: [i32; 3],
+    pub size: [u32; 3],
+    // Chunk messages carrying the voxels, chunks without any are left out
+    pub chunks: u32,
+    // Linear RGBA of every palette entry
+    pub palette: Vec<[f32; 4]>,
+}
+
+// Sent as JSON text frames over a WebSocket, which browsers and native
+// clients can both open
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Message {
+    // Server to a new client: its id, its world, every edit so far and the
+    // clock. Without a grid the world is the procedural terrain.
+    Welcome {
+        client: ClientId,
+        grid: Option<GridHeader>,
+        journal: Vec<VoxelEdit>,
+        tick: u64,
+        hours: f32,
+    },
+    // Voxels of a box of the grid, x first, then y, then z
+    Chunk {
+        origin: [i32; 3],
+        size: [u32; 3],
+        voxels: Vec<u8>,
+    },
+    // Server clock, sent about once a second
+    Clock {
+        tick: u64,
+        hours: f32,
+    },
+    Edit {
+        edit: VoxelEdit,
+    },
+    // Client to server, then server to the other clients with its id
+    Pose {
+        client: ClientId,
+        pose: Pose,
+    },
+    Left {
+        client: ClientId,
+    },
+}
+
+impl Message {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("Messages always serialize")
+    }
+
+    pub fn decode(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|error| format!("Bad message: {}", error))
+    }
+}
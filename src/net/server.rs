@@ -0,0 +1,224 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use tungstenite::WebSocket;
+
+use nalgebra::Vector3;
+
+use super::{
+    journal::EditJournal,
+    protocol::{ClientId, GridHeader, Message},
+    transport,
+};
+use crate::{
+    palette,
+    sim::{self, Simulation},
+    world::World,
+};
+
+// Longest a client that just connected can stall the server before its
+// handshake is given up on
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+// Voxels per side of the chunks a grid is sent to new clients in
+const CHUNK_SIZE: u32 = 32;
+
+struct Peer {
+    client: ClientId,
+    socket: WebSocket<TcpStream>,
+    closed: bool,
+}
+
+// Relays edits and poses between clients, keeps the journal new clients
+// start from and ticks the simulation they follow. Polled without blocking,
+// except for the handshake of a client that just connected, see
+// HANDSHAKE_TIMEOUT.
+pub struct NetServer {
+    listener: TcpListener,
+    peers: Vec<Peer>,
+    next_client: ClientId,
+    pub journal: EditJournal,
+    pub sim: Simulation,
+    // Sent to every new client, encoded once
+    grid: Option<GridHeader>,
+    chunks: Vec<String>,
+}
+
+impl NetServer {
+    // Serves world, the procedural terrain or a grid
+    pub fn bind(address: &str, world: World) -> Result<NetServer, String> {
+        let listener = TcpListener::bind(address).map_err(|error| error.to_string())?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|error| error.to_string())?;
+        log::info!("Serving on ws://{}", address);
+        let (grid, chunks) = encode_grid(&world);
+        Ok(NetServer {
+            listener,
+            peers: Vec::new(),
+            // 0 is the server itself
+            next_client: 1,
+            journal: EditJournal::new(),
+            sim: Simulation::with_world(world),
+            grid,
+            chunks,
+        })
+    }
+
+    pub fn poll(&mut self, dt: f32) {
+        self.accept();
+
+        let mut outgoing = Vec::new();
+        let tick = self.sim.tick;
+        self.sim.advance(dt);
+        if self.sim.tick / sim::TICK_RATE as u64 != tick / sim::TICK_RATE as u64 {
+            // From the server itself
+            outgoing.push((
+                0,
+                Message::Clock {
+                    tick: self.sim.tick,
+                    hours: self.sim.hours,
+                },
+            ));
+        }
+        for peer in &mut self.peers {
+            for text in transport::receive(&mut peer.socket, &mut peer.closed) {
+                match Message::decode(&text) {
+                    Ok(Message::Edit { edit }) => {
+                        // Edits that lost are dropped, everyone already has
+                        // the one that won
+                        if self.journal.apply(edit) {
+                            if let Ok(material) = u8::try_from(edit.material) {
+                                self.sim.edit(edit.position, material);
+                            }
+                            outgoing.push((peer.client, Message::Edit { edit }));
+                        }
+                    }
+                    // Clients can't speak for each other
+                    Ok(Message::Pose { pose, .. }) => outgoing.push((
+                        peer.client,
+                        Message::Pose {
+                            client: peer.client,
+                            pose,
+                        },
+                    )),
+                    Ok(message) => {
+                        log::warn!("Unexpected message from {}: {:?}", peer.client, message)
+                    }
+                    Err(error) => log::warn!("Client {}: {}", peer.client, error),
+                }
+            }
+        }
+
+        for peer in self.peers.iter().filter(|peer| peer.closed) {
+            log::info!("Client {} left", peer.client);
+            outgoing.push((
+                peer.client,
+                Message::Left {
+                    client: peer.client,
+                },
+            ));
+        }
+        self.peers.retain(|peer| !peer.closed);
+
+        for (from, message) in outgoing {
+            let text = message.encode();
+            for peer in self.peers.iter_mut().filter(|peer| peer.client != from) {
+                transport::send(&mut peer.socket, text.clone(), &mut peer.closed);
+            }
+        }
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn accept(&mut self) {
+        while let Ok((stream, address)) = self.listener.accept() {
+            // The handshake is done blocking, the socket is polled after
+            let blocking = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+                .and_then(|_| stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT)));
+            if let Err(error) = blocking {
+                log::warn!("{}: {}", address, error);
+                continue;
+            }
+            let socket = match tungstenite::accept(stream) {
+                Ok(socket) => socket,
+                Err(error) => {
+                    log::warn!("Handshake with {} failed: {}", address, error);
+                    continue;
+                }
+            };
+            if let Err(error) = socket.get_ref().set_nonblocking(true) {
+                log::warn!("{}: {}", address, error);
+                continue;
+            }
+
+            let client = self.next_client;
+            self.next_client += 1;
+            log::info!("Client {} joined from {}", client, address);
+            let mut peer = Peer {
+                client,
+                socket,
+                closed: false,
+            };
+            let welcome = Message::Welcome {
+                client,
+                grid: self.grid.clone(),
+                journal: self.journal.snapshot(),
+                tick: self.sim.tick,
+                hours: self.sim.hours,
+            };
+            transport::send(&mut peer.socket, welcome.encode(), &mut peer.closed);
+            for chunk in &self.chunks {
+                transport::send(&mut peer.socket, chunk.clone(), &mut peer.closed);
+            }
+            self.peers.push(peer);
+        }
+    }
+}
+
+// Header of world's grid and its chunks, encoded
+fn encode_grid(world: &World) -> (Option<GridHeader>, Vec<String>) {
+    let Some(grid) = world.grid() else {
+        return (None, Vec::new());
+    };
+    let size = grid.size();
+    let mut chunks = Vec::new();
+    for z in (0..size.z).step_by(CHUNK_SIZE as usize) {
+        for y in (0..size.y).step_by(CHUNK_SIZE as usize) {
+            for x in (0..size.x).step_by(CHUNK_SIZE as usize) {
+                let start = Vector3::new(x, y, z);
+                let chunk_size = (size - start).map(|v| v.min(CHUNK_SIZE));
+                let origin = grid.origin() + start.map(|v| v as i32);
+                let mut voxels = Vec::with_capacity(chunk_size.product() as usize);
+                for z in 0..chunk_size.z as i32 {
+                    for y in 0..chunk_size.y as i32 {
+                        for x in 0..chunk_size.x as i32 {
+                            voxels.push(grid.material(origin + Vector3::new(x, y, z)));
+                        }
+                    }
+                }
+                if voxels.iter().all(|&voxel| voxel == palette::EMPTY) {
+                    continue;
+                }
+                let chunk = Message::Chunk {
+                    origin: origin.into(),
+                    size: chunk_size.into(),
+                    voxels,
+                };
+                chunks.push(chunk.encode());
+            }
+        }
+    }
+    let header = GridHeader {
+        origin: grid.origin().into(),
+        size: size.into(),
+        chunks: chunks.len() as u32,
+        palette: world.palette().to_vec(),
+    };
+    (Some(header), chunks)
+}
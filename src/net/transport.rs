@@ -0,0 +1,161 @@
+// A WebSocket carrying text frames, polled once per frame without blocking
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+#[cfg(target_arch = "wasm32")]
+pub use web::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        net::TcpStream,
+    };
+
+    use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+    pub struct Connection {
+        socket: WebSocket<MaybeTlsStream<TcpStream>>,
+        closed: bool,
+    }
+
+    impl Connection {
+        // Blocks until the handshake is done
+        pub fn connect(url: &str) -> Result<Connection, String> {
+            let (socket, _) = tungstenite::connect(url).map_err(|error| error.to_string())?;
+            if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+                stream
+                    .set_nonblocking(true)
+                    .map_err(|error| error.to_string())?;
+            }
+            Ok(Connection {
+                socket,
+                closed: false,
+            })
+        }
+
+        pub fn send(&mut self, text: String) {
+            send(&mut self.socket, text, &mut self.closed);
+        }
+
+        pub fn receive(&mut self) -> Vec<String> {
+            receive(&mut self.socket, &mut self.closed)
+        }
+
+        pub fn closed(&self) -> bool {
+            self.closed
+        }
+    }
+
+    // Messages that can't be written right away are queued by tungstenite
+    // and flushed by later sends and receives
+    pub(crate) fn send<S: Read + Write>(
+        socket: &mut WebSocket<S>,
+        text: String,
+        closed: &mut bool,
+    ) {
+        match socket.send(Message::Text(text)) {
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(error)) if error.kind() == ErrorKind::WouldBlock => {}
+            Err(error) => {
+                log::warn!("Connection lost: {}", error);
+                *closed = true;
+            }
+        }
+    }
+
+    pub(crate) fn receive<S: Read + Write>(
+        socket: &mut WebSocket<S>,
+        closed: &mut bool,
+    ) -> Vec<String> {
+        let mut messages = Vec::new();
+        while !*closed {
+            match socket.read() {
+                Ok(Message::Text(text)) => messages.push(text),
+                Ok(Message::Close(_)) => *closed = true,
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(error)) if error.kind() == ErrorKind::WouldBlock => {
+                    let _ = socket.flush();
+                    break;
+                }
+                Err(error) => {
+                    log::warn!("Connection lost: {}", error);
+                    *closed = true;
+                }
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use wasm_bindgen::{prelude::*, JsCast};
+
+    pub struct Connection {
+        socket: web_sys::WebSocket,
+        received: Rc<RefCell<VecDeque<String>>>,
+        // Sent once the socket is open
+        pending: Vec<String>,
+        _on_message: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    }
+
+    impl Connection {
+        // Returns right away, messages are queued until the socket opens
+        pub fn connect(url: &str) -> Result<Connection, String> {
+            let socket = web_sys::WebSocket::new(url).map_err(|error| format!("{:?}", error))?;
+            let received = Rc::new(RefCell::new(VecDeque::new()));
+            let on_message = {
+                let received = received.clone();
+                Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+                    move |event: web_sys::MessageEvent| {
+                        if let Some(text) = event.data().as_string() {
+                            received.borrow_mut().push_back(text);
+                        }
+                    },
+                )
+            };
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            Ok(Connection {
+                socket,
+                received,
+                pending: Vec::new(),
+                _on_message: on_message,
+            })
+        }
+
+        pub fn send(&mut self, text: String) {
+            self.pending.push(text);
+            self.flush();
+        }
+
+        pub fn receive(&mut self) -> Vec<String> {
+            self.flush();
+            self.received.borrow_mut().drain(..).collect()
+        }
+
+        pub fn closed(&self) -> bool {
+            self.socket.ready_state() == web_sys::WebSocket::CLOSED
+        }
+
+        fn flush(&mut self) {
+            if self.socket.ready_state() != web_sys::WebSocket::OPEN {
+                return;
+            }
+            for text in self.pending.drain(..) {
+                if let Err(error) = self.socket.send_with_str(&text) {
+                    log::warn!("Couldn't send: {:?}", error);
+                }
+            }
+        }
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            self.socket.set_onmessage(None);
+            let _ = self.socket.close();
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{Camera, Projection},
+    readback::ReadbackRing,
+};
+
+// Texels per side of the finest level, must match depth-pyramid.wgsl. Rows
+// are 256 bytes, as buffer copies need.
+pub const PYRAMID_SIZE: u32 = 64;
+const READBACK_BUFFERS: usize = 3;
+const READBACK_SIZE: u64 = (PYRAMID_SIZE * PYRAMID_SIZE * 4) as u64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OcclusionUniform {
+    size: [u32; 2],
+    _padding: [u32; 2],
+}
+
+// Farthest traced depth over screen regions, from a 64x64 grid up to a single
+// texel, with the camera it was traced from
+pub struct DepthPyramid {
+    levels: Vec<Vec<f32>>,
+    eye: Point3<f32>,
+    view_proj: Matrix4<f32>,
+    // Counts up with every pyramid read back, for caching results
+    pub generation: u64,
+}
+
+impl DepthPyramid {
+    fn new(finest: Vec<f32>, eye: Point3<f32>, view_proj: Matrix4<f32>, generation: u64) -> Self {
+        let mut levels = vec![finest];
+        let mut size = PYRAMID_SIZE as usize;
+        while size > 1 {
+            let previous = levels.last().unwrap();
+            size /= 2;
+            let level = (0..size * size)
+                .map(|i| {
+                    let (x, y) = (i % size * 2, i / size * 2);
+                    let texel = |x: usize, y: usize| previous[x + y * size * 2];
+                    texel(x, y)
+                        .max(texel(x + 1, y))
+                        .max(texel(x, y + 1))
+                        .max(texel(x + 1, y + 1))
+                })
+                .collect();
+            levels.push(level);
+        }
+        Self {
+            levels,
+            eye,
+            view_proj,
+            generation,
+        }
+    }
+
+    // Whether a box is hidden behind the traced surfaces. Conservative, a box
+    // reaching behind the camera or off the screen isn't occluded.
+    pub fn occludes(&self, min: &Vector3<f32>, max: &Vector3<f32>) -> bool {
+        let eye = self.eye.coords;
+        let mut ndc_min = Vector3::repeat(f32::MAX);
+        let mut ndc_max = Vector3::repeat(f32::MIN);
+        for corner in 0..8 {
+            let p = Vector3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            );
+            let clip = self.view_proj * Vector4::new(p.x - eye.x, p.y - eye.y, p.z - eye.z, 1.);
+            if clip.w <= 0. {
+                return false;
+            }
+            let ndc = clip.xyz() / clip.w;
+            ndc_min = ndc_min.inf(&ndc);
+            ndc_max = ndc_max.sup(&ndc);
+        }
+        if ndc_min.x < -1. || ndc_min.y < -1. || ndc_max.x > 1. || ndc_max.y > 1. {
+            return false;
+        }
+
+        // The level where the box covers at most two texels per side
+        let to_texel = |ndc: f32| (ndc * 0.5 + 0.5) * PYRAMID_SIZE as f32;
+        let extent = (to_texel(ndc_max.x) - to_texel(ndc_min.x))
+            .max(to_texel(ndc_max.y) - to_texel(ndc_min.y));
+        let level = (extent.max(1.).log2().ceil() as usize).min(self.levels.len() - 1);
+        let size = PYRAMID_SIZE as usize >> level;
+        let texel = |ndc: f32| ((to_texel(ndc) as usize) >> level).min(size - 1);
+
+        let mut farthest = 0f32;
+        for y in texel(ndc_min.y)..=texel(ndc_max.y) {
+            for x in texel(ndc_min.x)..=texel(ndc_max.x) {
+                farthest = farthest.max(self.levels[level][x + y * size]);
+            }
+        }
+        let nearest = (eye.sup(min).inf(max) - eye).norm();
+        nearest > farthest
+    }
+}
+
+// Reduces each frame's traced depth to a coarse grid and reads it back
+// through a ReadbackRing, like FrameTimer. The pyramid used for
+// culling is a few frames old, occluded chunks show up that much late when
+// they come into view.
+pub struct OcclusionCulling {
+    pub enabled: bool,
+    pub uniform: OcclusionUniform,
+    pub buffer: wgpu::Buffer,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pyramid: wgpu::Texture,
+    // Tagged with the camera the depths were traced from
+    readbacks: ReadbackRing<(Point3<f32>, Matrix4<f32>)>,
+    // Camera of the frame being recorded, None when it can't be culled with
+    camera: Option<(Point3<f32>, Matrix4<f32>)>,
+    generation: u64,
+    pub latest: Option<DepthPyramid>,
+}
+
+impl OcclusionCulling {
+    pub fn new(device: &wgpu::Device, depth_texture: &wgpu::TextureView) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth pyramid shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/depth-pyramid.wgsl").into()),
+        });
+
+        let uniform = OcclusionUniform {
+            size: [0; 2],
+            _padding: [0; 2],
+        };
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Occlusion Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let pyramid = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth pyramid texture"),
+            size: wgpu::Extent3d {
+                width: PYRAMID_SIZE,
+                height: PYRAMID_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::R32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("depth_pyramid_bind_group_layout"),
+        });
+        let bind_group =
+            create_bind_group(device, &bind_group_layout, depth_texture, &pyramid, &buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth pyramid Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Depth pyramid pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "reduce",
+        });
+
+        let readbacks = ReadbackRing::new(
+            device,
+            "Depth pyramid readback buffer",
+            READBACK_SIZE,
+            READBACK_BUFFERS,
+        );
+
+        Self {
+            enabled: true,
+            uniform,
+            buffer,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            pyramid,
+            readbacks,
+            camera: None,
+            generation: 0,
+            latest: None,
+        }
+    }
+
+    // The depth target was recreated
+    pub fn rebind(&mut self, device: &wgpu::Device, depth_texture: &wgpu::TextureView) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            depth_texture,
+            &self.pyramid,
+            &self.buffer,
+        );
+    }
+
+    // Only perspective views trace depths that are distances from a single
+    // eye, cull is false for the others
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        render_size: PhysicalSize<u32>,
+        cull: bool,
+    ) {
+        let cull = cull && self.enabled && camera.projection == Projection::Perspective;
+        self.camera = cull.then(|| {
+            (
+                camera.eye(),
+                camera.calc_view_proj(render_size.width, render_size.height),
+            )
+        });
+        if !cull {
+            self.latest = None;
+        }
+        self.uniform.size = [render_size.width, render_size.height];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Call after the ray tracing pass was recorded
+    pub fn encode(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(camera) = self.camera else {
+            return;
+        };
+        // If every readback is still in flight this frame isn't used
+        let Some(buffer) = self.readbacks.begin(camera) else {
+            return;
+        };
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Depth pyramid pass"),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups(PYRAMID_SIZE / 8, PYRAMID_SIZE / 8, 1);
+        }
+
+        encoder.copy_texture_to_buffer(
+            self.pyramid.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(PYRAMID_SIZE * 4),
+                    rows_per_image: None,
+                },
+            },
+            self.pyramid.size(),
+        );
+    }
+
+    // Call after the frame's command buffer has been submitted
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        self.readbacks.after_submit(device);
+
+        // Pyramids of frames that couldn't be culled are dropped
+        let cull = self.camera.is_some();
+        let (generation, latest) = (&mut self.generation, &mut self.latest);
+        self.readbacks.read(|(eye, view_proj), data| {
+            if cull {
+                *generation += 1;
+                *latest = Some(DepthPyramid::new(
+                    bytemuck::cast_slice(data).to_vec(),
+                    eye,
+                    view_proj,
+                    *generation,
+                ));
+            }
+        });
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    depth_texture: &wgpu::TextureView,
+    pyramid: &wgpu::Texture,
+    buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    let pyramid_view = pyramid.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth_texture),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&pyramid_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("depth_pyramid_bind_group"),
+    })
+}
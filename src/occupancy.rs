@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::world;
+
+// Bricks per side of the window around the camera that occupancy is known
+// in, must match OCCUPANCY_BRICKS in ray-tracing.wgsl and
+// occupancy-pyramid.wgsl
+const WINDOW_BRICKS: i32 = 512;
+const BRICK_SIZE: i32 = 8;
+// Bricks per side of a chunk
+const CHUNK_BRICKS: i32 = 8;
+const WINDOW_CHUNKS: i32 = WINDOW_BRICKS / CHUNK_BRICKS;
+// Chunks from the camera the traversal's top level can reach at most, the
+// terrain's top is taken over all of them
+const REACH_CHUNKS: i32 = 128;
+// The same in voxels
+pub const REACH: f32 = (REACH_CHUNKS * BRICK_SIZE * CHUNK_BRICKS) as f32;
+// Edited bricks the GPU keeps, later edits are left out of the bitmasks
+const MAX_EDITS: usize = 65536;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PyramidUniform {
+    origin: [i32; 2],
+    edit_count: u32,
+    // 0 leaves the terrain out, see BrickOccupancy::set_world
+    terrain: u32,
+}
+
+// Which bricks and chunks around the camera hold any solid voxels, tracked
+// on the CPU as the window's position and the edited bricks. The bitmasks
+// themselves are built on the GPU by OccupancyPyramid. The window follows
+// the camera a chunk at a time.
+pub struct BrickOccupancy {
+    // Brick coordinates of the window's first column, None before the first
+    // update
+    origin: Option<Vector2<i32>>,
+    // Height the terrain within reach of the traversal stays below, and the
+    // edited bricks
+    terrain_top: i32,
+    edit_top: i32,
+    // Bricks holding voxels set by edits, occupied whatever the terrain, in
+    // the order they were edited
+    edited: HashSet<Vector3<i32>>,
+    edits: Vec<Vector3<i32>>,
+    // Whether the edits were replaced rather than added to since the last
+    // build
+    edits_replaced: bool,
+    // False once a grid replaced the terrain
+    terrain: bool,
+    // Whether the bitmasks need to be built again
+    dirty: bool,
+}
+
+impl BrickOccupancy {
+    pub fn new() -> Self {
+        Self {
+            origin: None,
+            terrain_top: 0,
+            edit_top: i32::MIN,
+            edited: HashSet::new(),
+            edits: Vec::new(),
+            edits_replaced: false,
+            terrain: true,
+            dirty: false,
+        }
+    }
+
+    // The world changed. A grid's bricks are marked like edits and the
+    // terrain left out, earlier edits are dropped either way.
+    pub fn set_world(&mut self, grid: Option<&world::VoxelGrid>) {
+        self.edited.clear();
+        self.edits.clear();
+        self.edit_top = i32::MIN;
+        self.edits_replaced = true;
+        self.terrain = grid.is_none();
+        if let Some(grid) = grid {
+            for voxel in grid.solid() {
+                self.mark_edited(voxel.into());
+            }
+        }
+        // The terrain's top is taken again by update
+        self.origin = None;
+    }
+
+    // The textures were recreated, e.g. with the device
+    pub fn invalidate(&mut self) {
+        self.origin = None;
+    }
+
+    // A voxel was set, its brick is occupied once the bitmasks are built
+    // again
+    pub fn mark_edited(&mut self, position: Point3<i32>) {
+        let brick = position.coords.map(|v| v.div_euclid(BRICK_SIZE));
+        if self.edited.insert(brick) {
+            self.edits.push(brick);
+            self.edit_top = self.edit_top.max((brick.y + 1) * BRICK_SIZE);
+            self.dirty = true;
+        }
+    }
+
+    // Centers the window on the chunk of eye
+    pub fn update(&mut self, eye: Point3<f32>) {
+        let chunk_size = (BRICK_SIZE * CHUNK_BRICKS) as f32;
+        let chunk = Vector2::new(eye.x, eye.z).map(|v| (v / chunk_size).floor() as i32);
+        let origin = (chunk - Vector2::repeat(WINDOW_CHUNKS / 2)) * CHUNK_BRICKS;
+        if self.origin == Some(origin) {
+            return;
+        }
+        self.origin = Some(origin);
+        self.dirty = true;
+
+        let chunk_voxels = BRICK_SIZE * CHUNK_BRICKS;
+        let reach = (chunk - Vector2::repeat(REACH_CHUNKS)) * chunk_voxels;
+        self.terrain_top = if self.terrain {
+            let terrain_top = world::max_heights(reach, 1, REACH_CHUNKS * 2 * chunk_voxels)[0];
+            terrain_top.ceil() as i32 + 1
+        } else {
+            i32::MIN
+        };
+    }
+
+    // Brick coordinates of the window's first column once its bitmasks are
+    // built, for RaytracingUniform::update_occupancy
+    pub fn origin(&self) -> Option<Vector2<i32>> {
+        self.origin.filter(|_| !self.dirty)
+    }
+
+    // Height nothing within reach of the traversal is solid at or above, rays
+    // starting there and pointing up can't hit anything
+    pub fn top(&self) -> i32 {
+        self.terrain_top.max(self.edit_top)
+    }
+}
+
+impl Default for BrickOccupancy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Builds the brick and chunk bitmasks read by the brick and chunk levels of
+// the traversal, as a column of bits per texel. Bricks are filled from the
+// terrain and the edits, chunks from the bricks under them, so neither moving
+// the window nor an edit uploads more than the new edits.
+pub struct OccupancyPyramid {
+    pub textures: [wgpu::Texture; 2],
+    pub views: [wgpu::TextureView; 2],
+    uniform_buffer: wgpu::Buffer,
+    edits_buffer: wgpu::Buffer,
+    // Bits of edited bricks per brick column, then per chunk column
+    edit_bits: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    brick_bind_group: wgpu::BindGroup,
+    chunk_bind_group: wgpu::BindGroup,
+    mark_pipeline: wgpu::ComputePipeline,
+    brick_pipeline: wgpu::ComputePipeline,
+    chunk_pipeline: wgpu::ComputePipeline,
+    // Edits of BrickOccupancy already in edits_buffer
+    uploaded_edits: usize,
+}
+
+impl OccupancyPyramid {
+    // The textures stay zeroed, and unused by the traversal, until the first
+    // build
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Occupancy pyramid shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/occupancy-pyramid.wgsl").into()),
+        });
+
+        let textures = [
+            ("Brick occupancy", WINDOW_BRICKS),
+            ("Chunk occupancy", WINDOW_CHUNKS),
+        ]
+        .map(|(label, size)| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size as u32,
+                    height: size as u32,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Uint,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            })
+        });
+        let views =
+            [0, 1].map(|i| textures[i].create_view(&wgpu::TextureViewDescriptor::default()));
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occupancy pyramid buffer"),
+            size: std::mem::size_of::<PyramidUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let edits_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occupancy edits buffer"),
+            size: (MAX_EDITS * 16) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let edit_bits = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occupancy edit bits buffer"),
+            size: ((WINDOW_BRICKS * WINDOW_BRICKS + WINDOW_CHUNKS * WINDOW_CHUNKS) * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffer_entry = |binding: u32, ty: wgpu::BufferBindingType| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage_texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::R32Uint,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("occupancy_pyramid_bind_group_layout"),
+        });
+        // The brick texture is written by one pass and read by the next, so
+        // the two get bind groups of their own
+        let brick_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[storage_texture_entry(0)],
+                label: Some("occupancy_brick_bind_group_layout"),
+            });
+        let chunk_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Uint,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    storage_texture_entry(2),
+                ],
+                label: Some("occupancy_chunk_bind_group_layout"),
+            });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: edits_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: edit_bits.as_entire_binding(),
+                },
+            ],
+            label: Some("occupancy_pyramid_bind_group"),
+        });
+        let brick_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &brick_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&views[0]),
+            }],
+            label: Some("occupancy_brick_bind_group"),
+        });
+        let chunk_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &chunk_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
+                },
+            ],
+            label: Some("occupancy_chunk_bind_group"),
+        });
+
+        let pipeline = |entry_point: &str, layout: &wgpu::BindGroupLayout| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Occupancy pyramid Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout, layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Occupancy pyramid pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        // mark only uses group 0, it runs with the brick bind group bound
+        let mark_pipeline = pipeline("mark", &brick_bind_group_layout);
+        let brick_pipeline = pipeline("bricks", &brick_bind_group_layout);
+        let chunk_pipeline = pipeline("chunks", &chunk_bind_group_layout);
+
+        Self {
+            textures,
+            views,
+            uniform_buffer,
+            edits_buffer,
+            edit_bits,
+            bind_group,
+            brick_bind_group,
+            chunk_bind_group,
+            mark_pipeline,
+            brick_pipeline,
+            chunk_pipeline,
+            uploaded_edits: 0,
+        }
+    }
+
+    // Builds the bitmasks for the window of occupancy once it moved or was
+    // edited. Submitted right away, so the traversal can use them from this
+    // frame on.
+    pub fn build(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        occupancy: &mut BrickOccupancy,
+    ) {
+        let Some(origin) = occupancy.origin else {
+            return;
+        };
+        if !occupancy.dirty {
+            return;
+        }
+        occupancy.dirty = false;
+
+        if std::mem::take(&mut occupancy.edits_replaced) {
+            self.uploaded_edits = 0;
+        }
+        let count = occupancy.edits.len().min(MAX_EDITS);
+        if self.uploaded_edits < count {
+            let edits: Vec<[i32; 4]> = occupancy.edits[self.uploaded_edits..count]
+                .iter()
+                .map(|brick| [brick.x, brick.y, brick.z, 0])
+                .collect();
+            queue.write_buffer(
+                &self.edits_buffer,
+                self.uploaded_edits as u64 * 16,
+                bytemuck::cast_slice(&edits),
+            );
+            if count == MAX_EDITS {
+                log::warn!(
+                    "Over {} edited bricks, later ones may be skipped",
+                    MAX_EDITS
+                );
+            }
+            self.uploaded_edits = count;
+        }
+        let uniform = PyramidUniform {
+            origin: [origin.x, origin.y],
+            edit_count: count as u32,
+            terrain: occupancy.terrain as u32,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Occupancy pyramid Encoder"),
+        });
+        encoder.clear_buffer(&self.edit_bits, 0, None);
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Occupancy pyramid pass"),
+            });
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_bind_group(1, &self.brick_bind_group, &[]);
+            pass.set_pipeline(&self.mark_pipeline);
+            pass.dispatch_workgroups((count as u32).div_ceil(64), 1, 1);
+            pass.set_pipeline(&self.brick_pipeline);
+            pass.dispatch_workgroups(WINDOW_BRICKS as u32 / 8, WINDOW_BRICKS as u32 / 8, 1);
+            pass.set_bind_group(1, &self.chunk_bind_group, &[]);
+            pass.set_pipeline(&self.chunk_pipeline);
+            pass.dispatch_workgroups(WINDOW_CHUNKS as u32 / 8, WINDOW_CHUNKS as u32 / 8, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
@@ -0,0 +1,73 @@
+use winit::event::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkBounds {
+    Off,
+    Chunks,
+    ChunksAndBricks,
+}
+
+impl ChunkBounds {
+    pub fn next(self) -> Self {
+        match self {
+            ChunkBounds::Off => ChunkBounds::Chunks,
+            ChunkBounds::Chunks => ChunkBounds::ChunksAndBricks,
+            ChunkBounds::ChunksAndBricks => ChunkBounds::Off,
+        }
+    }
+}
+
+// Helpers drawn on top of the ray traced image
+#[derive(Debug)]
+pub struct OverlaySettings {
+    pub crosshair: bool,
+    // Outline of the voxel under the cursor, drawn by the ray tracing pass
+    pub highlight_picked: bool,
+    // World space grid lines on the ray traced surfaces
+    pub chunk_bounds: ChunkBounds,
+    // Ground grid and axis gizmo
+    pub grid: bool,
+}
+
+impl OverlaySettings {
+    pub fn new() -> Self {
+        Self {
+            crosshair: true,
+            highlight_picked: true,
+            chunk_bounds: ChunkBounds::Off,
+            grid: false,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::H => {
+                if state == ElementState::Pressed {
+                    self.crosshair = !self.crosshair;
+                    self.highlight_picked = self.crosshair;
+                }
+                true
+            }
+            VirtualKeyCode::F10 => {
+                if state == ElementState::Pressed {
+                    self.chunk_bounds = self.chunk_bounds.next();
+                    log::info!("Chunk bounds: {:?}", self.chunk_bounds);
+                }
+                true
+            }
+            VirtualKeyCode::G => {
+                if state == ElementState::Pressed {
+                    self.grid = !self.grid;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,26 @@
+// Entries of the material palette, must match MaterialPalette in
+// ray-tracing.wgsl. Voxels on the GPU are stored as an index into it.
+pub const SIZE: usize = 256;
+
+// Index of empty voxels
+pub const EMPTY: u8 = 0;
+// Index of the procedural terrain, colored by position rather than by its
+// palette entry
+pub const TERRAIN: u8 = 1;
+
+// Linear RGB colors of the materials. An alpha of 0 leaves the voxel colored
+// by its position, like the terrain.
+pub fn default_colors() -> [[f32; 4]; SIZE] {
+    let mut colors = [[0.; 4]; SIZE];
+    // Hues around the color wheel for the rest, so edited materials can be
+    // told apart
+    for (index, color) in colors.iter_mut().enumerate().skip(TERRAIN as usize + 1) {
+        let hue = index as f32 / (SIZE - 2) as f32 * 6.;
+        let channel = |offset: f32| {
+            let distance = (hue - offset).rem_euclid(6.);
+            (1. - (distance.min(6. - distance) - 1.).clamp(0., 1.)) * 0.8
+        };
+        *color = [channel(0.), channel(2.), channel(4.), 1.];
+    }
+    colors
+}
@@ -0,0 +1,90 @@
+use winit::dpi::PhysicalSize;
+
+// Where in the frame a custom pass is recorded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PassStage {
+    // On the linear HDR image after motion blur, before exposure, captures
+    // and tonemapping. Changes are seen by everything after it.
+    Hdr,
+    // On the swapchain after the avatar, under the GUI
+    Output,
+}
+
+// Everything a pass can depend on when preparing a frame
+pub struct FrameInfo<'a> {
+    pub dt: f32,
+    // Size of the HDR image, smaller than the output with dynamic resolution
+    pub render_size: PhysicalSize<u32>,
+    pub output_size: PhysicalSize<u32>,
+    pub output_format: wgpu::TextureFormat,
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+}
+
+// Targets of the frame being recorded
+pub struct PassTargets<'a> {
+    pub render_size: PhysicalSize<u32>,
+    // Rgba16Float, can be read and written as a storage texture
+    pub hdr: &'a wgpu::Texture,
+    pub hdr_view: &'a wgpu::TextureView,
+    pub output: &'a wgpu::TextureView,
+    pub camera_bind_group: &'a wgpu::BindGroup,
+}
+
+// A compute or raster pass added to State::render from outside, e.g. a post
+// effect
+pub trait RenderPass {
+    // Label in the render graph and the GPU profiler
+    fn name(&self) -> &'static str;
+
+    fn stage(&self) -> PassStage;
+
+    // Called from State::update every frame, (re)creates size dependent
+    // resources and writes uniforms
+    fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, frame: &FrameInfo);
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &PassTargets);
+
+    // The device was lost and recreated, resources created on the old one
+    // have to be created again in the next prepare
+    fn device_recreated(&mut self, _device: &wgpu::Device) {}
+}
+
+// Custom passes, recorded in the order they were added within their stage
+#[derive(Default)]
+pub struct PassRegistry {
+    passes: Vec<Box<dyn RenderPass>>,
+}
+
+impl PassRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, pass: impl RenderPass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn RenderPass>> {
+        let index = self.passes.iter().position(|pass| pass.name() == name)?;
+        Some(self.passes.remove(index))
+    }
+
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, frame: &FrameInfo) {
+        for pass in &mut self.passes {
+            pass.prepare(device, queue, frame);
+        }
+    }
+
+    pub fn device_recreated(&mut self, device: &wgpu::Device) {
+        for pass in &mut self.passes {
+            pass.device_recreated(device);
+        }
+    }
+
+    pub fn stage(&self, stage: PassStage) -> impl Iterator<Item = &dyn RenderPass> {
+        self.passes
+            .iter()
+            .map(|pass| pass.as_ref())
+            .filter(move |pass| pass.stage() == stage)
+    }
+}
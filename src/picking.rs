@@ -0,0 +1,93 @@
+use nalgebra::{Point3, Vector3};
+
+use crate::readback::ReadbackRing;
+
+// Frames whose pick results can be in flight at once
+const READBACK_BUFFERS: usize = 3;
+
+// Must match PickResult in ray-tracing.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickResult {
+    // w is 1 if a voxel was hit
+    voxel: [i32; 4],
+    normal: [f32; 4],
+    // w is the distance along the ray
+    position: [f32; 4],
+}
+
+// The voxel that was under the crosshair a frame or two ago
+#[derive(Debug, Copy, Clone)]
+pub struct Pick {
+    pub voxel: Point3<i32>,
+    pub normal: Vector3<f32>,
+    pub position: Point3<f32>,
+    pub distance: f32,
+    // Frame the ray was traced in, counted by PickReadback
+    pub frame: u64,
+    // NDC position the ray was traced through, see State::pick_position
+    pub cursor: [f32; 2],
+}
+
+// Reads the result of the pick entry point back through a ReadbackRing, so
+// results lag a frame or two behind instead of stalling the frame
+pub struct PickReadback {
+    // Tagged with the frame and the cursor the ray was traced through
+    readbacks: ReadbackRing<(u64, [f32; 2])>,
+    frame: u64,
+    // None when nothing was under the crosshair
+    pub latest: Option<Pick>,
+}
+
+impl PickReadback {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            readbacks: ReadbackRing::new(
+                device,
+                "Pick readback buffer",
+                std::mem::size_of::<PickResult>() as u64,
+                READBACK_BUFFERS,
+            ),
+            frame: 0,
+            latest: None,
+        }
+    }
+
+    // Call after the ray tracing pass that picked through cursor was recorded
+    pub fn encode(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        pick_buffer: &wgpu::Buffer,
+        cursor: [f32; 2],
+    ) {
+        self.frame += 1;
+        // If every readback is still in flight this frame's result is skipped
+        if let Some(buffer) = self.readbacks.begin((self.frame, cursor)) {
+            encoder.copy_buffer_to_buffer(pick_buffer, 0, buffer, 0, buffer.size());
+        }
+    }
+
+    // Call after the frame's command buffer has been submitted
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        self.readbacks.after_submit(device);
+
+        let latest = &mut self.latest;
+        self.readbacks.read(|(frame, cursor), data| {
+            let result: PickResult = *bytemuck::from_bytes(data);
+            *latest = (result.voxel[3] != 0).then(|| Pick {
+                voxel: Point3::new(result.voxel[0], result.voxel[1], result.voxel[2]),
+                normal: Vector3::new(result.normal[0], result.normal[1], result.normal[2]),
+                position: Point3::new(result.position[0], result.position[1], result.position[2]),
+                distance: result.position[3],
+                frame,
+                cursor,
+            });
+        });
+    }
+
+    // The latest pick if it was traced through cursor, older ones may have
+    // hit something else
+    pub fn at(&self, cursor: [f32; 2]) -> Option<Pick> {
+        self.latest.filter(|pick| pick.cursor == cursor)
+    }
+}
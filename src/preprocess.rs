@@ -0,0 +1,311 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, Weak},
+};
+
+// Files the built in shaders can include
+pub fn builtin_include(name: &str) -> Result<String, String> {
+    match name {
+        "camera.wgsl" => Ok(include_str!("shaders/camera.wgsl").to_string()),
+        "lighting.wgsl" => Ok(include_str!("shaders/lighting.wgsl").to_string()),
+        _ => Err(format!("No shader named {}", name)),
+    }
+}
+
+// Creates a shader module from one of the built in shaders. They are known to
+// preprocess, so errors are bugs.
+pub fn shader_module(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+    defines: &[(&str, &str)],
+) -> wgpu::ShaderModule {
+    let source = preprocess(source, defines, builtin_include)
+        .unwrap_or_else(|error| panic!("Couldn't preprocess {}: {}", label, error));
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    })
+}
+
+// Shader modules by a hash of their preprocessed source, so source that
+// didn't change isn't parsed and validated again, e.g. when variants are
+// compiled again after a reload. Only modules that pipelines still use are
+// kept. Modules belong to the device they were created on, and wgpu has no
+// pipeline cache to persist them between runs with.
+pub struct ShaderCache {
+    modules: Mutex<HashMap<u64, Weak<wgpu::ShaderModule>>>,
+}
+
+impl ShaderCache {
+    pub fn new() -> Self {
+        Self {
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn module(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        source: String,
+    ) -> Arc<wgpu::ShaderModule> {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(module) = self
+            .modules
+            .lock()
+            .unwrap()
+            .get(&key)
+            .and_then(Weak::upgrade)
+        {
+            return module;
+        }
+        // Created without holding the lock, workers compiling other variants
+        // don't wait on it
+        let module = Arc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }));
+        let mut modules = self.modules.lock().unwrap();
+        modules.retain(|_, module| module.strong_count() > 0);
+        // Another worker may have created the same module meanwhile
+        match modules.get(&key).and_then(Weak::upgrade) {
+            Some(module) => module,
+            None => {
+                modules.insert(key, Arc::downgrade(&module));
+                module
+            }
+        }
+    }
+}
+
+impl Default for ShaderCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Branch {
+    // Whether the enclosing block is emitted
+    parent_active: bool,
+    // Whether this branch is emitted
+    active: bool,
+    // Whether an earlier branch of the block was taken
+    taken: bool,
+}
+
+struct Preprocessor<F> {
+    defines: HashMap<String, String>,
+    include: F,
+    // Files are only included once
+    included: HashSet<String>,
+    output: String,
+}
+
+// Runs the directives in a WGSL source before it is compiled:
+//
+// #include "camera.wgsl"   inserts a file, once per shader
+// #define NAME value       replaces the identifier NAME with value
+// #define NAME             only defines NAME for conditions
+// #undef NAME
+// #ifdef NAME / #ifndef NAME / #else / #endif
+//
+// Directives have to start their line. include resolves file names to their
+// source, e.g. builtin_include.
+pub fn preprocess<F>(source: &str, defines: &[(&str, &str)], include: F) -> Result<String, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut preprocessor = Preprocessor {
+        defines: defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        include,
+        included: HashSet::new(),
+        output: String::with_capacity(source.len()),
+    };
+    preprocessor.process("<shader>", source)?;
+    Ok(preprocessor.output)
+}
+
+impl<F> Preprocessor<F>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    fn process(&mut self, file: &str, source: &str) -> Result<(), String> {
+        let mut branches: Vec<Branch> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| format!("{}:{}: {}", file, index + 1, message);
+            let active = branches.last().is_none_or(|branch| branch.active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.substitute(line);
+                    self.output.push('\n');
+                }
+                continue;
+            };
+            let mut words = directive.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            let argument = words.next();
+
+            match (name, argument) {
+                ("ifdef" | "ifndef", Some(define)) => {
+                    let condition = self.defines.contains_key(define) == (name == "ifdef");
+                    branches.push(Branch {
+                        parent_active: active,
+                        active: active && condition,
+                        taken: condition,
+                    });
+                }
+                ("else", None) => {
+                    let branch = branches
+                        .last_mut()
+                        .ok_or_else(|| error("#else without #ifdef".to_string()))?;
+                    branch.active = branch.parent_active && !branch.taken;
+                    branch.taken = true;
+                }
+                ("endif", None) => {
+                    branches
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef".to_string()))?;
+                }
+                // Everything else only applies in emitted blocks
+                _ if !active => {}
+                ("include", Some(path)) => {
+                    let path = path.trim_matches('"');
+                    if self.included.insert(path.to_string()) {
+                        let included = (self.include)(path).map_err(error)?;
+                        self.process(path, &included)?;
+                    }
+                }
+                ("define", Some(define)) => {
+                    let value = words.collect::<Vec<_>>().join(" ");
+                    self.defines.insert(define.to_string(), value);
+                }
+                ("undef", Some(define)) => {
+                    self.defines.remove(define);
+                }
+                _ => return Err(error(format!("Invalid directive: {}", line.trim()))),
+            }
+        }
+
+        if !branches.is_empty() {
+            return Err(format!("{}: #ifdef without #endif", file));
+        }
+        Ok(())
+    }
+
+    // Appends a line with defined identifiers replaced by their values
+    fn substitute(&mut self, line: &str) {
+        if self.defines.values().all(String::is_empty) {
+            self.output.push_str(line);
+            return;
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| c.is_alphanumeric() || c == '_') {
+            let end = rest[start..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |length| start + length);
+            let identifier = &rest[start..end];
+
+            self.output.push_str(&rest[..start]);
+            // Numbers like 1e5 or 16u aren't identifiers
+            let number = identifier.starts_with(|c: char| c.is_ascii_digit());
+            match self.defines.get(identifier).filter(|_| !number) {
+                Some(value) if !value.is_empty() => self.output.push_str(value),
+                _ => self.output.push_str(identifier),
+            }
+            rest = &rest[end..];
+        }
+        self.output.push_str(rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_includes(name: &str) -> Result<String, String> {
+        Err(format!("No shader named {}", name))
+    }
+
+    fn lines(output: &str) -> Vec<&str> {
+        output.lines().map(str::trim).collect()
+    }
+
+    #[test]
+    fn nested_ifdef_and_else_keep_the_taken_branches() {
+        let source = "\
+#ifdef A
+a
+#ifdef B
+ab
+#else
+a_not_b
+#endif
+#else
+not_a
+#ifndef B
+not_a_not_b
+#endif
+#endif
+end";
+        let run = |defines: &[(&str, &str)]| preprocess(source, defines, no_includes).unwrap();
+        assert_eq!(lines(&run(&[("A", ""), ("B", "")])), ["a", "ab", "end"]);
+        assert_eq!(lines(&run(&[("A", "")])), ["a", "a_not_b", "end"]);
+        assert_eq!(lines(&run(&[])), ["not_a", "not_a_not_b", "end"]);
+        assert_eq!(lines(&run(&[("B", "")])), ["not_a", "end"]);
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers() {
+        let output = preprocess(
+            "let x = SIZE + SIZE_2 + 1e5;",
+            &[("SIZE", "8")],
+            no_includes,
+        );
+        assert_eq!(output.unwrap().trim(), "let x = 8 + SIZE_2 + 1e5;");
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let source = "#include \"a.wgsl\"\n#include \"a.wgsl\"\nmain";
+        let include = |name: &str| match name {
+            "a.wgsl" => Ok("a".to_string()),
+            _ => no_includes(name),
+        };
+        let output = preprocess(source, &[], include).unwrap();
+        assert_eq!(lines(&output), ["a", "main"]);
+    }
+
+    #[test]
+    fn missing_include_is_an_error() {
+        let error = preprocess("\n#include \"missing.wgsl\"", &[], no_includes).unwrap_err();
+        assert_eq!(error, "<shader>:2: No shader named missing.wgsl");
+    }
+
+    #[test]
+    fn missing_include_in_a_skipped_branch_is_ignored() {
+        let source = "#ifdef A\n#include \"missing.wgsl\"\n#endif";
+        assert!(preprocess(source, &[], no_includes).is_ok());
+    }
+
+    #[test]
+    fn unterminated_ifdef_is_an_error() {
+        let error = preprocess("#ifdef A\na", &[("A", "")], no_includes).unwrap_err();
+        assert_eq!(error, "<shader>: #ifdef without #endif");
+    }
+
+    #[test]
+    fn endif_without_ifdef_is_an_error() {
+        let error = preprocess("a\n#endif", &[], no_includes).unwrap_err();
+        assert_eq!(error, "<shader>:2: #endif without #ifdef");
+    }
+}
@@ -0,0 +1,97 @@
+use serde::Deserialize;
+use winit::event::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    // Vsync
+    Fifo,
+    // No tearing, but frames aren't limited to the refresh rate
+    Mailbox,
+    // Tears, for benchmarking
+    Immediate,
+}
+
+impl PresentMode {
+    const ALL: [PresentMode; 3] = [
+        PresentMode::Fifo,
+        PresentMode::Mailbox,
+        PresentMode::Immediate,
+    ];
+
+    pub fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+// Present mode of the window surface, cycled with F3 through the modes the
+// surface supports. Fifo is always supported.
+#[derive(Debug)]
+pub struct PresentSettings {
+    mode: PresentMode,
+    supported: Vec<PresentMode>,
+    // Set when the surface needs to be configured again
+    changed: bool,
+}
+
+impl PresentSettings {
+    pub fn new(supported: &[wgpu::PresentMode]) -> Self {
+        Self {
+            mode: PresentMode::Fifo,
+            supported: PresentMode::ALL
+                .into_iter()
+                .filter(|mode| *mode == PresentMode::Fifo || supported.contains(&mode.to_wgpu()))
+                .collect(),
+            changed: false,
+        }
+    }
+
+    pub fn mode(&self) -> PresentMode {
+        self.mode
+    }
+
+    // Unsupported modes fall back to the other mode without vsync if there
+    // is one, otherwise to Fifo
+    pub fn set_mode(&mut self, mode: PresentMode) {
+        let mode = if self.supported.contains(&mode) || mode == PresentMode::Fifo {
+            mode
+        } else {
+            let fallback = *self.supported.last().unwrap();
+            log::warn!(
+                "{:?} presentation isn't supported, using {:?}",
+                mode,
+                fallback
+            );
+            fallback
+        };
+        if mode != self.mode {
+            self.mode = mode;
+            self.changed = true;
+        }
+    }
+
+    // True once after the mode changed
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if key != VirtualKeyCode::F3 {
+            return false;
+        }
+        if state == ElementState::Pressed {
+            let index = self
+                .supported
+                .iter()
+                .position(|mode| *mode == self.mode)
+                .unwrap_or(0);
+            self.set_mode(self.supported[(index + 1) % self.supported.len()]);
+            log::info!("Present mode: {:?}", self.mode);
+        }
+        true
+    }
+}
@@ -0,0 +1,227 @@
+use std::iter;
+
+use winit::{dpi::PhysicalSize, window::Window};
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    exposure, inset,
+    overlay::{ChunkBounds, OverlaySettings},
+    raytracing, render, tonemap,
+    window::InitError,
+    world::{Sun, World},
+};
+
+// A second window showing the world through its own camera, e.g. a preview
+// next to the main (editor) window. It has its own surface, targets and
+// camera but shares the device, queue and world with the main window.
+pub struct PreviewWindow {
+    pub camera: Camera,
+    // Copies the main camera every frame
+    pub follow_main: bool,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    pub raytracing: raytracing::RaytracingPipeline,
+    tonemap: tonemap::TonemapPipeline,
+    render: render::RenderPipeline,
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+    // Declared last so the surface is dropped before its window
+    window: Window,
+}
+
+impl PreviewWindow {
+    pub fn new(
+        instance: &wgpu::Instance,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        window: Window,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        main_camera: &Camera,
+    ) -> Result<PreviewWindow, InitError> {
+        let size = window.inner_size();
+        let size = PhysicalSize::new(size.width.max(1), size.height.max(1));
+
+        // # Safety
+        //
+        // The surface is dropped before the window, see the field order
+        let surface = unsafe { instance.create_surface(&window) }?;
+        let surface_caps = surface.get_capabilities(adapter);
+        let format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .or_else(|| surface_caps.formats.first().copied())
+            .ok_or(InitError::UnsupportedSurface)?;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(device, &config);
+
+        let camera_uniform = CameraUniform::new();
+        let camera_buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Preview Camera Buffer"),
+                contents: bytemuck::cast_slice(&[camera_uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("preview_camera_bind_group"),
+        });
+
+        let raytracing =
+            raytracing::RaytracingPipeline::new(device, &size, camera_bind_group_layout);
+        let tonemap = tonemap::TonemapPipeline::new(device);
+        let render = inset::create_render_pipeline(device, &config, &raytracing, &tonemap);
+
+        Ok(PreviewWindow {
+            camera: main_camera.clone(),
+            follow_main: true,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            raytracing,
+            tonemap,
+            render,
+            surface,
+            config,
+            window,
+        })
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    // Draws the main window's world, see RaytracingPipeline::set_world
+    pub fn set_world(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, world: &World) {
+        self.raytracing.set_world(device, queue, world);
+        self.render =
+            inset::create_render_pipeline(device, &self.config, &self.raytracing, &self.tonemap);
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.surface.configure(device, &self.config);
+        self.raytracing.resize(device, size);
+        self.render =
+            inset::create_render_pipeline(device, &self.config, &self.raytracing, &self.tonemap);
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        main_camera: &Camera,
+        sun: &Sun,
+        main_tonemap: &tonemap::TonemapPipeline,
+    ) {
+        if self.follow_main {
+            self.camera = main_camera.clone();
+        }
+
+        self.camera_uniform.update_view(&self.camera);
+        self.camera_uniform.update_view_proj(
+            &self.camera,
+            self.config.width,
+            self.config.height,
+            [0., 0.],
+        );
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+
+        let overlay = OverlaySettings {
+            crosshair: false,
+            highlight_picked: false,
+            chunk_bounds: ChunkBounds::Off,
+            grid: false,
+        };
+        self.raytracing.settings.sun = *sun;
+        self.raytracing
+            .update(device, queue, &overlay, [0., 0.], None);
+
+        self.tonemap.settings.tonemapper = main_tonemap.settings.tonemapper;
+        self.tonemap.settings.exposure = main_tonemap.settings.exposure;
+        self.tonemap.update(queue, false);
+    }
+
+    // Ray traces and presents a frame. auto_exposure is the main window's
+    // exposure state, copied over on the GPU like for the inset.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        auto_exposure: Option<&wgpu::Buffer>,
+    ) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Preview Encoder"),
+        });
+
+        {
+            let mut ray_tracing_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Preview ray tracing pass"),
+            });
+
+            self.raytracing
+                .dispatch(&mut ray_tracing_pass, &self.camera_bind_group);
+        }
+        if let Some(state) = auto_exposure {
+            encoder.copy_buffer_to_buffer(
+                state,
+                exposure::EXPOSURE_OFFSET,
+                &self.tonemap.buffer,
+                0,
+                4,
+            );
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Preview Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&self.render.pipeline);
+            render_pass.set_bind_group(0, &self.render.bind_group, &[]);
+            render_pass.set_bind_group(1, &self.tonemap.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        queue.submit(iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+}
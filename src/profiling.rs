@@ -0,0 +1,204 @@
+use winit::event::*;
+
+use crate::readback::ReadbackRing;
+
+// Scopes a frame starts out with room for, grown by reserve
+const INITIAL_SCOPES: u32 = 16;
+const READBACK_BUFFERS: usize = 3;
+// Seconds between logged timings
+const LOG_INTERVAL: f32 = 2.;
+
+// Measures the GPU time of a whole frame, and of the groups of passes between
+// the scopes ended in it, with timestamp queries. Results are read back
+// through a ReadbackRing, so they arrive a few frames late but never stall the
+// render loop. F2 logs the timings every few seconds.
+pub struct FrameTimer {
+    pub logging: bool,
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    // Tagged with the scope ending at each timestamp after the first
+    readbacks: ReadbackRing<Vec<&'static str>>,
+    // Scopes the query set has timestamps for, besides the frame's start
+    capacity: u32,
+    // Scopes ended so far in the frame being recorded
+    scopes: Vec<&'static str>,
+    // The frame's timestamps went to a query set replaced by reserve
+    skipped: bool,
+    // Whether a scope that didn't fit was already reported
+    overflowed: bool,
+    period: f32,
+    pub last_frame_time: Option<f32>,
+    // Milliseconds of every scope of the last measured frame, in order
+    pub timings: Vec<(&'static str, f32)>,
+    log_elapsed: f32,
+}
+
+impl FrameTimer {
+    // Returns None if the device was created without timestamp queries
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<FrameTimer> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let (query_set, resolve_buffer, readbacks) = create_queries(device, INITIAL_SCOPES);
+        Some(FrameTimer {
+            logging: false,
+            query_set,
+            resolve_buffer,
+            readbacks,
+            capacity: INITIAL_SCOPES,
+            scopes: Vec::new(),
+            skipped: false,
+            overflowed: false,
+            period: queue.get_timestamp_period(),
+            last_frame_time: None,
+            timings: Vec::new(),
+            log_elapsed: 0.,
+        })
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::F2 => {
+                if state == ElementState::Pressed {
+                    self.logging = !self.logging;
+                    log::info!("GPU profiler logging: {}", self.logging);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.scopes.clear();
+        self.skipped = false;
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    // Makes room for the scopes of a frame, e.g. one per render graph node
+    // and the one ended by end. Growing replaces the query set the frame
+    // started on, so that frame isn't measured.
+    pub fn reserve(&mut self, device: &wgpu::Device, scopes: usize) {
+        let scopes = scopes as u32;
+        if scopes <= self.capacity {
+            return;
+        }
+        let capacity = scopes.next_power_of_two();
+        (self.query_set, self.resolve_buffer, self.readbacks) = create_queries(device, capacity);
+        self.capacity = capacity;
+        self.skipped = true;
+    }
+
+    // Ends the scope that started at the previous timestamp
+    pub fn scope(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        if self.skipped {
+            return;
+        }
+        // The last timestamp is kept for end
+        if self.scopes.len() as u32 + 1 >= self.capacity {
+            if !self.overflowed {
+                log::warn!(
+                    "More than {} GPU profiler scopes, {} and later ones are measured together",
+                    self.capacity - 1,
+                    name
+                );
+                self.overflowed = true;
+            }
+            return;
+        }
+        self.scopes.push(name);
+        encoder.write_timestamp(&self.query_set, self.scopes.len() as u32);
+    }
+
+    // Ends the frame, and with it the last scope
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        if self.skipped {
+            return;
+        }
+        self.scopes.push(name);
+        let count = self.scopes.len() as u32 + 1;
+        encoder.write_timestamp(&self.query_set, count - 1);
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+
+        // If every readback is still in flight this frame simply isn't measured
+        if let Some(buffer) = self.readbacks.begin(self.scopes.clone()) {
+            encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, buffer, 0, count as u64 * 8);
+        }
+    }
+
+    // Call after the frame's command buffer has been submitted
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        self.readbacks.after_submit(device);
+
+        let period = self.period;
+        let milliseconds =
+            |start: u64, end: u64| end.wrapping_sub(start) as f32 * period / 1_000_000.;
+        let (last_frame_time, timings) = (&mut self.last_frame_time, &mut self.timings);
+        self.readbacks.read(|scopes, data| {
+            let timestamps: &[u64] = bytemuck::cast_slice(data);
+            let timestamps = &timestamps[..scopes.len() + 1];
+            *last_frame_time = Some(milliseconds(
+                timestamps[0],
+                timestamps[timestamps.len() - 1],
+            ));
+            *timings = scopes
+                .iter()
+                .zip(timestamps.windows(2))
+                .map(|(name, pair)| (*name, milliseconds(pair[0], pair[1])))
+                .collect();
+        });
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if !self.logging {
+            return;
+        }
+        self.log_elapsed += dt;
+        if self.log_elapsed < LOG_INTERVAL {
+            return;
+        }
+        self.log_elapsed = 0.;
+
+        let timings: Vec<String> = self
+            .timings
+            .iter()
+            .map(|(name, time)| format!("{} {:.2} ms", name, time))
+            .collect();
+        log::info!("GPU: {}", timings.join(", "));
+    }
+}
+
+// Timestamps for the frame's start and capacity scopes, and where they are
+// resolved to and read back from
+fn create_queries(
+    device: &wgpu::Device,
+    capacity: u32,
+) -> (
+    wgpu::QuerySet,
+    wgpu::Buffer,
+    ReadbackRing<Vec<&'static str>>,
+) {
+    let count = capacity + 1;
+    let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+        label: Some("Frame timestamps"),
+        ty: wgpu::QueryType::Timestamp,
+        count,
+    });
+
+    let size = count as u64 * 8;
+    let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Frame timestamp resolve buffer"),
+        size,
+        usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+
+    let readbacks = ReadbackRing::new(
+        device,
+        "Frame timestamp readback buffer",
+        size,
+        READBACK_BUFFERS,
+    );
+    (query_set, resolve_buffer, readbacks)
+}
@@ -1,11 +1,572 @@
+use std::{collections::HashMap, sync::Arc};
+
+use instant::{Duration, Instant};
+use nalgebra::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
 use wgpu::BindGroupLayout;
-use winit::dpi::PhysicalSize;
+use winit::{dpi::PhysicalSize, event::*};
+
+use crate::{
+    brick_pool,
+    camera::CameraUniform,
+    checkerboard::Pattern,
+    jobs::{Job, JobSystem},
+    occupancy,
+    overlay::{ChunkBounds, OverlaySettings},
+    palette,
+    preprocess::{self, ShaderCache},
+    residency,
+    uniforms::{self, UniformArena},
+    world::{Sun, VoxelGrid, World},
+};
+
+// Bits of RaytracingUniform::flags, must match ray-tracing.wgsl
+const WRITE_ALBEDO: u32 = 1;
+const WRITE_NORMAL: u32 = 2;
+const WRITE_DEPTH: u32 = 4;
+const HIGHLIGHT_PICKED: u32 = 8;
+const SHOW_CHUNK_BOUNDS: u32 = 16;
+const SHOW_BRICK_BOUNDS: u32 = 32;
+const BEAM_OPTIMIZATION: u32 = 64;
+const HALF_RES_LIGHTING: u32 = 128;
+const CHECKERBOARD: u32 = 256;
+const WAVEFRONT: u32 = 512;
+const TILED: u32 = 1024;
+const ADAPTIVE_SAMPLING: u32 = 2048;
+const INTERLEAVE_ROWS: u32 = 4096;
+const INTERLEAVE_COLUMNS: u32 = 8192;
+const SKY_TILES: u32 = 16384;
+
+// Pixels per side of the tiles traced by the beam pre-pass
+pub const BEAM_TILE_SIZE: u32 = 8;
+// Pixels of a texel of the noise mask, every workgroup size covers whole
+// blocks. Must match NOISE_BLOCK_* in ray-tracing.wgsl and accumulate.wgsl.
+pub const NOISE_BLOCK_WIDTH: u32 = 8;
+pub const NOISE_BLOCK_HEIGHT: u32 = 4;
+
+// Replaces the shaded color with a visualization of the traversal, must
+// match the DEBUG_* constants in ray-tracing.wgsl
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugView {
+    None,
+    Normals,
+    Depth,
+    StepCount,
+    ChunkId,
+    // Chunks in the frustum green, resident outside it yellow, occluded
+    // purple and culled red
+    Residency,
+}
+
+impl DebugView {
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::None => DebugView::Normals,
+            DebugView::Normals => DebugView::Depth,
+            DebugView::Depth => DebugView::StepCount,
+            DebugView::StepCount => DebugView::ChunkId,
+            DebugView::ChunkId => DebugView::Residency,
+            DebugView::Residency => DebugView::None,
+        }
+    }
+}
+
+// Renders two eye positions for cheap stereo viewing, must match the
+// STEREO_* constants in ray-tracing.wgsl
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StereoMode {
+    Off,
+    SideBySide,
+    // Red-cyan, traces both eyes for every pixel
+    Anaglyph,
+}
+
+impl StereoMode {
+    pub fn next(self) -> Self {
+        match self {
+            StereoMode::Off => StereoMode::SideBySide,
+            StereoMode::SideBySide => StereoMode::Anaglyph,
+            StereoMode::Anaglyph => StereoMode::Off,
+        }
+    }
+}
+
+const EYE_SEPARATION_STEP: f32 = 1.25;
+
+// Main passes timed for every workgroup size when tuning
+const TUNING_DISPATCHES: u32 = 8;
+
+// Threads per workgroup of the passes working on a pixel each, compiled into
+// the shader. Which is fastest depends on the GPU, see
+// RaytracingPipeline::tune_workgroup_size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub enum WorkgroupSize {
+    #[serde(rename = "8x8")]
+    Size8x8,
+    #[serde(rename = "16x16")]
+    Size16x16,
+    #[serde(rename = "8x4")]
+    Size8x4,
+}
+
+impl WorkgroupSize {
+    pub const ALL: [WorkgroupSize; 3] = [
+        WorkgroupSize::Size8x8,
+        WorkgroupSize::Size16x16,
+        WorkgroupSize::Size8x4,
+    ];
+
+    pub fn dimensions(self) -> (u32, u32) {
+        match self {
+            WorkgroupSize::Size8x8 => (8, 8),
+            WorkgroupSize::Size16x16 => (16, 16),
+            WorkgroupSize::Size8x4 => (8, 4),
+        }
+    }
+
+    fn defines(self) -> [(&'static str, &'static str); 2] {
+        let (width, height) = match self {
+            WorkgroupSize::Size8x8 => ("8", "8"),
+            WorkgroupSize::Size16x16 => ("16", "16"),
+            WorkgroupSize::Size8x4 => ("8", "4"),
+        };
+        [("WORKGROUP_WIDTH", width), ("WORKGROUP_HEIGHT", height)]
+    }
+}
+
+// How raytrace moves between the 64, 8 and 1 voxel levels, compiled into the
+// shader. Compare them with the benchmark's per pass timings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Traversal {
+    // Descends once through the levels, a ray that leaves an occupied coarse
+    // cell without a hit ends there
+    Descend,
+    // Goes back up a level when a cell is left without a hit, the parent
+    // cell is found again from the position instead of a stack
+    Stackless,
+}
+
+impl Traversal {
+    pub const ALL: [Traversal; 2] = [Traversal::Descend, Traversal::Stackless];
+}
+
+// Which auxiliary outputs (AOVs) the ray tracing pass writes besides color
+// and motion. Disabled outputs keep their previous contents.
+#[derive(Debug)]
+pub struct RaytracingSettings {
+    pub write_albedo: bool,
+    pub write_normal: bool,
+    pub write_depth: bool,
+    pub debug_view: DebugView,
+    pub stereo: StereoMode,
+    // Interpupillary distance in world units
+    pub eye_separation: f32,
+    // Start primary rays at the distance found by the low resolution beam pre-pass
+    pub beam_optimization: bool,
+    // Trace lighting at half resolution and upsample it with a depth and
+    // normal aware filter, primary visibility stays at full resolution
+    pub half_res_lighting: bool,
+    // Trace shadow rays towards the sun
+    pub shadows: bool,
+    // Split the work of main into passes for primary hits, shadow rays and
+    // shading. Shadow rays are queued by the pixels that need them, so the
+    // shadow pass runs full workgroups instead of idling on pixels facing
+    // away from the sun or looking at the sky. Not used with stereo.
+    pub wavefront: bool,
+    // Classify the workgroups of main into sky, near and far tiles first and
+    // dispatch each class indirectly over just its tiles. Sky tiles skip the
+    // traversal and far tiles the shadow rays.
+    pub tiled: bool,
+    // Just the sky part of tiled dispatch: a cheap pre-pass finds the tiles
+    // whose rays all miss the world, which are filled with the sky without
+    // the traversal. Implied by tiled.
+    pub sky_tiles: bool,
+    // Tiles whose nearest surface is farther away count as far
+    pub far_distance: f32,
+    // Only trace the tiles with a block the noise mask marks as noisy, over
+    // the tile passes of tiled dispatch. Set by whoever accumulates the
+    // samples and writes the mask, see capture::AdaptiveSampling.
+    pub adaptive_sampling: bool,
+    pub workgroup_size: WorkgroupSize,
+    pub traversal: Traversal,
+    pub render: RenderSettings,
+    pub sun: Sun,
+}
+
+impl RaytracingSettings {
+    pub fn new() -> Self {
+        Self {
+            write_albedo: true,
+            write_normal: true,
+            write_depth: true,
+            debug_view: DebugView::None,
+            stereo: StereoMode::Off,
+            eye_separation: 0.5,
+            beam_optimization: true,
+            half_res_lighting: false,
+            shadows: true,
+            wavefront: false,
+            tiled: false,
+            sky_tiles: false,
+            far_distance: 1024.,
+            adaptive_sampling: false,
+            workgroup_size: WorkgroupSize::Size16x16,
+            traversal: Traversal::Descend,
+            render: RenderSettings::new(),
+            sun: Sun::new(),
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::V => {
+                if state == ElementState::Pressed {
+                    self.debug_view = self.debug_view.next();
+                    log::info!("Debug view: {:?}", self.debug_view);
+                }
+                true
+            }
+            VirtualKeyCode::N => {
+                if state == ElementState::Pressed {
+                    self.stereo = self.stereo.next();
+                    log::info!("Stereo: {:?}", self.stereo);
+                }
+                true
+            }
+            VirtualKeyCode::Y => {
+                if state == ElementState::Pressed {
+                    self.beam_optimization = !self.beam_optimization;
+                    log::info!("Beam optimization: {}", self.beam_optimization);
+                }
+                true
+            }
+            VirtualKeyCode::L => {
+                if state == ElementState::Pressed {
+                    self.half_res_lighting = !self.half_res_lighting;
+                    log::info!("Half resolution lighting: {}", self.half_res_lighting);
+                }
+                true
+            }
+            VirtualKeyCode::Comma | VirtualKeyCode::Period => {
+                if state == ElementState::Pressed {
+                    if key == VirtualKeyCode::Comma {
+                        self.eye_separation /= EYE_SEPARATION_STEP;
+                    } else {
+                        self.eye_separation *= EYE_SEPARATION_STEP;
+                    }
+                    log::info!("Eye separation: {}", self.eye_separation);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn wavefront_enabled(&self) -> bool {
+        self.wavefront && self.stereo == StereoMode::Off
+    }
+}
+
+impl Default for RaytracingSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Limits of the traversal that trade quality for speed at runtime, in a
+// binding of their own. Must match RenderSettings in ray-tracing.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RenderSettings {
+    // Rays end as misses once they got this far
+    pub max_distance: f32,
+    // DDA iterations over all levels before a ray ends as a miss
+    pub max_steps: u32,
+    // Hits on bricks and chunks farther away than 64 times their size
+    // divided by this are kept without descending into them, 0 always
+    // descends
+    pub lod_bias: f32,
+    _padding: u32,
+}
+
+impl RenderSettings {
+    // Limits that cover everything within reach, ray tracing looks the same
+    // as without them
+    pub fn new() -> Self {
+        Self::for_reach(occupancy::REACH)
+    }
+
+    // Rays may cross the distance along all three axes at the chunk level,
+    // and take as many steps again for each finer level they descend into
+    pub fn for_reach(reach: f32) -> Self {
+        let chunk_steps = (reach / 64.).ceil() as u32 * 3;
+        Self {
+            max_distance: reach,
+            max_steps: chunk_steps * 3,
+            lod_bias: 0.,
+            _padding: 0,
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RaytracingUniform {
+    flags: u32,
+    debug_view: u32,
+    cursor: [f32; 2],
+    stereo: u32,
+    eye_separation: f32,
+    // Which half of the pixels is traced with checkerboard rendering or
+    // interleaving
+    frame_parity: u32,
+    far_distance: f32,
+    sun_direction: [f32; 4],
+    // Brick coordinates of the occupancy window's first column in x and z,
+    // the terrain's top in y. w is 0 while the occupancy textures aren't
+    // usable.
+    occupancy_origin: [i32; 4],
+    // Pixels from xy up to zw traced at both parities with foveated
+    // checkerboarding
+    fovea: [u32; 4],
+    // First voxel of the grid texture, w is 0 while the terrain is drawn
+    grid_origin: [i32; 4],
+}
+
+impl RaytracingUniform {
+    pub fn new() -> Self {
+        Self {
+            flags: 0,
+            debug_view: 0,
+            cursor: [0.; 2],
+            stereo: 0,
+            eye_separation: 0.,
+            frame_parity: 0,
+            far_distance: 0.,
+            sun_direction: Sun::new().direction().push(0.).into(),
+            occupancy_origin: [0; 4],
+            fovea: [0; 4],
+            grid_origin: [0; 4],
+        }
+    }
+
+    pub fn update_cursor(&mut self, cursor: [f32; 2]) {
+        self.cursor = cursor;
+    }
+
+    pub fn update_occupancy(&mut self, origin: Option<Vector2<i32>>, top: i32) {
+        self.occupancy_origin = match origin {
+            Some(origin) => [origin.x, top, origin.y, 1],
+            None => [0; 4],
+        };
+    }
+
+    pub fn update_grid(&mut self, origin: Option<Vector3<i32>>) {
+        self.grid_origin = match origin {
+            Some(origin) => [origin.x, origin.y, origin.z, 1],
+            None => [0; 4],
+        };
+    }
+
+    pub fn update_fovea(&mut self, fovea: Option<[u32; 4]>) {
+        self.fovea = fovea.unwrap_or([0; 4]);
+    }
+
+    pub fn update_checkerboard(&mut self, parity: Option<(u32, Pattern)>) {
+        self.flags &= !(CHECKERBOARD | INTERLEAVE_ROWS | INTERLEAVE_COLUMNS);
+        if let Some((parity, pattern)) = parity {
+            self.flags |= CHECKERBOARD;
+            match pattern {
+                Pattern::Checkerboard => {}
+                Pattern::Rows => self.flags |= INTERLEAVE_ROWS,
+                Pattern::Columns => self.flags |= INTERLEAVE_COLUMNS,
+            }
+            self.frame_parity = parity;
+        }
+    }
+
+    pub fn update(&mut self, settings: &RaytracingSettings, overlay: &OverlaySettings) {
+        let mut flags = 0;
+        if settings.write_albedo {
+            flags |= WRITE_ALBEDO;
+        }
+        if settings.write_normal {
+            flags |= WRITE_NORMAL;
+        }
+        if settings.write_depth {
+            flags |= WRITE_DEPTH;
+        }
+        if settings.beam_optimization {
+            flags |= BEAM_OPTIMIZATION;
+        }
+        if settings.half_res_lighting && settings.stereo == StereoMode::Off {
+            flags |= HALF_RES_LIGHTING;
+        }
+        if settings.wavefront_enabled() {
+            flags |= WAVEFRONT;
+        }
+        if settings.tiled {
+            flags |= TILED;
+        }
+        if settings.sky_tiles {
+            flags |= SKY_TILES;
+        }
+        if settings.adaptive_sampling {
+            flags |= ADAPTIVE_SAMPLING;
+        }
+        if overlay.highlight_picked {
+            flags |= HIGHLIGHT_PICKED;
+        }
+        match overlay.chunk_bounds {
+            ChunkBounds::Off => {}
+            ChunkBounds::Chunks => flags |= SHOW_CHUNK_BOUNDS,
+            ChunkBounds::ChunksAndBricks => flags |= SHOW_CHUNK_BOUNDS | SHOW_BRICK_BOUNDS,
+        }
+        self.flags = flags;
+        self.debug_view = settings.debug_view as u32;
+        self.stereo = settings.stereo as u32;
+        self.eye_separation = settings.eye_separation;
+        self.far_distance = settings.far_distance;
+        self.sun_direction = settings.sun.direction().push(0.).into();
+    }
+}
+
+impl Default for RaytracingUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Features compiled into the ray tracing shader with preprocessor defines
+// instead of branching on the uniform. Each combination has its own
+// pipelines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderVariant {
+    pub shadows: bool,
+    pub debug_views: bool,
+    pub workgroup_size: WorkgroupSize,
+    pub traversal: Traversal,
+}
+
+impl ShaderVariant {
+    pub fn new(settings: &RaytracingSettings) -> Self {
+        Self {
+            shadows: settings.shadows,
+            debug_views: settings.debug_view != DebugView::None,
+            workgroup_size: settings.workgroup_size,
+            traversal: settings.traversal,
+        }
+    }
+
+    fn defines(&self) -> Vec<(&'static str, &'static str)> {
+        let mut defines = Vec::from(self.workgroup_size.defines());
+        if self.shadows {
+            defines.push(("SHADOWS", ""));
+        }
+        if self.debug_views {
+            defines.push(("DEBUG_VIEWS", ""));
+        }
+        if self.traversal == Traversal::Stackless {
+            defines.push(("STACKLESS", ""));
+        }
+        defines
+    }
+}
+
+// Pipelines for the entry points of one shader variant
+pub struct RaytracingPipelines {
+    pub main: wgpu::ComputePipeline,
+    // Traces a single ray through the cursor into pick_buffer
+    pub pick: wgpu::ComputePipeline,
+    // Writes the per tile starting distance read by the main pipeline
+    pub beam: wgpu::ComputePipeline,
+    // Traces sun light at half resolution, upsampled by the main pipeline
+    pub lighting: wgpu::ComputePipeline,
+    // Wavefront mode passes, run after main
+    pub prepare_shadows: wgpu::ComputePipeline,
+    pub trace_shadows: wgpu::ComputePipeline,
+    pub shade: wgpu::ComputePipeline,
+    // Tiled dispatch passes, run instead of main
+    pub classify: wgpu::ComputePipeline,
+    pub prepare_tiles: wgpu::ComputePipeline,
+    pub sky_tiles: wgpu::ComputePipeline,
+    pub near_tiles: wgpu::ComputePipeline,
+    pub far_tiles: wgpu::ComputePipeline,
+    // Traces the rest of the fovea with foveated checkerboarding, run last
+    pub fovea: wgpu::ComputePipeline,
+    // Module the pipelines were created from, see ShaderCache
+    shader: Arc<wgpu::ShaderModule>,
+}
 
 pub struct RaytracingPipeline {
-    pub pipeline: wgpu::ComputePipeline,
+    pub settings: RaytracingSettings,
+    pub uniform: RaytracingUniform,
+    // Holds the uniform and settings.render, written once per update
+    pub uniforms: UniformArena,
+    // Offsets of the uniform and settings.render in uniforms, set by update
+    uniform_offsets: [u32; 2],
+    pub pick_buffer: wgpu::Buffer,
+    // Chunks the traversal may enter, written by residency::ChunkResidency.
+    // Zeroed, every chunk is resident.
+    pub residency_buffer: wgpu::Buffer,
+    // Brick and chunk occupancy bitmasks, built for occupancy::BrickOccupancy
+    pub occupancy: occupancy::OccupancyPyramid,
+    // Voxels of the bricks near the camera and the table of their slots,
+    // written by brick_pool::BrickPool. Zeroed, no brick is pooled.
+    pub brick_pool: wgpu::Texture,
+    pub pool_table: wgpu::Buffer,
+    brick_pool_view: wgpu::TextureView,
+    // Voxels of the grid drawn in place of the terrain, see set_world. A
+    // single empty voxel while the terrain is drawn.
+    grid: wgpu::Texture,
+    grid_view: wgpu::TextureView,
+    // Colors of the materials the pool's voxels index, see palette
+    pub palette_buffer: wgpu::Buffer,
+    // Workgroup count of the shadow pass in wavefront mode
+    pub shadow_dispatch_buffer: wgpu::Buffer,
+    // Workgroup counts of the sky, near and far tile passes
+    pub tile_dispatch_buffer: wgpu::Buffer,
+    pub dispatch_bind_group: wgpu::BindGroup,
+    // Variant used for the next dispatch, always in variants
+    variant: ShaderVariant,
+    // Every variant used so far, so toggling back doesn't recompile
+    variants: HashMap<ShaderVariant, RaytracingPipelines>,
+    // Variant shaders by their preprocessed source, shared with the workers
+    shaders: Arc<ShaderCache>,
+    // Variants being compiled on workers, see compile_in_background
+    compiling: HashMap<ShaderVariant, Job<Result<RaytracingPipelines, String>>>,
+    // Unpreprocessed shader source and the includes it is preprocessed with
+    source: String,
+    include: fn(&str) -> Result<String, String>,
+    // Whether source was reloaded at runtime and may not compile
+    reloaded: bool,
+    // Shared with the workers compiling variants
+    pipeline_layout: Arc<wgpu::PipelineLayout>,
+    prepass_pipeline_layout: Arc<wgpu::PipelineLayout>,
+    dispatch_pipeline_layout: Arc<wgpu::PipelineLayout>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    prepass_write_layout: wgpu::BindGroupLayout,
+    prepass_read_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
+    pub prepass_write_bind_group: wgpu::BindGroup,
+    pub prepass_read_bind_group: wgpu::BindGroup,
     pub sampler: wgpu::Sampler,
     pub texture: wgpu::TextureView,
+    pub motion: wgpu::TextureView,
+    pub albedo: wgpu::TextureView,
+    pub normal: wgpu::TextureView,
+    pub depth: wgpu::TextureView,
+    // A texel per noise block, non-zero where adaptive sampling still
+    // traces. Written by capture::CapturePipeline.
+    pub noise_mask: wgpu::TextureView,
+    pub size: PhysicalSize<u32>,
 }
 
 impl RaytracingPipeline {
@@ -14,73 +575,1269 @@ impl RaytracingPipeline {
         size: &PhysicalSize<u32>,
         camera_bind_group_layout: &BindGroupLayout,
     ) -> RaytracingPipeline {
-        let raytrace_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Ray tracing shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/ray-tracing.wgsl").into()),
+        let settings = RaytracingSettings::new();
+        let uniform = RaytracingUniform::new();
+
+        // Room for the camera as well, see CameraPipeline::bind_to_arena
+        let uniforms = UniformArena::new(
+            device,
+            "Ray tracing uniforms",
+            &[
+                std::mem::size_of::<CameraUniform>(),
+                std::mem::size_of::<RaytracingUniform>(),
+                std::mem::size_of::<RenderSettings>(),
+            ],
+        );
+
+        let pick_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick result buffer"),
+            size: 48,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
-        let color_buffer = device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: size.width,
-                height: size.height,
-                depth_or_array_layers: 1,
+        let residency_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Chunk residency buffer"),
+            size: residency::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let occupancy = occupancy::OccupancyPyramid::new(device);
+
+        let brick_pool = brick_pool::create_pool(device);
+        let brick_pool_view = brick_pool.create_view(&wgpu::TextureViewDescriptor::default());
+        let pool_table = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Brick pool table buffer"),
+            size: brick_pool::TABLE_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let grid = create_grid(device, None);
+        let grid_view = grid.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let palette_buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Material palette buffer"),
+                contents: bytemuck::cast_slice(&palette::default_colors()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             },
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: Some("Color buffer texture"),
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            view_formats: &[],
+        );
+
+        let shadow_dispatch_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow dispatch buffer"),
+            size: 12,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
         });
 
-        let color_buffer_view = color_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+        let tile_dispatch_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile dispatch buffer"),
+            size: 36,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
 
-        let color_buffer_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        // Bilinear, so a lower render scale is upscaled smoothly
+        let color_buffer_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Color buffer sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
+        let storage_entry =
+            |binding: u32, format: wgpu::TextureFormat| wgpu::BindGroupLayoutEntry {
+                binding,
                 visibility: wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::StorageTexture {
                     access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    format,
                     view_dimension: wgpu::TextureViewDimension::D2,
                 },
                 count: None,
-            }],
+            };
+
+        let buffer_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uint_texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Uint,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage_entry(0, wgpu::TextureFormat::Rgba16Float),
+                storage_entry(1, wgpu::TextureFormat::Rgba32Float),
+                storage_entry(2, wgpu::TextureFormat::Rgba8Unorm),
+                storage_entry(3, wgpu::TextureFormat::Rgba16Float),
+                storage_entry(4, wgpu::TextureFormat::R32Float),
+                uniforms::layout_entry::<RaytracingUniform>(5, wgpu::ShaderStages::COMPUTE),
+                buffer_entry(6),
+                buffer_entry(7),
+                buffer_entry(8),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                buffer_entry(10),
+                uint_texture_entry(11),
+                uint_texture_entry(12),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 15,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                uniforms::layout_entry::<RenderSettings>(16, wgpu::ShaderStages::COMPUTE),
+                uint_texture_entry(17),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 18,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
             label: Some("color buffer bind group layout"),
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Ray tracing bind group"),
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&color_buffer_view),
-            }],
+        // Outputs of the pre-passes (beam distances and half resolution lighting)
+        // are written and read by different dispatches, so they live in their
+        // own group with a layout for each
+        let prepass_write_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    storage_entry(0, wgpu::TextureFormat::R32Float),
+                    storage_entry(1, wgpu::TextureFormat::Rgba32Float),
+                ],
+                label: Some("prepass write bind group layout"),
+            });
+
+        let read_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let prepass_read_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[read_entry(2), read_entry(3)],
+                label: Some("prepass read bind group layout"),
+            });
+
+        // The dispatch arguments can't be bound while the shadow and tile
+        // passes are dispatched with them, so they take the place of the
+        // pre-pass outputs for the dispatches writing them
+        let dispatch_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[buffer_entry(4), buffer_entry(5)],
+            label: Some("dispatch bind group layout"),
+        });
+        let dispatch_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Dispatch bind group"),
+            layout: &dispatch_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: shadow_dispatch_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: tile_dispatch_buffer.as_entire_binding(),
+                },
+            ],
         });
 
+        let targets = create_targets(
+            device,
+            size,
+            TargetDesc {
+                bind_group_layout: &bind_group_layout,
+                prepass_write_layout: &prepass_write_layout,
+                prepass_read_layout: &prepass_read_layout,
+                uniforms: &uniforms,
+                pick_buffer: &pick_buffer,
+                residency_buffer: &residency_buffer,
+                occupancy_views: &occupancy.views,
+                pool_table: &pool_table,
+                brick_pool_view: &brick_pool_view,
+                grid_view: &grid_view,
+                palette_buffer: &palette_buffer,
+            },
+        );
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Ray tracing Pipeline Layout"),
-            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            bind_group_layouts: &[
+                &bind_group_layout,
+                camera_bind_group_layout,
+                &prepass_read_layout,
+            ],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Ray tracing pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &raytrace_shader,
-            entry_point: "main",
-        });
+        let prepass_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Prepass Pipeline Layout"),
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    camera_bind_group_layout,
+                    &prepass_write_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let dispatch_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Shadow dispatch Pipeline Layout"),
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    camera_bind_group_layout,
+                    &dispatch_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let source = include_str!("shaders/ray-tracing.wgsl").to_string();
+        let include = preprocess::builtin_include;
+        let variant = ShaderVariant::new(&settings);
+        let shaders = Arc::new(ShaderCache::new());
+        let shader =
+            variant_shader(device, &shaders, &source, include, variant).unwrap_or_else(|error| {
+                panic!("Couldn't preprocess the ray tracing shader: {}", error)
+            });
+        let pipelines = create_variant(
+            device,
+            shader,
+            &pipeline_layout,
+            &prepass_pipeline_layout,
+            &dispatch_pipeline_layout,
+        );
 
         RaytracingPipeline {
-            pipeline,
-            bind_group,
+            settings,
+            uniform,
+            uniforms,
+            uniform_offsets: [0; 2],
+            pick_buffer,
+            residency_buffer,
+            occupancy,
+            brick_pool,
+            pool_table,
+            brick_pool_view,
+            grid,
+            grid_view,
+            palette_buffer,
+            shadow_dispatch_buffer,
+            tile_dispatch_buffer,
+            dispatch_bind_group,
+            variant,
+            variants: HashMap::from([(variant, pipelines)]),
+            shaders,
+            compiling: HashMap::new(),
+            source,
+            include,
+            reloaded: false,
+            pipeline_layout: Arc::new(pipeline_layout),
+            prepass_pipeline_layout: Arc::new(prepass_pipeline_layout),
+            dispatch_pipeline_layout: Arc::new(dispatch_pipeline_layout),
+            bind_group_layout,
+            prepass_write_layout,
+            prepass_read_layout,
+            bind_group: targets.bind_group,
+            prepass_write_bind_group: targets.prepass_write_bind_group,
+            prepass_read_bind_group: targets.prepass_read_bind_group,
             sampler: color_buffer_sampler,
-            texture: color_buffer_view,
+            texture: targets.texture,
+            motion: targets.motion,
+            albedo: targets.albedo,
+            normal: targets.normal,
+            depth: targets.depth,
+            noise_mask: targets.noise_mask,
+            size: *size,
         }
     }
+
+    // Recreates the render targets and their bind groups at a new size. The
+    // pipelines don't depend on the size and are kept. Pipelines reading the
+    // targets, like the checkerboard reconstruction, have to be rebound.
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        let targets = create_targets(
+            device,
+            &size,
+            TargetDesc {
+                bind_group_layout: &self.bind_group_layout,
+                prepass_write_layout: &self.prepass_write_layout,
+                prepass_read_layout: &self.prepass_read_layout,
+                uniforms: &self.uniforms,
+                pick_buffer: &self.pick_buffer,
+                residency_buffer: &self.residency_buffer,
+                occupancy_views: &self.occupancy.views,
+                pool_table: &self.pool_table,
+                brick_pool_view: &self.brick_pool_view,
+                grid_view: &self.grid_view,
+                palette_buffer: &self.palette_buffer,
+            },
+        );
+        self.bind_group = targets.bind_group;
+        self.prepass_write_bind_group = targets.prepass_write_bind_group;
+        self.prepass_read_bind_group = targets.prepass_read_bind_group;
+        self.texture = targets.texture;
+        self.motion = targets.motion;
+        self.albedo = targets.albedo;
+        self.normal = targets.normal;
+        self.depth = targets.depth;
+        self.noise_mask = targets.noise_mask;
+        self.size = size;
+    }
+
+    // Draws the grid of world in place of the terrain, or the terrain again
+    // without one, with the world's palette. Recreates the targets like
+    // resize.
+    pub fn set_world(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, world: &World) {
+        let grid = world.grid();
+        queue.write_buffer(
+            &self.palette_buffer,
+            0,
+            bytemuck::cast_slice(&world.palette()[..]),
+        );
+        self.grid = create_grid(device, grid);
+        if let Some(grid) = grid {
+            write_grid(
+                queue,
+                &self.grid,
+                Vector3::zeros(),
+                grid.size(),
+                grid.voxels(),
+            );
+        }
+        self.grid_view = self
+            .grid
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.uniform.update_grid(grid.map(VoxelGrid::origin));
+        self.resize(device, self.size);
+    }
+
+    // Writes an edited voxel of the grid, voxels outside of it are only
+    // drawn from the brick pool
+    pub fn edit_grid(&self, queue: &wgpu::Queue, c: Vector3<i32>, material: u8) {
+        let [x, y, z, set] = self.uniform.grid_origin;
+        let local = c - Vector3::new(x, y, z);
+        let size = self.grid.size();
+        let size = Vector3::new(size.width, size.height, size.depth_or_array_layers);
+        if set != 0 && (0..3).all(|i| local[i] >= 0 && (local[i] as u32) < size[i]) {
+            write_grid(
+                queue,
+                &self.grid,
+                local.map(|v| v as u32),
+                Vector3::repeat(1),
+                &[material],
+            );
+        }
+    }
+
+    // Recreates everything on a new device after the old one was lost,
+    // keeping the settings and a shader reloaded at runtime
+    pub fn recreate(&mut self, device: &wgpu::Device, camera_bind_group_layout: &BindGroupLayout) {
+        let mut pipeline = RaytracingPipeline::new(device, &self.size, camera_bind_group_layout);
+        if self.reloaded {
+            if let Err(error) = pipeline.reload_shader(device, &self.source, self.include) {
+                log::warn!("Couldn't compile the reloaded shader again: {}", error);
+            }
+        }
+        std::mem::swap(&mut pipeline.settings, &mut self.settings);
+        *self = pipeline;
+    }
+
+    // Memory of the render targets, from their sizes and formats
+    pub fn target_bytes(&self) -> u64 {
+        let (width, height) = (self.size.width as u64, self.size.height as u64);
+        // Color, motion, albedo, normal and depth, then the wavefront hits
+        // and shadow queue
+        let full = width * height * (8 + 16 + 4 + 8 + 4 + 8 + 4);
+        let tile = BEAM_TILE_SIZE as u64;
+        let beam = width.div_ceil(tile) * height.div_ceil(tile) * 4;
+        let lighting = width.div_ceil(2) * height.div_ceil(2) * 16;
+        let tiles = tile_list_bytes(&self.size);
+        let noise = noise_mask_size(&self.size);
+        let mask = noise.width as u64 * noise.height as u64 * 4;
+        full + beam + lighting + tiles + mask
+    }
+
+    pub fn pipelines(&self) -> &RaytracingPipelines {
+        &self.variants[&self.variant]
+    }
+
+    // Swaps in pipelines compiled from new shader source, keeping the current
+    // ones if it doesn't compile. Other variants are compiled again when
+    // they are next used. Source that preprocesses to what the current
+    // variant was compiled from keeps its pipelines, and reloading the same
+    // source changes nothing. Blocks until the device reports errors.
+    pub fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+        source: &str,
+        include: fn(&str) -> Result<String, String>,
+    ) -> Result<(), String> {
+        let pipelines = compile_checked(device, || {
+            let shader = variant_shader(device, &self.shaders, source, include, self.variant)?;
+            if Arc::ptr_eq(&shader, &self.pipelines().shader) {
+                return Ok(None);
+            }
+            Ok(Some(create_variant(
+                device,
+                shader,
+                &self.pipeline_layout,
+                &self.prepass_pipeline_layout,
+                &self.dispatch_pipeline_layout,
+            )))
+        })?;
+        if pipelines.is_none() && source == self.source {
+            return Ok(());
+        }
+
+        self.source = source.to_string();
+        self.include = include;
+        self.reloaded = true;
+        let variant = self.variant;
+        match pipelines {
+            Some(pipelines) => self.variants = HashMap::from([(variant, pipelines)]),
+            None => self.variants.retain(|&other, _| other == variant),
+        }
+        // Compiled from the old source
+        self.compiling.clear();
+        Ok(())
+    }
+
+    // Starts compiling the variant for the current settings on a worker if
+    // it isn't compiled yet, so changing them doesn't stall a frame on the
+    // shader compiler. The current variant keeps rendering until update swaps
+    // the new one in. Shaders reloaded at runtime are still compiled in
+    // update, where their errors are caught.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn compile_in_background(&mut self, jobs: &JobSystem, device: &Arc<wgpu::Device>) {
+        let variant = ShaderVariant::new(&self.settings);
+        self.compile_variants_in_background(jobs, device, [variant]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn compile_variants_in_background(
+        &mut self,
+        jobs: &JobSystem,
+        device: &Arc<wgpu::Device>,
+        variants: impl IntoIterator<Item = ShaderVariant>,
+    ) {
+        if self.reloaded {
+            return;
+        }
+        for variant in variants {
+            if self.variants.contains_key(&variant) || self.compiling.contains_key(&variant) {
+                continue;
+            }
+            let device = device.clone();
+            let shaders = self.shaders.clone();
+            let source = self.source.clone();
+            let include = self.include;
+            let layouts = [
+                self.pipeline_layout.clone(),
+                self.prepass_pipeline_layout.clone(),
+                self.dispatch_pipeline_layout.clone(),
+            ];
+            let job = jobs.spawn(move || {
+                let shader = variant_shader(&device, &shaders, &source, include, variant)?;
+                Ok(create_variant(
+                    &device,
+                    shader,
+                    &layouts[0],
+                    &layouts[1],
+                    &layouts[2],
+                ))
+            });
+            self.compiling.insert(variant, job);
+        }
+    }
+
+    // Whether every one of variants can be used without compiling it first
+    pub fn variants_ready(&mut self, variants: impl IntoIterator<Item = ShaderVariant>) -> bool {
+        self.collect_compiled();
+        variants
+            .into_iter()
+            .all(|variant| self.variants.contains_key(&variant))
+    }
+
+    // Keeps the variants the workers finished
+    fn collect_compiled(&mut self) {
+        let finished: Vec<_> = self
+            .compiling
+            .iter()
+            .filter_map(|(variant, job)| job.poll().map(|result| (*variant, result)))
+            .collect();
+        for (variant, result) in finished {
+            self.compiling.remove(&variant);
+            match result {
+                Ok(pipelines) => {
+                    self.variants.insert(variant, pipelines);
+                }
+                Err(error) => log::warn!("Couldn't compile {:?}: {}", variant, error),
+            }
+        }
+    }
+
+    // Switches to the variant for the current settings, compiling it the
+    // first time it is used unless a worker is already compiling it
+    fn select_variant(&mut self, device: &wgpu::Device) {
+        self.collect_compiled();
+        let variant = ShaderVariant::new(&self.settings);
+        if self.compiling.contains_key(&variant) {
+            return;
+        }
+        if !self.variants.contains_key(&variant) {
+            let create = || {
+                let shader =
+                    variant_shader(device, &self.shaders, &self.source, self.include, variant)?;
+                Ok(create_variant(
+                    device,
+                    shader,
+                    &self.pipeline_layout,
+                    &self.prepass_pipeline_layout,
+                    &self.dispatch_pipeline_layout,
+                ))
+            };
+            // Built in shaders are known to compile, and error scopes can't
+            // be waited on on the web
+            let result = if self.reloaded {
+                compile_checked(device, create)
+            } else {
+                create()
+            };
+            match result {
+                Ok(pipelines) => {
+                    self.variants.insert(variant, pipelines);
+                }
+                Err(error) => {
+                    log::warn!("Couldn't compile {:?}: {}", variant, error);
+                    return;
+                }
+            }
+        }
+        self.variant = variant;
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        overlay: &OverlaySettings,
+        cursor: [f32; 2],
+        checkerboard: Option<(u32, Pattern)>,
+    ) {
+        self.select_variant(device);
+        self.uniform.update(&self.settings, overlay);
+        self.uniform.update_cursor(cursor);
+        self.uniform.update_checkerboard(checkerboard);
+        self.uniform_offsets = [
+            self.uniforms.push(&self.uniform),
+            self.uniforms.push(&self.settings.render),
+        ];
+        self.uniforms.flush(queue);
+    }
+
+    // Dynamic offsets to bind bind_group with
+    pub fn uniform_offsets(&self) -> &[u32] {
+        &self.uniform_offsets
+    }
+
+    // Traces the whole target from the given camera, without picking or
+    // checkerboarding. For views other than the main one.
+    pub fn dispatch<'a>(
+        &'a self,
+        pass: &mut wgpu::ComputePass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        pass.set_bind_group(0, &self.bind_group, &self.uniform_offsets);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, &self.prepass_write_bind_group, &[]);
+        if self.settings.beam_optimization {
+            pass.set_pipeline(&self.pipelines().beam);
+            pass.dispatch_workgroups(
+                self.size.width.div_ceil(BEAM_TILE_SIZE).div_ceil(8),
+                self.size.height.div_ceil(BEAM_TILE_SIZE).div_ceil(8),
+                1,
+            );
+        }
+        if self.settings.half_res_lighting {
+            pass.set_pipeline(&self.pipelines().lighting);
+            let (x, y) = self.workgroups(self.size.width.div_ceil(2), self.size.height.div_ceil(2));
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        pass.set_bind_group(2, &self.prepass_read_bind_group, &[]);
+        let workgroups = self.workgroups(self.size.width, self.size.height);
+        self.dispatch_main(pass, workgroups);
+        self.dispatch_wavefront(pass, workgroups);
+    }
+
+    // Workgroups covering width by height pixels with the current variant
+    pub fn workgroups(&self, width: u32, height: u32) -> (u32, u32) {
+        let (x, y) = self.variant.workgroup_size.dimensions();
+        (width.div_ceil(x), height.div_ceil(y))
+    }
+
+    // Times the main pass with every workgroup size and keeps the fastest.
+    // Waits for the GPU, so it is meant to run once at startup. The camera
+    // uniform has to be written already.
+    pub fn tune_workgroup_size(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_bind_group: &wgpu::BindGroup,
+    ) -> WorkgroupSize {
+        let mut fastest = (self.settings.workgroup_size, Duration::MAX);
+        for size in WorkgroupSize::ALL {
+            self.settings.workgroup_size = size;
+            self.select_variant(device);
+            if self.variant.workgroup_size != size {
+                continue;
+            }
+            // The first submission also waits for the pipeline to be ready
+            self.time_main_pass(device, queue, camera_bind_group, 1);
+            let time = self.time_main_pass(device, queue, camera_bind_group, TUNING_DISPATCHES);
+            log::info!(
+                "Workgroup size {:?}: {:.2} ms",
+                size,
+                time.as_secs_f32() * 1000. / TUNING_DISPATCHES as f32
+            );
+            if time < fastest.1 {
+                fastest = (size, time);
+            }
+        }
+        self.settings.workgroup_size = fastest.0;
+        self.select_variant(device);
+        fastest.0
+    }
+
+    fn time_main_pass(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_bind_group: &wgpu::BindGroup,
+        dispatches: u32,
+    ) -> Duration {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Workgroup tuning encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Workgroup tuning pass"),
+            });
+            pass.set_bind_group(0, &self.bind_group, &self.uniform_offsets);
+            pass.set_bind_group(1, camera_bind_group, &[]);
+            pass.set_bind_group(2, &self.prepass_read_bind_group, &[]);
+            pass.set_pipeline(&self.pipelines().main);
+            let (x, y) = self.workgroups(self.size.width, self.size.height);
+            for _ in 0..dispatches {
+                pass.dispatch_workgroups(x, y, 1);
+            }
+        }
+        let start = Instant::now();
+        queue.submit(Some(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+        start.elapsed()
+    }
+
+    // Dispatches main over workgroups, or with tiled dispatch classifies them
+    // and runs the tile pass of each class over just its tiles. Expects the
+    // bind groups main is dispatched with.
+    pub fn dispatch_main<'a>(&'a self, pass: &mut wgpu::ComputePass<'a>, workgroups: (u32, u32)) {
+        let pipelines = self.pipelines();
+        let settings = &self.settings;
+        if !settings.tiled && !settings.sky_tiles && !settings.adaptive_sampling {
+            pass.set_pipeline(&pipelines.main);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+            return;
+        }
+        pass.set_pipeline(&pipelines.classify);
+        pass.dispatch_workgroups(workgroups.0.div_ceil(8), workgroups.1.div_ceil(8), 1);
+        pass.set_bind_group(2, &self.dispatch_bind_group, &[]);
+        pass.set_pipeline(&pipelines.prepare_tiles);
+        pass.dispatch_workgroups(1, 1, 1);
+        pass.set_bind_group(2, &self.prepass_read_bind_group, &[]);
+        let tile_passes = [
+            &pipelines.sky_tiles,
+            &pipelines.near_tiles,
+            &pipelines.far_tiles,
+        ];
+        for (class, pipeline) in tile_passes.into_iter().enumerate() {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups_indirect(&self.tile_dispatch_buffer, class as u64 * 12);
+        }
+    }
+
+    // Traces the pixels of the fovea that checkerboarding skipped, see
+    // checkerboard::CheckerboardPipeline::fovea. Expects the bind groups main
+    // was dispatched with.
+    pub fn dispatch_fovea<'a>(
+        &'a self,
+        pass: &mut wgpu::ComputePass<'a>,
+        fovea: [u32; 4],
+        pattern: Pattern,
+    ) {
+        let size = PhysicalSize::new(fovea[2] - fovea[0], fovea[3] - fovea[1]);
+        let traced = pattern.traced_size(size);
+        let (x, y) = self.workgroups(traced.width, traced.height);
+        if x == 0 || y == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.pipelines().fovea);
+        pass.dispatch_workgroups(x, y, 1);
+    }
+
+    // The passes after main in wavefront mode, dispatched with the same
+    // workgroups as main. Expects the bind groups main was dispatched with.
+    pub fn dispatch_wavefront<'a>(
+        &'a self,
+        pass: &mut wgpu::ComputePass<'a>,
+        workgroups: (u32, u32),
+    ) {
+        if !self.settings.wavefront_enabled() {
+            return;
+        }
+        let pipelines = self.pipelines();
+        if self.settings.shadows && !self.settings.half_res_lighting {
+            pass.set_bind_group(2, &self.dispatch_bind_group, &[]);
+            pass.set_pipeline(&pipelines.prepare_shadows);
+            pass.dispatch_workgroups(1, 1, 1);
+            pass.set_bind_group(2, &self.prepass_read_bind_group, &[]);
+            pass.set_pipeline(&pipelines.trace_shadows);
+            pass.dispatch_workgroups_indirect(&self.shadow_dispatch_buffer, 0);
+        }
+        pass.set_pipeline(&pipelines.shade);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+    }
+}
+
+// Everything that depends on the size of the ray traced image
+struct Targets {
+    bind_group: wgpu::BindGroup,
+    prepass_write_bind_group: wgpu::BindGroup,
+    prepass_read_bind_group: wgpu::BindGroup,
+    texture: wgpu::TextureView,
+    motion: wgpu::TextureView,
+    albedo: wgpu::TextureView,
+    normal: wgpu::TextureView,
+    depth: wgpu::TextureView,
+    noise_mask: wgpu::TextureView,
+}
+
+// Layouts and the resources that the targets are bound with, which keep
+// their size
+// A texel per voxel of the grid, x first, then y, then z like its voxels
+fn create_grid(device: &wgpu::Device, grid: Option<&VoxelGrid>) -> wgpu::Texture {
+    let size = grid.map_or(Vector3::repeat(1), VoxelGrid::size);
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Voxel grid"),
+        size: wgpu::Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: size.z,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R8Uint,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn write_grid(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    origin: Vector3<u32>,
+    size: Vector3<u32>,
+    voxels: &[u8],
+) {
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: origin.x,
+                y: origin.y,
+                z: origin.z,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        voxels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(size.x),
+            rows_per_image: Some(size.y),
+        },
+        wgpu::Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: size.z,
+        },
+    );
+}
+
+struct TargetDesc<'a> {
+    bind_group_layout: &'a BindGroupLayout,
+    prepass_write_layout: &'a BindGroupLayout,
+    prepass_read_layout: &'a BindGroupLayout,
+    uniforms: &'a UniformArena,
+    pick_buffer: &'a wgpu::Buffer,
+    residency_buffer: &'a wgpu::Buffer,
+    occupancy_views: &'a [wgpu::TextureView; 2],
+    pool_table: &'a wgpu::Buffer,
+    brick_pool_view: &'a wgpu::TextureView,
+    grid_view: &'a wgpu::TextureView,
+    palette_buffer: &'a wgpu::Buffer,
+}
+
+fn create_targets(device: &wgpu::Device, size: &PhysicalSize<u32>, desc: TargetDesc) -> Targets {
+    let TargetDesc {
+        bind_group_layout,
+        prepass_write_layout,
+        prepass_read_layout,
+        uniforms,
+        pick_buffer,
+        residency_buffer,
+        occupancy_views,
+        pool_table,
+        brick_pool_view,
+        grid_view,
+        palette_buffer,
+    } = desc;
+    let create_target = |label: &str, format: wgpu::TextureFormat| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                format,
+                usage: wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                label: Some(label),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+
+    let color_buffer_view =
+        create_target("HDR color buffer texture", wgpu::TextureFormat::Rgba16Float);
+    // Screen space motion (NDC) of every pixel since the previous frame in xy,
+    // its depth as seen from the previous frame in z
+    let motion_buffer_view =
+        create_target("Motion vector texture", wgpu::TextureFormat::Rgba32Float);
+    let albedo_buffer_view = create_target("Albedo texture", wgpu::TextureFormat::Rgba8Unorm);
+    let normal_buffer_view = create_target("Normal texture", wgpu::TextureFormat::Rgba16Float);
+    // Distance along the primary ray, a large constant for misses
+    let depth_buffer_view = create_target("Depth texture", wgpu::TextureFormat::R32Float);
+
+    // Primary hit of every pixel and the pixels waiting for a shadow ray, for
+    // wavefront mode. A queue holds its counts in front of the pixels.
+    let pixels = size.width as u64 * size.height as u64;
+    let pixel_hits = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Pixel hit buffer"),
+        size: pixels * 8,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let shadow_queue = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Shadow queue buffer"),
+        size: 8 + pixels * 4,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    // Tiles of main sorted by the visibility pass, sized for the smallest
+    // workgroup size. The counts of the three classes come first.
+    let tile_lists = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Tile list buffer"),
+        size: tile_list_bytes(size),
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    // Conservative distance to the closest surface for every tile
+    let beam_view = device
+        .create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.width.div_ceil(BEAM_TILE_SIZE),
+                height: size.height.div_ceil(BEAM_TILE_SIZE),
+                depth_or_array_layers: 1,
+            },
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some("Beam distance texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+
+    // Half resolution sun light, with the depth and normal it was traced
+    // at to guide the upsampling
+    let lighting_view = device
+        .create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.width.div_ceil(2),
+                height: size.height.div_ceil(2),
+                depth_or_array_layers: 1,
+            },
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some("Half resolution lighting texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+
+    let noise_mask_view = device
+        .create_texture(&wgpu::TextureDescriptor {
+            size: noise_mask_size(size),
+            format: wgpu::TextureFormat::R32Uint,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some("Noise mask texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Ray tracing bind group"),
+        layout: bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&color_buffer_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&motion_buffer_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&albedo_buffer_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&normal_buffer_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&depth_buffer_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: uniforms.binding::<RaytracingUniform>(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: pick_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: pixel_hits.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: shadow_queue.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: residency_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: tile_lists.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::TextureView(&occupancy_views[0]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::TextureView(&occupancy_views[1]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: pool_table.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: wgpu::BindingResource::TextureView(brick_pool_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 15,
+                resource: palette_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 16,
+                resource: uniforms.binding::<RenderSettings>(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 17,
+                resource: wgpu::BindingResource::TextureView(&noise_mask_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 18,
+                resource: wgpu::BindingResource::TextureView(grid_view),
+            },
+        ],
+    });
+
+    let prepass_write_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Prepass write bind group"),
+        layout: prepass_write_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&beam_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&lighting_view),
+            },
+        ],
+    });
+
+    let prepass_read_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Prepass read bind group"),
+        layout: prepass_read_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&beam_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&lighting_view),
+            },
+        ],
+    });
+
+    Targets {
+        bind_group,
+        prepass_write_bind_group,
+        prepass_read_bind_group,
+        texture: color_buffer_view,
+        motion: motion_buffer_view,
+        albedo: albedo_buffer_view,
+        normal: normal_buffer_view,
+        depth: depth_buffer_view,
+        noise_mask: noise_mask_view,
+    }
+}
+
+// A texel per noise block of a target of size
+pub fn noise_mask_size(size: &PhysicalSize<u32>) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: size.width.div_ceil(NOISE_BLOCK_WIDTH),
+        height: size.height.div_ceil(NOISE_BLOCK_HEIGHT),
+        depth_or_array_layers: 1,
+    }
+}
+
+fn tile_list_bytes(size: &PhysicalSize<u32>) -> u64 {
+    let tiles = size.width.div_ceil(8) as u64 * size.height.div_ceil(4) as u64;
+    12 + tiles * 3 * 4
+}
+
+// Runs create inside a validation error scope, so invalid shaders return an
+// error instead of panicking
+fn compile_checked<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error.to_string()),
+        None => result,
+    }
+}
+
+// Preprocesses the shader with the defines of a variant. Variants and
+// reloads that preprocess to the same source share a module.
+fn variant_shader(
+    device: &wgpu::Device,
+    shaders: &ShaderCache,
+    source: &str,
+    include: fn(&str) -> Result<String, String>,
+    variant: ShaderVariant,
+) -> Result<Arc<wgpu::ShaderModule>, String> {
+    let source = preprocess::preprocess(source, &variant.defines(), include)?;
+    Ok(shaders.module(device, "Ray tracing shader", source))
+}
+
+// Creates the pipelines for all entry points of a variant's shader
+fn create_variant(
+    device: &wgpu::Device,
+    shader: Arc<wgpu::ShaderModule>,
+    pipeline_layout: &wgpu::PipelineLayout,
+    prepass_pipeline_layout: &wgpu::PipelineLayout,
+    dispatch_pipeline_layout: &wgpu::PipelineLayout,
+) -> RaytracingPipelines {
+    let raytrace_shader = &*shader;
+
+    let main = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Ray tracing pipeline"),
+        layout: Some(pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "main",
+    });
+
+    let pick = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Picking pipeline"),
+        layout: Some(pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "pick",
+    });
+
+    let beam = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Beam pre-pass pipeline"),
+        layout: Some(prepass_pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "beam",
+    });
+
+    let lighting = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Half resolution lighting pipeline"),
+        layout: Some(prepass_pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "lighting",
+    });
+
+    let prepare_shadows = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Shadow queue pipeline"),
+        layout: Some(dispatch_pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "prepare_shadows",
+    });
+
+    let trace_shadows = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Shadow ray pipeline"),
+        layout: Some(pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "trace_shadows",
+    });
+
+    let shade = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Shading pipeline"),
+        layout: Some(pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "shade_pixels",
+    });
+
+    let classify = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Tile classification pipeline"),
+        layout: Some(pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "classify",
+    });
+
+    let prepare_tiles = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Tile dispatch pipeline"),
+        layout: Some(dispatch_pipeline_layout),
+        module: raytrace_shader,
+        entry_point: "prepare_tiles",
+    });
+
+    let tile_pipeline = |label, entry_point| {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(pipeline_layout),
+            module: raytrace_shader,
+            entry_point,
+        })
+    };
+    let sky_tiles = tile_pipeline("Sky tile pipeline", "sky_tiles");
+    let near_tiles = tile_pipeline("Near tile pipeline", "near_tiles");
+    let far_tiles = tile_pipeline("Far tile pipeline", "far_tiles");
+    let fovea = tile_pipeline("Fovea pipeline", "fovea");
+
+    RaytracingPipelines {
+        main,
+        pick,
+        beam,
+        lighting,
+        prepare_shadows,
+        trace_shadows,
+        shade,
+        classify,
+        prepare_tiles,
+        sky_tiles,
+        near_tiles,
+        far_tiles,
+        fovea,
+        shader,
+    }
 }
@@ -0,0 +1,100 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+// States of Slot::state, set from the map_async callback
+const MAPPING: u8 = 0;
+const MAPPED: u8 = 1;
+const FAILED: u8 = 2;
+
+struct Slot<T> {
+    buffer: wgpu::Buffer,
+    state: Arc<AtomicU8>,
+    // What the result belongs to, e.g. the camera it was traced from
+    tag: Option<T>,
+}
+
+// Reads GPU results back without waiting for the GPU. Each frame copies its
+// result into a free staging buffer of a small ring, which is mapped once the
+// frame is submitted. Results come out in the order they were submitted, a
+// few frames late, with the tag they were copied with. When every buffer is
+// still in flight the frame's result is skipped.
+pub struct ReadbackRing<T> {
+    slots: Vec<Slot<T>>,
+    // Slot copied into by the frame being recorded, mapped after submit
+    pending: Option<usize>,
+    // Slots being mapped, oldest first
+    in_flight: VecDeque<usize>,
+}
+
+impl<T> ReadbackRing<T> {
+    pub fn new(device: &wgpu::Device, label: &str, size: u64, count: usize) -> Self {
+        let slots = (0..count)
+            .map(|_| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                state: Arc::new(AtomicU8::new(MAPPING)),
+                tag: None,
+            })
+            .collect();
+        Self {
+            slots,
+            pending: None,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    // The buffer to copy this frame's result into, None when every buffer is
+    // still in flight
+    pub fn begin(&mut self, tag: T) -> Option<&wgpu::Buffer> {
+        let index = (0..self.slots.len()).find(|i| !self.in_flight.contains(i))?;
+        self.pending = Some(index);
+        let slot = &mut self.slots[index];
+        slot.tag = Some(tag);
+        Some(&slot.buffer)
+    }
+
+    // Call after the frame's command buffer has been submitted, maps the
+    // buffer begun in it
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        if let Some(index) = self.pending.take() {
+            let state = self.slots[index].state.clone();
+            self.slots[index]
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let mapped = if result.is_ok() { MAPPED } else { FAILED };
+                    state.store(mapped, Ordering::Release);
+                });
+            self.in_flight.push_back(index);
+        }
+        device.poll(wgpu::Maintain::Poll);
+    }
+
+    // Passes each result that was mapped to read, oldest first. A result
+    // still being mapped holds back the later ones, failed ones are skipped.
+    pub fn read(&mut self, mut read: impl FnMut(T, &[u8])) {
+        while let Some(&index) = self.in_flight.front() {
+            let slot = &mut self.slots[index];
+            match slot.state.swap(MAPPING, Ordering::Acquire) {
+                MAPPING => break,
+                MAPPED => {
+                    if let Some(tag) = slot.tag.take() {
+                        read(tag, &slot.buffer.slice(..).get_mapped_range());
+                    }
+                    slot.buffer.unmap();
+                }
+                _ => slot.tag = None,
+            }
+            self.in_flight.pop_front();
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+use crate::{
+    camera::{Camera, Projection},
+    world,
+};
+
+// A slow line by line port of the ray generation and traversal in
+// ray-tracing.wgsl, used by the tests below to check the camera matrices and
+// the DDA without a GPU. Changes to the shader should be mirrored here.
+
+// Must match FISHEYE_FOV in ray-tracing.wgsl
+const FISHEYE_FOV: f32 = std::f32::consts::PI;
+// Cells per side of a parent cell, must match CHUNK_CELLS in ray-tracing.wgsl
+const CHUNK_CELLS: i32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hit {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub hit: bool,
+    // Number of DDA iterations over all levels
+    pub steps: u32,
+}
+
+// What the pick entry point writes for the voxel under the cursor
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pick {
+    pub voxel: Vector3<i32>,
+    pub normal: Vector3<f32>,
+    pub position: Point3<f32>,
+    // Along the ray from the eye
+    pub distance: f32,
+}
+
+// The matrices of camera::CameraUniform for a camera and viewport
+pub struct RayCamera {
+    pub view_pos: Point3<f32>,
+    pub view: Matrix4<f32>,
+    pub proj: Matrix4<f32>,
+    pub view_proj: Matrix4<f32>,
+    pub projection: Projection,
+    pub viewport: [u32; 2],
+}
+
+impl RayCamera {
+    pub fn new(camera: &Camera, width: u32, height: u32) -> Self {
+        Self {
+            view_pos: camera.eye(),
+            view: camera.calc_view(),
+            proj: camera.calc_proj(width, height),
+            view_proj: camera.calc_view_proj(width, height),
+            projection: camera.projection,
+            viewport: [width, height],
+        }
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.viewport[0] as f32 / self.viewport[1] as f32
+    }
+
+    pub fn primary_ray(&self, ndc: [f32; 2]) -> Ray {
+        let target = self.proj * Vector4::new(ndc[0], ndc[1], -1., 1.);
+
+        if self.projection == Projection::Orthographic {
+            // Parallel rays starting on the camera plane
+            let offset = Vector4::new(target.x / target.w, target.y / target.w, 0., 0.);
+            let origin = self.view_pos + (self.view * offset).xyz();
+            let direction = (self.view * Vector4::new(0., 0., -1., 0.)).xyz();
+            return Ray { origin, direction };
+        }
+
+        let mut local = (target.xyz() / target.w).normalize();
+        if self.projection == Projection::Fisheye {
+            let scaled = Vector3::new(ndc[0] * self.aspect_ratio(), ndc[1], 0.);
+            let radius = scaled.norm();
+            let theta = radius * FISHEYE_FOV * 0.5;
+            let around = if radius > 0. {
+                scaled / radius
+            } else {
+                Vector3::zeros()
+            };
+            local = Vector3::new(
+                around.x * theta.sin(),
+                around.y * theta.sin(),
+                -theta.cos(),
+            );
+        } else if self.projection == Projection::Equirectangular {
+            let longitude = ndc[0] * std::f32::consts::PI;
+            let latitude = ndc[1] * std::f32::consts::PI * 0.5;
+            local = Vector3::new(
+                latitude.cos() * longitude.sin(),
+                latitude.sin(),
+                -latitude.cos() * longitude.cos(),
+            );
+        }
+
+        Ray {
+            origin: self.view_pos,
+            direction: (self.view * local.push(0.)).xyz(),
+        }
+    }
+
+    // Inverse of the non-linear mappings in primary_ray, from a camera space
+    // direction to NDC
+    fn project_direction(&self, local: Vector3<f32>) -> [f32; 2] {
+        let direction = local.normalize();
+        if self.projection == Projection::Fisheye {
+            let theta = (-direction.z).clamp(-1., 1.).acos();
+            let radius = theta / (FISHEYE_FOV * 0.5);
+            let around = direction
+                .xy()
+                .try_normalize(0.)
+                .unwrap_or_else(nalgebra::Vector2::zeros);
+            return [around.x * radius / self.aspect_ratio(), around.y * radius];
+        }
+        let longitude = direction.x.atan2(-direction.z);
+        let latitude = direction.y.clamp(-1., 1.).asin();
+        [
+            longitude / std::f32::consts::PI,
+            latitude / (std::f32::consts::PI * 0.5),
+        ]
+    }
+
+    // NDC of a world position, like reproject with this frame as the
+    // previous one
+    pub fn project(&self, p: Point3<f32>) -> [f32; 2] {
+        let clip = self.view_proj * (p - self.view_pos).push(1.);
+        if !self.projection.is_linear() {
+            // view_proj only holds the view rotation for these
+            return self.project_direction(clip.xyz());
+        }
+        [clip.x / clip.w, clip.y / clip.w]
+    }
+
+    pub fn pick(&self, cursor: [f32; 2]) -> Option<Pick> {
+        let ray = self.primary_ray(cursor);
+        let hit = raytrace(ray);
+        hit.hit.then(|| Pick {
+            voxel: hit_voxel(&hit),
+            normal: hit.normal,
+            position: hit.position,
+            distance: (hit.position - ray.origin).norm(),
+        })
+    }
+}
+
+// Hit positions lie on a voxel face, step back inside along the normal
+pub fn hit_voxel(hit: &Hit) -> Vector3<i32> {
+    (hit.position.coords - hit.normal * 0.5).map(|v| v.floor() as i32)
+}
+
+pub fn raytrace(ray: Ray) -> Hit {
+    let mut hit = Hit {
+        position: ray.origin,
+        normal: Vector3::zeros(),
+        hit: false,
+        steps: 0,
+    };
+    let mut scale = 64;
+    let mut steps = 0;
+
+    for _ in 0..3 {
+        hit = dda(
+            Ray {
+                origin: hit.position,
+                direction: ray.direction,
+            },
+            scale,
+            hit.normal,
+        );
+        steps += hit.steps;
+        scale /= 8;
+    }
+    hit.steps = steps;
+    hit
+}
+
+// entry_normal is the normal of the face the ray entered the current cell
+// through
+pub fn dda(r: Ray, scale: i32, entry_normal: Vector3<f32>) -> Hit {
+    let mut direction = r.direction.normalize();
+    for axis in 0..3 {
+        if direction[axis] == 0. {
+            direction[axis] = 0.001;
+        }
+    }
+
+    let ray_sign = direction.map(|v| v.signum() as i32);
+    let ray_positivity = ray_sign.map(|v| (1 + v) / 2);
+    let ray_inverse = direction.map(|v| 1. / v);
+
+    let mut grid_coords = (r.origin.coords / scale as f32).map(|v| v.floor() as i32);
+    let mut within_voxel_coords = r.origin.coords / scale as f32 - grid_coords.cast::<f32>();
+    // Beginning of the chunk, WGSL integer division truncates like Rust's
+    let entry_coords = (grid_coords / scale) * scale;
+
+    let mut normal = entry_normal;
+    let mut i = 0;
+    let position = |grid: Vector3<i32>, within: Vector3<f32>| {
+        Point3::from((grid.cast::<f32>() + within) * scale as f32)
+    };
+    while in_chunk(grid_coords, entry_coords, scale) {
+        let t = (ray_positivity.cast::<f32>() - within_voxel_coords).component_mul(&ray_inverse);
+        if get_voxel(grid_coords, scale) {
+            return Hit {
+                position: position(grid_coords, within_voxel_coords),
+                normal,
+                hit: true,
+                steps: i,
+            };
+        }
+
+        let min_idx = if t.x < t.y {
+            if t.x < t.z {
+                0
+            } else {
+                2
+            }
+        } else if t.y < t.z {
+            1
+        } else {
+            2
+        };
+
+        grid_coords[min_idx] += ray_sign[min_idx];
+        within_voxel_coords += direction * t[min_idx];
+        within_voxel_coords[min_idx] = 1. - ray_positivity[min_idx] as f32;
+        normal = Vector3::zeros();
+        normal[min_idx] = -ray_sign[min_idx] as f32;
+        i += 1;
+    }
+    Hit {
+        position: position(grid_coords, within_voxel_coords),
+        normal,
+        hit: false,
+        steps: i,
+    }
+}
+
+// A parent cell is CHUNK_CELLS cells wide, so the finer levels walk at least
+// that far from where they entered it. The top level keeps its wider reach.
+fn in_chunk(coords: Vector3<i32>, reference: Vector3<i32>, scale: i32) -> bool {
+    let reach = scale.max(CHUNK_CELLS);
+    let diff = (coords - reference).abs();
+    !(diff.x > reach || diff.y > reach || diff.z > reach)
+}
+
+fn get_voxel(c: Vector3<i32>, _scale: i32) -> bool {
+    world::is_terrain(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 1280;
+    const HEIGHT: u32 = 720;
+    const EPSILON: f32 = 1e-3;
+
+    fn camera(projection: Projection) -> Camera {
+        let mut camera = Camera::new([3.5, 20., -7.25], 70., 0.1, 500.);
+        camera.direction = Vector3::new(1., -0.5, 0.3).normalize();
+        camera.projection = projection;
+        camera
+    }
+
+    fn sample_ndcs() -> Vec<[f32; 2]> {
+        vec![
+            [0., 0.],
+            [0.5, 0.25],
+            [-0.75, 0.6],
+            [0.3, -0.9],
+            [-0.2, -0.4],
+        ]
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).norm() < EPSILON, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn center_ray_follows_the_camera() {
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Fisheye,
+            Projection::Equirectangular,
+        ] {
+            let camera = camera(projection);
+            let ray = RayCamera::new(&camera, WIDTH, HEIGHT).primary_ray([0., 0.]);
+            assert_close(ray.direction.normalize(), camera.direction);
+            assert_close(ray.origin.coords, camera.eye().coords);
+        }
+    }
+
+    #[test]
+    fn rays_match_inverse_view_proj() {
+        for projection in [Projection::Perspective, Projection::Orthographic] {
+            let camera = camera(projection);
+            let rays = RayCamera::new(&camera, WIDTH, HEIGHT);
+            for ndc in sample_ndcs() {
+                let ray = rays.primary_ray(ndc);
+                let direction = ray.direction.normalize();
+                let (origin, expected) = camera.screen_ray(ndc, WIDTH, HEIGHT).unwrap();
+                assert_close(direction, expected);
+                // screen_ray starts on the near plane, on the same line
+                let offset = origin - ray.origin;
+                assert_close(offset.cross(&direction), Vector3::zeros());
+            }
+        }
+    }
+
+    #[test]
+    fn projecting_a_ray_gives_its_ndc() {
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Fisheye,
+            Projection::Equirectangular,
+        ] {
+            let rays = RayCamera::new(&camera(projection), WIDTH, HEIGHT);
+            for ndc in sample_ndcs() {
+                if projection == Projection::Fisheye && ndc[0].abs() * WIDTH as f32 > HEIGHT as f32
+                {
+                    continue;
+                }
+                let ray = rays.primary_ray(ndc);
+                let projected = rays.project(ray.origin + ray.direction.normalize() * 10.);
+                assert!(
+                    (projected[0] - ndc[0]).abs() < EPSILON
+                        && (projected[1] - ndc[1]).abs() < EPSILON,
+                    "{:?}: {:?} != {:?}",
+                    projection,
+                    projected,
+                    ndc
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn downward_ray_hits_the_ground() {
+        let origin = Point3::new(0.5, 20., 0.5);
+        let ray = Ray {
+            origin,
+            direction: -Vector3::y(),
+        };
+        let hit = raytrace(ray);
+        assert!(hit.hit);
+        assert_close(hit.normal, Vector3::y());
+        assert_eq!(hit_voxel(&hit), Vector3::new(0, -1, 0));
+        assert!(world::is_terrain(hit_voxel(&hit)));
+
+        let expected = world::World::default()
+            .raycast(origin, ray.direction, 100.)
+            .unwrap();
+        assert!(((hit.position - origin).norm() - expected).abs() < 0.05);
+    }
+
+    #[test]
+    fn upward_ray_misses() {
+        let hit = raytrace(Ray {
+            origin: Point3::new(0.5, 20., 0.5),
+            direction: Vector3::y(),
+        });
+        assert!(!hit.hit);
+    }
+
+    #[test]
+    fn pick_through_the_center_hits_what_the_camera_faces() {
+        let mut camera = Camera::new([0.5, 20., 0.5], 70., 0.1, 500.);
+        // Not straight down, the view's up vector would be parallel
+        camera.direction = Vector3::new(0., -1., 1e-3).normalize();
+        let pick = RayCamera::new(&camera, WIDTH, HEIGHT)
+            .pick([0., 0.])
+            .unwrap();
+        assert_eq!(pick.voxel, Vector3::new(0, -1, 0));
+        assert_close(pick.normal, Vector3::y());
+        assert!((pick.distance - 20.).abs() < 0.05);
+    }
+
+    #[test]
+    fn finest_level_agrees_with_the_cpu_raycast() {
+        let origin = Point3::new(0.5, 2.5, 0.5);
+        for direction in [
+            Vector3::new(0.3, -1., 0.2),
+            Vector3::new(-0.4, -1., 0.1),
+            Vector3::new(0.1, -1., -0.5),
+        ] {
+            let hit = dda(Ray { origin, direction }, 1, Vector3::zeros());
+            let expected = world::World::default().raycast(origin, direction, 10.);
+            assert_eq!(hit.hit, expected.is_some());
+            if let Some(expected) = expected {
+                assert!(((hit.position - origin).norm() - expected).abs() < EPSILON);
+            }
+        }
+    }
+}
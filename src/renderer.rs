@@ -0,0 +1,72 @@
+use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
+
+use crate::{
+    camera::Camera,
+    window::{InitError, State},
+    world::VoxelGrid,
+};
+
+// Entry point for applications embedding the ray tracer in their own event
+// loop. They own the camera and call render_frame once per frame, the
+// built-in controls only apply to events passed to handle_event.
+pub struct Renderer {
+    state: State,
+    last_frame: instant::Instant,
+}
+
+impl Renderer {
+    pub async fn new(window: Window) -> Result<Renderer, InitError> {
+        Ok(Renderer {
+            state: State::new(window).await?,
+            last_frame: instant::Instant::now(),
+        })
+    }
+
+    pub fn window(&self) -> &Window {
+        self.state.window()
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.state.size
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.state.resize(size);
+    }
+
+    // Feeds a window event to the built-in controls, true if it was used
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        self.state.input(event)
+    }
+
+    // Draws grid in place of the built-in terrain, None restores the terrain.
+    // Collisions and picking use it as well. Fails for grids larger than the
+    // device's 3D textures.
+    pub fn set_world(&mut self, grid: Option<VoxelGrid>) -> Result<(), String> {
+        self.state.set_world(grid)
+    }
+
+    // The full renderer state, for settings without a wrapper here
+    pub fn state(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    // Renders and presents one frame from the given camera, the built-in
+    // controls, bookmarks and camera paths don't move it. Lost or outdated
+    // surfaces are reconfigured and the frame skipped, other errors are
+    // returned.
+    pub fn render_frame(&mut self, camera: &Camera) -> Result<(), wgpu::SurfaceError> {
+        let now = instant::Instant::now();
+        let dt = now - self.last_frame;
+        self.last_frame = now;
+
+        self.state.update_with_camera(dt, camera);
+        match self.state.render() {
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.state.resize(self.state.size);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
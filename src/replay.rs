@@ -0,0 +1,373 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::*,
+    event_loop::EventLoopWindowTarget,
+    window::{Window, WindowId},
+};
+
+use crate::{app::App, camera};
+
+// Input that reaches the renderer, everything else (resizes, focus of other
+// windows) depends on the machine and isn't recorded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Key {
+        key: VirtualKeyCode,
+        state: ElementState,
+    },
+    Modifiers(ModifiersState),
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    // Scroll wheel notches
+    Scroll(f32),
+    Focused(bool),
+    // Raw mouse movement, used while the mouse is grabbed
+    MouseMotion(f64, f64),
+}
+
+impl RecordedEvent {
+    fn from_window_event(event: &WindowEvent) -> Option<RecordedEvent> {
+        Some(match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => RecordedEvent::Key {
+                key: *key,
+                state: *state,
+            },
+            WindowEvent::ModifiersChanged(modifiers) => RecordedEvent::Modifiers(*modifiers),
+            WindowEvent::CursorMoved { position, .. } => RecordedEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            },
+            WindowEvent::MouseInput { button, state, .. } => RecordedEvent::MouseButton {
+                button: *button,
+                state: *state,
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                RecordedEvent::Scroll(camera::scroll_steps(delta))
+            }
+            WindowEvent::Focused(focused) => RecordedEvent::Focused(*focused),
+            _ => return None,
+        })
+    }
+
+    #[allow(deprecated)]
+    fn to_window_event(&self) -> Option<WindowEvent<'static>> {
+        // # Safety
+        //
+        // The dummy id is only compared against other ids, which nothing
+        // here does
+        let device_id = unsafe { DeviceId::dummy() };
+        Some(match *self {
+            RecordedEvent::Key { key, state } => WindowEvent::KeyboardInput {
+                device_id,
+                input: KeyboardInput {
+                    scancode: 0,
+                    state,
+                    virtual_keycode: Some(key),
+                    modifiers: ModifiersState::empty(),
+                },
+                is_synthetic: false,
+            },
+            RecordedEvent::Modifiers(modifiers) => WindowEvent::ModifiersChanged(modifiers),
+            RecordedEvent::CursorMoved { x, y } => WindowEvent::CursorMoved {
+                device_id,
+                position: PhysicalPosition::new(x, y),
+                modifiers: ModifiersState::empty(),
+            },
+            RecordedEvent::MouseButton { button, state } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers: ModifiersState::empty(),
+            },
+            RecordedEvent::Scroll(steps) => WindowEvent::MouseWheel {
+                device_id,
+                delta: MouseScrollDelta::LineDelta(0., steps),
+                phase: TouchPhase::Moved,
+                modifiers: ModifiersState::empty(),
+            },
+            RecordedEvent::Focused(focused) => WindowEvent::Focused(focused),
+            RecordedEvent::MouseMotion(..) => return None,
+        })
+    }
+}
+
+// One line of a recording
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    // Seconds
+    pub dt: f32,
+    // Arrived before the frame, in order
+    pub events: Vec<RecordedEvent>,
+}
+
+// Writes the input and frame time of every frame to a file, one JSON line
+// per frame. Lines are flushed as they are written so a crash keeps the
+// frames leading up to it.
+pub struct Recording<A> {
+    app: A,
+    file: BufWriter<File>,
+    frame: RecordedFrame,
+}
+
+impl<A: App> Recording<A> {
+    pub fn new(app: A, path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|error| format!("{}: {}", path, error))?;
+        log::info!("Recording input to {}", path);
+        Ok(Self {
+            app,
+            file: BufWriter::new(file),
+            frame: RecordedFrame::default(),
+        })
+    }
+}
+
+impl<A: App> App for Recording<A> {
+    fn window(&self) -> &Window {
+        self.app.window()
+    }
+
+    fn size(&self) -> PhysicalSize<u32> {
+        self.app.size()
+    }
+
+    fn init(&mut self, target: &EventLoopWindowTarget<()>) {
+        self.app.init(target)
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        if let Some(recorded) = RecordedEvent::from_window_event(event) {
+            self.frame.events.push(recorded);
+        }
+        self.app.input(event)
+    }
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.app.resize(new_size)
+    }
+
+    fn update(&mut self, dt: instant::Duration) {
+        self.frame.dt = dt.as_secs_f32();
+        let frame = std::mem::take(&mut self.frame);
+        let line = serde_json::to_string(&frame).expect("Frames always serialize");
+        if let Err(error) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            log::warn!("Couldn't write the recording: {}", error);
+        }
+        self.app.update(dt)
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.app.render()
+    }
+
+    fn device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.frame
+                .events
+                .push(RecordedEvent::MouseMotion(delta.0, delta.1));
+        }
+        self.app.device_event(event)
+    }
+
+    fn other_window_event(&mut self, window_id: WindowId, event: &WindowEvent) -> bool {
+        self.app.other_window_event(window_id, event)
+    }
+
+    fn unhandled_event(&mut self, target: &EventLoopWindowTarget<()>, event: &WindowEvent) {
+        self.app.unhandled_event(target, event)
+    }
+
+    fn should_exit(&self) -> bool {
+        self.app.should_exit()
+    }
+}
+
+// Feeds a recording back frame by frame with the recorded frame times, so
+// the camera moves exactly as it did however fast frames render. Live input
+// is ignored until the recording ends, then the app exits.
+pub struct Replay<A> {
+    app: A,
+    frames: VecDeque<RecordedFrame>,
+}
+
+impl<A: App> Replay<A> {
+    pub fn new(app: A, path: &str) -> Result<Self, String> {
+        let frames = load(path)?;
+        log::info!("Replaying {} frames from {}", frames.len(), path);
+        Ok(Self { app, frames })
+    }
+}
+
+pub fn load(path: &str) -> Result<VecDeque<RecordedFrame>, String> {
+    let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.map_err(|error| format!("{}: {}", path, error))?;
+            serde_json::from_str(&line)
+                .map_err(|error| format!("{}:{}: {}", path, index + 1, error))
+        })
+        .collect()
+}
+
+impl<A: App> App for Replay<A> {
+    fn window(&self) -> &Window {
+        self.app.window()
+    }
+
+    fn size(&self) -> PhysicalSize<u32> {
+        self.app.size()
+    }
+
+    fn init(&mut self, target: &EventLoopWindowTarget<()>) {
+        self.app.init(target)
+    }
+
+    // Live input is swallowed, the rest (resizes) still reaches the app
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        RecordedEvent::from_window_event(event).is_some()
+    }
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.app.resize(new_size)
+    }
+
+    fn update(&mut self, dt: instant::Duration) {
+        let Some(frame) = self.frames.pop_front() else {
+            self.app.update(dt);
+            return;
+        };
+        for event in &frame.events {
+            match event {
+                RecordedEvent::MouseMotion(x, y) => self
+                    .app
+                    .device_event(&DeviceEvent::MouseMotion { delta: (*x, *y) }),
+                event => {
+                    if let Some(event) = event.to_window_event() {
+                        self.app.input(&event);
+                    }
+                }
+            }
+        }
+        self.app.update(instant::Duration::from_secs_f32(frame.dt));
+        if self.frames.is_empty() {
+            log::info!("Replay finished");
+        }
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.app.render()
+    }
+
+    fn other_window_event(&mut self, window_id: WindowId, event: &WindowEvent) -> bool {
+        self.app.other_window_event(window_id, event)
+    }
+
+    fn should_exit(&self) -> bool {
+        self.frames.is_empty() || self.app.should_exit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<RecordedEvent> {
+        vec![
+            RecordedEvent::Key {
+                key: VirtualKeyCode::W,
+                state: ElementState::Pressed,
+            },
+            RecordedEvent::Modifiers(ModifiersState::SHIFT),
+            RecordedEvent::CursorMoved { x: 12.5, y: 300. },
+            RecordedEvent::MouseButton {
+                button: MouseButton::Left,
+                state: ElementState::Released,
+            },
+            RecordedEvent::Scroll(-2.),
+            RecordedEvent::Focused(false),
+        ]
+    }
+
+    // A file in the temp directory, removed when dropped
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn window_events_round_trip() {
+        for event in events() {
+            let window_event = event.to_window_event().unwrap();
+            assert_eq!(RecordedEvent::from_window_event(&window_event), Some(event));
+        }
+        assert!(RecordedEvent::MouseMotion(1., 2.)
+            .to_window_event()
+            .is_none());
+    }
+
+    #[test]
+    fn loads_recorded_frames() {
+        let frames = [
+            RecordedFrame {
+                dt: 0.016,
+                events: events(),
+            },
+            RecordedFrame {
+                dt: 0.02,
+                events: vec![RecordedEvent::MouseMotion(-3., 0.5)],
+            },
+        ];
+        let lines: Vec<String> = frames
+            .iter()
+            .map(|frame| serde_json::to_string(frame).unwrap())
+            .collect();
+        let file = TempFile::new("replay-frames.jsonl", &lines.join("\n"));
+        assert_eq!(load(file.path()).unwrap(), frames);
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        let file = TempFile::new("replay-invalid.jsonl", "{\"dt\":0.1,\"events\":[]}\nnope");
+        let error = load(file.path()).unwrap_err();
+        assert!(error.contains(":2:"), "{}", error);
+        assert!(load("missing-recording.jsonl").is_err());
+    }
+}
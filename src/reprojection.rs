@@ -0,0 +1,287 @@
+use winit::dpi::PhysicalSize;
+
+#[derive(Debug)]
+pub struct ReprojectionSettings {
+    pub enabled: bool,
+    // Relative difference between the expected and the stored depth that
+    // still counts as the same surface
+    pub depth_tolerance: f32,
+}
+
+impl ReprojectionSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            depth_tolerance: 0.02,
+        }
+    }
+}
+
+impl Default for ReprojectionSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ReprojectionUniform {
+    size: [u32; 2],
+    // Render size of the frame stored in the history textures
+    history_size: [u32; 2],
+    depth_tolerance: f32,
+    reset: u32,
+    _padding: [u32; 2],
+}
+
+impl ReprojectionUniform {
+    fn new() -> Self {
+        Self {
+            size: [0; 2],
+            history_size: [0; 2],
+            depth_tolerance: 0.,
+            reset: 1,
+            _padding: [0; 2],
+        }
+    }
+}
+
+// Keeps the reconstructed color and the depth of every frame and moves them
+// to where the same surfaces are in the next frame, using the motion buffer.
+// History whose depth doesn't match the depth the surface should have had is
+// marked invalid, so passes accumulating over frames can start over on
+// disocclusions instead of smearing the previous frame across them.
+pub struct ReprojectionPipeline {
+    pub settings: ReprojectionSettings,
+    pub uniform: ReprojectionUniform,
+    pub buffer: wgpu::Buffer,
+    pub reproject_pipeline: wgpu::ComputePipeline,
+    pub store_pipeline: wgpu::ComputePipeline,
+    pub reproject_bind_group: wgpu::BindGroup,
+    pub store_bind_group: wgpu::BindGroup,
+    // Previous frame's color in rgb, whether it is valid in alpha
+    pub output: wgpu::Texture,
+    pub output_view: wgpu::TextureView,
+    pub color_history: wgpu::Texture,
+    pub depth_history: wgpu::Texture,
+    // Size of the region being rendered to this frame
+    pub size: PhysicalSize<u32>,
+    reset: bool,
+}
+
+impl ReprojectionPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        color_texture: &wgpu::TextureView,
+        motion_texture: &wgpu::TextureView,
+        depth_texture: &wgpu::TextureView,
+    ) -> ReprojectionPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Reprojection shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/reproject.wgsl").into()),
+        });
+
+        let settings = ReprojectionSettings::new();
+        let uniform = ReprojectionUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Reprojection Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let extent = wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        };
+        let create_target = |label: &str, format: wgpu::TextureFormat| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: extent,
+                format,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                label: Some(label),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                view_formats: &[],
+            })
+        };
+
+        let output = create_target("Reprojected texture", wgpu::TextureFormat::Rgba16Float);
+        let output_view = output.create_view(&wgpu::TextureViewDescriptor::default());
+        let color_history = create_target(
+            "Reprojection color history texture",
+            wgpu::TextureFormat::Rgba16Float,
+        );
+        let color_history_view = color_history.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_history = create_target(
+            "Reprojection depth history texture",
+            wgpu::TextureFormat::R32Float,
+        );
+        let depth_history_view = depth_history.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage_entry =
+            |binding: u32, format: wgpu::TextureFormat| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            };
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        // The history textures are read by one pass and written by the other,
+        // so each gets a bind group with only its own bindings
+        let reproject_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0),
+                    texture_entry(1),
+                    texture_entry(2),
+                    storage_entry(3, wgpu::TextureFormat::Rgba16Float),
+                    uniform_entry,
+                ],
+                label: Some("reproject_bind_group_layout"),
+            });
+        let store_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_entry,
+                    texture_entry(5),
+                    texture_entry(6),
+                    storage_entry(7, wgpu::TextureFormat::Rgba16Float),
+                    storage_entry(8, wgpu::TextureFormat::R32Float),
+                ],
+                label: Some("reprojection_store_bind_group_layout"),
+            });
+
+        let view_entry = |binding: u32, view| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+        let reproject_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &reproject_bind_group_layout,
+            entries: &[
+                view_entry(0, motion_texture),
+                view_entry(1, &color_history_view),
+                view_entry(2, &depth_history_view),
+                view_entry(3, &output_view),
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("reproject_bind_group"),
+        });
+        let store_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &store_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffer.as_entire_binding(),
+                },
+                view_entry(5, color_texture),
+                view_entry(6, depth_texture),
+                view_entry(7, &color_history_view),
+                view_entry(8, &depth_history_view),
+            ],
+            label: Some("reprojection_store_bind_group"),
+        });
+
+        let create_pipeline = |label: &str, layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let reproject_pipeline = create_pipeline(
+            "Reprojection pipeline",
+            &reproject_bind_group_layout,
+            "reproject",
+        );
+        let store_pipeline = create_pipeline(
+            "Reprojection store pipeline",
+            &store_bind_group_layout,
+            "store",
+        );
+
+        ReprojectionPipeline {
+            settings,
+            uniform,
+            buffer,
+            reproject_pipeline,
+            store_pipeline,
+            reproject_bind_group,
+            store_bind_group,
+            output,
+            output_view,
+            color_history,
+            depth_history,
+            size: *size,
+            reset: true,
+        }
+    }
+
+    // Invalidates the whole history, e.g. after a camera cut
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, render_size: PhysicalSize<u32>) {
+        self.uniform.history_size = [self.size.width, self.size.height];
+        self.size = render_size;
+
+        self.uniform.size = [self.size.width, self.size.height];
+        self.uniform.depth_tolerance = self.settings.depth_tolerance;
+        // A disabled pass stores no history, so it is stale once enabled again
+        self.uniform.reset = (self.reset || !self.settings.enabled) as u32;
+        self.reset = !self.settings.enabled;
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Reprojects the stored history, then replaces it with the current frame
+    pub fn dispatch<'a>(&'a self, pass: &mut wgpu::ComputePass<'a>) {
+        let workgroups = (self.size.width.div_ceil(16), self.size.height.div_ceil(16));
+        pass.set_pipeline(&self.reproject_pipeline);
+        pass.set_bind_group(0, &self.reproject_bind_group, &[]);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        pass.set_pipeline(&self.store_pipeline);
+        pass.set_bind_group(0, &self.store_bind_group, &[]);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+    }
+}
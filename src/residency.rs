@@ -0,0 +1,251 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{Camera, Projection},
+    occlusion::DepthPyramid,
+    upload::Uploader,
+};
+
+// Voxels per side of a chunk, must match CHUNK_SIZE in ray-tracing.wgsl
+pub const CHUNK_SIZE: i32 = 64;
+// Chunks per side of the grid around the camera that residency is tracked
+// in, must match ray-tracing.wgsl. Everything outside it is culled. The grid
+// covers as far as the traversal reaches horizontally, the world is flat.
+const GRID_WIDTH: i32 = 128;
+const GRID_HEIGHT: i32 = 16;
+// Two bits of ChunkState per chunk
+const CHUNKS_PER_WORD: usize = 16;
+const WORDS: usize = (GRID_WIDTH * GRID_HEIGHT * GRID_WIDTH) as usize / CHUNKS_PER_WORD;
+// Grid origin followed by the packed states
+pub const BUFFER_SIZE: u64 = (4 + WORDS as u64) * 4;
+
+// Must match the CHUNK_* constants in ray-tracing.wgsl
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkState {
+    // Not on the GPU, the traversal skips it like empty space
+    Culled,
+    // Outside the frustum but near enough to be kept, for rays leaving the
+    // view like shadows
+    Resident,
+    // Inside the frustum
+    Visible,
+    // Inside the frustum but hidden behind nearer terrain in the last depth
+    // pyramid, not on the GPU like culled chunks
+    Occluded,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResidencySettings {
+    pub enabled: bool,
+    // Chunks farther from the camera are never resident
+    pub view_distance: u32,
+    // Most chunks resident at once, those in the frustum go first, then the
+    // nearest
+    pub max_resident: usize,
+}
+
+impl ResidencySettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            view_distance: 64,
+            max_resident: 65536,
+        }
+    }
+}
+
+impl Default for ResidencySettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Eye, view projection and settings of an update. The depth pyramid is
+// identified by its generation.
+type UpdateInputs = (
+    Point3<f32>,
+    Option<Matrix4<f32>>,
+    Option<u64>,
+    ResidencySettings,
+);
+
+// Decides which chunks around the camera are resident on the GPU and visible
+// to the traversal, from the frustum of the culling camera. Chunks behind the
+// camera are only kept while the limit leaves room for them. The result is
+// streamed to the ray tracing pass as a grid of ChunkState centered on the
+// camera's chunk.
+pub struct ChunkResidency {
+    pub settings: ResidencySettings,
+    // Header and packed grid as read by ray-tracing.wgsl
+    data: Vec<u32>,
+    // Whether data is on the GPU
+    uploaded: bool,
+    // Nothing is recomputed while the inputs stay the same
+    last: Option<UpdateInputs>,
+    pub resident: usize,
+    pub visible: usize,
+    pub occluded: usize,
+}
+
+impl ChunkResidency {
+    pub fn new() -> Self {
+        Self {
+            settings: ResidencySettings::new(),
+            data: vec![0; 4 + WORDS],
+            // Matches the zeroed buffer, where every chunk is resident
+            uploaded: true,
+            last: None,
+            resident: 0,
+            visible: 0,
+            occluded: 0,
+        }
+    }
+
+    // The buffer was recreated, e.g. with the device
+    pub fn invalidate(&mut self) {
+        self.uploaded = false;
+    }
+
+    // camera is the one culling uses, see FrustumFreeze::culling_camera,
+    // with the aspect ratio of size. Chunks in the frustum that the depth
+    // pyramid shows to be hidden aren't made resident.
+    pub fn update(
+        &mut self,
+        camera: &Camera,
+        size: PhysicalSize<u32>,
+        occlusion: Option<&DepthPyramid>,
+    ) {
+        let eye = camera.eye();
+        let view_proj = match camera.projection {
+            // Not expressible as a matrix, every chunk in range counts as in
+            // the frustum
+            Projection::Fisheye | Projection::Equirectangular => None,
+            _ => Some(camera.calc_view_proj(size.width, size.height)),
+        };
+        let inputs = (
+            eye,
+            view_proj,
+            occlusion.map(|pyramid| pyramid.generation),
+            self.settings.clone(),
+        );
+        if self.last.as_ref() == Some(&inputs) {
+            return;
+        }
+        self.last = Some(inputs);
+
+        let mut data = vec![0; 4 + WORDS];
+        if !self.settings.enabled {
+            self.set_data(data);
+            return;
+        }
+
+        let center = eye.coords.map(|v| (v / CHUNK_SIZE as f32).floor() as i32);
+        let origin = center - Vector3::new(GRID_WIDTH, GRID_HEIGHT, GRID_WIDTH) / 2;
+        let planes = view_proj.map_or(Vec::new(), |view_proj| frustum_planes(&view_proj));
+
+        // Priority, grid index and whether the chunk is in the frustum
+        let distance = self.settings.view_distance as i32;
+        let mut candidates = Vec::new();
+        let mut occluded = Vec::new();
+        for y in 0..GRID_HEIGHT {
+            for z in 0..GRID_WIDTH {
+                for x in 0..GRID_WIDTH {
+                    let chunk = origin + Vector3::new(x, y, z);
+                    let offset = chunk - center;
+                    let distance_squared = offset.dot(&offset);
+                    if distance_squared > distance * distance {
+                        continue;
+                    }
+                    let min = chunk.cast::<f32>() * CHUNK_SIZE as f32;
+                    let max = min.add_scalar(CHUNK_SIZE as f32);
+                    // view_proj works on camera relative positions
+                    let visible = intersects(&planes, &(min - eye.coords), &(max - eye.coords));
+                    let index = (x + (z + y * GRID_WIDTH) * GRID_WIDTH) as usize;
+                    if visible && occlusion.is_some_and(|pyramid| pyramid.occludes(&min, &max)) {
+                        occluded.push(index);
+                        continue;
+                    }
+                    let priority = ((!visible as u64) << 32) | distance_squared as u64;
+                    candidates.push((priority, index, visible));
+                }
+            }
+        }
+        if candidates.len() > self.settings.max_resident {
+            candidates.select_nth_unstable_by_key(self.settings.max_resident, |c| c.0);
+            candidates.truncate(self.settings.max_resident);
+        }
+
+        self.resident = candidates.len();
+        self.visible = candidates.iter().filter(|c| c.2).count();
+        self.occluded = occluded.len();
+        data[..4].copy_from_slice(&[origin.x as u32, origin.y as u32, origin.z as u32, 1]);
+        for (_, index, visible) in candidates {
+            let state = if visible {
+                ChunkState::Visible
+            } else {
+                ChunkState::Resident
+            };
+            data[4 + index / CHUNKS_PER_WORD] |= (state as u32) << (index % CHUNKS_PER_WORD * 2);
+        }
+        for index in occluded {
+            data[4 + index / CHUNKS_PER_WORD] |=
+                (ChunkState::Occluded as u32) << (index % CHUNKS_PER_WORD * 2);
+        }
+        self.set_data(data);
+    }
+
+    // Streams the grid to buffer once it changed, retried next frame when
+    // the upload budget is spent
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+        buffer: &wgpu::Buffer,
+    ) {
+        if !self.uploaded {
+            self.uploaded =
+                uploader.write_buffer(device, buffer, 0, bytemuck::cast_slice(&self.data));
+        }
+    }
+
+    fn set_data(&mut self, data: Vec<u32>) {
+        if data != self.data {
+            self.data = data;
+            self.uploaded = false;
+        }
+    }
+}
+
+impl Default for ChunkResidency {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Left, right, bottom, top and near planes of an OpenGL style view_proj, as
+// normal and distance. The far plane is left out as the traversal doesn't
+// stop at it.
+fn frustum_planes(view_proj: &Matrix4<f32>) -> Vec<Vector4<f32>> {
+    let row = |i: usize| view_proj.row(i).transpose();
+    vec![
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(3) + row(2),
+    ]
+}
+
+// Whether the box is at least partly on the inner side of every plane
+fn intersects(planes: &[Vector4<f32>], min: &Vector3<f32>, max: &Vector3<f32>) -> bool {
+    planes.iter().all(|plane| {
+        // The corner farthest along the normal
+        let corner = Vector3::new(
+            if plane.x > 0. { max.x } else { min.x },
+            if plane.y > 0. { max.y } else { min.y },
+            if plane.z > 0. { max.z } else { min.z },
+        );
+        plane.xyz().dot(&corner) + plane.w >= 0.
+    })
+}
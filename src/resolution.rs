@@ -0,0 +1,88 @@
+use winit::{dpi::PhysicalSize, event::*};
+
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 1.0;
+const SCALE_STEP: f32 = 0.125;
+
+// Fraction of the internal resolution that is actually ray traced. The internal
+// textures keep their size and only their top left corner is rendered to,
+// so changing the scale never reallocates anything.
+#[derive(Debug)]
+pub struct RenderScale {
+    pub scale: f32,
+    // Adjust the scale every frame to reach the target frame time
+    pub dynamic: bool,
+    pub target_frame_time: f32,
+    // Ray trace at twice the window resolution in each axis, downsampled when
+    // blitting to the screen
+    pub supersampling: bool,
+}
+
+impl RenderScale {
+    pub fn new(scale: f32) -> Self {
+        Self {
+            scale: scale.clamp(MIN_SCALE, MAX_SCALE),
+            dynamic: false,
+            target_frame_time: 16.6,
+            supersampling: false,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    // frame_time in milliseconds, preferably measured on the GPU
+    pub fn adapt(&mut self, frame_time: f32) {
+        if !self.dynamic || frame_time <= 0. {
+            return;
+        }
+
+        // Ray tracing cost scales with the pixel count, so with the square of the scale
+        let ideal = self.scale * (self.target_frame_time / frame_time).sqrt();
+        // Damped to avoid oscillating between resolutions
+        self.scale = (self.scale + (ideal - self.scale) * 0.1).clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    // Size of the internal render targets for a window size
+    pub fn target_size(&self, window_size: PhysicalSize<u32>) -> PhysicalSize<u32> {
+        let factor = if self.supersampling { 2 } else { 1 };
+        PhysicalSize::new(window_size.width * factor, window_size.height * factor)
+    }
+
+    pub fn render_size(&self, size: PhysicalSize<u32>) -> PhysicalSize<u32> {
+        PhysicalSize::new(
+            ((size.width as f32 * self.scale) as u32).max(1),
+            ((size.height as f32 * self.scale) as u32).max(1),
+        )
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if key == VirtualKeyCode::K {
+            if state == ElementState::Pressed {
+                self.supersampling = !self.supersampling;
+                log::info!("Supersampling: {}", self.supersampling);
+            }
+            return true;
+        }
+
+        if key == VirtualKeyCode::Backslash {
+            if state == ElementState::Pressed {
+                self.dynamic = !self.dynamic;
+                log::info!("Dynamic resolution: {}", self.dynamic);
+            }
+            return true;
+        }
+
+        let step = match key {
+            VirtualKeyCode::LBracket => -SCALE_STEP,
+            VirtualKeyCode::RBracket => SCALE_STEP,
+            _ => return false,
+        };
+        if state == ElementState::Pressed {
+            self.scale = (self.scale + step).clamp(MIN_SCALE, MAX_SCALE);
+            log::info!("Render scale: {}", self.scale);
+        }
+        true
+    }
+}
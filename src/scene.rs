@@ -0,0 +1,75 @@
+use crate::{camera::Camera, world::Sun};
+
+// Directional light, the ray tracer has a single sun so only the first one
+// is used
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SunLight(pub Sun);
+
+// Marks the camera entity that mirrors the controlled camera
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MainCamera;
+
+pub type System = Box<dyn FnMut(&mut hecs::World, f32)>;
+
+// Entities and systems on top of the renderer. Each frame the renderer state
+// is pulled into the entities, the systems run, and the result is pushed
+// back before the uniforms are uploaded, so the camera controller, console
+// and overlay keep working alongside the systems.
+pub struct Scene {
+    pub world: hecs::World,
+    systems: Vec<System>,
+    sun: hecs::Entity,
+    camera: hecs::Entity,
+}
+
+impl Scene {
+    pub fn new(sun: Sun, camera: &Camera) -> Self {
+        let mut world = hecs::World::new();
+        let sun = world.spawn((SunLight(sun),));
+        let camera = world.spawn((MainCamera, camera.clone()));
+        Self {
+            world,
+            systems: Vec::new(),
+            sun,
+            camera,
+        }
+    }
+
+    // Systems run in the order they were added
+    pub fn add_system(&mut self, system: impl FnMut(&mut hecs::World, f32) + 'static) {
+        self.systems.push(Box::new(system));
+    }
+
+    pub fn update(&mut self, dt: f32, camera: &mut Camera, sun: &mut Sun) {
+        self.pull(camera, sun);
+        for system in &mut self.systems {
+            system(&mut self.world, dt);
+        }
+        self.push(camera, sun);
+    }
+
+    fn pull(&mut self, camera: &Camera, sun: &Sun) {
+        // Systems may have despawned them
+        if !self.world.contains(self.sun) {
+            self.sun = self.world.spawn((SunLight(*sun),));
+        }
+        if !self.world.contains(self.camera) {
+            self.camera = self.world.spawn((MainCamera, camera.clone()));
+        }
+        if let Ok(mut light) = self.world.get::<&mut SunLight>(self.sun) {
+            light.0 = *sun;
+        }
+        if let Ok(mut main) = self.world.get::<&mut Camera>(self.camera) {
+            *main = camera.clone();
+        }
+    }
+
+    fn push(&self, camera: &mut Camera, sun: &mut Sun) {
+        if let Some((_, light)) = self.world.query::<&SunLight>().iter().next() {
+            *sun = light.0;
+        }
+        if let Some((_, (_, main))) = self.world.query::<(&MainCamera, &Camera)>().iter().next() {
+            *camera = main.clone();
+        }
+    }
+}
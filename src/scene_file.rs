@@ -0,0 +1,186 @@
+use serde::Deserialize;
+
+use crate::{
+    assets,
+    config::QualityConfig,
+    watcher::FileWatcher,
+    world::{Sun, World},
+};
+
+pub const SCENE_PATH: &str = "scene.toml";
+
+// Where the voxels come from: the built in terrain, a MagicaVoxel model or a
+// PNG heightmap, see State::load_world
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum WorldSource {
+    #[default]
+    Procedural,
+    Vox {
+        path: String,
+    },
+    Heightmap {
+        path: String,
+    },
+}
+
+impl WorldSource {
+    // A .vox model or a .png heightmap
+    pub fn from_path(path: &str) -> Result<Self, String> {
+        let path = path.to_string();
+        match path.rsplit_once('.').map(|(_, extension)| extension) {
+            Some("vox") => Ok(WorldSource::Vox { path }),
+            Some("png") => Ok(WorldSource::Heightmap { path }),
+            _ => Err(format!("{}: not a .vox or .png file", path)),
+        }
+    }
+
+    pub fn load(&self) -> Result<World, String> {
+        let world = match self {
+            WorldSource::Procedural => return Ok(World::default()),
+            WorldSource::Vox { path } => {
+                let (grid, colors) = read_file(path)
+                    .and_then(|bytes| assets::vox::decode_grid(&bytes))
+                    .map_err(|error| format!("{}: {}", path, error))?;
+                log::info!("Loaded {}", path);
+                World::new(Some(grid)).with_palette(colors)
+            }
+            WorldSource::Heightmap { path } => {
+                let grid = read_file(path)
+                    .and_then(|bytes| assets::heightmap::decode_grid(&bytes))
+                    .map_err(|error| format!("{}: {}", path, error))?;
+                log::info!("Loaded {}", path);
+                World::new(Some(grid))
+            }
+        };
+        Ok(world)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WorldDescription {
+    pub source: WorldSource,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CameraStart {
+    pub position: Option<[f32; 3]>,
+    // Point the camera looks at from its position
+    pub look_at: Option<[f32; 3]>,
+    pub fov: Option<f32>,
+}
+
+// Degrees, like set sun in the console
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct SunDescription {
+    pub azimuth: f32,
+    pub elevation: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Lights {
+    pub sun: Option<SunDescription>,
+}
+
+impl Lights {
+    pub fn sun(&self) -> Option<Sun> {
+        self.sun.map(|sun| Sun {
+            azimuth: sun.azimuth,
+            elevation: sun.elevation,
+        })
+    }
+}
+
+// A demo scene, loaded at startup and whenever the file changes, e.g.
+//
+// [world]
+// source = { type = "procedural" }
+//
+// [camera]
+// position = [0.0, 12.0, -40.0]
+// look_at = [0.0, 0.0, 0.0]
+// fov = 50.0
+//
+// [lights]
+// sun = { azimuth = 120.0, elevation = 25.0 }
+//
+// [render]
+// shadows = true
+// taa = true
+//
+// [render] takes the same keys as [quality] in config.toml and is applied
+// after it. Top level scripts = ["demo.rhai"] runs Rhai scripts with the
+// scripting feature.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SceneFile {
+    pub world: WorldDescription,
+    pub camera: CameraStart,
+    pub lights: Lights,
+    pub render: QualityConfig,
+    pub scripts: Vec<String>,
+}
+
+impl SceneFile {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        toml::from_str(&source).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    // None if there is no scene file or it is invalid
+    pub fn load(path: &str) -> Option<Self> {
+        if std::fs::metadata(path).is_err() {
+            return None;
+        }
+
+        Self::from_file(path)
+            .map_err(|error| log::warn!("Invalid scene: {}", error))
+            .ok()
+    }
+}
+
+// Reloads the scene file when it changes
+pub struct SceneWatcher {
+    watcher: FileWatcher,
+}
+
+impl SceneWatcher {
+    pub fn new(path: &str) -> Self {
+        Self {
+            watcher: FileWatcher::new(path),
+        }
+    }
+
+    // The new scene if the file changed and is valid
+    pub fn poll(&mut self, dt: f32) -> Option<SceneFile> {
+        if !self.watcher.poll(dt) {
+            return None;
+        }
+
+        match SceneFile::from_file(&self.watcher.path) {
+            Ok(scene) => {
+                log::info!("Reloaded {}", self.watcher.path);
+                Some(scene)
+            }
+            Err(error) => {
+                log::warn!("Invalid scene: {}", error);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|error| error.to_string())
+}
+
+// Worlds are only fetched as assets on the web
+#[cfg(target_arch = "wasm32")]
+fn read_file(_path: &str) -> Result<Vec<u8>, String> {
+    Err("Files can't be read in the browser".to_string())
+}
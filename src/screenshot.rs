@@ -0,0 +1,217 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use winit::{dpi::PhysicalSize, event::*};
+
+use crate::{
+    capture::{padded_bytes_per_row, read_pixels, write_png},
+    render,
+};
+
+const SCREENSHOT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Progress of mapping the readback buffer
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+enum ScreenshotState {
+    Idle,
+    // Taken with the next frame
+    Requested,
+    // Copied into the readback buffer by the submitted frame
+    Copied(String),
+    // Waiting for the readback buffer to be mapped
+    Mapping(String),
+}
+
+// Saves the tonemapped image at the window size to a timestamped PNG with
+// F12. The readback is mapped asynchronously and the PNG encoded on another
+// thread, so taking one doesn't stall the render loop.
+pub struct Screenshot {
+    pub render: render::RenderPipeline,
+    pub target: wgpu::Texture,
+    readback: wgpu::Buffer,
+    map_state: Arc<AtomicU8>,
+    state: ScreenshotState,
+}
+
+impl Screenshot {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        color_texture: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        tonemap_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Screenshot {
+        let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
+        });
+
+        let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fragment shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/frag.wgsl").into()),
+        });
+
+        // Only the format is used when creating the pipeline
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: SCREENSHOT_FORMAT,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+
+        let render = render::RenderPipeline::new(
+            device,
+            vert_shader,
+            frag_shader,
+            &config,
+            sampler,
+            color_texture,
+            tonemap_bind_group_layout,
+        );
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            format: SCREENSHOT_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Screenshot target texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Readback Buffer"),
+            size: (padded_bytes_per_row(size.width) * size.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Screenshot {
+            render,
+            target,
+            readback,
+            map_state: Arc::new(AtomicU8::new(MAP_PENDING)),
+            state: ScreenshotState::Idle,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::F12 => {
+                if state == ElementState::Pressed {
+                    if self.state == ScreenshotState::Idle {
+                        self.state = ScreenshotState::Requested;
+                    } else {
+                        log::warn!("Still saving the last screenshot");
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn encode(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        tonemap_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.state != ScreenshotState::Requested {
+            return;
+        }
+
+        let target_view = self
+            .target
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        {
+            let mut screenshot_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Screenshot Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            screenshot_pass.set_pipeline(&self.render.pipeline);
+            screenshot_pass.set_bind_group(0, &self.render.bind_group, &[]);
+            screenshot_pass.set_bind_group(1, tonemap_bind_group, &[]);
+            screenshot_pass.draw(0..3, 0..1);
+        }
+
+        let size = self.target.size();
+        encoder.copy_texture_to_buffer(
+            self.target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row(size.width)),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        self.state = ScreenshotState::Copied(format!("screenshot_{}.png", timestamp));
+    }
+
+    // Called after submitting every frame, until the screenshot is read back
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        match std::mem::replace(&mut self.state, ScreenshotState::Idle) {
+            ScreenshotState::Copied(path) => {
+                let map_state = self.map_state.clone();
+                self.readback
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        let state = if result.is_ok() { MAP_DONE } else { MAP_FAILED };
+                        map_state.store(state, Ordering::Release);
+                    });
+                self.state = ScreenshotState::Mapping(path);
+            }
+            ScreenshotState::Mapping(path) => {
+                device.poll(wgpu::Maintain::Poll);
+                match self.map_state.swap(MAP_PENDING, Ordering::Acquire) {
+                    MAP_PENDING => self.state = ScreenshotState::Mapping(path),
+                    MAP_DONE => {
+                        let size = self.target.size();
+                        let pixels = read_pixels(&self.readback, size);
+                        let save = move || match write_png(&pixels, size, &path) {
+                            Ok(()) => log::info!("Saved {}", path),
+                            Err(error) => log::warn!("Couldn't save {}: {}", path, error),
+                        };
+                        // No threads on the web
+                        if cfg!(target_arch = "wasm32") {
+                            save();
+                        } else {
+                            std::thread::spawn(save);
+                        }
+                    }
+                    _ => log::warn!("Couldn't read back {}", path),
+                }
+            }
+            state => self.state = state,
+        }
+    }
+}
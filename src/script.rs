@@ -0,0 +1,181 @@
+use std::{cell::RefCell, rc::Rc};
+
+use nalgebra::Point3;
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+
+use crate::{console::Command, world::Sun};
+
+type Commands = Rc<RefCell<Vec<Command>>>;
+
+struct Script {
+    path: String,
+    ast: AST,
+    scope: Scope<'static>,
+    // Whether the script defines fn update(dt)
+    has_update: bool,
+}
+
+// Rhai scripts listed in the scene file. The top level of a script runs once
+// when it is loaded and fn update(dt) runs every frame, e.g.
+//
+// teleport(0, 12, -40);
+// let time = 6.0;
+// fn update(dt) {
+//     time += dt;
+//     set_time(time);
+// }
+//
+// The functions queue console commands, which State applies after the
+// scripts ran: teleport(x, y, z), look_at(x, y, z), set_fov(degrees),
+// set_speed(units), set_sun(azimuth, elevation), set_time(hours) and
+// set_voxel(x, y, z, material) with a palette index from 0 to 255.
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: Vec<Script>,
+    commands: Commands,
+}
+
+impl ScriptHost {
+    pub fn new() -> Self {
+        let commands = Commands::default();
+        let mut engine = Engine::new();
+
+        let queue = commands.clone();
+        engine.register_fn("teleport", move |x: Dynamic, y: Dynamic, z: Dynamic| {
+            let position = point(x, y, z)?;
+            queue.borrow_mut().push(Command::Teleport(position));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("look_at", move |x: Dynamic, y: Dynamic, z: Dynamic| {
+            let target = point(x, y, z)?;
+            queue.borrow_mut().push(Command::LookAt(target));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_fov", move |fov: Dynamic| {
+            queue.borrow_mut().push(Command::SetFov(number(fov)?));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_speed", move |speed: Dynamic| {
+            queue.borrow_mut().push(Command::SetSpeed(number(speed)?));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_sun", move |azimuth: Dynamic, elevation: Dynamic| {
+            let sun = Sun {
+                azimuth: number(azimuth)?,
+                elevation: number(elevation)?,
+            };
+            queue.borrow_mut().push(Command::SetSun(sun));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_time", move |hours: Dynamic| {
+            queue.borrow_mut().push(Command::SetTime(number(hours)?));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_voxel", move |x: i64, y: i64, z: i64, material: i64| {
+            let position = Point3::new(x as i32, y as i32, z as i32);
+            let material = u8::try_from(material).map_err(|_| {
+                Box::<EvalAltResult>::from(format!("Invalid material: {}", material))
+            })?;
+            queue
+                .borrow_mut()
+                .push(Command::SetVoxel(position, material));
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+
+        Self {
+            engine,
+            scripts: Vec::new(),
+            commands,
+        }
+    }
+
+    // Replaces the running scripts. Scripts that fail to compile or whose
+    // top level fails are left out.
+    pub fn load(&mut self, paths: &[String]) {
+        self.scripts.clear();
+        for path in paths {
+            match self.load_script(path) {
+                Ok(script) => {
+                    log::info!("Loaded script {}", path);
+                    self.scripts.push(script)
+                }
+                Err(error) => log::warn!("Script {}: {}", path, error),
+            }
+        }
+    }
+
+    fn load_script(&self, path: &str) -> Result<Script, String> {
+        let source = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|error| error.to_string())?;
+        let mut scope = Scope::new();
+        self.engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|error| error.to_string())?;
+        let has_update = ast
+            .iter_functions()
+            .any(|function| function.name == "update" && function.params.len() == 1);
+        Ok(Script {
+            path: path.to_string(),
+            ast,
+            scope,
+            has_update,
+        })
+    }
+
+    // Runs the update callbacks and returns the commands queued since the
+    // last call. A script whose update fails is stopped.
+    pub fn update(&mut self, dt: f32) -> Vec<Command> {
+        let engine = &self.engine;
+        self.scripts.retain_mut(|script| {
+            if !script.has_update {
+                return true;
+            }
+            let result = engine.call_fn::<Dynamic>(
+                &mut script.scope,
+                &script.ast,
+                "update",
+                (dt as rhai::FLOAT,),
+            );
+            if let Err(error) = result {
+                log::warn!("Script {} stopped: {}", script.path, error);
+                return false;
+            }
+            true
+        });
+        self.commands.take()
+    }
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Scripts can pass integers and floats alike
+fn number(value: Dynamic) -> Result<f32, Box<EvalAltResult>> {
+    let number = match value.as_float() {
+        Ok(value) => value as f32,
+        Err(_) => value.as_int().map_err(|kind| {
+            Box::new(EvalAltResult::ErrorMismatchDataType(
+                "number".into(),
+                kind.into(),
+                rhai::Position::NONE,
+            ))
+        })? as f32,
+    };
+    Ok(number)
+}
+
+fn point(x: Dynamic, y: Dynamic, z: Dynamic) -> Result<Point3<f32>, Box<EvalAltResult>> {
+    Ok(Point3::new(number(x)?, number(y)?, number(z)?))
+}
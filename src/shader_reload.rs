@@ -0,0 +1,70 @@
+use crate::watcher::FileWatcher;
+
+const SHADER_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+const RAYTRACING_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/ray-tracing.wgsl");
+const FRAG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/frag.wgsl");
+
+// Watches the ray tracing and output shaders in the source tree of debug
+// builds, so edits show up without restarting. Release builds and the web
+// only use the sources included at compile time.
+pub struct ShaderReload {
+    raytracing: Option<FileWatcher>,
+    frag: Option<FileWatcher>,
+    // Last reloaded fragment shader that compiled, also used when the output
+    // pipelines are recreated. The ray tracing pipelines keep their own source
+    // when resized.
+    frag_source: Option<String>,
+}
+
+impl ShaderReload {
+    pub fn new() -> Self {
+        let enabled = cfg!(debug_assertions) && !cfg!(target_arch = "wasm32");
+        Self {
+            raytracing: enabled.then(|| FileWatcher::new(RAYTRACING_PATH)),
+            frag: enabled.then(|| FileWatcher::new(FRAG_PATH)),
+            frag_source: None,
+        }
+    }
+
+    pub fn frag_source(&self) -> &str {
+        self.frag_source
+            .as_deref()
+            .unwrap_or(include_str!("shaders/frag.wgsl"))
+    }
+
+    // Sources of the ray tracing and fragment shaders that changed since the
+    // last call. Editing only an included file doesn't trigger a reload.
+    pub fn poll(&mut self, dt: f32) -> (Option<String>, Option<String>) {
+        (
+            read_changed(&mut self.raytracing, dt),
+            read_changed(&mut self.frag, dt),
+        )
+    }
+
+    // Called once a polled fragment shader compiled
+    pub fn frag_compiled(&mut self, source: String) {
+        self.frag_source = Some(source);
+    }
+}
+
+impl Default for ShaderReload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_changed(watcher: &mut Option<FileWatcher>, dt: f32) -> Option<String> {
+    let watcher = watcher.as_mut()?;
+    if !watcher.poll(dt) {
+        return None;
+    }
+    std::fs::read_to_string(&watcher.path)
+        .map_err(|error| log::warn!("Couldn't read {}: {}", watcher.path, error))
+        .ok()
+}
+
+// Includes of reloaded shaders come from the source tree too
+pub fn read_include(name: &str) -> Result<String, String> {
+    let path = format!("{}/{}", SHADER_DIRECTORY, name);
+    std::fs::read_to_string(&path).map_err(|error| format!("{}: {}", path, error))
+}
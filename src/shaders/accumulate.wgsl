@@ -0,0 +1,55 @@
+@group(0) @binding(0) var current_buffer: texture_2d<f32>;
+@group(0) @binding(1) var history_buffer: texture_2d<f32>;
+@group(0) @binding(2) var output_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(3)
+var<uniform> params: AccumulateUniform;
+// Mean luminance, mean squared luminance and sample count of every pixel
+@group(0) @binding(4) var moments_history: texture_2d<f32>;
+@group(0) @binding(5) var moments_output: texture_storage_2d<rgba32float, write>;
+// Blocks that were traced this sample when adaptive is set, see
+// noise-mask.wgsl
+@group(0) @binding(6) var noise_mask: texture_2d<u32>;
+
+struct AccumulateUniform {
+    size: vec2<u32>,
+    sample_index: u32,
+    adaptive: u32,
+    noise_threshold: f32,
+}
+
+// Pixels of a noise mask texel, must match raytracing.rs
+const NOISE_BLOCK: vec2<i32> = vec2<i32>(8, 4);
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Running average of every sample rendered for the current frame. With
+// adaptive sampling pixels of converged blocks weren't traced again and keep
+// their average, so every pixel is averaged over its own sample count.
+@compute @workgroup_size(16,16,1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let pos = vec2<i32>(global_id.xy);
+    if pos.x >= i32(params.size.x) || pos.y >= i32(params.size.y) { return; }
+
+    let current = textureLoad(current_buffer, pos, 0).rgb;
+    let current_luminance = luminance(current);
+    var color = current;
+    var moments = vec4<f32>(current_luminance, current_luminance * current_luminance, 1., 0.);
+    if params.sample_index != 0u {
+        let history = textureLoad(history_buffer, pos, 0).rgb;
+        let previous = textureLoad(moments_history, pos, 0);
+        let traced = params.adaptive == 0u || textureLoad(noise_mask, pos / NOISE_BLOCK, 0).r != 0u;
+        if traced {
+            let weight = 1. / (previous.z + 1.);
+            color = mix(history, current, weight);
+            moments = vec4<f32>(mix(previous.xy, moments.xy, weight), previous.z + 1., 0.);
+        } else {
+            color = history;
+            moments = previous;
+        }
+    }
+
+    textureStore(output_buffer, pos, vec4<f32>(color, 1.));
+    textureStore(moments_output, pos, moments);
+}
@@ -0,0 +1,73 @@
+#include "camera.wgsl"
+#include "lighting.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0) var depth_buffer: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> avatar: AvatarUniform;
+
+struct AvatarUniform {
+    // Same sun as the ray traced scene
+    sun_direction: vec4<f32>,
+}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
+}
+
+// Columns of the model matrix of the instance
+struct InstanceInput {
+    @location(3) model_0: vec4<f32>,
+    @location(4) model_1: vec4<f32>,
+    @location(5) model_2: vec4<f32>,
+    @location(6) model_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) relative: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
+    // Clip position before the depth remap, to find the ray traced pixel
+    @location(3) clip: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world = model * vec4<f32>(in.position, 1.);
+    out.relative = world.xyz - camera.view_pos.xyz;
+    out.normal = (model * vec4<f32>(in.normal, 0.)).xyz;
+    out.color = in.color;
+    out.clip = camera.view_proj * vec4<f32>(out.relative, 1.);
+
+    // view_proj produces OpenGL style -w..w depth
+    out.position = out.clip;
+    out.position.z = (out.clip.z + out.clip.w) * 0.5;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let ndc = in.clip.xy / in.clip.w;
+    let size = vec2<f32>(camera.viewport.xy);
+    let texel = clamp(vec2<i32>((ndc * 0.5 + 0.5) * size), vec2<i32>(0), vec2<i32>(size) - 1);
+
+    // Hidden behind ray traced geometry, depth is the distance along the ray
+    var distance_to_surface = length(in.relative);
+    if camera.projection == PROJECTION_ORTHOGRAPHIC {
+        // Orthographic rays start on the camera plane
+        distance_to_surface = dot(in.relative, (camera.view * vec4<f32>(0., 0., -1., 0.)).xyz);
+    }
+    let scene_depth = textureLoad(depth_buffer, texel, 0).r;
+    if distance_to_surface > scene_depth * 1.001 + 0.01 {
+        discard;
+    }
+
+    let light = max(dot(normalize(in.normal), avatar.sun_direction.xyz), 0.) + AMBIENT_LIGHT;
+    return vec4<f32>(in.color * min(light, 1.), 1.);
+}
@@ -0,0 +1,20 @@
+// Matches camera::CameraUniform
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    prev_view: mat4x4<f32>,
+    prev_proj: mat4x4<f32>,
+    prev_view_pos: vec4<f32>,
+    jitter: vec4<f32>,
+    viewport: vec4<u32>,
+    projection: u32,
+};
+
+// CameraUniform projections, match camera::Projection
+const PROJECTION_PERSPECTIVE: u32 = 0u;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1u;
+const PROJECTION_FISHEYE: u32 = 2u;
+const PROJECTION_EQUIRECTANGULAR: u32 = 3u;
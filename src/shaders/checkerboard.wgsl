@@ -0,0 +1,93 @@
+@group(0) @binding(0) var current_buffer: texture_2d<f32>;
+@group(0) @binding(1) var motion_buffer: texture_2d<f32>;
+@group(0) @binding(2) var history_buffer: texture_2d<f32>;
+@group(0) @binding(3) var history_sampler: sampler;
+@group(0) @binding(4) var output_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(5)
+var<uniform> params: CheckerboardUniform;
+
+struct CheckerboardUniform {
+    size: vec2<u32>,
+    history_size: vec2<u32>,
+    enabled: u32,
+    parity: u32,
+    reset: u32,
+    pattern: u32,
+    // Pixels from xy up to zw were all traced, see CheckerboardPipeline::fovea
+    fovea: vec4<u32>,
+}
+
+// Must match checkerboard::Pattern, 0 is a checkerboard
+const PATTERN_ROWS: u32 = 1u;
+const PATTERN_COLUMNS: u32 = 2u;
+
+fn traced(pos: vec2<i32>) -> bool {
+    let parity = i32(params.parity);
+    if params.pattern == PATTERN_ROWS {
+        return ((pos.y + parity) & 1) == 0;
+    }
+    if params.pattern == PATTERN_COLUMNS {
+        return ((pos.x + parity) & 1) == 0;
+    }
+    return ((pos.x + pos.y + parity) & 1) == 0;
+}
+
+@compute @workgroup_size(16,16,1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(params.size);
+    let pos = vec2<i32>(global_id.xy);
+    if pos.x >= size.x || pos.y >= size.y { return; }
+
+    let current = textureLoad(current_buffer, pos, 0).rgb;
+    let in_fovea = all(vec2<u32>(pos) >= params.fovea.xy) && all(vec2<u32>(pos) < params.fovea.zw);
+    if params.enabled == 0u || in_fovea || traced(pos) {
+        textureStore(output_buffer, pos, vec4<f32>(current, 1.));
+        return;
+    }
+
+    // The direct neighbours that were traced this frame, all four with a
+    // checkerboard, the ones above and below with rows and the ones to the
+    // sides with columns
+    var low = vec3<f32>(1e9);
+    var high = vec3<f32>(-1e9);
+    var average = vec3<f32>(0.);
+    var motion = vec2<f32>(0.);
+    var count = 0.;
+    for (var i = 0; i < 4; i++) {
+        let direction = i >> 1u;
+        if (params.pattern == PATTERN_ROWS && direction == 0) || (params.pattern == PATTERN_COLUMNS && direction == 1) {
+            continue;
+        }
+        let side = (i & 1) * 2 - 1;
+        var offset = vec2<i32>(0);
+        offset[direction] = side;
+        let neighbour_pos = pos + offset;
+        if any(neighbour_pos < vec2<i32>(0)) || any(neighbour_pos >= size) {
+            continue;
+        }
+
+        let neighbour = textureLoad(current_buffer, neighbour_pos, 0).rgb;
+        low = min(low, neighbour);
+        high = max(high, neighbour);
+        average += neighbour;
+        motion += textureLoad(motion_buffer, neighbour_pos, 0).xy;
+        count += 1.;
+    }
+    average /= count;
+    motion /= count;
+
+    let uv = (vec2<f32>(pos) + 0.5) / vec2<f32>(size);
+    let prev_uv = uv - motion * 0.5; // NDC to uv
+    if params.reset != 0u || any(prev_uv < vec2<f32>(0.)) || any(prev_uv > vec2<f32>(1.)) {
+        textureStore(output_buffer, pos, vec4<f32>(average, 1.));
+        return;
+    }
+
+    // The pixel was traced last frame, reproject it and reject it if it
+    // doesn't fit in with its neighbours
+    let history_scale = vec2<f32>(params.history_size) / vec2<f32>(textureDimensions(history_buffer));
+    let history_uv = min(prev_uv * history_scale, history_scale - 0.5 / vec2<f32>(textureDimensions(history_buffer)));
+    let history = textureSampleLevel(history_buffer, history_sampler, history_uv, 0.).rgb;
+
+    textureStore(output_buffer, pos, vec4<f32>(clamp(history, low, high), 1.));
+}
@@ -0,0 +1,30 @@
+@group(0) @binding(0) var depth_buffer: texture_2d<f32>;
+@group(0) @binding(1) var pyramid: texture_storage_2d<r32float, write>;
+@group(0) @binding(2)
+var<uniform> params: OcclusionUniform;
+
+struct OcclusionUniform {
+    // Render size, the part of depth_buffer that was traced
+    size: vec2<u32>,
+}
+
+// Must match occlusion::PYRAMID_SIZE
+const PYRAMID_SIZE: u32 = 64u;
+
+// Farthest depth of the block of pixels under each texel. A chunk whose
+// nearest point is farther away than that is behind every pixel of the block.
+@compute @workgroup_size(8,8,1)
+fn reduce(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= vec2<u32>(PYRAMID_SIZE)) { return; }
+
+    // Blocks overlap when the render size is below the pyramid size
+    let start = global_id.xy * params.size / PYRAMID_SIZE;
+    let end = max((global_id.xy + 1u) * params.size / PYRAMID_SIZE, start + 1u);
+    var farthest = 0.;
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            farthest = max(farthest, textureLoad(depth_buffer, vec2<i32>(vec2<u32>(x, y)), 0).r);
+        }
+    }
+    textureStore(pyramid, vec2<i32>(global_id.xy), vec4<f32>(farthest));
+}
@@ -1,8 +1,69 @@
 @group(0) @binding(0) var screen_sampler: sampler;
 @group(0) @binding(1) var color_buffer: texture_2d<f32>;
+@group(1) @binding(0)
+var<uniform> tonemap: TonemapUniform;
+
+struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+    uv_scale: vec2<f32>,
+    crosshair: u32,
+    hdr_output: u32,
+}
 
 @fragment
 fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
     let coord = tex_coord / 2. + 0.5; // normalize between 0...1
-    return textureSample(color_buffer, screen_sampler, coord);
+
+    // Rescale the rendered region, without filtering in texels outside of it.
+    // With supersampling the bilinear tap lands between four texels, which is
+    // a 2x2 box downsample.
+    let half_texel = 0.5 / vec2<f32>(textureDimensions(color_buffer));
+    let scaled_coord = min(coord * tonemap.uv_scale, tonemap.uv_scale - half_texel);
+    let hdr = textureSample(color_buffer, screen_sampler, scaled_coord).rgb * tonemap.exposure;
+
+    var color: vec3<f32>;
+    // Matches tonemap::Tonemapper
+    switch tonemap.tonemapper {
+        case 1u: { color = reinhard(hdr); }
+        case 2u: { color = aces(hdr); }
+        default: { color = hdr; }
+    }
+
+    // HDR swapchains are linear extended range, 1 is SDR white
+    if tonemap.hdr_output != 0u {
+        color = max(color, vec3<f32>(0.));
+    } else {
+        color = clamp(color, vec3<f32>(0.), vec3<f32>(1.));
+    }
+    if tonemap.crosshair != 0u {
+        color = crosshair(tex_coord, color);
+    }
+
+    return vec4<f32>(color, 1.);
+}
+
+fn crosshair(tex_coord: vec2<f32>, color: vec3<f32>) -> vec3<f32> {
+    // Distance from the screen center in pixels
+    let pixels = abs(tex_coord) / fwidth(tex_coord);
+    let arm = (pixels.x < 1. && pixels.y < 10.) || (pixels.y < 1. && pixels.x < 10.);
+    if arm {
+        // Inverted, so it stays visible on bright and dark backgrounds
+        return 1. - clamp(color, vec3<f32>(0.), vec3<f32>(1.));
+    }
+    return color;
+}
+
+fn reinhard(c: vec3<f32>) -> vec3<f32> {
+    return c / (1. + c);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(c: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let d = 2.43;
+    let e = 0.59;
+    let f = 0.14;
+    return (c * (a * c + b)) / (c * (d * c + e) + f);
 }
@@ -0,0 +1,52 @@
+#include "camera.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0) var depth_buffer: texture_2d<f32>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) relative: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    // Clip position before the depth remap, to find the ray traced pixel
+    @location(2) clip: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.relative = in.position - camera.view_pos.xyz;
+    out.clip = camera.view_proj * vec4<f32>(out.relative, 1.);
+    out.color = in.color;
+
+    // view_proj produces OpenGL style -w..w depth
+    out.position = out.clip;
+    out.position.z = (out.clip.z + out.clip.w) * 0.5;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let ndc = in.clip.xy / in.clip.w;
+    let size = vec2<f32>(camera.viewport.xy);
+    let texel = clamp(vec2<i32>((ndc * 0.5 + 0.5) * size), vec2<i32>(0), vec2<i32>(size) - 1);
+
+    // Hidden behind ray traced geometry, depth is the distance along the ray
+    var distance_to_line = length(in.relative);
+    if camera.projection == PROJECTION_ORTHOGRAPHIC {
+        // Orthographic rays start on the camera plane
+        distance_to_line = dot(in.relative, (camera.view * vec4<f32>(0., 0., -1., 0.)).xyz);
+    }
+    let scene_depth = textureLoad(depth_buffer, texel, 0).r;
+    if distance_to_line > scene_depth * 1.001 + 0.01 {
+        discard;
+    }
+
+    let fade = 1. - smoothstep(128., 256., distance_to_line);
+    return vec4<f32>(in.color.rgb, in.color.a * fade);
+}
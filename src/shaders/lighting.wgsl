@@ -0,0 +1,3 @@
+// Light reaching surfaces facing away from the sun or in shadow, shared so
+// rasterized geometry matches the ray traced scene
+const AMBIENT_LIGHT: f32 = 0.25;
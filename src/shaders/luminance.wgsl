@@ -0,0 +1,87 @@
+@group(0) @binding(0) var hdr_buffer: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> partials: array<vec2<f32>>;
+@group(0) @binding(2) var<storage, read_write> state: ExposureState;
+@group(0) @binding(3)
+var<uniform> params: ExposureUniform;
+
+struct ExposureState {
+    adapted: f32,
+    exposure: f32,
+    average_luminance: f32,
+}
+
+struct ExposureUniform {
+    dt: f32,
+    speed: f32,
+    key_value: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+    compensation: f32,
+    size: vec2<u32>,
+}
+
+// x: sum of log luminance, y: number of pixels
+var<workgroup> shared_sums: array<vec2<f32>, 256>;
+
+@compute @workgroup_size(16,16,1)
+fn reduce(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) group_id: vec3<u32>,
+    @builtin(num_workgroups) group_count: vec3<u32>,
+) {
+    let size = params.size;
+
+    var value = vec2<f32>(0.);
+    if global_id.x < size.x && global_id.y < size.y {
+        let color = textureLoad(hdr_buffer, vec2<i32>(global_id.xy), 0).rgb;
+        value = vec2<f32>(log(max(luminance(color), 0.0001)), 1.);
+    }
+
+    shared_sums[local_index] = value;
+    workgroupBarrier();
+    sum_shared(local_index);
+
+    if local_index == 0u {
+        partials[group_id.y * group_count.x + group_id.x] = shared_sums[0];
+    }
+}
+
+@compute @workgroup_size(256,1,1)
+fn adapt(@builtin(local_invocation_index) local_index: u32) {
+    // Only the tiles covering the rendered region were written this frame
+    let tiles = (params.size + 15u) / 16u;
+    var sum = vec2<f32>(0.);
+    for (var i = local_index; i < tiles.x * tiles.y; i += 256u) {
+        sum += partials[i];
+    }
+
+    shared_sums[local_index] = sum;
+    workgroupBarrier();
+    sum_shared(local_index);
+
+    if local_index == 0u {
+        let total = shared_sums[0];
+        let average = exp(total.x / max(total.y, 1.));
+        let target_exposure = clamp(params.key_value / average, params.min_exposure, params.max_exposure);
+
+        // Exponential eye adaptation, independent of frame rate
+        let blend = 1. - exp(-params.dt * params.speed);
+        state.adapted = mix(state.adapted, target_exposure, blend);
+        state.average_luminance = average;
+        state.exposure = state.adapted * params.compensation;
+    }
+}
+
+fn sum_shared(local_index: u32) {
+    for (var stride = 128u; stride > 0u; stride = stride / 2u) {
+        if local_index < stride {
+            shared_sums[local_index] += shared_sums[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+}
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
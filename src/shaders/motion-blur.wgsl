@@ -0,0 +1,42 @@
+@group(0) @binding(0) var color_buffer: texture_2d<f32>;
+@group(0) @binding(1) var motion_buffer: texture_2d<f32>;
+@group(0) @binding(2) var color_sampler: sampler;
+@group(0) @binding(3) var output_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(4)
+var<uniform> params: MotionBlurUniform;
+
+struct MotionBlurUniform {
+    size: vec2<u32>,
+    shutter: f32,
+    samples: u32,
+    max_length: f32,
+}
+
+@compute @workgroup_size(16,16,1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<f32>(params.size);
+    let pos = vec2<i32>(global_id.xy);
+    if f32(pos.x) >= size.x || f32(pos.y) >= size.y { return; }
+
+    // Motion is stored in NDC, convert to pixels and scale by the shutter
+    var velocity = textureLoad(motion_buffer, pos, 0).xy * 0.5 * size * params.shutter;
+    let speed = length(velocity);
+    if speed > params.max_length {
+        velocity *= params.max_length / speed;
+    }
+
+    // Only the top left render sized region of the texture is valid
+    let texture_size = vec2<f32>(textureDimensions(color_buffer));
+    let max_uv = (size - 0.5) / texture_size;
+
+    let center = vec2<f32>(pos) + 0.5;
+    var color = vec3<f32>(0.);
+    for (var i = 0u; i < params.samples; i++) {
+        let t = f32(i) / f32(max(params.samples - 1u, 1u)) - 0.5;
+        let uv = min((center + velocity * t) / texture_size, max_uv);
+        color += textureSampleLevel(color_buffer, color_sampler, uv, 0.).rgb;
+    }
+    color /= f32(max(params.samples, 1u));
+
+    textureStore(output_buffer, pos, vec4<f32>(color, 1.));
+}
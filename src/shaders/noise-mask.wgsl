@@ -0,0 +1,42 @@
+@group(0) @binding(0) var moments: texture_2d<f32>;
+@group(0) @binding(1) var noise_mask: texture_storage_2d<r32uint, write>;
+@group(0) @binding(2)
+var<uniform> params: AccumulateUniform;
+
+struct AccumulateUniform {
+    size: vec2<u32>,
+    sample_index: u32,
+    adaptive: u32,
+    noise_threshold: f32,
+}
+
+// Pixels of a noise mask texel, must match raytracing.rs
+const NOISE_BLOCK: vec2<i32> = vec2<i32>(8, 4);
+// Darker pixels are held to the error allowed at this luminance, so nearly
+// black ones don't need endless samples
+const MIN_LUMINANCE: f32 = 0.05;
+
+// Marks the blocks with a pixel whose average is still uncertain, where the
+// standard error of its luminance is above noise_threshold times the
+// luminance. Reads the moments accumulate.wgsl wrote for this sample.
+@compute @workgroup_size(8,8,1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let block = vec2<i32>(global_id.xy);
+    let start = block * NOISE_BLOCK;
+    if any(start >= vec2<i32>(params.size)) { return; }
+
+    var noisy = 0u;
+    for (var y = 0; y < NOISE_BLOCK.y; y++) {
+        for (var x = 0; x < NOISE_BLOCK.x; x++) {
+            let pos = start + vec2<i32>(x, y);
+            if any(pos >= vec2<i32>(params.size)) { continue; }
+            let pixel = textureLoad(moments, pos, 0);
+            let variance = max(pixel.y - pixel.x * pixel.x, 0.);
+            let error = sqrt(variance / max(pixel.z - 1., 1.));
+            if error > params.noise_threshold * max(pixel.x, MIN_LUMINANCE) {
+                noisy = 1u;
+            }
+        }
+    }
+    textureStore(noise_mask, block, vec4<u32>(noisy));
+}
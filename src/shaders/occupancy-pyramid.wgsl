@@ -0,0 +1,118 @@
+@group(0) @binding(0)
+var<uniform> params: PyramidUniform;
+@group(0) @binding(1) var<storage, read> edits: array<vec4<i32>>;
+// Bits of edited bricks per brick column, then per chunk column
+@group(0) @binding(2) var<storage, read_write> edit_bits: array<atomic<u32>>;
+
+// Bound to the brick pass
+@group(1) @binding(0) var brick_output: texture_storage_2d<r32uint, write>;
+// Bound to the chunk pass
+@group(1) @binding(1) var brick_input: texture_2d<u32>;
+@group(1) @binding(2) var chunk_output: texture_storage_2d<r32uint, write>;
+
+struct PyramidUniform {
+    // Brick coordinates of the window's first column
+    origin: vec2<i32>,
+    edit_count: u32,
+    // 0 when a voxel grid replaced the terrain
+    terrain: u32,
+}
+
+// Must match occupancy.rs
+const WINDOW_BRICKS: i32 = 512;
+const WINDOW_CHUNKS: i32 = 64;
+const BRICK_SIZE: i32 = 8;
+const CHUNK_BRICKS: i32 = 8;
+const LAYERS: i32 = 32;
+const LAYER_OFFSET: i32 = 16;
+
+// Lowest and highest of sin(v / 5) over size integers from start
+fn sine_range(start: i32, size: i32) -> vec2<f32> {
+    var range = vec2<f32>(1., -1.);
+    for (var i = 0; i < size; i++) {
+        let v = sin(f32(start + i) / 5.);
+        range = vec2<f32>(min(range.x, v), max(range.y, v));
+    }
+    return range;
+}
+
+// Highest terrain over a size by size column from start, like
+// world::height_ranges
+fn max_height(start: vec2<i32>, size: i32) -> f32 {
+    let x = sine_range(start.x, size);
+    let z = sine_range(start.y, size);
+    return max(max(x.x * z.x, x.x * z.y), max(x.y * z.x, x.y * z.y)) * 5.;
+}
+
+// Bit of every cell of a column of bricks that lies partly below height
+fn column_bits(height: f32) -> u32 {
+    var bits = 0u;
+    for (var layer = 0; layer < LAYERS; layer++) {
+        if f32((layer - LAYER_OFFSET) * BRICK_SIZE) < height + 0.01 {
+            bits |= 1u << u32(layer);
+        }
+    }
+    return bits;
+}
+
+// Chunk layer of a brick layer
+fn chunk_layer(brick_y: i32) -> i32 {
+    return i32(floor(f32(brick_y) / f32(CHUNK_BRICKS))) + LAYER_OFFSET;
+}
+
+// Sets the bits of the edited bricks in the window, edit_bits starts zeroed
+@compute @workgroup_size(64,1,1)
+fn mark(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= params.edit_count { return; }
+
+    let brick = edits[global_id.x].xyz;
+    let local = brick.xz - params.origin;
+    if any(local < vec2<i32>(0)) || any(local >= vec2<i32>(WINDOW_BRICKS)) { return; }
+
+    let layer = brick.y + LAYER_OFFSET;
+    if layer >= 0 && layer < LAYERS {
+        atomicOr(&edit_bits[local.x + local.y * WINDOW_BRICKS], 1u << u32(layer));
+    }
+    let chunk = local / CHUNK_BRICKS;
+    let coarse = chunk_layer(brick.y);
+    if coarse >= 0 && coarse < LAYERS {
+        let index = WINDOW_BRICKS * WINDOW_BRICKS + chunk.x + chunk.y * WINDOW_CHUNKS;
+        atomicOr(&edit_bits[index], 1u << u32(coarse));
+    }
+}
+
+// Brick level from the terrain and the marked edits
+@compute @workgroup_size(8,8,1)
+fn bricks(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let texel = vec2<i32>(global_id.xy);
+    if any(texel >= vec2<i32>(WINDOW_BRICKS)) { return; }
+
+    var bits = atomicLoad(&edit_bits[texel.x + texel.y * WINDOW_BRICKS]);
+    if params.terrain != 0u {
+        let start = (params.origin + texel) * BRICK_SIZE;
+        bits |= column_bits(max_height(start, BRICK_SIZE));
+    }
+    textureStore(brick_output, texel, vec4<u32>(bits));
+}
+
+// Chunk level as the union of the bricks under each chunk, and the edits
+// above the brick level's layers
+@compute @workgroup_size(8,8,1)
+fn chunks(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let texel = vec2<i32>(global_id.xy);
+    if any(texel >= vec2<i32>(WINDOW_CHUNKS)) { return; }
+
+    var below = 0u;
+    for (var z = 0; z < CHUNK_BRICKS; z++) {
+        for (var x = 0; x < CHUNK_BRICKS; x++) {
+            below |= textureLoad(brick_input, texel * CHUNK_BRICKS + vec2<i32>(x, z), 0).r;
+        }
+    }
+    var bits = atomicLoad(&edit_bits[WINDOW_BRICKS * WINDOW_BRICKS + texel.x + texel.y * WINDOW_CHUNKS]);
+    for (var layer = 0; layer < LAYERS; layer++) {
+        if ((below >> u32(layer)) & 1u) != 0u {
+            bits |= 1u << u32(chunk_layer(layer - LAYER_OFFSET));
+        }
+    }
+    textureStore(chunk_output, texel, vec4<u32>(bits));
+}
@@ -1,49 +1,1023 @@
-@group(0) @binding(0) var color_buffer: texture_storage_2d<rgba8unorm, write>;
+#include "camera.wgsl"
+#include "lighting.wgsl"
+
+// WORKGROUP_WIDTH and WORKGROUP_HEIGHT are defined by the shader variant, see
+// raytracing::WorkgroupSize
+
+// FRAGMENT_FALLBACK builds the fallback_fragment entry point for devices
+// without compute shaders or storage bindings, i.e. WebGL2, and leaves out
+// everything that needs them
+#ifndef FRAGMENT_FALLBACK
+@group(0) @binding(0) var color_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(1) var motion_buffer: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(2) var albedo_buffer: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(3) var normal_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(4) var depth_buffer: texture_storage_2d<r32float, write>;
+@group(0) @binding(6) var<storage, read_write> pick_result: PickResult;
+// Wavefront mode, where main only finds the primary hits and lighting and
+// shading run as passes of their own
+@group(0) @binding(7) var<storage, read_write> pixel_hits: array<vec2<u32>>;
+@group(0) @binding(8) var<storage, read_write> shadow_queue: RayQueue;
+// Chunks the traversal may enter, see residency::ChunkResidency
+@group(0) @binding(9) var<storage, read> residency: ChunkResidency;
+// Tiled dispatch, filled by classify and read by the per class tile passes
+@group(0) @binding(10) var<storage, read_write> tile_lists: TileLists;
+// A column of bits per brick and per chunk around the camera, see
+// occupancy::OccupancyPyramid
+@group(0) @binding(11) var brick_occupancy: texture_2d<u32>;
+@group(0) @binding(12) var chunk_occupancy: texture_2d<u32>;
+// Voxels of the bricks near the camera and their slots, see
+// brick_pool::BrickPool
+@group(0) @binding(13) var<storage, read> pool_table: PoolTable;
+@group(0) @binding(14) var brick_pool: texture_3d<u32>;
+@group(0) @binding(15) var<uniform> palette: MaterialPalette;
+// Non-zero for the blocks adaptive sampling still traces, see
+// capture::AdaptiveSampling
+@group(0) @binding(17) var noise_mask: texture_2d<u32>;
+// The grid drawn in place of the terrain, see RaytracingPipeline::set_world
+@group(0) @binding(18) var voxel_grid: texture_3d<u32>;
+#endif
+@group(0) @binding(5)
+var<uniform> settings: RaytracingUniform;
+@group(0) @binding(16)
+var<uniform> render_settings: RenderSettings;
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
+#ifndef FRAGMENT_FALLBACK
+// Pre-pass outputs, written by the beam and lighting entry points and read by main
+@group(2) @binding(0) var beam_output: texture_storage_2d<r32float, write>;
+@group(2) @binding(1) var lighting_output: texture_storage_2d<rgba32float, write>;
+@group(2) @binding(2) var beam_input: texture_2d<f32>;
+@group(2) @binding(3) var lighting_input: texture_2d<f32>;
+// Written by prepare_shadows, with the queue in use as indirect dispatch
+// arguments
+@group(2) @binding(4) var<storage, read_write> shadow_dispatch: DispatchArgs;
+// Written by prepare_tiles, one set of arguments per tile class
+@group(2) @binding(5) var<storage, read_write> tile_dispatch: array<DispatchArgs, 3>;
+#endif
+
+// RaytracingUniform flags
+const WRITE_ALBEDO: u32 = 1u;
+const WRITE_NORMAL: u32 = 2u;
+const WRITE_DEPTH: u32 = 4u;
+const HIGHLIGHT_PICKED: u32 = 8u;
+const SHOW_CHUNK_BOUNDS: u32 = 16u;
+const SHOW_BRICK_BOUNDS: u32 = 32u;
+const BEAM_OPTIMIZATION: u32 = 64u;
+const HALF_RES_LIGHTING: u32 = 128u;
+const CHECKERBOARD: u32 = 256u;
+const WAVEFRONT: u32 = 512u;
+const TILED: u32 = 1024u;
+const ADAPTIVE_SAMPLING: u32 = 2048u;
+// The pattern of CHECKERBOARD, a checkerboard without either
+const INTERLEAVE_ROWS: u32 = 4096u;
+const INTERLEAVE_COLUMNS: u32 = 8192u;
+const SKY_TILES: u32 = 16384u;
+
+// Bits of the second component of pixel_hits
+const PIXEL_HIT: u32 = 1u;
+const PIXEL_SHADOWED: u32 = 2u;
+// Three bits of pack_normal from here, the DDA step count above them
+const PIXEL_NORMAL_SHIFT: u32 = 2u;
+const PIXEL_STEPS_SHIFT: u32 = 8u;
+
+// Threads per workgroup of the passes working through a ray queue
+const QUEUE_WORKGROUP_SIZE: u32 = 64u;
+
+// Must match raytracing::BEAM_TILE_SIZE
+const BEAM_TILE_SIZE: u32 = 8u;
+
+const SKY_DEPTH: f32 = 1e9;
+// Cells per side of a parent cell, at every level
+const CHUNK_CELLS: i32 = 8;
+
+// Tile classes of the visibility pass, index tile_lists and tile_dispatch
+const TILE_SKY: u32 = 0u;
+const TILE_NEAR: u32 = 1u;
+const TILE_FAR: u32 = 2u;
+// Pixels of a noise mask texel, must match raytracing.rs
+const NOISE_BLOCK_WIDTH: u32 = 8u;
+const NOISE_BLOCK_HEIGHT: u32 = 4u;
+// Nothing is solid at or above this height before the occupancy textures
+// are built, must match world::is_solid
+const WORLD_TOP: f32 = 5.;
+
+// RaytracingUniform debug views, match raytracing::DebugView
+const DEBUG_NONE: u32 = 0u;
+const DEBUG_NORMALS: u32 = 1u;
+const DEBUG_DEPTH: u32 = 2u;
+const DEBUG_STEP_COUNT: u32 = 3u;
+const DEBUG_CHUNK_ID: u32 = 4u;
+const DEBUG_RESIDENCY: u32 = 5u;
+
+const PI: f32 = 3.14159265;
+const FISHEYE_FOV: f32 = 3.14159265;
+
+// RaytracingUniform stereo modes, match raytracing::StereoMode
+const STEREO_OFF: u32 = 0u;
+const STEREO_SIDE_BY_SIDE: u32 = 1u;
+const STEREO_ANAGLYPH: u32 = 2u;
+
+const CHUNK_SIZE: f32 = 64.;
+
+// ChunkResidency states, match residency::ChunkState
+const CHUNK_CULLED: u32 = 0u;
+const CHUNK_RESIDENT: u32 = 1u;
+const CHUNK_VISIBLE: u32 = 2u;
+const CHUNK_OCCLUDED: u32 = 3u;
+// Chunks per side of the residency grid, match residency::GRID_WIDTH and
+// GRID_HEIGHT
+const RESIDENCY_WIDTH: i32 = 128;
+const RESIDENCY_HEIGHT: i32 = 16;
+const BRICK_SIZE: f32 = 8.;
+// Bricks per side of brick_occupancy, must match occupancy::WINDOW_BRICKS
+const OCCUPANCY_BRICKS: i32 = 512;
+// Cell of a column in the lowest bit of an occupancy texel, must match
+// LAYER_OFFSET in occupancy-pyramid.wgsl
+const OCCUPANCY_LAYER_OFFSET: i32 = 16;
+// Must match brick_pool::POOL_BRICKS, TABLE_BRICKS and TABLE_LAYERS
+const POOL_BRICKS: u32 = 32u;
+const POOL_TABLE_BRICKS: i32 = 64;
+const POOL_TABLE_LAYERS: i32 = 32;
+// Material of voxels that aren't pooled, must match palette::TERRAIN
+const TERRAIN_MATERIAL: u32 = 1u;
+// Bounds the descents and ascents of the stackless traversal, rays that
+// reach it end as they are
+const MAX_LEVEL_CHANGES: u32 = 64u;
+// Distance per voxel of a cell's size that a hit on it is kept at with a
+// lod_bias of 1
+const LOD_DISTANCE: f32 = 64.;
 
 struct Ray {
     origin: vec3<f32>,
     direction: vec3<f32>,
 }
 
-struct CameraUniform {
-    view_pos: vec4<f32>,
-    view: mat4x4<f32>,
-    proj: mat4x4<f32>
-};
+struct Hit {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    hit: bool,
+    // Number of DDA iterations over all levels
+    steps: u32,
+}
+
+struct RaytracingUniform {
+    flags: u32,
+    debug_view: u32,
+    // NDC position of the cursor, used for picking
+    cursor: vec2<f32>,
+    stereo: u32,
+    // Distance between the eyes in world units
+    eye_separation: f32,
+    frame_parity: u32,
+    // Tiles whose nearest corner hit is farther away are lit without shadow rays
+    far_distance: f32,
+    // Towards the sun, xyz is normalized
+    sun_direction: vec4<f32>,
+    // Brick coordinates of the occupancy textures' first texel in x and z,
+    // the height nothing in reach is solid at or above in y. w is 0 while
+    // they aren't usable.
+    occupancy_origin: vec4<i32>,
+    // Pixels from xy up to zw the fovea pass traces at the other parity
+    fovea: vec4<u32>,
+    // First voxel of voxel_grid, w is 0 while the terrain is drawn
+    grid_origin: vec4<i32>,
+}
+
+// Limits of the traversal, see raytracing::RenderSettings
+struct RenderSettings {
+    // Rays end as misses once they got this far
+    max_distance: f32,
+    // DDA iterations over all levels before a ray ends as a miss
+    max_steps: u32,
+    // Hits on bricks and chunks farther than their size times
+    // LOD_DISTANCE / lod_bias are kept without descending, 0 always descends
+    lod_bias: f32,
+}
+
+// The voxel under the cursor, written by the pick entry point
+struct PickResult {
+    // w is 1 if a voxel was hit
+    voxel: vec4<i32>,
+    normal: vec4<f32>,
+    // w is the distance along the ray
+    position: vec4<f32>,
+}
+
+// Pixels waiting for a ray of the next pass, compacted so its threads all
+// have work
+struct RayQueue {
+    // Pixels added so far this frame
+    count: atomic<u32>,
+    // Pixels added by the last stage, fixed by the prepare entry point as
+    // count is reset for the next frame
+    length: u32,
+    // Indices into pixel_hits
+    pixels: array<u32>,
+}
+
+struct ChunkResidency {
+    // Chunk coordinates of the grid's first chunk, w is 0 when every chunk
+    // is resident
+    origin: vec4<i32>,
+    // Two bits of state per chunk, x first, then z, then y
+    states: array<u32>,
+}
+
+// Workgroup sized tiles of the traced pixels, sorted by class
+struct TileLists {
+    counts: array<atomic<u32>, 3>,
+    // A third of the array per class, tiles packed as x | y << 16
+    tiles: array<u32>,
+}
+
+struct PoolTable {
+    // Brick coordinates of the table's first brick, w is 0 while nothing is
+    // pooled
+    origin: vec4<i32>,
+    // Slot + 1 per brick, 0 for bricks not in the pool. x first, then z,
+    // then y.
+    slots: array<u32>,
+}
+
+// Colors of the material indices stored in brick_pool, see palette.rs
+struct MaterialPalette {
+    // Linear RGB, an alpha of 0 colors the voxel by position
+    colors: array<vec4<f32>, 256>,
+}
+
+struct DispatchArgs {
+    x: u32,
+    y: u32,
+    z: u32,
+}
 
-@compute @workgroup_size(16,16,1)
+#ifndef FRAGMENT_FALLBACK
+// Dispatched at half width with checkerboarding, where every row traces
+// alternating pixels, or interleaved columns, and at half height with
+// interleaved rows
+fn traced_pixel(id: vec2<u32>) -> vec2<i32> {
+    if (settings.flags & CHECKERBOARD) != 0u {
+        return vec2<i32>(interleaved_pixel(id, settings.frame_parity));
+    }
+    return vec2<i32>(id);
+}
+
+// Pixel id of the half of the pixels with parity
+fn interleaved_pixel(id: vec2<u32>, parity: u32) -> vec2<u32> {
+    if (settings.flags & INTERLEAVE_ROWS) != 0u {
+        return vec2<u32>(id.x, id.y * 2u + (parity & 1u));
+    }
+    if (settings.flags & INTERLEAVE_COLUMNS) != 0u {
+        return vec2<u32>(id.x * 2u + (parity & 1u), id.y);
+    }
+    return vec2<u32>(id.x * 2u + ((id.y + parity) & 1u), id.y);
+}
+
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
 fn main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
-    let screen_pos = vec2<i32>(GlobalInvocationID.xy);
-    let screen_size = textureDimensions(color_buffer);
-    var pixel_color = vec3<f32>(.1, .2, .3);
-    let pixel_coord = (vec2<f32>(screen_pos) / vec2<f32>(screen_size)) * 2. - 1.;
+    trace_pixel(GlobalInvocationID.xy, TILE_NEAR);
+}
+
+// Traces the pixels checkerboarding left out inside the fovea, so the center
+// of the screen is traced every frame. Dispatched over the traced half of the
+// fovea after the other passes, and shaded right away in wavefront mode too.
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn fovea(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    // The fovea starts on an even row and column, so its pixels keep their
+    // parity
+    let pos = settings.fovea.xy + interleaved_pixel(GlobalInvocationID.xy, settings.frame_parity + 1u);
+    if any(pos >= settings.fovea.zw) {
+        return;
+    }
+    trace_screen_pixel(vec2<i32>(pos), TILE_NEAR, false);
+}
+
+fn trace_pixel(id: vec2<u32>, tile_class: u32) {
+    trace_screen_pixel(traced_pixel(id), tile_class, (settings.flags & WAVEFRONT) != 0u);
+}
+
+// Pixels of sky tiles skip the traversal and pixels of far tiles aren't
+// shadowed, everything else is traced in full. Without wavefront the pixel is
+// lit and shaded right away.
+fn trace_screen_pixel(screen_pos: vec2<i32>, tile_class: u32, wavefront: bool) {
+    let screen_size = camera.viewport.xy;
+    // The last workgroups of a row or column stick out of the render size
+    if any(vec2<u32>(screen_pos) >= screen_size) {
+        return;
+    }
+    var pixel_coord = ((vec2<f32>(screen_pos) + 0.5) / vec2<f32>(screen_size)) * 2. - 1.;
+
+    // -1 for the left eye, 1 for the right eye, 0 without stereo
+    var eye = 0.;
+    if settings.stereo == STEREO_SIDE_BY_SIDE {
+        // Each eye is squeezed into half of the screen (half SBS), the
+        // display stretches it back
+        let half_width = f32(screen_size.x) * 0.5;
+        var x = f32(screen_pos.x) + 0.5;
+        eye = -1.;
+        if x >= half_width {
+            x -= half_width;
+            eye = 1.;
+        }
+        pixel_coord.x = x / half_width * 2. - 1.;
+    } else if settings.stereo == STEREO_ANAGLYPH {
+        eye = -1.;
+    }
+
+    // Outside of the fisheye circle
+    if !in_projection(pixel_coord) {
+        textureStore(color_buffer, screen_pos, vec4<f32>(0., 0., 0., 1.));
+        textureStore(motion_buffer, screen_pos, vec4<f32>(0., 0., SKY_DEPTH, 0.));
+        if (settings.flags & WRITE_DEPTH) != 0u {
+            textureStore(depth_buffer, screen_pos, vec4<f32>(SKY_DEPTH, 0., 0., 0.));
+        }
+        return;
+    }
+
+    let ray = eye_ray(pixel_coord + camera.jitter.xy, eye);
+    let origin = ray.origin;
+    let direction = ray.direction;
+
+    // Misses are reprojected as a far away point, so only camera rotation moves the sky
+    var world_pos = origin + normalize(direction) * 10000.;
+    var albedo = vec3<f32>(0.);
+    var depth = SKY_DEPTH;
+    var hit = Hit(origin, vec3<f32>(0.), false, 0u);
+    if tile_class != TILE_SKY && !misses_world(ray) {
+        hit = raytrace(beam_start(ray, screen_pos));
+    }
+    if hit.hit {
+        albedo = clamp(hit.position / 100., vec3<f32>(0.), vec3<f32>(1.));
+        world_pos = hit.position;
+        depth = distance(origin, hit.position);
+    }
+    if wavefront {
+        // Lit and shaded by the trace_shadows and shade_pixels passes
+        store_hit(screen_pos, hit, depth);
+    } else {
+        var light = 1.;
+        if hit.hit {
+            if (settings.flags & HALF_RES_LIGHTING) != 0u {
+                light = upsample_lighting(screen_pos, depth, hit.normal);
+            } else if tile_class == TILE_FAR {
+                light = unshadowed_light(hit);
+            } else {
+                light = sun_light(hit);
+            }
+        }
+        var pixel_color = shade(hit, depth, light);
+
+        if settings.stereo == STEREO_ANAGLYPH {
+            // Red from the left eye, green and blue from the right one
+            let right_ray = eye_ray(pixel_coord + camera.jitter.xy, 1.);
+            let right_hit = raytrace(right_ray);
+            let right_depth = distance(right_ray.origin, right_hit.position);
+            let right_color = shade(right_hit, right_depth, sun_light(right_hit));
+            pixel_color = vec3<f32>(pixel_color.r, right_color.gb);
+        }
+        textureStore(color_buffer, screen_pos, vec4<f32>(pixel_color, 1.0));
+    }
+
+    var motion = pixel_coord - reproject(world_pos);
+    if settings.stereo == STEREO_SIDE_BY_SIDE {
+        motion.x *= 0.5;
+    }
+
+    // Depth the point had from where the ray started in the previous frame,
+    // to tell disocclusions apart when reprojecting
+    var prev_depth = SKY_DEPTH;
+    if hit.hit {
+        let prev_origin = origin - camera.view_pos.xyz + camera.prev_view_pos.xyz;
+        prev_depth = distance(prev_origin, world_pos);
+    }
+
+    textureStore(motion_buffer, screen_pos, vec4<f32>(motion, prev_depth, 0.));
+    if (settings.flags & WRITE_ALBEDO) != 0u {
+        textureStore(albedo_buffer, screen_pos, vec4<f32>(albedo, 1.));
+    }
+    if (settings.flags & WRITE_NORMAL) != 0u {
+        textureStore(normal_buffer, screen_pos, vec4<f32>(hit.normal, 0.));
+    }
+    if (settings.flags & WRITE_DEPTH) != 0u {
+        textureStore(depth_buffer, screen_pos, vec4<f32>(depth, 0., 0., 0.));
+    }
+}
+
+// Whether a block of the noise mask under the pixels from start to end is
+// still noisy
+fn tile_noisy(start: vec2<u32>, end: vec2<u32>) -> bool {
+    let block = vec2<u32>(NOISE_BLOCK_WIDTH, NOISE_BLOCK_HEIGHT);
+    let last = min((end - 1u) / block, vec2<u32>(textureDimensions(noise_mask)) - 1u);
+    for (var y = start.y / block.y; y <= last.y; y++) {
+        for (var x = start.x / block.x; x <= last.x; x++) {
+            if textureLoad(noise_mask, vec2<u32>(x, y), 0).r != 0u {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// Visibility pass of tiled dispatch, one thread per workgroup of main. Tiles
+// whose rays all miss the world are sky, tiles whose corners
+// all hit farther away than far_distance are far, the rest are near. Only the
+// linear projections without stereo bound every ray of a tile by its corners,
+// otherwise all tiles are near. With just sky tiles only the cheap sky test
+// runs and the rest are near. Adaptive sampling leaves out the tiles without
+// noisy blocks, and without either lists the rest as near so they are traced
+// like main would.
+@compute @workgroup_size(8,8,1)
+fn classify(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let tile = GlobalInvocationID.xy;
+    let tile_size = vec2<u32>(u32(WORKGROUP_WIDTH), u32(WORKGROUP_HEIGHT));
+    let screen_size = camera.viewport.xy;
+    // Screen pixels covered by a tile, twice as wide with checkerboarding
+    // and columns, twice as high with rows
+    var covered = tile_size;
+    if (settings.flags & (CHECKERBOARD | INTERLEAVE_ROWS)) == (CHECKERBOARD | INTERLEAVE_ROWS) {
+        covered.y *= 2u;
+    } else if (settings.flags & CHECKERBOARD) != 0u {
+        covered.x *= 2u;
+    }
+    if any(tile * covered >= screen_size) {
+        return;
+    }
+
+    if (settings.flags & ADAPTIVE_SAMPLING) != 0u && !tile_noisy(tile * covered, (tile + 1u) * covered) {
+        return;
+    }
+
+    var tile_class = TILE_NEAR;
+    let linear = camera.projection == PROJECTION_PERSPECTIVE || camera.projection == PROJECTION_ORTHOGRAPHIC;
+    if (settings.flags & (TILED | SKY_TILES)) != 0u && linear && settings.stereo == STEREO_OFF {
+        // Corners pushed out by a pixel to cover the jitter
+        var rays: array<Ray, 4>;
+        for (var i = 0u; i < 4u; i++) {
+            let side = vec2<u32>(i & 1u, i >> 1u);
+            let corner = vec2<f32>((tile + side) * covered) + vec2<f32>(side) * 2. - 1.;
+            rays[i] = primary_ray(corner / vec2<f32>(screen_size) * 2. - 1.);
+        }
+
+        if tile_misses_world(rays) {
+            tile_class = TILE_SKY;
+        } else if (settings.flags & TILED) != 0u {
+            // A corner that misses could be next to nearer geometry
+            var nearest = SKY_DEPTH;
+            for (var i = 0u; i < 4u; i++) {
+                let hit = raytrace(rays[i]);
+                if !hit.hit {
+                    nearest = 0.;
+                    break;
+                }
+                nearest = min(nearest, distance(rays[i].origin, hit.position));
+            }
+            if nearest > settings.far_distance {
+                tile_class = TILE_FAR;
+            }
+        }
+    }
+
+    let index = atomicAdd(&tile_lists.counts[tile_class], 1u);
+    let stride = arrayLength(&tile_lists.tiles) / 3u;
+    tile_lists.tiles[tile_class * stride + index] = tile.x | (tile.y << 16u);
+}
+
+// Turns the tile counts into the workgroup counts of the tile passes and
+// empties the lists for the next frame
+@compute @workgroup_size(1,1,1)
+fn prepare_tiles() {
+    for (var tile_class = 0u; tile_class < 3u; tile_class++) {
+        tile_dispatch[tile_class] = DispatchArgs(atomicExchange(&tile_lists.counts[tile_class], 0u), 1u, 1u);
+    }
+}
+
+// One workgroup per listed tile, the same pixels main would have traced there
+fn trace_tile(tile_class: u32, workgroup: u32, local_id: vec2<u32>) {
+    let stride = arrayLength(&tile_lists.tiles) / 3u;
+    let packed = tile_lists.tiles[tile_class * stride + workgroup];
+    let tile = vec2<u32>(packed & 0xffffu, packed >> 16u);
+    trace_pixel(tile * vec2<u32>(u32(WORKGROUP_WIDTH), u32(WORKGROUP_HEIGHT)) + local_id, tile_class);
+}
+
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn sky_tiles(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_id) local_id: vec3<u32>) {
+    trace_tile(TILE_SKY, workgroup_id.x, local_id.xy);
+}
+
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn near_tiles(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_id) local_id: vec3<u32>) {
+    trace_tile(TILE_NEAR, workgroup_id.x, local_id.xy);
+}
+
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn far_tiles(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_id) local_id: vec3<u32>) {
+    trace_tile(TILE_FAR, workgroup_id.x, local_id.xy);
+}
+
+// Traces the corners of every tile at low resolution and stores how far the
+// rays of the tile can safely skip ahead
+@compute @workgroup_size(8,8,1)
+fn beam(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let tile = GlobalInvocationID.xy;
+    let screen_size = camera.viewport.xy;
+    if any(tile * BEAM_TILE_SIZE >= screen_size) {
+        return;
+    }
+
+    var min_distance = SKY_DEPTH;
+    var directions: array<vec3<f32>, 4>;
+    for (var i = 0u; i < 4u; i++) {
+        let corner = (tile + vec2<u32>(i & 1u, i >> 1u)) * BEAM_TILE_SIZE;
+        let ndc = vec2<f32>(min(corner, screen_size)) / vec2<f32>(screen_size) * 2. - 1.;
+        let ray = primary_ray(ndc);
+        directions[i] = normalize(ray.direction);
+
+        let hit = raytrace(ray);
+        if hit.hit {
+            min_distance = min(min_distance, distance(ray.origin, hit.position));
+        }
+    }
+
+    // Geometry between the corner rays can stick out towards the camera by
+    // up to the width of the beam at that distance, plus a brick for the
+    // coarse traversal levels. Tiles where every corner misses aren't skipped.
+    var start = 0.;
+    if min_distance < SKY_DEPTH {
+        let spread = max(distance(directions[0], directions[3]), distance(directions[1], directions[2]));
+        start = max(0., min_distance * (1. - spread) - BRICK_SIZE * 1.75);
+    }
+    textureStore(beam_output, vec2<i32>(tile), vec4<f32>(start, 0., 0., 0.));
+}
+
+// Sun light for every 2x2 block of pixels, along with the depth and normal of
+// the surface it was computed for
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn lighting(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let half_pos = GlobalInvocationID.xy;
+    let screen_size = camera.viewport.xy;
+    if any(half_pos * 2u >= screen_size) {
+        return;
+    }
+
+    // The center of the block in full resolution pixels
+    let pixel_coord = vec2<f32>(half_pos * 2u + 1u) / vec2<f32>(screen_size) * 2. - 1.;
+    let ray = primary_ray(pixel_coord + camera.jitter.xy);
+    let hit = raytrace(ray);
+
+    var result = vec4<f32>(1., SKY_DEPTH, 0., 0.);
+    if hit.hit {
+        result = vec4<f32>(sun_light(hit), distance(ray.origin, hit.position), normal_code(hit.normal), 0.);
+    }
+    textureStore(lighting_output, vec2<i32>(half_pos), result);
+}
+
+// Wavefront mode. The primary hit of the pixel is kept for the later passes,
+// and a shadow ray is queued if the surface faces the sun.
+fn store_hit(screen_pos: vec2<i32>, hit: Hit, depth: f32) {
+    let index = pixel_index(screen_pos);
+    var bits = (pack_normal(hit.normal) << PIXEL_NORMAL_SHIFT) | (hit.steps << PIXEL_STEPS_SHIFT);
+    if hit.hit {
+        bits |= PIXEL_HIT;
+    }
+    pixel_hits[index] = vec2<u32>(bitcast<u32>(depth), bits);
+
+#ifdef SHADOWS
+    let facing = dot(hit.normal, settings.sun_direction.xyz) > 0.;
+    if hit.hit && facing && (settings.flags & HALF_RES_LIGHTING) == 0u {
+        let slot = atomicAdd(&shadow_queue.count, 1u);
+        shadow_queue.pixels[slot] = index;
+    }
+#endif
+}
+
+// Turns the number of queued shadow rays into the workgroup count of the
+// shadow pass and empties the queue for the next frame
+@compute @workgroup_size(1,1,1)
+fn prepare_shadows() {
+    let count = atomicExchange(&shadow_queue.count, 0u);
+    shadow_queue.length = count;
+    shadow_dispatch = DispatchArgs((count + QUEUE_WORKGROUP_SIZE - 1u) / QUEUE_WORKGROUP_SIZE, 1u, 1u);
+}
+
+// One thread per queued pixel, marks the pixels whose surface is in shadow
+@compute @workgroup_size(64,1,1)
+fn trace_shadows(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    if GlobalInvocationID.x >= shadow_queue.length {
+        return;
+    }
+    let index = shadow_queue.pixels[GlobalInvocationID.x];
+    let width = camera.viewport.x;
+    let screen_pos = vec2<i32>(vec2<u32>(index % width, index / width));
+    let hit = load_hit(index, pixel_ray(screen_pos));
+
+    let shadow = raytrace(Ray(hit.position + hit.normal * 0.01, settings.sun_direction.xyz));
+    if shadow.hit {
+        pixel_hits[index].y |= PIXEL_SHADOWED;
+    }
+}
+
+// Shades the pixels traced by main from their stored hits, dispatched like main
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn shade_pixels(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
+    let screen_pos = traced_pixel(GlobalInvocationID.xy);
+    let screen_size = camera.viewport.xy;
+    if any(vec2<u32>(screen_pos) >= screen_size) {
+        return;
+    }
+    // Already cleared by main
+    let pixel_coord = ((vec2<f32>(screen_pos) + 0.5) / vec2<f32>(screen_size)) * 2. - 1.;
+    if !in_projection(pixel_coord) {
+        return;
+    }
+
+    let index = pixel_index(screen_pos);
+    let hit = load_hit(index, pixel_ray(screen_pos));
+    let depth = bitcast<f32>(pixel_hits[index].x);
+    var light = 1.;
+    if hit.hit {
+        if (settings.flags & HALF_RES_LIGHTING) != 0u {
+            light = upsample_lighting(screen_pos, depth, hit.normal);
+        } else if (pixel_hits[index].y & PIXEL_SHADOWED) != 0u {
+            light = AMBIENT_LIGHT;
+        } else {
+            light = unshadowed_light(hit);
+        }
+    }
+    textureStore(color_buffer, screen_pos, vec4<f32>(shade(hit, depth, light), 1.));
+}
+
+fn pixel_index(screen_pos: vec2<i32>) -> u32 {
+    return u32(screen_pos.y) * camera.viewport.x + u32(screen_pos.x);
+}
+
+// The primary ray main traced for a pixel, without stereo
+fn pixel_ray(screen_pos: vec2<i32>) -> Ray {
+    let pixel_coord = ((vec2<f32>(screen_pos) + 0.5) / vec2<f32>(camera.viewport.xy)) * 2. - 1.;
+    return primary_ray(pixel_coord + camera.jitter.xy);
+}
+
+// The hit store_hit kept, its position is found again from the depth
+fn load_hit(index: u32, ray: Ray) -> Hit {
+    let stored = pixel_hits[index];
+    let depth = bitcast<f32>(stored.x);
+    return Hit(
+        ray.origin + normalize(ray.direction) * depth,
+        unpack_normal((stored.y >> PIXEL_NORMAL_SHIFT) & 7u),
+        (stored.y & PIXEL_HIT) != 0u,
+        stored.y >> PIXEL_STEPS_SHIFT,
+    );
+}
+
+// Axis aligned normals as axis * 2 plus 1 if negative, 6 for no normal
+fn pack_normal(normal: vec3<f32>) -> u32 {
+    for (var axis = 0u; axis < 3u; axis++) {
+        if normal[axis] != 0. {
+            return axis * 2u + u32(normal[axis] < 0.);
+        }
+    }
+    return 6u;
+}
+
+fn unpack_normal(code: u32) -> vec3<f32> {
+    var normal = vec3<f32>(0.);
+    if code < 6u {
+        normal[code / 2u] = select(1., -1., (code & 1u) != 0u);
+    }
+    return normal;
+}
+#endif
+
+fn sun_light(hit: Hit) -> f32 {
+#ifdef SHADOWS
+    if hit.hit && dot(hit.normal, settings.sun_direction.xyz) > 0. {
+        let shadow = raytrace(Ray(hit.position + hit.normal * 0.01, settings.sun_direction.xyz));
+        if shadow.hit {
+            return AMBIENT_LIGHT;
+        }
+    }
+#endif
+    return unshadowed_light(hit);
+}
+
+fn unshadowed_light(hit: Hit) -> f32 {
+    let n_dot_l = dot(hit.normal, settings.sun_direction.xyz);
+    if !hit.hit || n_dot_l <= 0. {
+        return AMBIENT_LIGHT;
+    }
+    return AMBIENT_LIGHT + (1. - AMBIENT_LIGHT) * n_dot_l;
+}
+
+// Voxel normals are axis aligned, so a single number identifies them
+fn normal_code(normal: vec3<f32>) -> f32 {
+    return dot(normal, vec3<f32>(1., 2., 3.));
+}
+
+#ifndef FRAGMENT_FALLBACK
+// Bilateral upsampling of the half resolution lighting, samples from other
+// surfaces are rejected by their depth and normal
+fn upsample_lighting(screen_pos: vec2<i32>, depth: f32, normal: vec3<f32>) -> f32 {
+    let half_size = vec2<i32>((camera.viewport.xy + 1u) / 2u);
+    let position = (vec2<f32>(screen_pos) + 0.5) * 0.5 - 0.5;
+    let base = vec2<i32>(floor(position));
+    let f = fract(position);
+    let code = normal_code(normal);
+
+    var total = 0.;
+    var weight_sum = 0.;
+    for (var i = 0; i < 4; i++) {
+        let offset = vec2<i32>(i & 1, i >> 1u);
+        let coord = clamp(base + offset, vec2<i32>(0), half_size - 1);
+        let sample = textureLoad(lighting_input, coord, 0);
+
+        let bilinear = select(1. - f.x, f.x, offset.x == 1) * select(1. - f.y, f.y, offset.y == 1);
+        let depth_weight = exp(-abs(sample.y - depth) / (depth * 0.02 + 0.1));
+        let normal_weight = select(0.05, 1., sample.z == code);
+        let weight = max(bilinear, 0.001) * depth_weight * normal_weight;
+        total += sample.x * weight;
+        weight_sum += weight;
+    }
+
+    // Nothing similar nearby, e.g. thin geometry missed at half resolution
+    if weight_sum < 0.0001 {
+        return textureLoad(lighting_input, clamp(screen_pos / 2, vec2<i32>(0), half_size - 1), 0).x;
+    }
+    return total / weight_sum;
+}
+
+// Moves the ray origin up to the distance found by the beam pre-pass
+fn beam_start(ray: Ray, screen_pos: vec2<i32>) -> Ray {
+    if (settings.flags & BEAM_OPTIMIZATION) == 0u || settings.stereo != STEREO_OFF {
+        return ray;
+    }
+    let start = textureLoad(beam_input, screen_pos / i32(BEAM_TILE_SIZE), 0).r;
+    return Ray(ray.origin + normalize(ray.direction) * start, ray.direction);
+}
+
+@compute @workgroup_size(1,1,1)
+fn pick() {
+    let ray = primary_ray(settings.cursor);
+    let hit = raytrace(ray);
+
+    var result: PickResult;
+    result.voxel = vec4<i32>(hit_voxel(hit), i32(hit.hit));
+    result.normal = vec4<f32>(hit.normal, 0.);
+    result.position = vec4<f32>(hit.position, distance(ray.origin, hit.position));
+    pick_result = result;
+}
+#endif
+
+fn shade(hit: Hit, depth: f32, light: f32) -> vec3<f32> {
+#ifdef DEBUG_VIEWS
+    if settings.debug_view != DEBUG_NONE {
+        return debug_color(hit, depth);
+    }
+#endif
+    if !hit.hit {
+        return vec3<f32>(.1, .2, .3);
+    }
+
+    var color = hit.position / 100. * light;
+#ifndef FRAGMENT_FALLBACK
+    let material = palette.colors[hit_material(hit)];
+    if material.a > 0. {
+        color = material.rgb * light;
+    }
+    if (settings.flags & HIGHLIGHT_PICKED) != 0u && is_picked_edge(hit) {
+        color = mix(color, vec3<f32>(1.), 0.8);
+    }
+#endif
 
-    let targetPoint = camera.proj * vec4<f32>(pixel_coord, -1., 1.);
-    var origin = camera.view_pos.xyz;
-    var direction = (camera.view * vec4<f32>(normalize(targetPoint.xyz / targetPoint.w), 0.)).xyz;
+    // Keep the lines roughly a constant width on screen
+    let line_width = max(0.05, depth * 0.002);
+    if (settings.flags & SHOW_BRICK_BOUNDS) != 0u && is_on_grid(hit, BRICK_SIZE, line_width) {
+        color = mix(color, vec3<f32>(0., 1., 1.), 0.5);
+    }
+    if (settings.flags & SHOW_CHUNK_BOUNDS) != 0u && is_on_grid(hit, CHUNK_SIZE, line_width) {
+        color = mix(color, vec3<f32>(1., 0.8, 0.), 0.8);
+    }
+    return color;
+}
+
+// Primary ray shifted sideways by half the eye separation
+fn eye_ray(ndc: vec2<f32>, eye: f32) -> Ray {
+    var ray = primary_ray(ndc);
+    let right = normalize((camera.view * vec4<f32>(1., 0., 0., 0.)).xyz);
+    ray.origin += right * eye * settings.eye_separation * 0.5;
+    return ray;
+}
+
+fn primary_ray(ndc: vec2<f32>) -> Ray {
+    let targetPoint = camera.proj * vec4<f32>(ndc, -1., 1.);
+
+    if camera.projection == PROJECTION_ORTHOGRAPHIC {
+        // Parallel rays starting on the camera plane
+        let offset = vec4<f32>(targetPoint.xy / targetPoint.w, 0., 0.);
+        let origin = camera.view_pos.xyz + (camera.view * offset).xyz;
+        let direction = (camera.view * vec4<f32>(0., 0., -1., 0.)).xyz;
+        return Ray(origin, direction);
+    }
+
+    var local = normalize(targetPoint.xyz / targetPoint.w);
+    if camera.projection == PROJECTION_FISHEYE {
+        let scaled = ndc * vec2<f32>(aspect_ratio(), 1.);
+        let radius = length(scaled);
+        let theta = radius * FISHEYE_FOV * 0.5;
+        var around = vec2<f32>(0.);
+        if radius > 0. {
+            around = scaled / radius;
+        }
+        local = vec3<f32>(around * sin(theta), -cos(theta));
+    } else if camera.projection == PROJECTION_EQUIRECTANGULAR {
+        let longitude = ndc.x * PI;
+        let latitude = ndc.y * PI * 0.5;
+        local = vec3<f32>(cos(latitude) * sin(longitude), sin(latitude), -cos(latitude) * cos(longitude));
+    }
+
+    let origin = camera.view_pos.xyz;
+    let direction = (camera.view * vec4<f32>(local, 0.)).xyz;
+    return Ray(origin, direction);
+}
+
+fn aspect_ratio() -> f32 {
+    return f32(camera.viewport.x) / f32(camera.viewport.y);
+}
+
+fn in_projection(ndc: vec2<f32>) -> bool {
+    if camera.projection == PROJECTION_FISHEYE {
+        return length(ndc * vec2<f32>(aspect_ratio(), 1.)) <= 1.;
+    }
+    return true;
+}
+
+// Inverse of the non-linear mappings in primary_ray, from a camera space direction to NDC
+fn project_direction(local: vec3<f32>) -> vec2<f32> {
+    let direction = normalize(local);
+    if camera.projection == PROJECTION_FISHEYE {
+        let theta = acos(clamp(-direction.z, -1., 1.));
+        let radius = theta / (FISHEYE_FOV * 0.5);
+        var around = vec2<f32>(0.);
+        if length(direction.xy) > 0. {
+            around = normalize(direction.xy);
+        }
+        return around * radius / vec2<f32>(aspect_ratio(), 1.);
+    }
+    let longitude = atan2(direction.x, -direction.z);
+    let latitude = asin(clamp(direction.y, -1., 1.));
+    return vec2<f32>(longitude / PI, latitude / (PI * 0.5));
+}
+
+// Hit positions lie on a voxel face, step back inside along the normal
+fn hit_voxel(hit: Hit) -> vec3<i32> {
+    return vec3<i32>(floor(hit.position - hit.normal * 0.5));
+}
+
+#ifndef FRAGMENT_FALLBACK
+fn is_picked_edge(hit: Hit) -> bool {
+    let voxel = hit_voxel(hit);
+    if pick_result.voxel.w == 0 || any(voxel != pick_result.voxel.xyz) {
+        return false;
+    }
+
+    // Distance to the edges of the face that was hit, ignoring the axis along the normal
+    let local = hit.position - vec3<f32>(voxel);
+    let edge = min(local, 1. - local) + abs(hit.normal);
+    return min(edge.x, min(edge.y, edge.z)) < 0.06;
+}
+#endif
+
+// Whether the hit lies on a plane of a world aligned grid, ignoring the
+// plane of the face that was hit
+fn is_on_grid(hit: Hit, cell_size: f32, width: f32) -> bool {
+    let local = hit.position / cell_size;
+    let dist = abs(local - round(local)) * cell_size + abs(hit.normal) * width;
+    return min(dist.x, min(dist.y, dist.z)) < width * 0.5;
+}
+
+fn debug_color(hit: Hit, depth: f32) -> vec3<f32> {
+    if settings.debug_view == DEBUG_NORMALS {
+        return hit.normal * 0.5 + 0.5;
+    }
+    if settings.debug_view == DEBUG_STEP_COUNT {
+        return heatmap(f32(hit.steps) / 128.);
+    }
+    if !hit.hit {
+        return vec3<f32>(0.);
+    }
+    if settings.debug_view == DEBUG_DEPTH {
+        // Logarithmic, so both near and far geometry is distinguishable
+        return vec3<f32>(1. - clamp(log2(depth + 1.) / 12., 0., 1.));
+    }
+    if settings.debug_view == DEBUG_CHUNK_ID {
+        return hash_color(vec3<i32>(floor(hit.position / CHUNK_SIZE)));
+    }
+#ifndef FRAGMENT_FALLBACK
+    if settings.debug_view == DEBUG_RESIDENCY {
+        let state = chunk_state(vec3<i32>(floor(vec3<f32>(hit_voxel(hit)) / CHUNK_SIZE)));
+        let shading = 0.6 + 0.4 * abs(hit.normal.y);
+        if state == CHUNK_VISIBLE {
+            return vec3<f32>(0.2, 0.8, 0.2) * shading;
+        }
+        if state == CHUNK_RESIDENT {
+            return vec3<f32>(0.9, 0.8, 0.2) * shading;
+        }
+        if state == CHUNK_OCCLUDED {
+            return vec3<f32>(0.6, 0.3, 0.9) * shading;
+        }
+        return vec3<f32>(0.9, 0.2, 0.2) * shading;
+    }
+#endif
+    return vec3<f32>(0.);
+}
 
-    var ray = Ray(origin, direction);
+// Blue (cheap) to green to red (expensive)
+fn heatmap(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0., 1.);
+    return clamp(vec3<f32>(2. * x - 0.5, 1. - abs(2. * x - 1.), 1.5 - 2. * x), vec3<f32>(0.), vec3<f32>(1.));
+}
 
-    let pixel = raytrace(ray);
-    if pixel.w == 1. { pixel_color = pixel.rgb; }
+fn hash_color(c: vec3<i32>) -> vec3<f32> {
+    var h = u32(c.x) * 73856093u ^ u32(c.y) * 19349663u ^ u32(c.z) * 83492791u;
+    h = (h ^ (h >> 16u)) * 2246822519u;
+    h = h ^ (h >> 13u);
+    return vec3<f32>(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.;
+}
 
-    textureStore(color_buffer, screen_pos, vec4<f32>(pixel_color, 1.0));
+// Screen position (NDC) of a world space point in the previous frame
+fn reproject(p: vec3<f32>) -> vec2<f32> {
+    let clip = camera.prev_view_proj * vec4<f32>(p - camera.prev_view_pos.xyz, 1.);
+    if camera.projection == PROJECTION_FISHEYE || camera.projection == PROJECTION_EQUIRECTANGULAR {
+        // prev_view_proj only holds the view rotation for these
+        return project_direction(clip.xyz);
+    }
+    return clip.xy / clip.w;
 }
 
-fn raytrace(ray: Ray) -> vec4<f32> {
-    var coord = vec4<f32>(ray.origin, 0.0);
+#ifndef STACKLESS
+fn raytrace(ray: Ray) -> Hit {
+    var hit = Hit(ray.origin, vec3<f32>(0.), false, 0u);
     var scale = 64;
+    var steps = 0u;
 
     for (var i = 0; i < 3; i++) {
-        coord = dda(Ray(coord.xyz, ray.direction), scale);
+        hit = dda(Ray(hit.position, ray.direction), scale, hit.normal, remaining_steps(steps), remaining_distance(ray, hit));
+        steps += hit.steps;
+        if hit.hit && coarse_enough(ray, hit, scale) {
+            break;
+        }
         scale /= 8;
     }
-    return vec4<f32>(vec3<f32>(coord.xyz) / 100., coord.w);
+    hit.steps = steps;
+    return hit;
+}
+#else
+// Stackless traversal. A level that runs out of its parent cell without a
+// hit goes back up to the parent level where it left off instead of ending
+// the ray, so rays passing through occupied coarse cells continue behind
+// them. The parent cell follows from the position, only the current scale
+// is kept.
+fn raytrace(ray: Ray) -> Hit {
+    var hit = Hit(ray.origin, vec3<f32>(0.), false, 0u);
+    var scale = 64;
+    var steps = 0u;
+
+    for (var i = 0u; i < MAX_LEVEL_CHANGES; i++) {
+        hit = dda(Ray(hit.position, ray.direction), scale, hit.normal, remaining_steps(steps), remaining_distance(ray, hit));
+        steps += hit.steps;
+        if hit.hit {
+            if scale == 1 || coarse_enough(ray, hit, scale) {
+                break;
+            }
+            scale /= 8;
+        } else {
+            if scale == 64 {
+                break;
+            }
+            scale *= 8;
+        }
+    }
+    hit.steps = steps;
+    return hit;
+}
+#endif
+
+fn remaining_steps(steps: u32) -> u32 {
+    return render_settings.max_steps - min(steps, render_settings.max_steps);
+}
+
+fn remaining_distance(ray: Ray, hit: Hit) -> f32 {
+    return render_settings.max_distance - distance(hit.position, ray.origin);
 }
 
-fn dda(r: Ray, scale: i32) -> vec4<f32> {
+// Whether a hit on a cell of the given scale is far enough away to be kept
+// instead of descending into the cell, see RenderSettings::lod_bias
+fn coarse_enough(ray: Ray, hit: Hit, scale: i32) -> bool {
+    return scale > 1 && distance(hit.position, ray.origin) * render_settings.lod_bias > f32(scale) * LOD_DISTANCE;
+}
+
+// entry_normal is the normal of the face the ray entered the current cell
+// through. Misses once it took max_steps steps or went max_distance.
+fn dda(r: Ray, scale: i32, entry_normal: vec3<f32>, max_steps: u32, max_distance: f32) -> Hit {
     var direction = normalize(r.direction);
     if direction.x == 0. { direction.x = 0.001; }
     if direction.y == 0. { direction.y = 0.001; }
@@ -57,11 +1031,14 @@ fn dda(r: Ray, scale: i32) -> vec4<f32> {
     var withinVoxelCoords = r.origin / f32(scale) - vec3<f32>(gridCoords);
     let entryCoords = (gridCoords / scale) * scale; // get beginning of the chunk
 
+    var normal = entry_normal;
     var i = 0;
-    while inChunk(gridCoords, entryCoords, scale) {
+    // Along the ray, in cells
+    var travelled = 0.;
+    while inChunk(gridCoords, entryCoords, scale) && u32(i) < max_steps && travelled * f32(scale) <= max_distance {
         let t = (vec3f(rayPositivity) - withinVoxelCoords) * rayInverse;
         if getVoxel(gridCoords, scale) {
-            return vec4<f32>((vec3<f32>(gridCoords) + withinVoxelCoords) * f32(scale), 1.0);
+            return Hit((vec3<f32>(gridCoords) + withinVoxelCoords) * f32(scale), normal, true, u32(i));
         }
 
         var minIdx: i32;
@@ -79,20 +1056,185 @@ fn dda(r: Ray, scale: i32) -> vec4<f32> {
 
         gridCoords[minIdx] += raySign[minIdx];
         withinVoxelCoords += direction * t[minIdx];
+        travelled += t[minIdx];
         withinVoxelCoords[minIdx] = 1. - f32(rayPositivity[minIdx]);
+        normal = vec3<f32>(0.);
+        normal[minIdx] = -f32(raySign[minIdx]);
         i++;
     }
-    return vec4<f32>((vec3<f32>(gridCoords) + withinVoxelCoords) * f32(scale), 0.0);
+    return Hit((vec3<f32>(gridCoords) + withinVoxelCoords) * f32(scale), normal, false, u32(i));
 }
 
+// A parent cell is CHUNK_CELLS cells wide, so the finer levels walk at least
+// that far from where they entered it. The top level keeps its wider reach.
 fn inChunk(coords: vec3<i32>, reference: vec3<i32>, scale: i32) -> bool {
+    let reach = max(scale, CHUNK_CELLS);
     let diff = abs(coords - reference);
 
-    if diff.x > scale || diff.y > scale || diff.z > scale {return false; }
+    if diff.x > reach || diff.y > reach || diff.z > reach {return false; }
     return true;
 }
 
+#ifndef FRAGMENT_FALLBACK
+fn chunk_state(chunk: vec3<i32>) -> u32 {
+    if residency.origin.w == 0 {
+        return CHUNK_VISIBLE;
+    }
+    let local = chunk - residency.origin.xyz;
+    if any(local < vec3<i32>(0)) || local.x >= RESIDENCY_WIDTH || local.y >= RESIDENCY_HEIGHT || local.z >= RESIDENCY_WIDTH {
+        return CHUNK_CULLED;
+    }
+    let index = u32(local.x + (local.z + local.y * RESIDENCY_WIDTH) * RESIDENCY_WIDTH);
+    return (residency.states[index / 16u] >> ((index % 16u) * 2u)) & 3u;
+}
+#endif
+
+// Nothing is solid at or above this height, from the occupancy textures once
+// they are built
+fn world_top() -> f32 {
+    if settings.occupancy_origin.w != 0 {
+        return f32(settings.occupancy_origin.y);
+    }
+#ifndef FRAGMENT_FALLBACK
+    if settings.grid_origin.w != 0 {
+        return f32(settings.grid_origin.y + i32(textureDimensions(voxel_grid).y));
+    }
+#endif
+    return WORLD_TOP;
+}
+
+// Whether a ray stays above everything solid until it ends at max_distance
+fn misses_world(ray: Ray) -> bool {
+    let end = ray.origin.y + normalize(ray.direction).y * render_settings.max_distance;
+    return min(ray.origin.y, end) >= world_top();
+}
+
+// Whether every ray between the corner rays of a tile misses the world. The
+// rays of linear projections start and point in between the corners, so
+// none gets lower within max_distance than the lowest corner origin plus the
+// steepest corner direction, stretched by how far the corners spread.
+fn tile_misses_world(corners: array<Ray, 4>) -> bool {
+    // Only variables can be indexed dynamically
+    var rays = corners;
+    var center = vec3<f32>(0.);
+    for (var i = 0; i < 4; i++) {
+        center += normalize(rays[i].direction);
+    }
+    center = normalize(center);
+
+    var lowest = rays[0].origin.y;
+    var steepest = 0.;
+    // Directions in between are at least this long along center
+    var spread = 1.;
+    for (var i = 0; i < 4; i++) {
+        let direction = normalize(rays[i].direction);
+        lowest = min(lowest, rays[i].origin.y);
+        steepest = min(steepest, direction.y);
+        spread = min(spread, dot(direction, center));
+    }
+    let drop = steepest / max(spread, 0.001) * render_settings.max_distance;
+    return lowest + drop >= world_top();
+}
+
+#ifndef FRAGMENT_FALLBACK
+// Whether a brick (scale 8) or chunk (scale 64) holds any solid voxels.
+// Cells outside of the occupancy textures count as occupied.
+fn occupied(c: vec3<i32>, scale: i32) -> bool {
+    var size = OCCUPANCY_BRICKS;
+    var texel = c.xz - settings.occupancy_origin.xz;
+    if scale == 64 {
+        size /= 8;
+        texel = c.xz - settings.occupancy_origin.xz / 8;
+    }
+    let layer = c.y + OCCUPANCY_LAYER_OFFSET;
+    if any(texel < vec2<i32>(0)) || any(texel >= vec2<i32>(size)) || layer < 0 || layer >= 32 {
+        return true;
+    }
+
+    var bits: u32;
+    if scale == 64 {
+        bits = textureLoad(chunk_occupancy, texel, 0).r;
+    } else {
+        bits = textureLoad(brick_occupancy, texel, 0).r;
+    }
+    return ((bits >> u32(layer)) & 1u) != 0u;
+}
+
+// Palette index of voxel c in the brick at slot + 1
+fn pooled_material(slot: u32, c: vec3<i32>) -> u32 {
+    let s = slot - 1u;
+    let base = vec3<u32>(s % POOL_BRICKS, s / POOL_BRICKS % POOL_BRICKS, s / (POOL_BRICKS * POOL_BRICKS)) * 8u;
+    return textureLoad(brick_pool, vec3<i32>(base) + (c & vec3<i32>(7)), 0).r;
+}
+
+// Palette index of voxel c of the grid, 0 outside of it
+fn grid_material(c: vec3<i32>) -> u32 {
+    let local = c - settings.grid_origin.xyz;
+    if any(local < vec3<i32>(0)) || any(local >= vec3<i32>(textureDimensions(voxel_grid))) {
+        return 0u;
+    }
+    return textureLoad(voxel_grid, local, 0).r;
+}
+
+// Whether the cell c at scale overlaps the grid
+fn overlaps_grid(c: vec3<i32>, scale: i32) -> bool {
+    let local = c * scale - settings.grid_origin.xyz;
+    return all(local + scale > vec3<i32>(0)) && all(local < vec3<i32>(textureDimensions(voxel_grid)));
+}
+
+// Palette index of the voxel a hit landed on
+fn hit_material(hit: Hit) -> u32 {
+    let voxel = vec3<i32>(floor(hit.position - hit.normal * 0.5));
+    let slot = pool_slot(voxel);
+    if slot != 0u {
+        return pooled_material(slot, voxel);
+    }
+    if settings.grid_origin.w != 0 {
+        return grid_material(voxel);
+    }
+    return TERRAIN_MATERIAL;
+}
+
+// Slot + 1 of the brick holding voxel c in brick_pool, 0 if it isn't pooled
+fn pool_slot(c: vec3<i32>) -> u32 {
+    let local = (c >> vec3<u32>(3u)) - pool_table.origin.xyz;
+    let size = vec3<i32>(POOL_TABLE_BRICKS, POOL_TABLE_LAYERS, POOL_TABLE_BRICKS);
+    if pool_table.origin.w == 0 || any(local < vec3<i32>(0)) || any(local >= size) {
+        return 0u;
+    }
+    return pool_table.slots[u32(local.x + (local.z + local.y * POOL_TABLE_BRICKS) * POOL_TABLE_BRICKS)];
+}
+#endif
+
 fn getVoxel(c: vec3<i32>, scale: i32) -> bool {
+#ifndef FRAGMENT_FALLBACK
+    // Culled and occluded chunks are empty to the traversal, except in the
+    // residency debug view which shows them
+    let chunk = vec3<i32>(floor(vec3<f32>(c * scale) / CHUNK_SIZE));
+    let state = chunk_state(chunk);
+    if settings.debug_view != DEBUG_RESIDENCY && (state == CHUNK_CULLED || state == CHUNK_OCCLUDED) {
+        return false;
+    }
+    // The brick and chunk levels skip empty cells from the occupancy
+    // bitmasks, before they are built they sample the terrain at a point
+    if scale > 1 && settings.occupancy_origin.w != 0 {
+        return occupied(c, scale);
+    }
+    if scale == 1 {
+        let slot = pool_slot(c);
+        if slot != 0u {
+            return pooled_material(slot, c) != 0u;
+        }
+    }
+    // The coarser levels descend wherever they overlap the grid until the
+    // bitmasks are built
+    if settings.grid_origin.w != 0 {
+        if scale > 1 {
+            return overlaps_grid(c, scale);
+        }
+        return grid_material(c) != 0u;
+    }
+#endif
     //let s = 50 / scale;
     //let c = c - s * vec3<i32>(round(vec3<f32>(c) / f32(s)));
     //return df_sphere(c, scale) <= 0.;
@@ -104,3 +1246,24 @@ fn df_sphere(c: vec3<i32>, scale: i32) -> f32 {
     let r = 8. / f32(scale);
     return distance(vec3<f32>(c), p) - r;
 }
+
+#ifdef FRAGMENT_FALLBACK
+// Traces every pixel of a full screen triangle drawn with vert.wgsl, without
+// the pre-passes, reprojection or stereo
+@fragment
+fn fallback_fragment(@location(0) ndc: vec2<f32>) -> @location(0) vec4<f32> {
+    if !in_projection(ndc) {
+        return vec4<f32>(0., 0., 0., 1.);
+    }
+
+    let ray = primary_ray(ndc);
+    let hit = raytrace(ray);
+    var depth = SKY_DEPTH;
+    var light = 1.;
+    if hit.hit {
+        depth = distance(ray.origin, hit.position);
+        light = sun_light(hit);
+    }
+    return vec4<f32>(shade(hit, depth, light), 1.);
+}
+#endif
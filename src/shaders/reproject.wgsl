@@ -0,0 +1,61 @@
+// Reads the previous frame
+@group(0) @binding(0) var motion_buffer: texture_2d<f32>;
+@group(0) @binding(1) var color_history: texture_2d<f32>;
+@group(0) @binding(2) var depth_history: texture_2d<f32>;
+@group(0) @binding(3) var output_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(4)
+var<uniform> params: ReprojectionUniform;
+
+// Keeps this frame for the next one, in a bind group of its own as it writes
+// the history textures read above
+@group(0) @binding(5) var current_color: texture_2d<f32>;
+@group(0) @binding(6) var current_depth: texture_2d<f32>;
+@group(0) @binding(7) var color_output: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(8) var depth_output: texture_storage_2d<r32float, write>;
+
+struct ReprojectionUniform {
+    size: vec2<u32>,
+    history_size: vec2<u32>,
+    depth_tolerance: f32,
+    reset: u32,
+}
+
+// The previous frame's color where this frame's pixels were, alpha 1 where
+// the history shows the same surface and 0 where it was disoccluded or off
+// screen
+@compute @workgroup_size(16,16,1)
+fn reproject(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(params.size);
+    let pos = vec2<i32>(global_id.xy);
+    if pos.x >= size.x || pos.y >= size.y { return; }
+
+    // Motion in xy, the depth expected in the history in z
+    let motion = textureLoad(motion_buffer, pos, 0).xyz;
+    let uv = (vec2<f32>(pos) + 0.5) / vec2<f32>(size);
+    let prev_uv = uv - motion.xy * 0.5; // NDC to uv
+
+    if params.reset != 0u || any(prev_uv < vec2<f32>(0.)) || any(prev_uv >= vec2<f32>(1.)) {
+        textureStore(output_buffer, pos, vec4<f32>(0.));
+        return;
+    }
+
+    // Nearest texel, filtering would blend the depths of different surfaces.
+    // The history may have been rendered at a different resolution.
+    let history_size = vec2<i32>(params.history_size);
+    let prev_pos = min(vec2<i32>(prev_uv * vec2<f32>(history_size)), history_size - 1);
+    let prev_depth = textureLoad(depth_history, prev_pos, 0).r;
+    let color = textureLoad(color_history, prev_pos, 0).rgb;
+
+    let valid = abs(prev_depth - motion.z) <= params.depth_tolerance * motion.z;
+    textureStore(output_buffer, pos, vec4<f32>(color, f32(valid)));
+}
+
+@compute @workgroup_size(16,16,1)
+fn store(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(params.size);
+    let pos = vec2<i32>(global_id.xy);
+    if pos.x >= size.x || pos.y >= size.y { return; }
+
+    textureStore(color_output, pos, textureLoad(current_color, pos, 0));
+    textureStore(depth_output, pos, textureLoad(current_depth, pos, 0));
+}
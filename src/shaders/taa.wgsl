@@ -0,0 +1,59 @@
+@group(0) @binding(0) var current_buffer: texture_2d<f32>;
+@group(0) @binding(1) var motion_buffer: texture_2d<f32>;
+@group(0) @binding(2) var history_buffer: texture_2d<f32>;
+@group(0) @binding(3) var history_sampler: sampler;
+@group(0) @binding(4) var output_buffer: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(5)
+var<uniform> params: TaaUniform;
+@group(0) @binding(6) var reprojected_buffer: texture_2d<f32>;
+
+struct TaaUniform {
+    feedback: f32,
+    reset: u32,
+    size: vec2<u32>,
+    history_size: vec2<u32>,
+    reprojection: u32,
+}
+
+@compute @workgroup_size(16,16,1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(params.size);
+    let pos = vec2<i32>(global_id.xy);
+    if pos.x >= size.x || pos.y >= size.y { return; }
+
+    let current = textureLoad(current_buffer, pos, 0).rgb;
+
+    // Clamp history to the current 3x3 neighbourhood to reject disoccluded samples
+    var low = current;
+    var high = current;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour_pos = clamp(pos + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let neighbour = textureLoad(current_buffer, neighbour_pos, 0).rgb;
+            low = min(low, neighbour);
+            high = max(high, neighbour);
+        }
+    }
+
+    let uv = (vec2<f32>(pos) + 0.5) / vec2<f32>(size);
+    let motion = textureLoad(motion_buffer, pos, 0).xy;
+    let prev_uv = uv - motion * 0.5; // NDC to uv
+
+    var feedback = params.feedback;
+    if params.reset != 0u || any(prev_uv < vec2<f32>(0.)) || any(prev_uv > vec2<f32>(1.)) {
+        feedback = 0.;
+    }
+    // Reprojection knows from the depth where the history shows another surface
+    if params.reprojection != 0u {
+        feedback *= textureLoad(reprojected_buffer, pos, 0).a;
+    }
+
+    // Only the top left region of the history is valid, and it may have been
+    // rendered at a different resolution than the current frame
+    let history_scale = vec2<f32>(params.history_size) / vec2<f32>(textureDimensions(history_buffer));
+    let history_uv = min(prev_uv * history_scale, history_scale - 0.5 / vec2<f32>(textureDimensions(history_buffer)));
+    let history = textureSampleLevel(history_buffer, history_sampler, history_uv, 0.).rgb;
+    let color = mix(current, clamp(history, low, high), feedback);
+
+    textureStore(output_buffer, pos, vec4<f32>(color, 1.));
+}
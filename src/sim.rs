@@ -0,0 +1,78 @@
+use nalgebra::Vector3;
+
+use crate::world::{Sun, World};
+
+// Simulation steps per second, independent of the frame rate
+pub const TICK_RATE: u32 = 20;
+// Real seconds for a full day by default
+const DAY_LENGTH: f32 = 600.;
+
+// World state that advances on its own, without a window or GPU, so a
+// headless server can own it and clients only render it. Rendering clients
+// are sent its voxels and follow the clock of the server they are connected
+// to.
+pub struct Simulation {
+    pub tick: u64,
+    // Time of day in hours
+    pub hours: f32,
+    // Real seconds per day, 0 stops the clock
+    pub day_length: f32,
+    accumulator: f32,
+    world: World,
+}
+
+impl Simulation {
+    // Starts on the procedural terrain
+    pub fn new() -> Self {
+        Self::with_world(World::default())
+    }
+
+    pub fn with_world(world: World) -> Self {
+        Self {
+            tick: 0,
+            hours: 9.,
+            day_length: DAY_LENGTH,
+            accumulator: 0.,
+            world,
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    // Edits that won in the journal, see net::journal
+    pub fn edit(&mut self, position: [i32; 3], material: u8) {
+        self.world.edit(Vector3::from(position), material);
+    }
+
+    // Runs the ticks that fit in dt, returns how many ran
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        let step = 1. / TICK_RATE as f32;
+        self.accumulator += dt;
+        let mut ticks = 0;
+        while self.accumulator >= step {
+            self.accumulator -= step;
+            self.step(step);
+            ticks += 1;
+        }
+        ticks
+    }
+
+    fn step(&mut self, dt: f32) {
+        self.tick += 1;
+        if self.day_length > 0. {
+            self.hours = (self.hours + dt / self.day_length * 24.).rem_euclid(24.);
+        }
+    }
+
+    pub fn sun(&self) -> Sun {
+        Sun::at_time(self.hours)
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,314 @@
+use winit::{dpi::PhysicalSize, event::*};
+
+// Number of distinct sub-pixel offsets before the jitter sequence repeats
+const JITTER_SAMPLES: u32 = 8;
+
+#[derive(Debug)]
+pub struct TaaSettings {
+    pub enabled: bool,
+    // Weight of the accumulated history in the resolved image
+    pub feedback: f32,
+}
+
+impl TaaSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            feedback: 0.9,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::J => {
+                if state == ElementState::Pressed {
+                    self.enabled = !self.enabled;
+                    log::info!("TAA: {}", self.enabled);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TaaUniform {
+    feedback: f32,
+    reset: u32,
+    size: [u32; 2],
+    // Render size of the frame stored in the history texture
+    history_size: [u32; 2],
+    // Whether to drop history that reprojection marked invalid
+    reprojection: u32,
+    _padding: u32,
+}
+
+impl TaaUniform {
+    fn new() -> Self {
+        Self {
+            feedback: 0.,
+            reset: 1,
+            size: [0; 2],
+            history_size: [0; 2],
+            reprojection: 0,
+            _padding: 0,
+        }
+    }
+}
+
+pub struct TaaPipeline {
+    pub settings: TaaSettings,
+    pub uniform: TaaUniform,
+    pub pipeline: wgpu::ComputePipeline,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub output: wgpu::Texture,
+    pub output_view: wgpu::TextureView,
+    pub history: wgpu::Texture,
+    pub frame: u32,
+    // Size of the region being rendered to this frame
+    pub size: PhysicalSize<u32>,
+    reset: bool,
+}
+
+impl TaaPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        color_texture: &wgpu::TextureView,
+        motion_texture: &wgpu::TextureView,
+        reprojected_texture: &wgpu::TextureView,
+    ) -> TaaPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA resolve shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/taa.wgsl").into()),
+        });
+
+        let settings = TaaSettings::new();
+        let uniform = TaaUniform::new();
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("TAA Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let extent = wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        };
+
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            label: Some("TAA output texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let output_view = output.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let history = device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("TAA history texture"),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            view_formats: &[],
+        });
+        let history_view = history.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let history_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA history sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding: u32, filterable: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, false),
+                texture_entry(1, false),
+                texture_entry(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(6, false),
+            ],
+            label: Some("taa_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(motion_texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&history_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(reprojected_texture),
+                },
+            ],
+            label: Some("taa_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("TAA resolve pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        TaaPipeline {
+            settings,
+            uniform,
+            pipeline,
+            buffer,
+            bind_group,
+            output,
+            output_view,
+            history,
+            frame: 0,
+            size: *size,
+            reset: true,
+        }
+    }
+
+    // Sub-pixel camera offset in NDC for the current frame
+    pub fn jitter(&self) -> [f32; 2] {
+        if !self.settings.enabled {
+            return [0., 0.];
+        }
+
+        let index = self.frame % JITTER_SAMPLES + 1;
+        let x = halton(index, 2) - 0.5;
+        let y = halton(index, 3) - 0.5;
+
+        [
+            x * 2. / self.size.width as f32,
+            y * 2. / self.size.height as f32,
+        ]
+    }
+
+    // Discards the accumulated history, e.g. after a camera cut
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        render_size: PhysicalSize<u32>,
+        reprojection: bool,
+    ) {
+        self.uniform.history_size = [self.size.width, self.size.height];
+        self.size = render_size;
+
+        self.uniform.feedback = if self.settings.enabled {
+            self.settings.feedback
+        } else {
+            0.
+        };
+        self.uniform.reset = self.reset as u32;
+        self.uniform.reprojection = reprojection as u32;
+        self.uniform.size = [self.size.width, self.size.height];
+        self.reset = false;
+        self.frame = self.frame.wrapping_add(1);
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+pub(crate) fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.;
+    let mut result = 0.;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
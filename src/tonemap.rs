@@ -0,0 +1,191 @@
+use winit::{dpi::PhysicalSize, event::*};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tonemapper {
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::None => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Aces,
+            Tonemapper::Aces => Tonemapper::None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TonemapSettings {
+    pub tonemapper: Tonemapper,
+    // Exposure in stops (EV), applied as a 2^exposure multiplier
+    pub exposure: f32,
+    // Present to an HDR swapchain when the surface supports one
+    pub hdr_output: bool,
+}
+
+impl TonemapSettings {
+    pub fn new(tonemapper: Tonemapper, exposure: f32) -> Self {
+        Self {
+            tonemapper,
+            exposure,
+            hdr_output: false,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if state != ElementState::Pressed {
+            return false;
+        }
+        match key {
+            VirtualKeyCode::T => {
+                self.tonemapper = self.tonemapper.next();
+                log::info!("Tonemapper: {:?}", self.tonemapper);
+                true
+            }
+            VirtualKeyCode::U => {
+                self.hdr_output = !self.hdr_output;
+                log::info!("HDR output: {}", self.hdr_output);
+                true
+            }
+            VirtualKeyCode::Equals => {
+                self.exposure += 0.25;
+                true
+            }
+            VirtualKeyCode::Minus => {
+                self.exposure -= 0.25;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+    // Fraction of the HDR texture covered by the rendered region
+    uv_scale: [f32; 2],
+    // Composited after tonemapping
+    crosshair: u32,
+    // Values above 1 are kept for HDR swapchains instead of being clamped
+    hdr_output: u32,
+    _padding: [u32; 2],
+}
+
+impl TonemapUniform {
+    fn new() -> Self {
+        Self {
+            exposure: 1.0,
+            tonemapper: Tonemapper::Aces as u32,
+            uv_scale: [1.; 2],
+            crosshair: 0,
+            hdr_output: 0,
+            _padding: [0; 2],
+        }
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn update(&mut self, settings: &TonemapSettings) {
+        self.exposure = settings.exposure.exp2();
+        self.tonemapper = settings.tonemapper as u32;
+    }
+
+    // Shows the image unmodified, e.g. for debug views
+    pub fn update_passthrough(&mut self) {
+        self.exposure = 1.;
+        self.tonemapper = Tonemapper::None as u32;
+    }
+
+    pub fn update_crosshair(&mut self, crosshair: bool) {
+        self.crosshair = crosshair as u32;
+    }
+
+    pub fn update_hdr_output(&mut self, hdr_output: bool) {
+        self.hdr_output = hdr_output as u32;
+    }
+
+    pub fn update_uv_scale(
+        &mut self,
+        render_size: PhysicalSize<u32>,
+        texture_size: PhysicalSize<u32>,
+    ) {
+        self.uv_scale = [
+            render_size.width as f32 / texture_size.width as f32,
+            render_size.height as f32 / texture_size.height as f32,
+        ];
+    }
+}
+
+pub struct TonemapPipeline {
+    pub settings: TonemapSettings,
+    pub uniform: TonemapUniform,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl TonemapPipeline {
+    pub fn new(device: &wgpu::Device) -> TonemapPipeline {
+        let settings = TonemapSettings::new(Tonemapper::Aces, 0.);
+
+        let mut uniform = TonemapUniform::new();
+        uniform.update(&settings);
+
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Tonemap Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("tonemap_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("tonemap_bind_group"),
+        });
+
+        TonemapPipeline {
+            settings,
+            uniform,
+            buffer,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, passthrough: bool) {
+        if passthrough {
+            self.uniform.update_passthrough();
+        } else {
+            self.uniform.update(&self.settings);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
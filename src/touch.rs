@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use nalgebra::Vector3;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{Touch, TouchPhase},
+};
+
+use crate::camera::CameraController;
+
+// World units moved per pixel of two finger panning and pinching
+const PAN_SPEED: f32 = 0.05;
+const PINCH_SPEED: f32 = 0.1;
+
+// Turns touch gestures into camera movement: one finger looks around, two
+// fingers pan and pinch to move forward and backward
+#[derive(Debug, Default)]
+pub struct TouchController {
+    touches: HashMap<u64, PhysicalPosition<f64>>,
+    // Accumulated over all touch events since the last frame
+    look: (f64, f64),
+    translation: Vector3<f32>,
+}
+
+impl TouchController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process_touch(&mut self, touch: &Touch) {
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(touch.id, touch.location);
+            }
+            TouchPhase::Moved => {
+                let Some(previous) = self.touches.get(&touch.id).copied() else {
+                    return;
+                };
+                let before = self.pinch();
+                self.touches.insert(touch.id, touch.location);
+
+                match (before, self.pinch()) {
+                    (Some((center_before, spread_before)), Some((center, spread))) => {
+                        self.translation += Vector3::new(
+                            -(center.0 - center_before.0) as f32 * PAN_SPEED,
+                            (center.1 - center_before.1) as f32 * PAN_SPEED,
+                            (spread - spread_before) as f32 * PINCH_SPEED,
+                        );
+                    }
+                    _ if self.touches.len() == 1 => {
+                        self.look.0 += touch.location.x - previous.x;
+                        self.look.1 += touch.location.y - previous.y;
+                    }
+                    _ => {}
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+            }
+        }
+    }
+
+    // Center and distance of the two touches of a pinch gesture
+    fn pinch(&self) -> Option<((f64, f64), f64)> {
+        if self.touches.len() != 2 {
+            return None;
+        }
+        let mut touches = self.touches.values();
+        let a = touches.next()?;
+        let b = touches.next()?;
+
+        let center = ((a.x + b.x) * 0.5, (a.y + b.y) * 0.5);
+        let spread = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt();
+        Some((center, spread))
+    }
+
+    // Call once per frame, before the controller updates the camera
+    pub fn apply(&mut self, controller: &mut CameraController) {
+        if self.look != (0., 0.) {
+            controller.process_mouse(self.look);
+        }
+        controller.process_translation(self.translation);
+
+        self.look = (0., 0.);
+        self.translation = Vector3::zeros();
+    }
+}
@@ -0,0 +1,104 @@
+use std::num::NonZeroU64;
+
+// A buffer that the uniforms written every frame are packed into, each at an
+// offset aligned for dynamic binding. The CPU side is reused from frame to
+// frame and uploaded with a single write, instead of a write per uniform
+// buffer. Uniforms that are bound without a dynamic offset, like the camera
+// shared by many pipelines, keep a slot reserved at the start. Bindings read
+// the rest with the offsets push returned.
+pub struct UniformArena {
+    pub buffer: wgpu::Buffer,
+    data: Vec<u8>,
+    alignment: usize,
+    // Bytes of the reserved slots, kept by flush
+    reserved: usize,
+}
+
+impl UniformArena {
+    // Room for uniforms of the given sizes at once, reserved or pushed
+    pub fn new(device: &wgpu::Device, label: &str, sizes: &[usize]) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let capacity = sizes
+            .iter()
+            .map(|size| size.div_ceil(alignment) * alignment)
+            .sum::<usize>();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            data: Vec::with_capacity(capacity),
+            alignment,
+            reserved: 0,
+        }
+    }
+
+    // A slot for a T that keeps its offset, written with write. Only before
+    // anything was pushed.
+    pub fn reserve<T: bytemuck::Pod>(&mut self) -> u32 {
+        assert_eq!(self.data.len(), self.reserved, "Reserved after a push");
+        let offset = self.push(&T::zeroed());
+        self.reserved = self.data.len();
+        offset
+    }
+
+    // Replaces the value of a reserved slot, uploaded by the next flush
+    pub fn write<T: bytemuck::Pod>(&mut self, offset: u32, value: &T) {
+        let start = offset as usize;
+        let bytes = bytemuck::bytes_of(value);
+        assert!(start + bytes.len() <= self.reserved, "Not a reserved slot");
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // Appends value, returning its dynamic offset
+    pub fn push<T: bytemuck::Pod>(&mut self, value: &T) -> u32 {
+        let offset = self.data.len();
+        self.data.extend_from_slice(bytemuck::bytes_of(value));
+        let aligned = self.data.len().div_ceil(self.alignment) * self.alignment;
+        self.data.resize(aligned, 0);
+        assert!(
+            self.data.len() as u64 <= self.buffer.size(),
+            "Uniform arena overflow"
+        );
+        offset as u32
+    }
+
+    // Uploads the reserved slots and everything pushed since the last flush
+    pub fn flush(&mut self, queue: &wgpu::Queue) {
+        if !self.data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, &self.data);
+            self.data.truncate(self.reserved);
+        }
+    }
+
+    // A T in the arena, at the dynamic offset given when binding
+    pub fn binding<T>(&self) -> wgpu::BindingResource<'_> {
+        self.slot_binding::<T>(0)
+    }
+
+    // The T in the reserved slot at offset
+    pub fn slot_binding<T>(&self, offset: u32) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: offset as u64,
+            size: NonZeroU64::new(std::mem::size_of::<T>() as u64),
+        })
+    }
+}
+
+// Layout of a uniform T bound from an arena
+pub fn layout_entry<T>(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: NonZeroU64::new(std::mem::size_of::<T>() as u64),
+        },
+        count: None,
+    }
+}
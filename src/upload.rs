@@ -0,0 +1,331 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// Size of one staging buffer, larger uploads are split over several
+const STAGING_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
+// Bytes staged per frame unless config.toml sets upload_budget_mb
+pub const DEFAULT_UPLOAD_BUDGET: u64 = 32 * 1024 * 1024;
+
+struct StagingBuffer {
+    buffer: wgpu::Buffer,
+    // Set by map_async once the GPU copied everything out of the buffer
+    mapped: Arc<AtomicBool>,
+}
+
+// A texture filled over as many frames as the upload budget needs. Rows are
+// staged in order, so the texture is only complete once done returns true.
+pub struct TextureUpload {
+    pub texture: wgpu::Texture,
+    data: Vec<u8>,
+    // Next row of blocks to stage, counted over all layers
+    row: u32,
+}
+
+impl TextureUpload {
+    // data holds the first mip level, tightly packed
+    pub fn new(texture: wgpu::Texture, data: Vec<u8>) -> Self {
+        Self {
+            texture,
+            data,
+            row: 0,
+        }
+    }
+
+    // Starts over with new contents for the same texture
+    pub fn restart(&mut self, data: Vec<u8>) {
+        self.data = data;
+        self.row = 0;
+    }
+
+    pub fn done(&self) -> bool {
+        self.row >= self.rows_per_layer() * self.texture.size().depth_or_array_layers
+    }
+
+    fn rows_per_layer(&self) -> u32 {
+        let (_, block_height) = self.texture.format().block_dimensions();
+        self.texture.size().height.div_ceil(block_height)
+    }
+
+    fn row_bytes(&self) -> u32 {
+        let format = self.texture.format();
+        let (block_width, _) = format.block_dimensions();
+        let block_size = format.block_size(None).unwrap_or(4);
+        self.texture.size().width.div_ceil(block_width) * block_size
+    }
+}
+
+// Streams data to the GPU through a ring of staging buffers that are reused
+// once the GPU is done copying out of them. Copies are recorded into an
+// encoder of their own and submitted separately from the frame, and at most
+// budget bytes are staged per frame so large uploads are spread over frames
+// instead of stalling one.
+pub struct Uploader {
+    pub budget: u64,
+    // Bytes staged since the last submit
+    staged: u64,
+    encoder: Option<wgpu::CommandEncoder>,
+    // Written this frame, the last one is filled from offset on
+    current: Vec<StagingBuffer>,
+    offset: u64,
+    // Submitted, waiting for the GPU to finish copying
+    in_flight: Vec<StagingBuffer>,
+    // Mapped and ready to be written
+    free: Vec<StagingBuffer>,
+}
+
+impl Uploader {
+    pub fn new() -> Self {
+        Self {
+            budget: DEFAULT_UPLOAD_BUDGET,
+            staged: 0,
+            encoder: None,
+            current: Vec::new(),
+            offset: 0,
+            in_flight: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    // Whether more can be staged this frame. The first upload of a frame
+    // always gets through, however large, so a budget smaller than a row
+    // still makes progress.
+    pub fn has_budget(&self) -> bool {
+        self.staged < self.budget
+    }
+
+    // Stages as many rows of the upload as the budget allows, true once the
+    // whole texture is staged
+    pub fn write_texture(&mut self, device: &wgpu::Device, upload: &mut TextureUpload) -> bool {
+        let row_bytes = upload.row_bytes();
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let rows_per_layer = upload.rows_per_layer();
+        let (_, block_height) = upload.texture.format().block_dimensions();
+        let size = upload.texture.size();
+
+        while !upload.done() {
+            let budget_rows = match self.budget.saturating_sub(self.staged) / row_bytes as u64 {
+                0 if self.staged > 0 => return false,
+                rows => rows.max(1),
+            };
+            let layer = upload.row / rows_per_layer;
+            let y = upload.row % rows_per_layer;
+            let staging_rows = STAGING_BUFFER_SIZE / padded_row_bytes as u64;
+            let rows = (rows_per_layer - y)
+                .min(budget_rows.min(staging_rows) as u32)
+                .max(1);
+
+            let (buffer, offset) = self.reserve(device, rows as u64 * padded_row_bytes as u64);
+            {
+                let start = (layer * rows_per_layer + y) as usize * row_bytes as usize;
+                let slice = buffer.slice(offset..offset + rows as u64 * padded_row_bytes as u64);
+                let mut mapped = slice.get_mapped_range_mut();
+                for row in 0..rows as usize {
+                    let source = start + row * row_bytes as usize;
+                    let target = row * padded_row_bytes as usize;
+                    mapped[target..target + row_bytes as usize]
+                        .copy_from_slice(&upload.data[source..source + row_bytes as usize]);
+                }
+            }
+
+            let encoder = self.encoder.get_or_insert_with(|| {
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Upload Encoder"),
+                })
+            });
+            encoder.copy_buffer_to_texture(
+                wgpu::ImageCopyBuffer {
+                    buffer: &self.current.last().unwrap().buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset,
+                        bytes_per_row: Some(padded_row_bytes),
+                        rows_per_image: Some(rows),
+                    },
+                },
+                wgpu::ImageCopyTexture {
+                    texture: &upload.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: y * block_height,
+                        z: layer,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    width: size.width,
+                    height: rows * block_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            upload.row += rows;
+            self.staged += rows as u64 * row_bytes as u64;
+        }
+        true
+    }
+
+    // Stages data to be copied to buffer at offset, whole or not at all.
+    // false if the budget is spent, the write should be retried next frame.
+    // The length and offset must be multiples of 4.
+    pub fn write_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        buffer: &wgpu::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) -> bool {
+        if self.staged > 0 && self.staged + data.len() as u64 > self.budget {
+            return false;
+        }
+        for (index, chunk) in data.chunks(STAGING_BUFFER_SIZE as usize).enumerate() {
+            let (staging, staging_offset) = self.reserve(device, chunk.len() as u64);
+            staging
+                .slice(staging_offset..staging_offset + chunk.len() as u64)
+                .get_mapped_range_mut()
+                .copy_from_slice(chunk);
+
+            let encoder = self.encoder.get_or_insert_with(|| {
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Upload Encoder"),
+                })
+            });
+            encoder.copy_buffer_to_buffer(
+                &self.current.last().unwrap().buffer,
+                staging_offset,
+                buffer,
+                offset + (index * STAGING_BUFFER_SIZE as usize) as u64,
+                chunk.len() as u64,
+            );
+        }
+        self.staged += data.len() as u64;
+        true
+    }
+
+    // Stages tightly packed data for a region of an uncompressed texture,
+    // whole or not at all like write_buffer. Counted against the budget with
+    // the row padding, which is what it takes of the staging buffers.
+    pub fn write_texture_region(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        origin: wgpu::Origin3d,
+        size: wgpu::Extent3d,
+        data: &[u8],
+    ) -> bool {
+        let texel_size = texture.format().block_size(None).unwrap_or(4);
+        let row_bytes = size.width * texel_size;
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let rows = size.height * size.depth_or_array_layers;
+        let staged_size = rows as u64 * padded_row_bytes as u64;
+        if self.staged > 0 && self.staged + staged_size > self.budget {
+            return false;
+        }
+
+        let (buffer, offset) = self.reserve(device, staged_size);
+        {
+            let mut mapped = buffer
+                .slice(offset..offset + staged_size)
+                .get_mapped_range_mut();
+            for (row, source) in data.chunks(row_bytes as usize).enumerate() {
+                let target = row * padded_row_bytes as usize;
+                mapped[target..target + source.len()].copy_from_slice(source);
+            }
+        }
+
+        let encoder = self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Upload Encoder"),
+            })
+        });
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &self.current.last().unwrap().buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            size,
+        );
+        self.staged += staged_size;
+        true
+    }
+
+    // Submits the copies staged this frame and takes back the staging
+    // buffers the GPU has finished with. Called before the frame is
+    // submitted, so the uploads are visible to it.
+    pub fn submit(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        // Runs the map_async callbacks of earlier submissions
+        device.poll(wgpu::Maintain::Poll);
+        let (free, in_flight): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|staging| staging.mapped.load(Ordering::Acquire));
+        self.free.extend(free);
+        self.in_flight = in_flight;
+
+        self.staged = 0;
+        let Some(encoder) = self.encoder.take() else {
+            return;
+        };
+        for staging in &self.current {
+            staging.buffer.unmap();
+        }
+        queue.submit(Some(encoder.finish()));
+
+        for staging in self.current.drain(..) {
+            let mapped = staging.mapped.clone();
+            mapped.store(false, Ordering::Release);
+            staging
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Write, move |result| {
+                    mapped.store(result.is_ok(), Ordering::Release);
+                });
+            self.in_flight.push(staging);
+        }
+        self.offset = 0;
+    }
+
+    // Staging memory allocated, counted with the frame's other buffers
+    pub fn staging_bytes(&self) -> u64 {
+        (self.current.len() + self.in_flight.len() + self.free.len()) as u64 * STAGING_BUFFER_SIZE
+    }
+
+    // Room for size bytes in a mapped staging buffer, offsets stay aligned
+    // for texture copies
+    fn reserve(&mut self, device: &wgpu::Device, size: u64) -> (&wgpu::Buffer, u64) {
+        let size = size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64);
+        if self.current.is_empty() || self.offset + size > STAGING_BUFFER_SIZE {
+            let staging = self.free.pop().unwrap_or_else(|| StagingBuffer {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Staging Buffer"),
+                    size: STAGING_BUFFER_SIZE,
+                    usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                }),
+                mapped: Arc::new(AtomicBool::new(true)),
+            });
+            self.current.push(staging);
+            self.offset = 0;
+        }
+        let offset = self.offset;
+        self.offset += size;
+        (&self.current.last().unwrap().buffer, offset)
+    }
+}
+
+impl Default for Uploader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    io::Write,
+    process::{Child, Command, Stdio},
+};
+
+use winit::event::*;
+
+use crate::{
+    capture::{padded_bytes_per_row, unpad_rows},
+    readback::ReadbackRing,
+    render,
+};
+
+const VIDEO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// Frames in flight between rendering and being written to the encoder
+const READBACK_COUNT: usize = 3;
+
+#[derive(Debug)]
+pub struct VideoSettings {
+    // Even sizes, the encoders subsample the chroma
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f32,
+    // The container follows the extension, mp4 or webm
+    pub extension: String,
+}
+
+impl VideoSettings {
+    pub fn new() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            frame_rate: 30.,
+            extension: "mp4".to_string(),
+        }
+    }
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Recording {
+    ffmpeg: Child,
+    path: String,
+    // Time since the last recorded frame
+    elapsed: f32,
+    frame_due: bool,
+    frames: u32,
+}
+
+// Records the ray traced image to a video by piping raw frames into ffmpeg.
+// Frames are read back through a ReadbackRing, so they reach the encoder a
+// few frames late without stalling the render loop.
+pub struct VideoRecorder {
+    pub settings: VideoSettings,
+    // Tonemaps the final color into the video target
+    pub render: render::RenderPipeline,
+    pub target: wgpu::Texture,
+    readbacks: ReadbackRing<()>,
+    recording: Option<Recording>,
+}
+
+impl VideoRecorder {
+    pub fn new(
+        device: &wgpu::Device,
+        color_texture: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        tonemap_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> VideoRecorder {
+        let settings = VideoSettings::new();
+        let render = create_render_pipeline(
+            device,
+            &settings,
+            color_texture,
+            sampler,
+            tonemap_bind_group_layout,
+        );
+        let (target, readbacks) = create_targets(device, &settings);
+
+        VideoRecorder {
+            settings,
+            render,
+            target,
+            readbacks,
+            recording: None,
+        }
+    }
+
+    // The render targets were reallocated
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        color_texture: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        tonemap_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.render = create_render_pipeline(
+            device,
+            &self.settings,
+            color_texture,
+            sampler,
+            tonemap_bind_group_layout,
+        );
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start(&mut self, device: &wgpu::Device) {
+        // Settings may have changed since the last recording
+        let size = self.target.size();
+        if size.width != self.settings.width || size.height != self.settings.height {
+            (self.target, self.readbacks) = create_targets(device, &self.settings);
+        }
+        let size = self.target.size();
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let path = format!("recording_{}.{}", timestamp, self.settings.extension);
+        let codec: &[&str] = match self.settings.extension.as_str() {
+            "webm" => &["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "30"],
+            _ => &["-c:v", "libx264", "-preset", "fast", "-crf", "18"],
+        };
+
+        let ffmpeg = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+            ])
+            .args(["-s", &format!("{}x{}", size.width, size.height)])
+            .args(["-r", &self.settings.frame_rate.to_string(), "-i", "-"])
+            .args(codec)
+            .args(["-pix_fmt", "yuv420p", &path])
+            .stdin(Stdio::piped())
+            .spawn();
+        match ffmpeg {
+            Ok(ffmpeg) => {
+                log::info!("Recording to {}", path);
+                self.recording = Some(Recording {
+                    ffmpeg,
+                    path,
+                    elapsed: 0.,
+                    frame_due: true,
+                    frames: 0,
+                });
+            }
+            Err(error) => log::warn!("Couldn't start ffmpeg: {}", error),
+        }
+    }
+
+    pub fn stop(&mut self, device: &wgpu::Device) {
+        // Write out the frames still in flight
+        device.poll(wgpu::Maintain::Wait);
+        self.write_mapped();
+
+        let Some(mut recording) = self.recording.take() else {
+            return;
+        };
+        // Closing stdin ends the video
+        drop(recording.ffmpeg.stdin.take());
+        match recording.ffmpeg.wait() {
+            Ok(status) if status.success() => {
+                log::info!("Recorded {} frames to {}", recording.frames, recording.path)
+            }
+            Ok(status) => log::warn!("ffmpeg failed with {}", status),
+            Err(error) => log::warn!("ffmpeg failed: {}", error),
+        }
+    }
+
+    pub fn process_keyboard(
+        &mut self,
+        key: VirtualKeyCode,
+        state: ElementState,
+        device: &wgpu::Device,
+    ) -> bool {
+        match key {
+            VirtualKeyCode::F9 => {
+                if state == ElementState::Pressed {
+                    if self.recording() {
+                        self.stop(device);
+                    } else {
+                        self.start(device);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Frames are taken at the video frame rate, independent of the render
+    // frame rate
+    pub fn update(&mut self, dt: f32) {
+        let frame_time = 1. / self.settings.frame_rate;
+        if let Some(recording) = &mut self.recording {
+            recording.elapsed += dt;
+            recording.frame_due = recording.elapsed >= frame_time;
+            if recording.frame_due {
+                recording.elapsed = (recording.elapsed - frame_time).min(frame_time);
+            }
+        }
+    }
+
+    // Renders and copies out this frame's image when one is due
+    pub fn encode(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        tonemap_bind_group: &wgpu::BindGroup,
+    ) {
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        if !recording.frame_due {
+            return;
+        }
+        let Some(buffer) = self.readbacks.begin(()) else {
+            log::warn!("Video encoder is falling behind, dropping a frame");
+            recording.frame_due = false;
+            return;
+        };
+
+        let target_view = self
+            .target
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        {
+            let mut video_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Video Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            video_pass.set_pipeline(&self.render.pipeline);
+            video_pass.set_bind_group(0, &self.render.bind_group, &[]);
+            video_pass.set_bind_group(1, tonemap_bind_group, &[]);
+            video_pass.draw(0..3, 0..1);
+        }
+
+        let size = self.target.size();
+        encoder.copy_texture_to_buffer(
+            self.target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row(size.width)),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+    }
+
+    // Called after submitting the frame. Starts mapping the new readback and
+    // writes the ones that finished to ffmpeg.
+    pub fn after_submit(&mut self, device: &wgpu::Device) {
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        recording.frame_due = false;
+        self.readbacks.after_submit(device);
+        self.write_mapped();
+    }
+
+    fn write_mapped(&mut self) {
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        let size = self.target.size();
+
+        // The ring keeps the frames in order
+        self.readbacks.read(|(), data| {
+            let Some(stdin) = &mut recording.ffmpeg.stdin else {
+                return;
+            };
+            if let Err(error) = stdin.write_all(&unpad_rows(data, size)) {
+                log::warn!("Couldn't write video frame: {}", error);
+                recording.ffmpeg.stdin = None;
+                return;
+            }
+            recording.frames += 1;
+        });
+    }
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    settings: &VideoSettings,
+    color_texture: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    tonemap_bind_group_layout: &wgpu::BindGroupLayout,
+) -> render::RenderPipeline {
+    let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Vertex shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
+    });
+
+    let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fragment shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/frag.wgsl").into()),
+    });
+
+    // Only the format is used when creating the pipeline
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: VIDEO_FORMAT,
+        width: settings.width,
+        height: settings.height,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+    };
+
+    render::RenderPipeline::new(
+        device,
+        vert_shader,
+        frag_shader,
+        &config,
+        sampler,
+        color_texture,
+        tonemap_bind_group_layout,
+    )
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    settings: &VideoSettings,
+) -> (wgpu::Texture, ReadbackRing<()>) {
+    let width = settings.width.max(2) & !1;
+    let height = settings.height.max(2) & !1;
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        format: VIDEO_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        label: Some("Video target texture"),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        view_formats: &[],
+    });
+
+    let readbacks = ReadbackRing::new(
+        device,
+        "Video Readback Buffer",
+        (padded_bytes_per_row(width) * height) as u64,
+        READBACK_COUNT,
+    );
+
+    (target, readbacks)
+}
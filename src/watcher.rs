@@ -0,0 +1,44 @@
+use std::time::SystemTime;
+
+// Seconds between checks of the file for changes
+const POLL_INTERVAL: f32 = 0.5;
+
+// Notices changes to a file by polling its modification time, which also
+// works where file system notifications don't
+pub struct FileWatcher {
+    pub path: String,
+    modified: Option<SystemTime>,
+    elapsed: f32,
+}
+
+impl FileWatcher {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            modified: modified(path),
+            elapsed: 0.,
+        }
+    }
+
+    // True once after every change to the file
+    pub fn poll(&mut self, dt: f32) -> bool {
+        self.elapsed += dt;
+        if self.elapsed < POLL_INTERVAL {
+            return false;
+        }
+        self.elapsed = 0.;
+
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
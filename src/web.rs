@@ -0,0 +1,66 @@
+use std::{cell::Cell, rc::Rc};
+
+use wasm_bindgen::{prelude::*, JsCast};
+use winit::dpi::PhysicalSize;
+
+// Keeps the canvas the size of its container. Winit prevents sizing the
+// canvas with CSS, so a ResizeObserver watches the container and the event
+// loop applies the latest size with take_size.
+pub struct CanvasResizer {
+    pending: Rc<Cell<Option<PhysicalSize<u32>>>>,
+    observer: web_sys::ResizeObserver,
+    _callback: Closure<dyn FnMut(js_sys::Array)>,
+}
+
+impl CanvasResizer {
+    pub fn new(container: &web_sys::Element) -> Option<Self> {
+        let pending = Rc::new(Cell::new(None));
+
+        let callback = {
+            let pending = pending.clone();
+            Closure::<dyn FnMut(js_sys::Array)>::new(move |entries: js_sys::Array| {
+                let Some(entry) = entries
+                    .iter()
+                    .last()
+                    .and_then(|entry| entry.dyn_into::<web_sys::ResizeObserverEntry>().ok())
+                else {
+                    return;
+                };
+                // The content rect is in CSS pixels, the canvas is drawn in
+                // device pixels so it stays sharp on high DPI screens
+                let rect = entry.content_rect();
+                let scale = web_sys::window()
+                    .map(|window| window.device_pixel_ratio())
+                    .unwrap_or(1.);
+                let size = PhysicalSize::new(
+                    (rect.width() * scale).round() as u32,
+                    (rect.height() * scale).round() as u32,
+                );
+                if size.width > 0 && size.height > 0 {
+                    pending.set(Some(size));
+                }
+            })
+        };
+
+        let observer = web_sys::ResizeObserver::new(callback.as_ref().unchecked_ref()).ok()?;
+        // Also reports the initial size
+        observer.observe(container);
+
+        Some(CanvasResizer {
+            pending,
+            observer,
+            _callback: callback,
+        })
+    }
+
+    // Latest container size since the last call, in physical pixels
+    pub fn take_size(&self) -> Option<PhysicalSize<u32>> {
+        self.pending.take()
+    }
+}
+
+impl Drop for CanvasResizer {
+    fn drop(&mut self) {
+        self.observer.disconnect();
+    }
+}
@@ -1,28 +1,155 @@
-use std::iter;
+use std::{iter, sync::Arc};
 
 use winit::{
-    event::{ElementState, KeyboardInput, MouseButton, WindowEvent},
-    window::Window,
+    event::{DeviceEvent, ElementState, KeyboardInput, MouseButton, WindowEvent},
+    window::{Window, WindowId},
 };
 
-use crate::{camera, raytracing, render};
-pub struct State {
+#[cfg(feature = "egui")]
+use crate::gui;
+#[cfg(feature = "net")]
+use crate::net;
+#[cfg(feature = "ecs")]
+use crate::scene;
+#[cfg(feature = "scripting")]
+use crate::script;
+use crate::{
+    app, assets, avatar, brick_pool, camera, capture, checkerboard, config, console, diagnostics,
+    exposure, frustum, gpu, graph, grid, input, inset, jobs, keybindings, memory, motion_blur,
+    mouse, occlusion, occupancy, overlay, palette, pass, picking, preprocess, present, preview,
+    raytracing, render, reprojection, residency, resolution, scene_file, screenshot, shader_reload,
+    taa, tonemap, touch, upload, video, world,
+};
+
+// Farthest voxel that can be double clicked as the orbit pivot
+const PIVOT_DISTANCE: f32 = 512.;
+
+#[derive(Debug, thiserror::Error)]
+pub enum InitError {
+    #[error("Couldn't create a surface for the window: {0}")]
+    Surface(#[from] wgpu::CreateSurfaceError),
+    #[error("No graphics adapter can render to the window")]
+    NoAdapter,
+    #[error("Couldn't create the graphics device: {0}")]
+    Device(#[from] wgpu::RequestDeviceError),
+    #[error("The window surface supports no texture formats")]
+    UnsupportedSurface,
+}
+
+// Uses the selected adapter if there is one. Otherwise prefers the discrete
+// GPU, then the integrated one, then a software rasterizer like WARP.
+pub(crate) async fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    gpu: Option<&gpu::GpuSelection>,
+) -> Option<wgpu::Adapter> {
+    if let Some(selection) = gpu {
+        match gpu::select_adapter(instance, selection, compatible_surface) {
+            Some(adapter) => {
+                let info = adapter.get_info();
+                log::info!("Using {} ({:?})", info.name, info.backend);
+                return Some(adapter);
+            }
+            None => log::warn!("No usable adapter matches {:?}", selection),
+        }
+    }
+
+    let options = [
+        (wgpu::PowerPreference::HighPerformance, false),
+        (wgpu::PowerPreference::LowPower, false),
+        (wgpu::PowerPreference::default(), true),
+    ];
+    for (power_preference, force_fallback_adapter) in options {
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference,
+                compatible_surface,
+                force_fallback_adapter,
+            })
+            .await;
+        match adapter {
+            Some(adapter) => {
+                let info = adapter.get_info();
+                log::info!("Using {} ({:?})", info.name, info.backend);
+                return Some(adapter);
+            }
+            None => log::warn!(
+                "No adapter for {:?} (fallback: {})",
+                power_preference,
+                force_fallback_adapter
+            ),
+        }
+    }
+    None
+}
+
+// The adapter request_adapter picks, or if it can't create a device every
+// other adapter that can render to compatible_surface in turn
+pub(crate) async fn request_adapter_and_device(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    gpu: Option<&gpu::GpuSelection>,
+) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), InitError> {
+    let preferred = request_adapter(instance, compatible_surface, gpu).await;
+    let preferred_info = preferred.as_ref().map(wgpu::Adapter::get_info);
+    let others = gpu::compatible_adapters(instance, compatible_surface)
+        .into_iter()
+        .filter(|adapter| Some(adapter.get_info()) != preferred_info);
+
+    let mut error = InitError::NoAdapter;
+    for adapter in preferred.into_iter().chain(others) {
+        match request_device(&adapter).await {
+            Ok((device, queue)) => return Ok((adapter, device, queue)),
+            Err(device_error) => {
+                log::warn!(
+                    "Couldn't create a device on {}: {}",
+                    adapter.get_info().name,
+                    device_error
+                );
+                error = InitError::Device(device_error);
+            }
+        }
+    }
+    Err(error)
+}
+
+pub(crate) async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                // Timestamps drive dynamic resolution when available
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web we'll have to disable some.
+                limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+            },
+            None, // Trace path
+        )
+        .await
+}
+
+// Device and surface of a window, created before deciding how to render to it
+pub struct GpuContext {
+    pub instance: wgpu::Instance,
     pub surface: wgpu::Surface,
+    pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub config: wgpu::SurfaceConfiguration,
-    pub size: winit::dpi::PhysicalSize<u32>,
-    pub window: Window,
-    pub render: render::RenderPipeline,
-    pub camera: camera::CameraPipeline,
-    pub raytracing: raytracing::RaytracingPipeline,
-    pub mouse_pressed: bool,
 }
 
-impl State {
-    pub async fn new(window: Window) -> Self {
-        let size = window.inner_size();
-
+impl GpuContext {
+    // The window has to outlive the context, or whatever it is turned into
+    pub async fn new(
+        window: &Window,
+        gpu: Option<&gpu::GpuSelection>,
+    ) -> Result<GpuContext, InitError> {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -34,34 +161,149 @@ impl State {
         //
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(window) }?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (adapter, device, queue) =
+            request_adapter_and_device(&instance, Some(&surface), gpu).await?;
+
+        Ok(GpuContext {
+            instance,
+            surface,
+            adapter,
+            device,
+            queue,
+        })
+    }
+
+    // Compute shaders and storage textures, which WebGL2 doesn't have. Without
+    // them only the fallback renderer works.
+    pub fn supports_compute(&self) -> bool {
+        self.adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && self.device.limits().max_storage_textures_per_shader_stage > 0
+    }
+}
+
+pub struct State {
+    // Kept to create the surfaces of preview windows
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    pub surface: wgpu::Surface,
+    // Shared with the workers that compile shader variants
+    pub device: Arc<wgpu::Device>,
+    pub queue: wgpu::Queue,
+    // Errors of the device, and whether it was lost and has to be recreated
+    pub diagnostics: diagnostics::GpuDiagnostics,
+    pub config: wgpu::SurfaceConfiguration,
+    pub present: present::PresentSettings,
+    pub sdr_format: wgpu::TextureFormat,
+    pub hdr_format: Option<wgpu::TextureFormat>,
+    pub size: winit::dpi::PhysicalSize<u32>,
+    pub window: Window,
+    pub render: render::RenderPipeline,
+    pub camera: camera::CameraPipeline,
+    pub raytracing: raytracing::RaytracingPipeline,
+    pub tonemap: tonemap::TonemapPipeline,
+    pub exposure: exposure::ExposurePipeline,
+    pub checkerboard: checkerboard::CheckerboardPipeline,
+    pub reprojection: reprojection::ReprojectionPipeline,
+    pub taa: taa::TaaPipeline,
+    pub motion_blur: motion_blur::MotionBlurPipeline,
+    pub capture: capture::CapturePipeline,
+    pub video: video::VideoRecorder,
+    pub screenshot: screenshot::Screenshot,
+    pub render_scale: resolution::RenderScale,
+    pub frame_timer: Option<crate::profiling::FrameTimer>,
+    graph_pool: graph::TexturePool,
+    pub overlay: overlay::OverlaySettings,
+    pub touch: touch::TouchController,
+    pub grid: grid::GridPipeline,
+    pub avatar: avatar::AvatarPipeline,
+    pub inset: inset::InsetPipeline,
+    // Textures loaded in the background, uploaded in update
+    pub assets: assets::AssetManager,
+    // GPU memory per subsystem against the budget from config.toml
+    pub memory: memory::MemoryTracker,
+    // Freed chunk buffers for streaming to reuse
+    pub buffer_pool: memory::BufferPool,
+    // Staging ring streaming assets and chunks in submissions of its own
+    pub uploads: upload::Uploader,
+    // Passes added from outside, see pass::RenderPass
+    pub passes: pass::PassRegistry,
+    // Extra windows sharing the device and world, see open_preview
+    pub previews: Vec<preview::PreviewWindow>,
+    pub frustum: frustum::FrustumFreeze,
+    // Chunks resident on the GPU, culled with the frustum's camera
+    pub residency: residency::ChunkResidency,
+    // Depth pyramid of recent frames that hidden chunks are culled with
+    pub occlusion: occlusion::OcclusionCulling,
+    // Voxel under the crosshair, read back a frame or two late
+    pub picking: picking::PickReadback,
+    // Empty bricks and chunks around the camera that the traversal skips
+    pub occupancy: occupancy::BrickOccupancy,
+    // Voxels of the bricks near the camera, traced instead of the terrain
+    pub brick_pool: brick_pool::BrickPool,
+    // The terrain or a grid replacing it, and the edits on top, see set_world
+    world: world::World,
+    // Workers that occupancy and the brick pool are generated and shader
+    // variants compiled on
+    pub jobs: jobs::JobSystem,
+    // The workgroup size is tuned once every size is compiled
+    tuning_pending: bool,
+    pub mouse: mouse::MouseLook,
+    // Key, mouse and axis state consumed once per frame in update
+    pub input: input::Input,
+    pub console: console::Console,
+    // Last applied config.toml, reloaded when the file changes
+    pub user_config: config::Config,
+    config_watcher: config::ConfigWatcher,
+    scene_watcher: scene_file::SceneWatcher,
+    // World of the last applied scene file
+    world_source: scene_file::WorldSource,
+    shader_reload: shader_reload::ShaderReload,
+    #[cfg(feature = "egui")]
+    pub gui: gui::Gui,
+    #[cfg(feature = "ecs")]
+    pub scene: scene::Scene,
+    #[cfg(feature = "scripting")]
+    pub scripts: script::ScriptHost,
+    // Connection to a world server, opened with the connect command
+    #[cfg(feature = "net")]
+    pub net: Option<net::NetSession>,
+    // Whether the current render targets were allocated for supersampling
+    supersampled: bool,
+    pub cursor_position: winit::dpi::PhysicalPosition<f64>,
+}
+
+impl State {
+    pub async fn new(window: Window) -> Result<Self, InitError> {
+        Self::with_gpu(window, None).await
+    }
+
+    // gpu picks the adapter instead of the default preference order
+    pub async fn with_gpu(
+        window: Window,
+        gpu: Option<gpu::GpuSelection>,
+    ) -> Result<Self, InitError> {
+        let context = GpuContext::new(&window, gpu.as_ref()).await?;
+        Self::from_context(window, context)
+    }
+
+    // context has to be created for this window
+    pub fn from_context(window: Window, context: GpuContext) -> Result<Self, InitError> {
+        let size = window.inner_size();
+        let GpuContext {
+            instance,
+            surface,
+            adapter,
+            device,
+            queue,
+        } = context;
+        let device = Arc::new(device);
+        let diagnostics = diagnostics::GpuDiagnostics::new();
+        diagnostics.install(&device);
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -69,7 +311,15 @@ impl State {
             .iter()
             .copied()
             .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+            .or_else(|| surface_caps.formats.first().copied())
+            .ok_or(InitError::UnsupportedSurface)?;
+        // Linear extended range (scRGB), only offered by HDR capable displays
+        let hdr_format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| *f == wgpu::TextureFormat::Rgba16Float);
+        log::info!("HDR output supported: {}", hdr_format.is_some());
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -81,42 +331,471 @@ impl State {
             view_formats: vec![],
         };
         surface.configure(&device, &config);
+        let present = present::PresentSettings::new(&surface_caps.present_modes);
 
-        let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Vertex shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
-        });
+        let mut camera = camera::CameraPipeline::new(&device);
 
-        let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Fragment shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/frag.wgsl").into()),
-        });
+        let render_scale = resolution::RenderScale::new(1.0);
+        let target_size = render_scale.target_size(size);
+
+        let mut raytracing =
+            raytracing::RaytracingPipeline::new(&device, &target_size, &camera.bind_group_layout);
+        camera.bind_to_arena(&device, &mut raytracing.uniforms);
 
-        let camera = camera::CameraPipeline::new(&device);
+        let checkerboard = checkerboard::CheckerboardPipeline::new(
+            &device,
+            &target_size,
+            &raytracing.texture,
+            &raytracing.motion,
+        );
 
-        let raytracing =
-            raytracing::RaytracingPipeline::new(&device, &size, &camera.bind_group_layout);
+        let reprojection = reprojection::ReprojectionPipeline::new(
+            &device,
+            &target_size,
+            &checkerboard.output_view,
+            &raytracing.motion,
+            &raytracing.depth,
+        );
 
-        let render = render::RenderPipeline::new(
+        let taa = taa::TaaPipeline::new(
             &device,
-            vert_shader,
-            frag_shader,
-            &config,
+            &target_size,
+            &checkerboard.output_view,
+            &raytracing.motion,
+            &reprojection.output_view,
+        );
+
+        let motion_blur = motion_blur::MotionBlurPipeline::new(
+            &device,
+            &target_size,
+            &taa.output_view,
+            &raytracing.motion,
+        );
+
+        let tonemap = tonemap::TonemapPipeline::new(&device);
+
+        let exposure =
+            exposure::ExposurePipeline::new(&device, &target_size, &motion_blur.output_view);
+
+        let capture = capture::CapturePipeline::new(
+            &device,
+            &target_size,
+            &size,
+            &motion_blur.output_view,
+            &raytracing.noise_mask,
             &raytracing.sampler,
-            &raytracing.texture,
+            &tonemap.bind_group_layout,
+        );
+
+        let video = video::VideoRecorder::new(
+            &device,
+            &motion_blur.output_view,
+            &raytracing.sampler,
+            &tonemap.bind_group_layout,
+        );
+
+        let screenshot = screenshot::Screenshot::new(
+            &device,
+            &size,
+            &motion_blur.output_view,
+            &raytracing.sampler,
+            &tonemap.bind_group_layout,
+        );
+
+        let shader_reload = shader_reload::ShaderReload::new();
+
+        let (render, grid, avatar) = create_output_pipelines(
+            &device,
+            &config,
+            &camera,
+            &raytracing,
+            &motion_blur,
+            &tonemap,
+            shader_reload.frag_source(),
         );
 
-        Self {
+        let inset = inset::InsetPipeline::new(&device, &config, &camera.bind_group_layout);
+        let frustum = frustum::FrustumFreeze::new(&device);
+        let occlusion = occlusion::OcclusionCulling::new(&device, &raytracing.depth);
+        let picking = picking::PickReadback::new(&device);
+
+        let frame_timer = crate::profiling::FrameTimer::new(&device, &queue);
+
+        #[cfg(feature = "egui")]
+        let gui = gui::Gui::new(&device, config.format, &window);
+        #[cfg(feature = "ecs")]
+        let scene = scene::Scene::new(raytracing.settings.sun, &camera.camera);
+
+        let mut state = Self {
+            instance,
+            adapter,
             surface,
             device,
             queue,
+            diagnostics,
             size,
             config,
+            present,
+            sdr_format: surface_format,
+            hdr_format,
             window,
             render,
             camera,
             raytracing,
-            mouse_pressed: false,
+            tonemap,
+            exposure,
+            checkerboard,
+            reprojection,
+            taa,
+            motion_blur,
+            capture,
+            video,
+            screenshot,
+            render_scale,
+            frame_timer,
+            graph_pool: graph::TexturePool::new(),
+            overlay: overlay::OverlaySettings::new(),
+            touch: touch::TouchController::new(),
+            grid,
+            avatar,
+            inset,
+            assets: assets::AssetManager::new(),
+            memory: memory::MemoryTracker::new(),
+            buffer_pool: memory::BufferPool::new(),
+            uploads: upload::Uploader::new(),
+            passes: pass::PassRegistry::new(),
+            previews: Vec::new(),
+            frustum,
+            residency: residency::ChunkResidency::new(),
+            occlusion,
+            picking,
+            occupancy: occupancy::BrickOccupancy::new(),
+            brick_pool: brick_pool::BrickPool::new(),
+            world: world::World::default(),
+            jobs: jobs::JobSystem::new(),
+            tuning_pending: false,
+            mouse: mouse::MouseLook::new(),
+            input: input::Input::new(),
+            console: console::Console::new(),
+            user_config: config::Config::default(),
+            config_watcher: config::ConfigWatcher::new(config::CONFIG_PATH),
+            scene_watcher: scene_file::SceneWatcher::new(scene_file::SCENE_PATH),
+            world_source: scene_file::WorldSource::Procedural,
+            shader_reload,
+            #[cfg(feature = "egui")]
+            gui,
+            #[cfg(feature = "ecs")]
+            scene,
+            #[cfg(feature = "scripting")]
+            scripts: script::ScriptHost::new(),
+            #[cfg(feature = "net")]
+            net: None,
+            supersampled: false,
+            cursor_position: winit::dpi::PhysicalPosition::new(0., 0.),
+        };
+
+        let user_config = config::Config::load(config::CONFIG_PATH);
+        // The start position is only used when the camera is created
+        if let Some(camera) = &user_config.camera {
+            state.camera.camera.position = camera.position.into();
+        }
+        state.apply_config(user_config);
+        // Without a size in the config the fastest one for this GPU is used.
+        // The sizes compile on the workers while the first frames render with
+        // the default one. The web can't wait on the GPU to time them.
+        state.tuning_pending =
+            state.user_config.quality.workgroup_size.is_none() && !cfg!(target_arch = "wasm32");
+        if let Some(scene) = scene_file::SceneFile::load(scene_file::SCENE_PATH) {
+            state.apply_scene(&scene);
+        }
+        Ok(state)
+    }
+
+    pub fn apply_config(&mut self, user_config: config::Config) {
+        // Only when changed, so a window resized by hand keeps its size
+        // through unrelated edits
+        let window = &user_config.window;
+        if *window != self.user_config.window {
+            if let (Some(width), Some(height)) = (window.width, window.height) {
+                self.window
+                    .set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+            }
+            let vsync = window.vsync.map(|vsync| {
+                if vsync {
+                    present::PresentMode::Fifo
+                } else {
+                    present::PresentMode::Immediate
+                }
+            });
+            if let Some(mode) = window.present_mode.or(vsync) {
+                self.present.set_mode(mode);
+            }
+        }
+
+        if let Some(camera) = &user_config.camera {
+            self.camera.settings = camera.clone();
+            self.camera.apply_settings();
+        }
+        if let Some(bindings) = &user_config.bindings {
+            self.camera.controller.bindings =
+                keybindings::KeyBindings::with_bindings(bindings.clone());
+        }
+
+        self.apply_quality(&user_config.quality);
+        self.memory.budget = user_config
+            .memory
+            .budget_mb
+            .map(|megabytes| megabytes * 1024 * 1024);
+        self.uploads.budget = user_config
+            .memory
+            .upload_budget_mb
+            .map_or(upload::DEFAULT_UPLOAD_BUDGET, |megabytes| {
+                megabytes * 1024 * 1024
+            });
+        if let Some(chunks) = user_config.memory.max_resident_chunks {
+            self.residency.settings.max_resident = chunks;
+        }
+
+        self.user_config = user_config;
+    }
+
+    // Settings left out keep their current value
+    fn apply_quality(&mut self, quality: &config::QualityConfig) {
+        if let Some(scale) = quality.render_scale {
+            self.render_scale.set_scale(scale);
+        }
+        if let Some(dynamic) = quality.dynamic_resolution {
+            self.render_scale.dynamic = dynamic;
+        }
+        if let Some(target_frame_time) = quality.target_frame_time {
+            self.render_scale.target_frame_time = target_frame_time;
+        }
+        // Targets are reallocated in update
+        if let Some(supersampling) = quality.supersampling {
+            self.render_scale.supersampling = supersampling;
+        }
+        if let Some(enabled) = quality.checkerboard {
+            self.checkerboard.settings.enabled = enabled;
+        }
+        if let Some(pattern) = quality.interleave_pattern {
+            self.checkerboard.settings.pattern = pattern;
+        }
+        if let Some(enabled) = quality.foveated {
+            self.checkerboard.settings.foveated = enabled;
+        }
+        if let Some(size) = quality.fovea_size {
+            self.checkerboard.settings.fovea_size = size;
+        }
+        if let Some(enabled) = quality.reprojection {
+            self.reprojection.settings.enabled = enabled;
+        }
+        if let Some(enabled) = quality.taa {
+            self.taa.settings.enabled = enabled;
+        }
+        if let Some(enabled) = quality.motion_blur {
+            self.motion_blur.settings.enabled = enabled;
+        }
+        if let Some(enabled) = quality.beam_optimization {
+            self.raytracing.settings.beam_optimization = enabled;
+        }
+        if let Some(enabled) = quality.half_res_lighting {
+            self.raytracing.settings.half_res_lighting = enabled;
+        }
+        if let Some(enabled) = quality.shadows {
+            self.raytracing.settings.shadows = enabled;
+        }
+        if let Some(enabled) = quality.wavefront {
+            self.raytracing.settings.wavefront = enabled;
+        }
+        if let Some(enabled) = quality.tiled_dispatch {
+            self.raytracing.settings.tiled = enabled;
+        }
+        if let Some(enabled) = quality.sky_tiles {
+            self.raytracing.settings.sky_tiles = enabled;
+        }
+        if let Some(size) = quality.workgroup_size {
+            self.raytracing.settings.workgroup_size = size;
+        }
+        if let Some(traversal) = quality.traversal {
+            self.raytracing.settings.traversal = traversal;
+        }
+        let render = &mut self.raytracing.settings.render;
+        if let Some(distance) = quality.max_distance {
+            render.max_distance = distance;
+        }
+        if let Some(steps) = quality.max_steps {
+            render.max_steps = steps;
+        }
+        if let Some(bias) = quality.lod_bias {
+            render.lod_bias = bias;
+        }
+        if let Some(enabled) = quality.chunk_culling {
+            self.residency.settings.enabled = enabled;
+        }
+        if let Some(distance) = quality.view_distance {
+            self.residency.settings.view_distance = distance;
+        }
+        if let Some(enabled) = quality.occlusion_culling {
+            self.occlusion.enabled = enabled;
+        }
+    }
+
+    // Compiles the ray tracing variant for changed settings on the workers.
+    // Captures render every sample with the variant of their settings, which
+    // update compiles right away.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn compile_variant_in_background(&mut self) {
+        if !self.capture.capturing() {
+            self.raytracing
+                .compile_in_background(&self.jobs, &self.device);
+        }
+    }
+
+    // Tunes the workgroup size once the variants of every size are compiled,
+    // unless the config picked one meanwhile
+    fn tune_when_compiled(&mut self) {
+        if self.user_config.quality.workgroup_size.is_some() {
+            self.tuning_pending = false;
+            return;
+        }
+        let current = raytracing::ShaderVariant::new(&self.raytracing.settings);
+        let variants =
+            raytracing::WorkgroupSize::ALL.map(|workgroup_size| raytracing::ShaderVariant {
+                workgroup_size,
+                ..current
+            });
+        #[cfg(not(target_arch = "wasm32"))]
+        self.raytracing
+            .compile_variants_in_background(&self.jobs, &self.device, variants);
+        if self.raytracing.variants_ready(variants) {
+            self.tuning_pending = false;
+            self.tune_workgroup_size();
+        }
+    }
+
+    // Blocks on the GPU, timing the main pass with every workgroup size. The
+    // web can't wait on the GPU and keeps the default size.
+    fn tune_workgroup_size(&mut self) {
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        let size = self.raytracing.size;
+        let mut uniform = camera::CameraUniform::new();
+        uniform.update_view(&self.camera.camera);
+        uniform.update_view_proj(&self.camera.camera, size.width, size.height, [0., 0.]);
+        self.camera
+            .write_uniform(&self.queue, &mut self.raytracing.uniforms, &uniform);
+        self.raytracing.uniforms.flush(&self.queue);
+        let fastest =
+            self.raytracing
+                .tune_workgroup_size(&self.device, &self.queue, &self.camera.bind_group);
+        log::info!("Workgroup size: {:?}", fastest);
+    }
+
+    // Draws grid in place of the terrain, or the terrain again with None.
+    // Edits of the old world are dropped, the bricks and bitmasks built from
+    // it are rebuilt over the next frames.
+    pub fn set_world(&mut self, grid: Option<world::VoxelGrid>) -> Result<(), String> {
+        self.replace_world(world::World::new(grid))
+    }
+
+    // Replaces the world with a MagicaVoxel model and its palette
+    pub fn load_vox(&mut self, path: &str) -> Result<(), String> {
+        self.load_world(&scene_file::WorldSource::Vox {
+            path: path.to_string(),
+        })
+    }
+
+    // Replaces the world with the source of a scene file
+    pub fn load_world(&mut self, source: &scene_file::WorldSource) -> Result<(), String> {
+        self.replace_world(source.load()?)
+    }
+
+    fn replace_world(&mut self, world: world::World) -> Result<(), String> {
+        let limit = self.device.limits().max_texture_dimension_3d;
+        if let Some(size) = world.grid().map(world::VoxelGrid::size) {
+            if size.max() > limit {
+                return Err(format!(
+                    "A {}x{}x{} grid is larger than the {} voxels per side the device supports",
+                    size.x, size.y, size.z, limit
+                ));
+            }
+        }
+        self.world = world;
+        self.occupancy.set_world(self.world.grid());
+        self.brick_pool.reset();
+        self.upload_world();
+        Ok(())
+    }
+
+    pub fn world(&self) -> &world::World {
+        &self.world
+    }
+
+    // Sets a voxel to a palette index, palette::EMPTY clears it. Its brick
+    // is pooled again and occupied once the bitmasks are rebuilt.
+    pub fn edit_voxel(&mut self, position: nalgebra::Point3<i32>, material: u8) {
+        self.world.edit(position.coords, material);
+        self.brick_pool.edit(position.coords);
+        if material != palette::EMPTY {
+            self.occupancy.mark_edited(position);
+        }
+        self.raytracing
+            .edit_grid(&self.queue, position.coords, material);
+        self.inset
+            .raytracing
+            .edit_grid(&self.queue, position.coords, material);
+        for preview in &self.previews {
+            preview
+                .raytracing
+                .edit_grid(&self.queue, position.coords, material);
+        }
+    }
+
+    // Uploads the world's grid to every view tracing it
+    fn upload_world(&mut self) {
+        self.raytracing
+            .set_world(&self.device, &self.queue, &self.world);
+        self.recreate_targets(self.raytracing.size);
+        self.inset
+            .set_world(&self.device, &self.queue, &self.config, &self.world);
+        for preview in &mut self.previews {
+            preview.set_world(&self.device, &self.queue, &self.world);
+        }
+    }
+
+    pub fn apply_scene(&mut self, scene: &scene_file::SceneFile) {
+        // Reloading an unchanged source would drop the edits made since
+        if scene.world.source != self.world_source {
+            match self.load_world(&scene.world.source) {
+                Ok(()) => self.world_source = scene.world.source.clone(),
+                Err(error) => log::warn!("Couldn't load the scene's world: {}", error),
+            }
+        }
+
+        let camera = &mut self.camera.camera;
+        if let Some(position) = scene.camera.position {
+            camera.position = position.into();
+        }
+        if let Some(target) = scene.camera.look_at {
+            let target = nalgebra::Point3::from(target);
+            if let Some(direction) = (target - camera.position).try_normalize(f32::EPSILON) {
+                camera.direction = direction;
+            }
+        }
+        if let Some(fov) = scene.camera.fov {
+            camera.fov = fov;
+        }
+        if let Some(sun) = scene.lights.sun() {
+            self.raytracing.settings.sun = sun;
+        }
+        self.apply_quality(&scene.render);
+
+        #[cfg(feature = "scripting")]
+        self.scripts.load(&scene.scripts);
+        #[cfg(not(feature = "scripting"))]
+        if !scene.scripts.is_empty() {
+            log::warn!("Scripts need the scripting feature, ignoring them");
         }
     }
 
@@ -124,6 +803,202 @@ impl State {
         &self.window
     }
 
+    // Opens a window that shows the world through its own camera, starting
+    // at the main camera
+    pub fn open_preview(&mut self, window: Window) -> Result<(), InitError> {
+        let mut preview = preview::PreviewWindow::new(
+            &self.instance,
+            &self.adapter,
+            &self.device,
+            window,
+            &self.camera.bind_group_layout,
+            &self.camera.camera,
+        )?;
+        preview.set_world(&self.device, &self.queue, &self.world);
+        self.previews.push(preview);
+        Ok(())
+    }
+
+    // Events of preview windows, true if the event belonged to one
+    pub fn preview_event(
+        &mut self,
+        window_id: winit::window::WindowId,
+        event: &WindowEvent,
+    ) -> bool {
+        let Some(index) = self
+            .previews
+            .iter()
+            .position(|preview| preview.window().id() == window_id)
+        else {
+            return false;
+        };
+        match event {
+            WindowEvent::CloseRequested => {
+                self.previews.remove(index);
+            }
+            WindowEvent::Resized(size) => self.previews[index].resize(&self.device, *size),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                self.previews[index].resize(&self.device, **new_inner_size)
+            }
+            _ => {}
+        }
+        true
+    }
+
+    // Debug views are displayed without exposure and tonemapping
+    pub fn debug_view_active(&self) -> bool {
+        self.raytracing.settings.debug_view != raytracing::DebugView::None
+    }
+
+    // Point used for picking in NDC: the screen center while looking around,
+    // the cursor otherwise
+    pub fn pick_position(&self) -> [f32; 2] {
+        if self.mouse.grabbed {
+            return [0., 0.];
+        }
+        [
+            (self.cursor_position.x / self.size.width as f64 * 2. - 1.) as f32,
+            (1. - self.cursor_position.y / self.size.height as f64 * 2.) as f32,
+        ]
+    }
+
+    // Size of the region that is ray traced this frame
+    pub fn render_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.render_scale.render_size(self.raytracing.size)
+    }
+
+    pub fn hdr_output(&self) -> bool {
+        Some(self.config.format) == self.hdr_format
+    }
+
+    // Switching the swapchain format also needs new pipelines for it
+    fn configure_output(&mut self, hdr_output: bool) {
+        self.config.format = match (hdr_output, self.hdr_format) {
+            (true, Some(format)) => format,
+            _ => self.sdr_format,
+        };
+        self.surface.configure(&self.device, &self.config);
+        self.inset.configure(&self.device, &self.config);
+        #[cfg(feature = "egui")]
+        self.gui.configure(&self.device, self.config.format);
+
+        let (render, grid, avatar) = create_output_pipelines(
+            &self.device,
+            &self.config,
+            &self.camera,
+            &self.raytracing,
+            &self.motion_blur,
+            &self.tonemap,
+            self.shader_reload.frag_source(),
+        );
+        self.render = render;
+        self.grid = grid;
+        self.avatar = avatar;
+    }
+
+    // Reallocates every internal render target, keeping the settings of the passes
+    fn recreate_targets(&mut self, target_size: winit::dpi::PhysicalSize<u32>) {
+        self.raytracing.resize(&self.device, target_size);
+        self.occlusion.rebind(&self.device, &self.raytracing.depth);
+
+        let mut checkerboard = checkerboard::CheckerboardPipeline::new(
+            &self.device,
+            &target_size,
+            &self.raytracing.texture,
+            &self.raytracing.motion,
+        );
+        std::mem::swap(&mut checkerboard.settings, &mut self.checkerboard.settings);
+        self.checkerboard = checkerboard;
+
+        let mut reprojection = reprojection::ReprojectionPipeline::new(
+            &self.device,
+            &target_size,
+            &self.checkerboard.output_view,
+            &self.raytracing.motion,
+            &self.raytracing.depth,
+        );
+        std::mem::swap(&mut reprojection.settings, &mut self.reprojection.settings);
+        self.reprojection = reprojection;
+
+        let mut taa = taa::TaaPipeline::new(
+            &self.device,
+            &target_size,
+            &self.checkerboard.output_view,
+            &self.raytracing.motion,
+            &self.reprojection.output_view,
+        );
+        std::mem::swap(&mut taa.settings, &mut self.taa.settings);
+        self.taa = taa;
+
+        let mut motion_blur = motion_blur::MotionBlurPipeline::new(
+            &self.device,
+            &target_size,
+            &self.taa.output_view,
+            &self.raytracing.motion,
+        );
+        std::mem::swap(&mut motion_blur.settings, &mut self.motion_blur.settings);
+        self.motion_blur = motion_blur;
+
+        let mut exposure = exposure::ExposurePipeline::new(
+            &self.device,
+            &target_size,
+            &self.motion_blur.output_view,
+        );
+        std::mem::swap(&mut exposure.settings, &mut self.exposure.settings);
+        self.exposure = exposure;
+
+        self.recreate_capture();
+        self.recreate_screenshot();
+        self.video.rebind(
+            &self.device,
+            &self.motion_blur.output_view,
+            &self.raytracing.sampler,
+            &self.tonemap.bind_group_layout,
+        );
+
+        let (render, grid, avatar) = create_output_pipelines(
+            &self.device,
+            &self.config,
+            &self.camera,
+            &self.raytracing,
+            &self.motion_blur,
+            &self.tonemap,
+            self.shader_reload.frag_source(),
+        );
+        self.render = render;
+        self.grid = grid;
+        self.avatar = avatar;
+    }
+
+    // The capture target matches the window, the accumulation the render targets
+    fn recreate_capture(&mut self) {
+        let mut capture = capture::CapturePipeline::new(
+            &self.device,
+            &self.raytracing.size,
+            &self.size,
+            &self.motion_blur.output_view,
+            &self.raytracing.noise_mask,
+            &self.raytracing.sampler,
+            &self.tonemap.bind_group_layout,
+        );
+        std::mem::swap(&mut capture.settings, &mut self.capture.settings);
+        if self.capture.capturing() {
+            log::warn!("Capture interrupted by resizing the render targets");
+        }
+        self.capture = capture;
+    }
+
+    // Matches the window like the capture target
+    fn recreate_screenshot(&mut self) {
+        self.screenshot = screenshot::Screenshot::new(
+            &self.device,
+            &self.size,
+            &self.motion_blur.output_view,
+            &self.raytracing.sampler,
+            &self.tonemap.bind_group_layout,
+        );
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.camera
@@ -133,11 +1008,23 @@ impl State {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.avatar.resize(&self.device, &self.config);
+            self.recreate_targets(self.render_scale.target_size(new_size));
         }
     }
 
     #[allow(unused_variables)]
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        // The overlay gets events first so typing into it doesn't move the camera
+        #[cfg(feature = "egui")]
+        if self.gui.input(event) {
+            return true;
+        }
+        if self.console.input(&self.window, event) {
+            // Keys held when it opened would keep moving the camera
+            self.input.clear();
+            return true;
+        }
         match event {
             WindowEvent::KeyboardInput {
                 input:
@@ -147,24 +1034,129 @@ impl State {
                         ..
                     },
                 ..
-            } => self.camera.controller.process_keyboard(*key, *state),
+            } => {
+                self.input.process_key(*key, *state);
+                // Bound actions are read from the input snapshot in update
+                if self.camera.controller.bindings.action(*key).is_some() {
+                    return true;
+                }
+                self.camera.camera.process_keyboard(*key, *state)
+                    || self.tonemap.settings.process_keyboard(*key, *state)
+                    || self.exposure.settings.process_keyboard(*key, *state)
+                    || self.taa.settings.process_keyboard(*key, *state)
+                    || self.checkerboard.settings.process_keyboard(*key, *state)
+                    || self.motion_blur.settings.process_keyboard(*key, *state)
+                    || self.render_scale.process_keyboard(*key, *state)
+                    || self.raytracing.settings.process_keyboard(*key, *state)
+                    || self.overlay.process_keyboard(*key, *state)
+                    || self.present.process_keyboard(*key, *state)
+                    || self
+                        .frame_timer
+                        .as_mut()
+                        .is_some_and(|timer| timer.process_keyboard(*key, *state))
+                    || self.camera.bookmarks.process_keyboard(
+                        *key,
+                        *state,
+                        self.input.modifiers,
+                        &mut self.camera.camera,
+                    )
+                    || camera::presets::process_keyboard(
+                        *key,
+                        *state,
+                        self.input.modifiers,
+                        &mut self.camera.camera,
+                        &mut self.camera.bookmarks,
+                    )
+                    || self
+                        .camera
+                        .path
+                        .process_keyboard(*key, *state, &self.camera.camera)
+                    || self
+                        .capture
+                        .process_keyboard(*key, *state, &self.camera.path.path)
+                    || self.video.process_keyboard(*key, *state, &self.device)
+                    || self.screenshot.process_keyboard(*key, *state)
+                    || self
+                        .inset
+                        .process_keyboard(*key, *state, &self.camera.camera)
+                    || self.frustum.process_keyboard(
+                        *key,
+                        *state,
+                        &self.camera.camera,
+                        self.render_size().width as f32 / self.render_size().height as f32,
+                    )
+            }
+            // Phones have no keyboard or mouse to control the web demo with
+            #[cfg(target_arch = "wasm32")]
+            WindowEvent::Touch(touch) => {
+                self.touch.process_touch(touch);
+                true
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.input.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.input.process_scroll(camera::scroll_steps(delta));
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                // The cursor is recentered while grabbed, picking uses the
+                // screen center then
+                if self.mouse.grabbed {
+                    if let Some(delta) = self.mouse.cursor_moved(&self.window, *position) {
+                        self.input.process_mouse_motion(delta);
+                    }
+                } else {
+                    self.cursor_position = *position;
+                }
+                false
+            }
+            WindowEvent::Focused(false) => {
+                self.mouse.release(&self.window);
+                // Key releases while unfocused never arrive
+                self.input.clear();
+                false
+            }
+            // Double clicking a voxel makes it the orbit pivot
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                if !self.mouse.double_click()
+                    || self.camera.controller.look_mode != camera::LookMode::Orbit
+                {
+                    return false;
+                }
+                // The GPU pick sees edits and pooled bricks, the world
+                // raycast is used until a result through the cursor was read
+                // back
+                let render_size = self.render_size();
+                let hit = match self.picking.at(self.pick_position()) {
+                    Some(pick) if pick.distance <= PIVOT_DISTANCE => Some(pick.position),
+                    _ => self
+                        .camera
+                        .camera
+                        .screen_ray(self.pick_position(), render_size.width, render_size.height)
+                        .and_then(|(origin, direction)| {
+                            let distance = self.world.raycast(origin, direction, PIVOT_DISTANCE)?;
+                            Some(origin + direction * distance)
+                        }),
+                };
+                if let Some(pivot) = hit {
+                    self.camera.controller.pivot = Some(pivot);
+                    log::info!("Orbit pivot: {:?}", pivot);
+                }
+                true
+            }
             WindowEvent::MouseInput {
                 button: MouseButton::Right,
                 state,
                 ..
             } => {
                 if *state == ElementState::Pressed {
-                    self.mouse_pressed = !self.mouse_pressed;
-                    if self.mouse_pressed {
-                        self.window()
-                            .set_cursor_grab(winit::window::CursorGrabMode::Confined)
-                            .unwrap();
-                    } else {
-                        self.window()
-                            .set_cursor_grab(winit::window::CursorGrabMode::None)
-                            .unwrap();
-                    }
-                    self.window().set_cursor_visible(!self.mouse_pressed);
+                    self.mouse.toggle(&self.window, self.cursor_position);
                 }
                 true
             }
@@ -173,68 +1165,1146 @@ impl State {
     }
 
     pub fn update(&mut self, dt: instant::Duration) {
+        self.update_frame(dt, None);
+    }
+
+    // Like update, with the camera of an application embedding the renderer
+    // in place of the built-in controls, bookmarks and paths
+    pub fn update_with_camera(&mut self, dt: instant::Duration, camera: &camera::Camera) {
+        self.update_frame(dt, Some(camera));
+    }
+
+    // The built-in controls, bookmarks and camera paths
+    fn update_camera(&mut self, dt: instant::Duration) {
         self.camera
             .controller
-            .update_camera(&mut self.camera.camera, dt, &mut self.camera.uniform);
-        self.queue.write_buffer(
-            &self.camera.buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera.uniform]),
+            .process_input(&self.input, &mut self.camera.camera);
+        self.input.end_frame();
+        self.touch.apply(&mut self.camera.controller);
+        self.camera
+            .bookmarks
+            .update(&mut self.camera.camera, dt.as_secs_f32());
+        self.camera
+            .path
+            .update(&mut self.camera.camera, dt.as_secs_f32());
+        // Captures step through the path one frame per accumulated image, every
+        // sample of it starting without TAA history
+        if let Some(time) = self.capture.time() {
+            if let Some(keyframe) = self.camera.path.path.sample(time) {
+                keyframe.apply(&mut self.camera.camera);
+            }
+            self.taa.reset();
+            self.reprojection.reset();
+        }
+        #[cfg(feature = "ecs")]
+        self.scene.update(
+            dt.as_secs_f32(),
+            &mut self.camera.camera,
+            &mut self.raytracing.settings.sun,
+        );
+        self.camera.controller.update_camera(
+            &mut self.camera.camera,
+            &self.world,
+            dt,
+            &mut self.camera.uniform,
+        );
+    }
+
+    #[::profiling::function]
+    fn update_frame(&mut self, dt: instant::Duration, camera: Option<&camera::Camera>) {
+        if self.diagnostics.device_lost() {
+            self.recover_device();
+        }
+        self.diagnostics.push_scope(&self.device);
+        self.reload_shaders(dt.as_secs_f32());
+        if let Some(user_config) = self.config_watcher.poll(dt.as_secs_f32()) {
+            self.apply_config(user_config);
+        }
+        if let Some(scene) = self.scene_watcher.poll(dt.as_secs_f32()) {
+            self.apply_scene(&scene);
+        }
+        self.assets.pump(
+            &self.device,
+            &mut self.uploads,
+            &mut self.memory,
+            dt.as_secs_f32(),
+        );
+        self.track_memory();
+        for line in self.console.take_submitted() {
+            self.run_command(&line);
+        }
+        #[cfg(feature = "scripting")]
+        for command in self.scripts.update(dt.as_secs_f32()) {
+            self.apply_command(command);
+        }
+        #[cfg(feature = "net")]
+        if let Some(net) = &mut self.net {
+            let mut changed = net.update(dt.as_secs_f32(), &self.camera.camera);
+            let world = net.take_world();
+            if world.is_some() {
+                // Every edit so far, on top of the server's world
+                changed = net.journal.snapshot();
+            }
+            // The server's simulation owns the time of day
+            if let Some(hours) = net.take_clock() {
+                self.raytracing.settings.sun = world::Sun::at_time(hours);
+            }
+            if net.closed() {
+                log::warn!("Disconnected from the world server");
+                self.net = None;
+            }
+            if let Some(world) = world {
+                if let Err(error) = self.replace_world(world) {
+                    log::warn!("Couldn't use the server's world: {}", error);
+                }
+            }
+            for edit in changed {
+                match u8::try_from(edit.material) {
+                    Ok(material) => self.edit_voxel(edit.position.into(), material),
+                    Err(_) => log::warn!("Invalid material {} from the server", edit.material),
+                }
+            }
+        }
+        if let Some(timer) = &mut self.frame_timer {
+            timer.update(dt.as_secs_f32());
+        }
+        // Settings edited in the overlay take effect this frame
+        #[cfg(feature = "egui")]
+        {
+            let stats = gui::FrameStats {
+                cpu_time: dt.as_secs_f32() * 1000.,
+                gpu_time: self
+                    .frame_timer
+                    .as_ref()
+                    .and_then(|timer| timer.last_frame_time),
+                passes: self
+                    .frame_timer
+                    .as_ref()
+                    .map(|timer| timer.timings.clone())
+                    .unwrap_or_default(),
+                render_size: self.render_size(),
+                memory: self.memory.usage(),
+                memory_budget: self.memory.budget,
+                errors: self.diagnostics.errors(),
+            };
+            self.gui.update(
+                &self.window,
+                stats,
+                &mut self.camera,
+                &mut self.tonemap.settings,
+                &mut self.raytracing.settings,
+            );
+        }
+        match camera {
+            Some(camera) => {
+                self.input.end_frame();
+                self.camera.camera = camera.clone();
+                #[cfg(feature = "ecs")]
+                self.scene.update(
+                    dt.as_secs_f32(),
+                    &mut self.camera.camera,
+                    &mut self.raytracing.settings.sun,
+                );
+                self.camera.camera.clip_boom(&self.world);
+                self.camera.uniform.update_view(&self.camera.camera);
+            }
+            None => self.update_camera(dt),
+        }
+        // Fall back to the CPU frame time without timestamp queries
+        let frame_time = self
+            .frame_timer
+            .as_ref()
+            .and_then(|timer| timer.last_frame_time)
+            .unwrap_or(dt.as_secs_f32() * 1000.);
+        if !self.capture.capturing() {
+            self.render_scale.adapt(frame_time);
+        }
+
+        if self.render_scale.supersampling != self.supersampled {
+            self.supersampled = self.render_scale.supersampling;
+            self.recreate_targets(self.render_scale.target_size(self.size));
+        }
+
+        if self.present.take_changed() {
+            self.config.present_mode = self.present.mode().to_wgpu();
+            self.surface.configure(&self.device, &self.config);
+        }
+
+        let hdr_output = self.tonemap.settings.hdr_output && self.hdr_format.is_some();
+        if hdr_output != self.hdr_output() {
+            self.configure_output(hdr_output);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.compile_variant_in_background();
+        if self.tuning_pending {
+            self.tune_when_compiled();
+        }
+
+        // Everything from here on only writes uniforms
+        ::profiling::scope!("Uniform uploads");
+        let render_size = self.render_size();
+        self.checkerboard.update(&self.queue, render_size);
+        self.occupancy.update(self.camera.camera.eye());
+        self.raytracing
+            .occupancy
+            .build(&self.device, &self.queue, &mut self.occupancy);
+        self.brick_pool
+            .update(self.camera.camera.eye(), &self.world);
+        self.brick_pool.upload(
+            &self.world,
+            &self.jobs,
+            &self.device,
+            &mut self.uploads,
+            &self.raytracing.pool_table,
+            &self.raytracing.brick_pool,
         );
+        self.raytracing
+            .uniform
+            .update_occupancy(self.occupancy.origin(), self.occupancy.top());
+        self.raytracing
+            .uniform
+            .update_fovea(self.checkerboard.fovea(render_size));
+        self.reprojection.update(&self.queue, render_size);
+        self.taa
+            .update(&self.queue, render_size, self.reprojection.settings.enabled);
+        self.motion_blur.update(&self.queue, render_size);
+        self.capture.update(&self.queue, render_size);
+        self.video.update(dt.as_secs_f32());
+        self.camera.uniform.update_view_proj(
+            &self.camera.camera,
+            render_size.width,
+            render_size.height,
+            self.taa.jitter(),
+        );
+        self.camera.write_uniform(
+            &self.queue,
+            &mut self.raytracing.uniforms,
+            &self.camera.uniform,
+        );
+        // Flushes the camera written above along with the ray tracing uniforms
+        self.raytracing.update(
+            &self.device,
+            &self.queue,
+            &self.overlay,
+            self.pick_position(),
+            self.checkerboard.parity(),
+        );
+        let mut avatars = Vec::new();
+        if self.camera.camera.mode == camera::CameraMode::ThirdPerson {
+            avatars.push((self.camera.camera.position, self.camera.camera.direction));
+        }
+        #[cfg(feature = "net")]
+        if let Some(net) = &self.net {
+            avatars.extend(
+                net.peers
+                    .values()
+                    .map(|pose| (pose.position.into(), pose.direction.into())),
+            );
+        }
+        self.avatar
+            .update(&self.queue, &avatars, &self.raytracing.settings.sun);
+        self.frustum.update(&self.queue);
+        // Stereo traces depths from two eyes, and without depth writes the
+        // pyramid would be stale
+        self.occlusion.update(
+            &self.queue,
+            &self.camera.camera,
+            render_size,
+            self.raytracing.settings.stereo == raytracing::StereoMode::Off
+                && self.raytracing.settings.write_depth,
+        );
+        self.residency.update(
+            self.frustum.culling_camera(&self.camera.camera),
+            render_size,
+            self.occlusion.latest.as_ref(),
+        );
+        self.residency.upload(
+            &self.device,
+            &mut self.uploads,
+            &self.raytracing.residency_buffer,
+        );
+        // Everything streamed this frame, before the frame is submitted
+        self.uploads.submit(&self.device, &self.queue);
+        self.passes.prepare(
+            &self.device,
+            &self.queue,
+            &pass::FrameInfo {
+                dt: dt.as_secs_f32(),
+                render_size,
+                output_size: self.size,
+                output_format: self.config.format,
+                camera_bind_group_layout: &self.camera.bind_group_layout,
+            },
+        );
+        for preview in &mut self.previews {
+            preview.update(
+                &self.device,
+                &self.queue,
+                &self.camera.camera,
+                &self.raytracing.settings.sun,
+                &self.tonemap,
+            );
+        }
+        self.tonemap
+            .uniform
+            .update_uv_scale(render_size, self.raytracing.size);
+        self.tonemap.uniform.update_crosshair(
+            self.overlay.crosshair && !self.capture.capturing() && !self.video.recording(),
+        );
+        self.tonemap.uniform.update_hdr_output(hdr_output);
+        self.tonemap.update(&self.queue, self.debug_view_active());
+        if self.inset.enabled() {
+            self.inset.update(
+                &self.device,
+                &self.queue,
+                &self.camera.camera,
+                &self.raytracing.settings.sun,
+                &self.tonemap,
+                hdr_output,
+            );
+        }
+        self.exposure.update(
+            &self.queue,
+            dt,
+            self.tonemap.uniform.exposure(),
+            render_size,
+        );
+        self.diagnostics.pop_scope(&self.device, "Update");
     }
 
+    // Measures the render targets, which are reallocated as a whole on
+    // resizes. World memory is reported by the allocations themselves. Over
+    // budget the chunk pool is emptied first.
+    fn track_memory(&mut self) {
+        let textures = |textures: &[&wgpu::Texture]| -> u64 {
+            textures
+                .iter()
+                .map(|texture| memory::texture_bytes(texture))
+                .sum()
+        };
+        self.memory.set(
+            memory::MemoryCategory::Accumulation,
+            textures(&[
+                &self.checkerboard.history,
+                &self.reprojection.color_history,
+                &self.reprojection.depth_history,
+                &self.taa.history,
+                &self.capture.output,
+                &self.capture.history,
+            ]),
+        );
+        self.memory.set(
+            memory::MemoryCategory::Post,
+            self.raytracing.target_bytes()
+                + self.inset.raytracing.target_bytes()
+                + self.graph_pool.bytes()
+                + self.uploads.staging_bytes()
+                + textures(&[
+                    &self.checkerboard.output,
+                    &self.reprojection.output,
+                    &self.taa.output,
+                    &self.motion_blur.output,
+                    &self.capture.target,
+                    &self.screenshot.target,
+                    &self.video.target,
+                ]),
+        );
+        if !self.memory.fits(0) {
+            self.buffer_pool.trim(&mut self.memory);
+        }
+    }
+
+    // Recreates the device and everything allocated on it after it was lost,
+    // e.g. by a driver reset. Settings, cameras and other CPU side state are
+    // kept, GPU only state like the TAA history starts over. Preview windows
+    // are closed.
+    fn recover_device(&mut self) {
+        // Requesting a device can't be waited on on the web, reloading the
+        // page recreates it
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        log::warn!("The graphics device was lost, recreating it");
+        // The same GPU if it is still there
+        let selection = gpu::GpuSelection::Name(self.adapter.get_info().name.to_lowercase());
+        let requested = pollster::block_on(request_adapter_and_device(
+            &self.instance,
+            Some(&self.surface),
+            Some(&selection),
+        ));
+        let (adapter, device, queue) = match requested {
+            Ok(requested) => requested,
+            Err(error) => {
+                log::error!("Couldn't recreate the graphics device: {}", error);
+                return;
+            }
+        };
+        self.diagnostics.install(&device);
+        self.adapter = adapter;
+        self.device = Arc::new(device);
+        self.queue = queue;
+        self.surface.configure(&self.device, &self.config);
+
+        self.camera.recreate(&self.device);
+        let mut tonemap = tonemap::TonemapPipeline::new(&self.device);
+        std::mem::swap(&mut tonemap.settings, &mut self.tonemap.settings);
+        self.tonemap = tonemap;
+        self.raytracing
+            .recreate(&self.device, &self.camera.bind_group_layout);
+        self.camera
+            .bind_to_arena(&self.device, &mut self.raytracing.uniforms);
+        self.graph_pool = graph::TexturePool::new();
+        let mut occlusion = occlusion::OcclusionCulling::new(&self.device, &self.raytracing.depth);
+        occlusion.enabled = self.occlusion.enabled;
+        self.occlusion = occlusion;
+        self.picking = picking::PickReadback::new(&self.device);
+        self.occupancy.invalidate();
+        self.brick_pool.invalidate();
+        self.raytracing
+            .set_world(&self.device, &self.queue, &self.world);
+        self.recreate_targets(self.raytracing.size);
+
+        if self.video.recording() {
+            log::warn!("Video recording stopped by the lost device");
+        }
+        let mut video = video::VideoRecorder::new(
+            &self.device,
+            &self.motion_blur.output_view,
+            &self.raytracing.sampler,
+            &self.tonemap.bind_group_layout,
+        );
+        std::mem::swap(&mut video.settings, &mut self.video.settings);
+        self.video = video;
+
+        self.inset
+            .recreate(&self.device, &self.config, &self.camera.bind_group_layout);
+        self.inset
+            .set_world(&self.device, &self.queue, &self.config, &self.world);
+        self.frustum.recreate(&self.device);
+        let logging = self.frame_timer.as_ref().is_some_and(|timer| timer.logging);
+        self.frame_timer = crate::profiling::FrameTimer::new(&self.device, &self.queue);
+        if let Some(timer) = &mut self.frame_timer {
+            timer.logging = logging;
+        }
+        let mut uploads = upload::Uploader::new();
+        uploads.budget = self.uploads.budget;
+        self.uploads = uploads;
+        self.assets.reload_all();
+        self.residency.invalidate();
+        self.passes.device_recreated(&self.device);
+        if !self.previews.is_empty() {
+            log::warn!("Closing the preview windows of the lost device");
+            self.previews.clear();
+        }
+        #[cfg(feature = "egui")]
+        {
+            let visible = self.gui.visible;
+            self.gui = gui::Gui::new(&self.device, self.config.format, &self.window);
+            self.gui.visible = visible;
+        }
+
+        self.diagnostics.recovered();
+        log::info!("Recreated the graphics device");
+    }
+
+    // Keeps the current pipelines when a changed shader doesn't compile
+    #[::profiling::function]
+    fn reload_shaders(&mut self, dt: f32) {
+        let (raytracing_source, frag_source) = self.shader_reload.poll(dt);
+
+        if let Some(source) = raytracing_source {
+            let result = self
+                .raytracing
+                .reload_shader(&self.device, &source, shader_reload::read_include)
+                .and_then(|()| {
+                    self.inset.raytracing.reload_shader(
+                        &self.device,
+                        &source,
+                        shader_reload::read_include,
+                    )
+                });
+            match result {
+                Ok(()) => log::info!("Reloaded ray-tracing.wgsl"),
+                Err(error) => log::warn!("Couldn't compile ray-tracing.wgsl: {}", error),
+            }
+        }
+
+        // Only for the main view, the inset and the capture, video and
+        // screenshot blits keep the built in shader
+        let frag_source = frag_source.and_then(|source| {
+            preprocess::preprocess(&source, &[], shader_reload::read_include)
+                .map_err(|error| log::warn!("Couldn't preprocess frag.wgsl: {}", error))
+                .ok()
+        });
+        if let Some(source) = frag_source {
+            self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let pipelines = create_output_pipelines(
+                &self.device,
+                &self.config,
+                &self.camera,
+                &self.raytracing,
+                &self.motion_blur,
+                &self.tonemap,
+                &source,
+            );
+            match pollster::block_on(self.device.pop_error_scope()) {
+                None => {
+                    log::info!("Reloaded frag.wgsl");
+                    (self.render, self.grid, self.avatar) = pipelines;
+                    self.shader_reload.frag_compiled(source);
+                }
+                Some(error) => log::warn!("Couldn't compile frag.wgsl: {}", error),
+            }
+        }
+    }
+
+    fn run_command(&mut self, line: &str) {
+        log::info!("> {}", line);
+        match console::Command::parse(line) {
+            Ok(command) => self.apply_command(command),
+            Err(error) => log::warn!("{}", error),
+        }
+    }
+
+    // Console commands, also issued by scripts
+    pub fn apply_command(&mut self, command: console::Command) {
+        match command {
+            console::Command::Teleport(position) => {
+                self.camera.camera.position = position;
+                self.taa.reset();
+                self.reprojection.reset();
+            }
+            console::Command::LookAt(target) => {
+                let camera = &mut self.camera.camera;
+                if let Some(direction) = (target - camera.position).try_normalize(f32::EPSILON) {
+                    camera.direction = direction;
+                }
+            }
+            console::Command::Load(path) => {
+                if let Err(error) = self.load_vox(&path) {
+                    log::warn!("Couldn't load the world: {}", error);
+                }
+            }
+            console::Command::SetSun(sun) => self.raytracing.settings.sun = sun,
+            console::Command::SetTime(hours) => {
+                self.raytracing.settings.sun = world::Sun::at_time(hours)
+            }
+            console::Command::SetVoxel(position, material) => {
+                #[cfg(feature = "net")]
+                if let Some(net) = &mut self.net {
+                    net.edit(position.coords.into(), material.into());
+                }
+                self.edit_voxel(position, material);
+            }
+            console::Command::SetFov(fov) => {
+                self.camera.settings.fov = fov;
+                self.camera.apply_settings();
+            }
+            console::Command::SetSpeed(speed) => {
+                self.camera.settings.speed = speed;
+                self.camera.apply_settings();
+            }
+            #[cfg(feature = "net")]
+            console::Command::Connect(url) => match net::NetSession::connect(&url) {
+                Ok(session) => self.net = Some(session),
+                Err(error) => log::warn!("Couldn't connect to {}: {}", url, error),
+            },
+            #[cfg(not(feature = "net"))]
+            console::Command::Connect(url) => {
+                log::warn!("Can't connect to {} without the net feature", url)
+            }
+            console::Command::Help => log::info!("{}", console::Command::help()),
+        }
+    }
+
+    #[::profiling::function]
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // Skipped until update recreated the device
+        if self.diagnostics.device_lost() {
+            return Ok(());
+        }
+        let render_size = self.render_size();
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
+        self.diagnostics.push_scope(&self.device);
+
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
-        {
+        if let Some(timer) = &mut self.frame_timer {
+            timer.begin(&mut encoder);
+        }
+
+        // Everything the passes branch on is read up front, the nodes only
+        // borrow the pipelines they record
+        let inset_enabled = self.inset.enabled();
+        let inset_viewport = self.inset.viewport(self.size).filter(|_| inset_enabled);
+        let linear_mono = self.camera.camera.projection.is_linear()
+            && self.raytracing.settings.stereo == raytracing::StereoMode::Off;
+        let draw_grid = self.overlay.grid && linear_mono;
+        // Drawn with the grid pipeline, under the same conditions
+        let draw_frustum = self.frustum.frozen.is_some() && linear_mono;
+        // Like the grid the avatars need a linear projection
+        let draw_avatar = self.avatar.instance_count > 0 && linear_mono;
+        let auto_exposure = self.exposure.settings.enabled && !self.debug_view_active();
+        let capturing = self.capture.capturing();
+        let last_sample = self.capture.last_sample();
+
+        let mut graph = graph::RenderGraph::new();
+        let ray_traced = graph.import("Ray traced image");
+        let inset_image = graph.import("Inset image");
+        let reconstructed = graph.import("Reconstructed image");
+        let reprojected = graph.import("Reprojected history");
+        let anti_aliased = graph.import("Anti-aliased image");
+        let blurred = graph.import("Motion blurred image");
+        let accumulation = graph.import("Capture accumulation");
+        let adapted_exposure = graph.import("Exposure");
+        let swapchain = graph.import("Swapchain");
+
+        let ray_tracing = &self.raytracing;
+        let camera_bind_group = &self.camera.bind_group;
+        let interleaving = self
+            .checkerboard
+            .settings
+            .active()
+            .then_some(self.checkerboard.settings.pattern);
+        let fovea = self.checkerboard.fovea(render_size);
+        graph.add_node("Ray tracing", &[], &[ray_traced], move |encoder, _| {
             let mut ray_tracing_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Ray tracing pass"),
             });
 
-            ray_tracing_pass.set_pipeline(&self.raytracing.pipeline);
-            ray_tracing_pass.set_bind_group(0, &self.raytracing.bind_group, &[]);
-            ray_tracing_pass.set_bind_group(1, &self.camera.bind_group, &[]);
-            ray_tracing_pass.dispatch_workgroups(self.size.width / 16, self.size.height / 16, 1);
+            ray_tracing_pass.set_bind_group(
+                0,
+                &ray_tracing.bind_group,
+                ray_tracing.uniform_offsets(),
+            );
+            ray_tracing_pass.set_bind_group(1, camera_bind_group, &[]);
+            ray_tracing_pass.set_bind_group(2, &ray_tracing.prepass_write_bind_group, &[]);
+            if ray_tracing.settings.beam_optimization {
+                let tile_size = raytracing::BEAM_TILE_SIZE;
+                ray_tracing_pass.set_pipeline(&ray_tracing.pipelines().beam);
+                ray_tracing_pass.dispatch_workgroups(
+                    render_size.width.div_ceil(tile_size).div_ceil(8),
+                    render_size.height.div_ceil(tile_size).div_ceil(8),
+                    1,
+                );
+            }
+            if ray_tracing.settings.half_res_lighting {
+                ray_tracing_pass.set_pipeline(&ray_tracing.pipelines().lighting);
+                let (x, y) = ray_tracing.workgroups(
+                    render_size.width.div_ceil(2),
+                    render_size.height.div_ceil(2),
+                );
+                ray_tracing_pass.dispatch_workgroups(x, y, 1);
+            }
+            ray_tracing_pass.set_bind_group(2, &ray_tracing.prepass_read_bind_group, &[]);
+            ray_tracing_pass.set_pipeline(&ray_tracing.pipelines().pick);
+            ray_tracing_pass.dispatch_workgroups(1, 1, 1);
+            let traced =
+                interleaving.map_or(render_size, |pattern| pattern.traced_size(render_size));
+            let workgroups = ray_tracing.workgroups(traced.width, traced.height);
+            ray_tracing.dispatch_main(&mut ray_tracing_pass, workgroups);
+            ray_tracing.dispatch_wavefront(&mut ray_tracing_pass, workgroups);
+            if let (Some(fovea), Some(pattern)) = (fovea, interleaving) {
+                ray_tracing.dispatch_fovea(&mut ray_tracing_pass, fovea, pattern);
+            }
+        });
+        if inset_enabled {
+            let inset = &self.inset;
+            graph.add_node(
+                "Inset ray tracing",
+                &[],
+                &[inset_image],
+                move |encoder, _| {
+                    let mut inset_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("Inset ray tracing pass"),
+                    });
+
+                    inset
+                        .raytracing
+                        .dispatch(&mut inset_pass, &inset.camera_bind_group);
+                },
+            );
         }
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
+        let checkerboard = &self.checkerboard;
+        graph.add_node(
+            "Checkerboard",
+            &[ray_traced],
+            &[reconstructed],
+            move |encoder, _| {
+                {
+                    let mut checkerboard_pass =
+                        encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: Some("Checkerboard reconstruction pass"),
+                        });
+
+                    checkerboard_pass.set_pipeline(&checkerboard.pipeline);
+                    checkerboard_pass.set_bind_group(0, &checkerboard.bind_group, &[]);
+                    checkerboard_pass.dispatch_workgroups(
+                        render_size.width.div_ceil(16),
+                        render_size.height.div_ceil(16),
+                        1,
+                    );
+                }
+                if checkerboard.settings.active() {
+                    encoder.copy_texture_to_texture(
+                        checkerboard.output.as_image_copy(),
+                        checkerboard.history.as_image_copy(),
+                        checkerboard.output.size(),
+                    );
+                }
+            },
+        );
+        if self.reprojection.settings.enabled {
+            let reprojection = &self.reprojection;
+            graph.add_node(
+                "Reprojection",
+                &[ray_traced, reconstructed],
+                &[reprojected],
+                move |encoder, _| {
+                    let mut reprojection_pass =
+                        encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: Some("Reprojection pass"),
+                        });
+
+                    reprojection.dispatch(&mut reprojection_pass);
+                },
+            );
+        }
+        let taa = &self.taa;
+        graph.add_node(
+            "TAA",
+            &[reconstructed, reprojected],
+            &[anti_aliased],
+            move |encoder, _| {
+                {
+                    let mut taa_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("TAA resolve pass"),
+                    });
+
+                    taa_pass.set_pipeline(&taa.pipeline);
+                    taa_pass.set_bind_group(0, &taa.bind_group, &[]);
+                    taa_pass.dispatch_workgroups(
+                        render_size.width.div_ceil(16),
+                        render_size.height.div_ceil(16),
+                        1,
+                    );
+                }
+                encoder.copy_texture_to_texture(
+                    taa.output.as_image_copy(),
+                    taa.history.as_image_copy(),
+                    taa.output.size(),
+                );
+            },
+        );
+        let motion_blur = &self.motion_blur;
+        graph.add_node(
+            "Motion blur",
+            &[anti_aliased],
+            &[blurred],
+            move |encoder, _| {
+                if motion_blur.settings.enabled {
+                    let mut motion_blur_pass =
+                        encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: Some("Motion blur pass"),
+                        });
+
+                    motion_blur_pass.set_pipeline(&motion_blur.pipeline);
+                    motion_blur_pass.set_bind_group(0, &motion_blur.bind_group, &[]);
+                    motion_blur_pass.dispatch_workgroups(
+                        render_size.width.div_ceil(16),
+                        render_size.height.div_ceil(16),
+                        1,
+                    );
+                } else {
+                    encoder.copy_texture_to_texture(
+                        taa.output.as_image_copy(),
+                        motion_blur.output.as_image_copy(),
+                        taa.output.size(),
+                    );
+                }
+            },
+        );
+        // Read and write the image in place, so they are ordered before
+        // everything else that reads it
+        let targets = pass::PassTargets {
+            render_size,
+            hdr: &self.motion_blur.output,
+            hdr_view: &self.motion_blur.output_view,
+            output: &view,
+            camera_bind_group,
+        };
+        for custom in self.passes.stage(pass::PassStage::Hdr) {
+            let targets = &targets;
+            graph.add_node(custom.name(), &[blurred], &[blurred], move |encoder, _| {
+                custom.record(encoder, targets)
+            });
+        }
+        let capture = &self.capture;
+        if capturing {
+            graph.add_node(
+                "Capture accumulation",
+                &[blurred],
+                &[accumulation],
+                move |encoder, _| capture.accumulate(encoder, render_size),
+            );
+        }
+        if auto_exposure {
+            let exposure_pipeline = &self.exposure;
+            let tonemap = &self.tonemap;
+            let inset = &self.inset;
+            graph.add_node(
+                "Auto exposure",
+                &[blurred],
+                &[adapted_exposure],
+                move |encoder, _| {
+                    {
+                        let mut exposure_pass =
+                            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                                label: Some("Auto exposure pass"),
+                            });
+
+                        exposure_pass.set_bind_group(0, &exposure_pipeline.bind_group, &[]);
+                        exposure_pass.set_pipeline(&exposure_pipeline.reduce_pipeline);
+                        exposure_pass.dispatch_workgroups(
+                            render_size.width.div_ceil(16),
+                            render_size.height.div_ceil(16),
+                            1,
+                        );
+                        exposure_pass.set_pipeline(&exposure_pipeline.adapt_pipeline);
+                        exposure_pass.dispatch_workgroups(1, 1, 1);
+                    }
+                    // Feed the adapted exposure straight into the tonemap uniform
+                    encoder.copy_buffer_to_buffer(
+                        &exposure_pipeline.state,
+                        exposure::EXPOSURE_OFFSET,
+                        &tonemap.buffer,
+                        0,
+                        4,
+                    );
+                    if inset_enabled {
+                        encoder.copy_buffer_to_buffer(
+                            &exposure_pipeline.state,
+                            exposure::EXPOSURE_OFFSET,
+                            &inset.tonemap.buffer,
+                            0,
+                            4,
+                        );
+                    }
+                },
+            );
+        }
+        let render = &self.render;
+        let tonemap = &self.tonemap;
+        let grid = &self.grid;
+        let frustum = &self.frustum;
+        let inset = &self.inset;
+        let view = &view;
+        graph.add_node(
+            "Blit",
+            &[blurred, adapted_exposure, inset_image],
+            &[swapchain],
+            move |encoder, _| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.0,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 1.0,
+                            }),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+
+                // Pipeline
+                render_pass.set_pipeline(&render.pipeline);
+                // Ray traced image
+                render_pass.set_bind_group(0, &render.bind_group, &[]);
+                // Tonemap settings
+                render_pass.set_bind_group(1, &tonemap.bind_group, &[]);
+                // Draw
+                render_pass.draw(0..3, 0..1);
+
+                // The grid is rasterized from the center of the camera
+                if draw_grid {
+                    render_pass.set_pipeline(&grid.pipeline);
+                    render_pass.set_bind_group(0, camera_bind_group, &[]);
+                    render_pass.set_bind_group(1, &grid.bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, grid.vertex_buffer.slice(..));
+                    render_pass.draw(0..grid.vertex_count, 0..1);
+                }
+                if draw_frustum {
+                    render_pass.set_pipeline(&grid.pipeline);
+                    render_pass.set_bind_group(0, camera_bind_group, &[]);
+                    render_pass.set_bind_group(1, &grid.bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, frustum.vertex_buffer.slice(..));
+                    render_pass.draw(0..frustum.vertex_count(), 0..1);
+                }
+
+                if let Some((x, y, width, height)) = inset_viewport {
+                    render_pass.set_viewport(x, y, width, height, 0., 1.);
+                    render_pass.set_pipeline(&inset.render.pipeline);
+                    render_pass.set_bind_group(0, &inset.render.bind_group, &[]);
+                    render_pass.set_bind_group(1, &inset.tonemap.bind_group, &[]);
+                    render_pass.draw(0..3, 0..1);
+                }
+            },
+        );
+        // Its own pass for the depth attachment
+        if draw_avatar {
+            let avatar = &self.avatar;
+            graph.add_node("Avatar", &[], &[swapchain], move |encoder, _| {
+                let mut avatar_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Avatar Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &avatar.depth_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.),
+                            store: false,
                         }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
+                        stencil_ops: None,
+                    }),
+                });
+
+                avatar_pass.set_pipeline(&avatar.pipeline);
+                avatar_pass.set_bind_group(0, camera_bind_group, &[]);
+                avatar_pass.set_bind_group(1, &avatar.bind_group, &[]);
+                avatar_pass.set_vertex_buffer(0, avatar.vertex_buffer.slice(..));
+                avatar_pass.set_vertex_buffer(1, avatar.instance_buffer.slice(..));
+                avatar_pass.draw(0..avatar.vertex_count, 0..avatar.instance_count);
+            });
+        }
+        for custom in self.passes.stage(pass::PassStage::Output) {
+            let targets = &targets;
+            graph.add_node(
+                custom.name(),
+                &[swapchain],
+                &[swapchain],
+                move |encoder, _| custom.record(encoder, targets),
+            );
+        }
+        // Last, over everything else on the swapchain. Captures, videos and
+        // screenshots are tonemapped separately and don't include it.
+        #[cfg(feature = "egui")]
+        {
+            let gui = &mut self.gui;
+            let (device, queue, size) = (&self.device, &self.queue, self.size);
+            graph.add_node("GUI", &[], &[swapchain], move |encoder, _| {
+                gui.render(device, queue, encoder, view, size)
             });
+        }
+        let video = &mut self.video;
+        let screenshot = &mut self.screenshot;
+        graph.add_node(
+            "Readbacks",
+            &[accumulation, blurred, adapted_exposure],
+            &[],
+            move |encoder, _| {
+                if last_sample {
+                    capture.resolve(encoder, &tonemap.bind_group);
+                }
+                video.encode(encoder, &tonemap.bind_group);
+                screenshot.encode(encoder, &tonemap.bind_group);
+            },
+        );
 
-            // Pipeline
-            render_pass.set_pipeline(&self.render.pipeline);
-            // Camera
-            render_pass.set_bind_group(0, &self.render.bind_group, &[]);
-            // Draw
-            render_pass.draw(0..3, 0..1);
+        let frame_timer = &mut self.frame_timer;
+        if let Some(timer) = frame_timer.as_mut() {
+            // A scope per node and the one ended with the frame
+            timer.reserve(&self.device, graph.node_count() + 1);
         }
+        graph.execute(
+            &self.device,
+            &mut self.graph_pool,
+            &mut encoder,
+            |encoder, name| {
+                if let Some(timer) = frame_timer.as_mut() {
+                    timer.scope(encoder, name);
+                }
+            },
+        );
+
+        self.occlusion.encode(&mut encoder);
+        let cursor = self.pick_position();
+        self.picking
+            .encode(&mut encoder, &self.raytracing.pick_buffer, cursor);
 
-        self.queue.submit(iter::once(encoder.finish()));
-        output.present();
+        if let Some(timer) = &mut self.frame_timer {
+            timer.end(&mut encoder, "Readbacks");
+        }
 
+        let commands = encoder.finish();
+        self.diagnostics.pop_scope(&self.device, "Frame");
+        // Submitting to a lost device panics
+        if self.diagnostics.device_lost() {
+            return Ok(());
+        }
+        {
+            ::profiling::scope!("Submit");
+            self.queue.submit(iter::once(commands));
+            output.present();
+        }
+
+        ::profiling::scope!("Readbacks");
+        if let Some(timer) = &mut self.frame_timer {
+            timer.after_submit(&self.device);
+        }
+        self.capture.after_submit(&self.device);
+        self.video.after_submit(&self.device);
+        self.screenshot.after_submit(&self.device);
+        self.occlusion.after_submit(&self.device);
+        self.picking.after_submit(&self.device);
+
+        self.render_previews();
         Ok(())
     }
+
+    // Previews are drawn along with the main window and skip frames they
+    // can't get a surface texture for
+    fn render_previews(&mut self) {
+        let auto_exposure = (self.exposure.settings.enabled && !self.debug_view_active())
+            .then_some(&self.exposure.state);
+        for preview in &mut self.previews {
+            match preview.render(&self.device, &self.queue, auto_exposure) {
+                Ok(()) => {}
+                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                    let size = preview.window().inner_size();
+                    preview.resize(&self.device, size);
+                }
+                Err(error) => log::warn!("Couldn't render a preview window: {}", error),
+            }
+        }
+    }
+}
+
+impl app::App for State {
+    fn window(&self) -> &Window {
+        State::window(self)
+    }
+
+    fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        State::input(self, event)
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        State::resize(self, new_size)
+    }
+
+    fn update(&mut self, dt: instant::Duration) {
+        State::update(self, dt)
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        State::render(self)
+    }
+
+    fn device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            if let Some(delta) = self.mouse.raw_motion(*delta) {
+                self.input.process_mouse_motion(delta)
+            }
+        }
+    }
+
+    fn other_window_event(&mut self, window_id: WindowId, event: &WindowEvent) -> bool {
+        self.preview_event(window_id, event)
+    }
+
+    // Opens another window with its own camera
+    #[cfg(not(target_arch = "wasm32"))]
+    fn unhandled_event(
+        &mut self,
+        target: &winit::event_loop::EventLoopWindowTarget<()>,
+        event: &WindowEvent,
+    ) {
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(winit::event::VirtualKeyCode::F4),
+                    ..
+                },
+            ..
+        } = event
+        {
+            let result = winit::window::WindowBuilder::new()
+                .with_title("Preview")
+                .build(target)
+                .map_err(|error| error.to_string())
+                .and_then(|window| self.open_preview(window).map_err(|error| error.to_string()));
+            if let Err(error) = result {
+                log::warn!("Couldn't open a preview window: {}", error);
+            }
+        }
+    }
+}
+
+// Pipelines that render to the swapchain and depend on its format
+fn create_output_pipelines(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    camera: &camera::CameraPipeline,
+    raytracing: &raytracing::RaytracingPipeline,
+    motion_blur: &motion_blur::MotionBlurPipeline,
+    tonemap: &tonemap::TonemapPipeline,
+    frag_source: &str,
+) -> (
+    render::RenderPipeline,
+    grid::GridPipeline,
+    avatar::AvatarPipeline,
+) {
+    let vert_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Vertex shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/vert.wgsl").into()),
+    });
+
+    let frag_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fragment shader"),
+        source: wgpu::ShaderSource::Wgsl(frag_source.into()),
+    });
+
+    let render = render::RenderPipeline::new(
+        device,
+        vert_shader,
+        frag_shader,
+        config,
+        &raytracing.sampler,
+        &motion_blur.output_view,
+        &tonemap.bind_group_layout,
+    );
+
+    let grid =
+        grid::GridPipeline::new(device, config, &camera.bind_group_layout, &raytracing.depth);
+
+    let avatar =
+        avatar::AvatarPipeline::new(device, config, &camera.bind_group_layout, &raytracing.depth);
+
+    (render, grid, avatar)
 }
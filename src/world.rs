@@ -0,0 +1,383 @@
+use std::{collections::HashMap, sync::Arc};
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::palette;
+
+// Bounds of the interesting part of the scene, the terrain repeats beyond it
+pub const SCENE_MIN: [f32; 3] = [-64., -8., -64.];
+pub const SCENE_MAX: [f32; 3] = [64., 8., 64.];
+
+// Direction the sun light comes from, in degrees. The azimuth turns from +x
+// towards +z.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sun {
+    pub azimuth: f32,
+    pub elevation: f32,
+}
+
+impl Sun {
+    pub fn new() -> Self {
+        Self {
+            azimuth: 36.9,
+            elevation: 58.,
+        }
+    }
+
+    // Sun for a time of day in hours, rising in +x at 6 and setting in -x at
+    // 18. The path leans towards +z so the sun isn't straight overhead at noon.
+    pub fn at_time(hours: f32) -> Self {
+        let angle = (hours.rem_euclid(24.) - 6.) / 12. * std::f32::consts::PI;
+        let direction = Vector3::new(angle.cos(), angle.sin(), 0.4).normalize();
+        Self {
+            azimuth: direction.z.atan2(direction.x).to_degrees(),
+            elevation: direction.y.asin().to_degrees(),
+        }
+    }
+
+    // Unit vector towards the sun
+    pub fn direction(&self) -> Vector3<f32> {
+        let azimuth = self.azimuth.to_radians();
+        let elevation = self.elevation.clamp(-90., 90.).to_radians();
+        Vector3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        )
+    }
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Largest distance moved at once while sweeping, below a voxel so thin
+// walls can't be skipped
+const SWEEP_STEP: f32 = 0.25;
+// Gap kept between a swept box and the voxels it stopped against
+const SKIN: f32 = 0.001;
+
+// Voxels an application embedding the renderer draws in place of the
+// terrain, see Renderer::set_world. A palette index per voxel of a box, x
+// first, then y, then z. Everything outside the box is empty.
+pub struct VoxelGrid {
+    origin: Vector3<i32>,
+    size: Vector3<i32>,
+    voxels: Vec<u8>,
+}
+
+impl VoxelGrid {
+    pub fn new(origin: Vector3<i32>, size: Vector3<u32>, voxels: Vec<u8>) -> Result<Self, String> {
+        if size.iter().any(|&v| v == 0) {
+            return Err("Grids need at least one voxel".to_string());
+        }
+        let count = size.iter().map(|&v| v as usize).product::<usize>();
+        if voxels.len() != count {
+            return Err(format!(
+                "{} voxels for a grid of {}x{}x{}",
+                voxels.len(),
+                size.x,
+                size.y,
+                size.z
+            ));
+        }
+        Ok(Self {
+            origin,
+            size: size.map(|v| v as i32),
+            voxels,
+        })
+    }
+
+    pub fn origin(&self) -> Vector3<i32> {
+        self.origin
+    }
+
+    pub fn size(&self) -> Vector3<u32> {
+        self.size.map(|v| v as u32)
+    }
+
+    pub fn voxels(&self) -> &[u8] {
+        &self.voxels
+    }
+
+    pub fn contains(&self, c: Vector3<i32>) -> bool {
+        let local = c - self.origin;
+        (0..3).all(|i| local[i] >= 0 && local[i] < self.size[i])
+    }
+
+    pub fn material(&self, c: Vector3<i32>) -> u8 {
+        if !self.contains(c) {
+            return palette::EMPTY;
+        }
+        let local = c - self.origin;
+        self.voxels[(local.x + (local.y + local.z * self.size.y) * self.size.x) as usize]
+    }
+
+    // Coordinates of the voxels that aren't empty
+    pub fn solid(&self) -> impl Iterator<Item = Vector3<i32>> + '_ {
+        let size = self.size;
+        self.voxels
+            .iter()
+            .enumerate()
+            .filter(|(_, &voxel)| voxel != palette::EMPTY)
+            .map(move |(i, _)| {
+                let i = i as i32;
+                self.origin + Vector3::new(i % size.x, i / size.x % size.y, i / (size.x * size.y))
+            })
+    }
+}
+
+// Voxels per side of the bricks edits are grouped by, like the bricks of
+// brick_pool
+const EDIT_BRICK: i32 = 8;
+
+// Edited voxels of a brick by their coordinates
+type BrickEdits = HashMap<Vector3<i32>, u8>;
+
+// What the renderer draws and collisions and picking run against: the
+// terrain or a grid replacing it, with the voxels set by edits on top
+pub struct World {
+    grid: Option<Arc<VoxelGrid>>,
+    // Shared with the brick jobs generating them, see BrickWorld
+    edits: HashMap<Vector3<i32>, Arc<BrickEdits>>,
+    palette: Box<[[f32; 4]; palette::SIZE]>,
+}
+
+impl World {
+    // The terrain with None, colored by the default palette
+    pub fn new(grid: Option<VoxelGrid>) -> Self {
+        Self {
+            grid: grid.map(Arc::new),
+            edits: HashMap::new(),
+            palette: Box::new(palette::default_colors()),
+        }
+    }
+
+    // Colors for the materials of a model that comes with its own
+    pub fn with_palette(mut self, colors: [[f32; 4]; palette::SIZE]) -> Self {
+        self.palette = Box::new(colors);
+        self
+    }
+
+    pub fn grid(&self) -> Option<&VoxelGrid> {
+        self.grid.as_deref()
+    }
+
+    pub fn palette(&self) -> &[[f32; 4]; palette::SIZE] {
+        &self.palette
+    }
+
+    // Sets the voxel at c to a palette index, palette::EMPTY clears it
+    pub fn edit(&mut self, c: Vector3<i32>, material: u8) {
+        let brick = c.map(|v| v.div_euclid(EDIT_BRICK));
+        Arc::make_mut(self.edits.entry(brick).or_default()).insert(c, material);
+    }
+
+    // Bricks of brick_pool's size holding edited voxels
+    pub fn edited_bricks(&self) -> impl Iterator<Item = &Vector3<i32>> {
+        self.edits.keys()
+    }
+
+    // The part of the world a brick is generated from
+    pub fn brick(&self, brick: Vector3<i32>) -> BrickWorld {
+        BrickWorld {
+            grid: self.grid.clone(),
+            edits: self.edits.get(&brick).cloned(),
+        }
+    }
+
+    // Palette index of the voxel at integer coordinates c
+    pub fn material(&self, c: Vector3<i32>) -> u8 {
+        let brick = c.map(|v| v.div_euclid(EDIT_BRICK));
+        match self.edits.get(&brick).and_then(|edits| edits.get(&c)) {
+            Some(&material) => material,
+            None => generated(self.grid.as_deref(), c),
+        }
+    }
+
+    // Whether the voxel at integer coordinates c is solid
+    pub fn is_solid(&self, c: Vector3<i32>) -> bool {
+        self.material(c) != palette::EMPTY
+    }
+
+    // Whether an axis aligned box overlaps any solid voxel
+    pub fn overlaps(&self, center: Point3<f32>, half_extents: Vector3<f32>) -> bool {
+        let min = (center - half_extents).map(|v| v.floor() as i32);
+        let max = (center + half_extents).map(|v| v.floor() as i32);
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    if self.is_solid(Vector3::new(x, y, z)) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Moves a box by motion, stopping it against solid voxels and sliding
+    // along them. A box that starts inside the terrain moves freely so it can
+    // get out.
+    pub fn sweep(
+        &self,
+        position: Point3<f32>,
+        motion: Vector3<f32>,
+        half_extents: Vector3<f32>,
+    ) -> Point3<f32> {
+        if self.overlaps(position, half_extents) {
+            return position + motion;
+        }
+
+        let steps = (motion.abs().max() / SWEEP_STEP).ceil().max(1.) as u32;
+        let step = motion / steps as f32;
+
+        let mut position = position;
+        for _ in 0..steps {
+            // Axes are resolved separately so blocked movement slides along walls
+            for axis in 0..3 {
+                if step[axis] == 0. {
+                    continue;
+                }
+
+                let mut next = position;
+                next[axis] += step[axis];
+                if self.overlaps(next, half_extents) {
+                    // Move up to the face of the voxel that was hit
+                    next[axis] = if step[axis] > 0. {
+                        (next[axis] + half_extents[axis]).floor() - half_extents[axis] - SKIN
+                    } else {
+                        (next[axis] - half_extents[axis]).ceil() + half_extents[axis] + SKIN
+                    };
+                    if self.overlaps(next, half_extents) {
+                        next[axis] = position[axis];
+                    }
+                }
+                position = next;
+            }
+        }
+        position
+    }
+
+    // Distance along direction to the first solid voxel within max_distance,
+    // stepping through the voxel grid one cell at a time
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<f32> {
+        let direction = direction.try_normalize(f32::EPSILON)?;
+        let step = direction.map(|v| if v > 0. { 1 } else { -1 });
+        let delta = direction.map(|v| (1. / v).abs());
+
+        let mut voxel = origin.coords.map(|v| v.floor() as i32);
+        // Distance to the next voxel boundary on each axis
+        let mut next = Vector3::from_fn(|i, _| {
+            if direction[i] == 0. {
+                return f32::INFINITY;
+            }
+            let boundary = voxel[i] as f32 + if direction[i] > 0. { 1. } else { 0. };
+            (boundary - origin[i]) / direction[i]
+        });
+
+        let mut t = 0.;
+        while t <= max_distance {
+            if self.is_solid(voxel) {
+                return Some(t);
+            }
+            let axis = next.imin();
+            t = next[axis];
+            voxel[axis] += step[axis];
+            next[axis] += delta[axis];
+        }
+        None
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+// A brick's grid and edits, cloned cheaply to generate it on a worker
+pub struct BrickWorld {
+    grid: Option<Arc<VoxelGrid>>,
+    edits: Option<Arc<BrickEdits>>,
+}
+
+impl BrickWorld {
+    pub fn material(&self, c: Vector3<i32>) -> u8 {
+        match self.edits.as_ref().and_then(|edits| edits.get(&c)) {
+            Some(&material) => material,
+            None => generated(self.grid.as_deref(), c),
+        }
+    }
+
+    pub fn edited(&self) -> bool {
+        self.edits.is_some()
+    }
+}
+
+// Palette index of the voxel at c before any edits
+fn generated(grid: Option<&VoxelGrid>, c: Vector3<i32>) -> u8 {
+    match grid {
+        Some(grid) => grid.material(c),
+        None if is_terrain(c) => palette::TERRAIN,
+        None => palette::EMPTY,
+    }
+}
+
+// Whether the terrain is solid at c, must match getVoxel in ray-tracing.wgsl
+// at the finest scale
+pub fn is_terrain(c: Vector3<i32>) -> bool {
+    (c.y as f32) < (c.x as f32 / 5.).sin() * (c.z as f32 / 5.).sin() * 5.
+}
+
+// Highest terrain over each cell of a count by count grid of cells of size
+// by size columns, starting at the x and z of origin and row by row along x.
+// Voxels below it are solid, see is_terrain.
+pub fn max_heights(origin: Vector2<i32>, count: i32, size: i32) -> Vec<f32> {
+    height_ranges(origin, count, size)
+        .into_iter()
+        .map(|(_, high)| high)
+        .collect()
+}
+
+// Lowest and highest terrain over each cell, like max_heights. The terrain is
+// the product of a sine along x and one along z, so its extremes over a cell
+// are among the products of the extremes of the two sines there.
+pub fn height_ranges(origin: Vector2<i32>, count: i32, size: i32) -> Vec<(f32, f32)> {
+    let extremes = |start: i32| -> Vec<(f32, f32)> {
+        (0..count)
+            .map(|cell| {
+                (0..size)
+                    .map(|i| ((start + cell * size + i) as f32 / 5.).sin())
+                    .fold((f32::MAX, f32::MIN), |(low, high), v| {
+                        (low.min(v), high.max(v))
+                    })
+            })
+            .collect()
+    };
+    let (xs, zs) = (extremes(origin.x), extremes(origin.y));
+    let mut heights = Vec::with_capacity((count * count) as usize);
+    for &(z_low, z_high) in &zs {
+        for &(x_low, x_high) in &xs {
+            let products = [
+                x_low * z_low,
+                x_low * z_high,
+                x_high * z_low,
+                x_high * z_high,
+            ];
+            heights.push((
+                products.into_iter().fold(f32::MAX, f32::min) * 5.,
+                products.into_iter().fold(f32::MIN, f32::max) * 5.,
+            ));
+        }
+    }
+    heights
+}
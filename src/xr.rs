@@ -0,0 +1,238 @@
+use nalgebra::{Matrix3, Matrix4, Quaternion, UnitQuaternion, Vector3};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{Camera, CameraMode, CameraUniform},
+    overlay::{ChunkBounds, OverlaySettings},
+    raytracing,
+};
+
+const VIEW_TYPE: openxr::ViewConfigurationType = openxr::ViewConfigurationType::PRIMARY_STEREO;
+
+// The OpenXR runtime and headset, without a session yet
+pub struct XrSystem {
+    pub instance: openxr::Instance,
+    pub system: openxr::SystemId,
+    // Per-eye resolution recommended by the runtime
+    pub eye_size: PhysicalSize<u32>,
+}
+
+impl XrSystem {
+    // Fails without an OpenXR loader, runtime or connected headset
+    pub fn new() -> Result<Self, String> {
+        let entry = unsafe { openxr::Entry::load() }.map_err(|error| error.to_string())?;
+
+        let available = entry
+            .enumerate_extensions()
+            .map_err(|error| error.to_string())?;
+        if !available.khr_vulkan_enable2 {
+            return Err("OpenXR runtime doesn't support Vulkan".to_string());
+        }
+        let mut extensions = openxr::ExtensionSet::default();
+        extensions.khr_vulkan_enable2 = true;
+
+        let instance = entry
+            .create_instance(
+                &openxr::ApplicationInfo {
+                    application_name: "voxel-raytracing",
+                    application_version: 0,
+                    engine_name: "voxel-raytracing",
+                    engine_version: 0,
+                },
+                &extensions,
+                &[],
+            )
+            .map_err(|error| error.to_string())?;
+        let system = instance
+            .system(openxr::FormFactor::HEAD_MOUNTED_DISPLAY)
+            .map_err(|error| error.to_string())?;
+
+        let views = instance
+            .enumerate_view_configuration_views(system, VIEW_TYPE)
+            .map_err(|error| error.to_string())?;
+        let Some(view) = views.first() else {
+            return Err("Headset has no views".to_string());
+        };
+        let eye_size = PhysicalSize::new(
+            view.recommended_image_rect_width,
+            view.recommended_image_rect_height,
+        );
+
+        if let Ok(properties) = instance.properties() {
+            log::info!(
+                "OpenXR runtime: {} {}",
+                properties.runtime_name,
+                properties.runtime_version
+            );
+        }
+        log::info!("Headset eye size: {:?}", eye_size);
+
+        Ok(Self {
+            instance,
+            system,
+            eye_size,
+        })
+    }
+}
+
+// OpenXR is right-handed with -z forward, the world is left-handed with +z
+// forward
+fn to_world_vector(v: openxr::Vector3f) -> Vector3<f32> {
+    Vector3::new(v.x, v.y, -v.z)
+}
+
+fn to_world_rotation(q: openxr::Quaternionf) -> UnitQuaternion<f32> {
+    UnitQuaternion::from_quaternion(Quaternion::new(q.w, -q.x, -q.y, q.z))
+}
+
+// The camera for one eye. The main camera is the origin of the tracking space,
+// only its heading is kept since the head provides pitch and roll.
+// world_scale is world units per meter.
+pub fn eye_camera(camera: &Camera, view: &openxr::View, world_scale: f32) -> Camera {
+    let up = Vector3::y();
+    let forward = Vector3::new(camera.direction.x, 0., camera.direction.z)
+        .try_normalize(f32::EPSILON)
+        .unwrap_or(Vector3::z());
+    let right = up.cross(&forward);
+    let base = Matrix3::from_columns(&[right, up, forward]);
+
+    let head = to_world_rotation(view.pose.orientation);
+    let direction = base * (head * Vector3::z());
+    let eye_up = base * (head * Vector3::y());
+
+    // Angle around the direction from the unrolled up vector to the head's
+    let roll = (up - direction * direction.dot(&up))
+        .try_normalize(f32::EPSILON)
+        .map_or(0., |level| {
+            direction
+                .dot(&level.cross(&eye_up))
+                .atan2(level.dot(&eye_up))
+        });
+
+    let mut eye = camera.clone();
+    eye.mode = CameraMode::FirstPerson;
+    eye.position = camera.position + base * to_world_vector(view.pose.position) * world_scale;
+    eye.direction = direction;
+    eye.roll = roll;
+    eye
+}
+
+// OpenGL style projection for the asymmetric frustum of an eye, like
+// Matrix4::new_perspective
+#[rustfmt::skip]
+pub fn eye_projection(fov: &openxr::Fovf, near: f32, far: f32) -> Matrix4<f32> {
+    let left = fov.angle_left.tan();
+    let right = fov.angle_right.tan();
+    let up = fov.angle_up.tan();
+    let down = fov.angle_down.tan();
+
+    Matrix4::new(
+        2. / (right - left), 0., (right + left) / (right - left), 0.,
+        0., 2. / (up - down), (up + down) / (up - down), 0.,
+        0., 0., -(far + near) / (far - near), -2. * far * near / (far - near),
+        0., 0., -1., 0.,
+    )
+}
+
+pub struct XrEye {
+    pub camera_uniform: CameraUniform,
+    pub camera_buffer: wgpu::Buffer,
+    pub camera_bind_group: wgpu::BindGroup,
+    pub raytracing: raytracing::RaytracingPipeline,
+}
+
+// Ray traces both eyes into their own HDR textures
+pub struct XrEyes {
+    pub size: PhysicalSize<u32>,
+    pub eyes: [XrEye; 2],
+    // World units per meter of head movement
+    pub world_scale: f32,
+}
+
+impl XrEyes {
+    pub fn new(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> XrEyes {
+        let create_eye = |label: &str| {
+            let camera_uniform = CameraUniform::new();
+            let camera_buffer = wgpu::util::DeviceExt::create_buffer_init(
+                device,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{} Eye Camera Buffer", label)),
+                    contents: bytemuck::cast_slice(&[camera_uniform]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+            );
+            let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: camera_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                }],
+                label: Some("xr_eye_camera_bind_group"),
+            });
+            let raytracing =
+                raytracing::RaytracingPipeline::new(device, &size, camera_bind_group_layout);
+
+            XrEye {
+                camera_uniform,
+                camera_buffer,
+                camera_bind_group,
+                raytracing,
+            }
+        };
+
+        XrEyes {
+            size,
+            eyes: [create_eye("Left"), create_eye("Right")],
+            world_scale: 1.,
+        }
+    }
+
+    // views are the located left and right eye views for the frame's predicted
+    // display time
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        views: &[openxr::View],
+    ) {
+        let overlay = OverlaySettings {
+            crosshair: false,
+            highlight_picked: false,
+            chunk_bounds: ChunkBounds::Off,
+            grid: false,
+        };
+
+        for (eye, view) in self.eyes.iter_mut().zip(views) {
+            let eye_camera = eye_camera(camera, view, self.world_scale);
+            eye.camera_uniform.update_view(&eye_camera);
+            eye.camera_uniform.update_view_proj_with(
+                &eye_camera,
+                eye_projection(&view.fov, eye_camera.near_clip, eye_camera.far_clip),
+                self.size.width,
+                self.size.height,
+            );
+            queue.write_buffer(
+                &eye.camera_buffer,
+                0,
+                bytemuck::cast_slice(&[eye.camera_uniform]),
+            );
+            eye.raytracing
+                .update(device, queue, &overlay, [0., 0.], None);
+        }
+    }
+
+    pub fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("XR eye pass"),
+        });
+
+        for eye in &self.eyes {
+            eye.raytracing.dispatch(&mut pass, &eye.camera_bind_group);
+        }
+    }
+}